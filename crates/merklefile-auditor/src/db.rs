@@ -0,0 +1,110 @@
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use merklefile_client::StorageError;
+use merklefile_core::log::{Cosignature, SignedTreeHead};
+
+/// A tree head the auditor verified, with its own cosignature over it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AuditedHead {
+    pub head: SignedTreeHead,
+    pub cosignature: Cosignature,
+}
+
+/// Local SQLite database of every tree head the auditor verified, per log: its own view of
+/// each log's history, against which every later head must be consistent.
+#[derive(Debug)]
+pub struct HeadDb {
+    conn: Connection,
+}
+
+impl HeadDb {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StorageError> {
+        Self::init(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> Result<Self, StorageError> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> Result<Self, StorageError> {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS heads (
+                log TEXT NOT NULL,
+                size INTEGER NOT NULL,
+                timestamp INTEGER NOT NULL,
+                root BLOB NOT NULL,
+                signature BLOB NOT NULL,
+                witness BLOB NOT NULL,
+                cosigned_at INTEGER NOT NULL,
+                cosignature BLOB NOT NULL,
+                PRIMARY KEY (log, size)
+            )",
+        )?;
+        Ok(Self { conn })
+    }
+
+    /// Adds a verified head to the log's history. A head of a size already recorded keeps
+    /// the earlier entry; the caller has checked that both have the same root.
+    pub fn record(&self, log: &str, audited: &AuditedHead) -> Result<(), StorageError> {
+        let (head, cosignature) = (&audited.head, &audited.cosignature);
+        self.conn.execute(
+            "INSERT OR IGNORE INTO heads (log, size, timestamp, root, signature, witness,
+                 cosigned_at, cosignature)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                log,
+                head.size as i64,
+                head.timestamp as i64,
+                head.root,
+                head.signature,
+                cosignature.witness,
+                cosignature.timestamp as i64,
+                cosignature.signature
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// The largest head recorded for `log`.
+    pub fn latest(&self, log: &str) -> Result<Option<AuditedHead>, StorageError> {
+        self.conn
+            .query_row(
+                "SELECT size, timestamp, root, signature, witness, cosigned_at, cosignature
+                     FROM heads WHERE log = ?1 ORDER BY size DESC LIMIT 1",
+                params![log],
+                audited_head,
+            )
+            .optional()
+            .map_err(StorageError::from)
+    }
+
+    /// Every head recorded for `log`, oldest first.
+    pub fn history(&self, log: &str) -> Result<Vec<AuditedHead>, StorageError> {
+        let mut statement = self.conn.prepare(
+            "SELECT size, timestamp, root, signature, witness, cosigned_at, cosignature
+                 FROM heads WHERE log = ?1 ORDER BY size",
+        )?;
+        let heads = statement
+            .query_map(params![log], audited_head)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(heads)
+    }
+}
+
+fn audited_head(row: &Row<'_>) -> rusqlite::Result<AuditedHead> {
+    Ok(AuditedHead {
+        head: SignedTreeHead {
+            size: row.get::<_, i64>(0)? as u64,
+            timestamp: row.get::<_, i64>(1)? as u64,
+            root: row.get(2)?,
+            signature: row.get(3)?,
+        },
+        cosignature: Cosignature {
+            witness: row.get(4)?,
+            timestamp: row.get::<_, i64>(5)? as u64,
+            signature: row.get(6)?,
+        },
+    })
+}
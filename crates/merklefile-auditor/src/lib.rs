@@ -0,0 +1,317 @@
+//! Auditor for servers running as transparency logs: it keeps its own history of every log's
+//! tree heads, checks each new head against it, cosigns the heads that check out, and
+//! alerts when a log forks, rolls back or presents a head it did not sign.
+
+use serde::Deserialize;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use merklefile_client::{Client, ClientConfig};
+use merklefile_core::error::MerkleError;
+use merklefile_core::log::{self, SigningKey};
+use merklefile_monitor::{Alert, AlertConfig, Alerter};
+
+mod db;
+
+pub use db::{AuditedHead, HeadDb};
+
+/// Settings for `merklefile-auditor`, loaded from a TOML file such as:
+///
+/// ```toml
+/// interval_secs = 300
+/// db = "/var/lib/merklefile/auditor.db"
+/// witness_key = "/etc/merklefile/witness.key"
+/// publish_dir = "/var/www/cosigned"
+///
+/// [[logs]]
+/// name = "releases"
+/// addr = "files.internal:8080"
+/// public_key = "3b6a27bcceb6a42d62a3a8d02a6f0d73653215771de243a63ac048a18b59da29"
+///
+/// [alerts]
+/// webhook = { url = "https://hooks.example.com/merklefile" }
+/// ```
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct AuditorConfig {
+    pub logs: Vec<LogConfig>,
+    /// Seconds between the end of one round over every log and the start of the next.
+    #[serde(default = "default_interval")]
+    pub interval_secs: u64,
+    /// Request timeout per server.
+    #[serde(default = "default_timeout")]
+    pub timeout_secs: u64,
+    #[serde(default = "default_db")]
+    pub db: PathBuf,
+    /// The auditor's own signing key, as written by `merklefile log keygen`.
+    pub witness_key: PathBuf,
+    /// Directory the latest cosigned head of every log is written to, as `<name>.json`, for
+    /// clients to fetch.
+    pub publish_dir: Option<PathBuf>,
+    /// Notify someone when a log is caught misbehaving.
+    pub alerts: Option<AlertConfig>,
+}
+
+/// A log to audit.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct LogConfig {
+    /// Name the log's history is kept under.
+    pub name: String,
+    pub addr: String,
+    /// Hex Ed25519 public key the log signs its tree heads with.
+    pub public_key: String,
+}
+
+fn default_interval() -> u64 {
+    300
+}
+
+fn default_timeout() -> u64 {
+    60
+}
+
+fn default_db() -> PathBuf {
+    PathBuf::from("merklefile-auditor.db")
+}
+
+impl AuditorConfig {
+    pub fn from_toml(contents: &str) -> io::Result<Self> {
+        let config: Self = toml::from_str(contents)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+        if config.interval_secs == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "interval_secs must be positive",
+            ));
+        }
+        for (index, log) in config.logs.iter().enumerate() {
+            if config.logs[..index]
+                .iter()
+                .any(|other| other.name == log.name)
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("log {} is configured twice", log.name),
+                ));
+            }
+            config.client_config(log).log_key()?;
+        }
+        Ok(config)
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_toml(&std::fs::read_to_string(path)?)
+    }
+
+    fn client_config(&self, log: &LogConfig) -> ClientConfig {
+        ClientConfig {
+            request_timeout_secs: Some(self.timeout_secs),
+            log_public_key: Some(log.public_key.clone()),
+            ..ClientConfig::new(&log.addr)
+        }
+    }
+}
+
+/// What auditing one log found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuditOutcome {
+    /// The log presented a head the auditor had not seen, consistent with its history,
+    /// and the auditor cosigned it.
+    Cosigned(AuditedHead),
+    /// The log presented the latest head the auditor already holds.
+    Unchanged { size: u64 },
+    /// The log presented a head that is unsigned, smaller than one seen before, or not an
+    /// extension of the history seen so far.
+    Inconsistent { problem: String },
+}
+
+/// Audits the configured logs, once or on an interval.
+#[derive(Debug)]
+pub struct Auditor {
+    config: AuditorConfig,
+    witness_key: SigningKey,
+    alerter: Option<Alerter>,
+}
+
+impl Auditor {
+    pub fn new(config: AuditorConfig) -> io::Result<Self> {
+        Ok(Self {
+            witness_key: log::read_signing_key(&config.witness_key)?,
+            alerter: config.alerts.clone().map(Alerter::new),
+            config,
+        })
+    }
+
+    pub fn config(&self) -> &AuditorConfig {
+        &self.config
+    }
+
+    /// Audits every log forever, passing each outcome to `on_audit`. A log that cannot be
+    /// reached is logged and tried again in the next round.
+    pub async fn run(&self, mut on_audit: impl FnMut(&LogConfig, &AuditOutcome)) -> io::Result<()> {
+        loop {
+            for (log, outcome) in self.audit_all().await? {
+                match outcome {
+                    Ok(outcome) => on_audit(log, &outcome),
+                    Err(err) => eprintln!("Auditing {} failed: {}", log.name, err),
+                }
+            }
+            tokio::time::sleep(Duration::from_secs(self.config.interval_secs)).await;
+        }
+    }
+
+    /// Audits every log once and alerts on each inconsistent one.
+    pub async fn audit_all(&self) -> io::Result<Vec<(&LogConfig, io::Result<AuditOutcome>)>> {
+        let db = HeadDb::open(&self.config.db)?;
+        let mut outcomes = Vec::new();
+        for log in &self.config.logs {
+            let outcome = self.audit(&db, log).await;
+            if let (Ok(AuditOutcome::Inconsistent { problem }), Some(alerter)) =
+                (&outcome, &self.alerter)
+            {
+                if let Err(err) = alerter
+                    .send(Alert::log_inconsistent(&log.name, &log.addr, problem))
+                    .await
+                {
+                    eprintln!("Alert failed: {}", err);
+                }
+            }
+            outcomes.push((log, outcome));
+        }
+        Ok(outcomes)
+    }
+
+    /// Fetches the log's current head and checks it against the latest head in `db`,
+    /// recording and cosigning it if it is new and consistent.
+    pub async fn audit(&self, db: &HeadDb, log: &LogConfig) -> io::Result<AuditOutcome> {
+        let client = Client::new(self.config.client_config(log));
+        let latest = db.latest(&log.name)?;
+        let head = match client
+            .tree_head(latest.as_ref().map(|seen| &seen.head))
+            .await
+        {
+            Ok(head) => head,
+            Err(err) => {
+                return match err
+                    .get_ref()
+                    .and_then(|err| err.downcast_ref::<MerkleError>())
+                {
+                    Some(
+                        MerkleError::TreeHeadSignatureInvalid { .. }
+                        | MerkleError::InconsistentTreeHeads { .. },
+                    ) => Ok(AuditOutcome::Inconsistent {
+                        problem: err.to_string(),
+                    }),
+                    _ => Err(err),
+                };
+            }
+        };
+        if let Some(latest) = latest.filter(|seen| seen.head.size == head.size) {
+            return Ok(AuditOutcome::Unchanged {
+                size: latest.head.size,
+            });
+        }
+
+        let audited = AuditedHead {
+            cosignature: head.cosign(&self.witness_key, now()),
+            head,
+        };
+        db.record(&log.name, &audited)?;
+        if let Some(dir) = &self.config.publish_dir {
+            publish(&dir.join(format!("{}.json", log.name)), &audited)?;
+        }
+        Ok(AuditOutcome::Cosigned(audited))
+    }
+}
+
+/// Replaces the published cosigned head in one step, so readers never see half of one.
+fn publish(path: &Path, audited: &AuditedHead) -> io::Result<()> {
+    let json = serde_json::to_vec_pretty(audited).expect("cosigned head serializes");
+    let partial = path.with_extension("partial");
+    std::fs::write(&partial, json)?;
+    std::fs::rename(partial, path)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use merklefile_core::log::SignedTreeHead;
+    use std::collections::BTreeMap;
+
+    #[tokio::test]
+    async fn test_auditor_cosigns_and_catches_forks() {
+        let addr = "127.0.0.1:8095";
+        let log_key = SigningKey::from_bytes(&[5; 32]);
+        let server = merklefile_server::Server::new().with_transparency_log(log_key.clone());
+        tokio::spawn(async move { server.start(addr).await.unwrap() });
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        let dir = std::env::temp_dir().join(format!("merklefile-auditor-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("witness.key"), hex::encode([6; 32])).unwrap();
+        let config = AuditorConfig::from_toml(&format!(
+            "witness_key = {:?}\npublish_dir = {:?}\n[[logs]]\nname = \"files\"\naddr = \"{}\"\n\
+             public_key = \"{}\"",
+            dir.join("witness.key"),
+            dir,
+            addr,
+            hex::encode(log_key.verifying_key().to_bytes())
+        ))
+        .unwrap();
+        let auditor = Auditor::new(config.clone()).unwrap();
+        let log = &config.logs[0];
+        let db = HeadDb::open_in_memory().unwrap();
+
+        let mut files = BTreeMap::new();
+        files.insert("a.txt".to_string(), b"first".to_vec());
+        let client = Client::new(ClientConfig::new(addr));
+        client.upload_files(files).await.unwrap();
+        let AuditOutcome::Cosigned(audited) = auditor.audit(&db, log).await.unwrap() else {
+            panic!("first head was not cosigned");
+        };
+        assert_eq!(audited.head.size, 1);
+        assert!(audited.cosignature.verify(&audited.head));
+        let published = std::fs::read(dir.join("files.json")).unwrap();
+        assert_eq!(
+            serde_json::from_slice::<AuditedHead>(&published).unwrap(),
+            audited
+        );
+        assert_eq!(
+            auditor.audit(&db, log).await.unwrap(),
+            AuditOutcome::Unchanged { size: 1 }
+        );
+
+        // A history the auditor saw that the log no longer extends is a fork
+        let forked = SignedTreeHead::sign(1, 0, vec![0; 32], &log_key);
+        let other = HeadDb::open_in_memory().unwrap();
+        other
+            .record(
+                "files",
+                &AuditedHead {
+                    cosignature: forked.cosign(&auditor.witness_key, 0),
+                    head: forked,
+                },
+            )
+            .unwrap();
+        let mut files = BTreeMap::new();
+        files.insert("b.txt".to_string(), b"second".to_vec());
+        client.upload_files(files).await.unwrap();
+        assert!(matches!(
+            auditor.audit(&other, log).await.unwrap(),
+            AuditOutcome::Inconsistent { .. }
+        ));
+        let extended = auditor.audit(&db, log).await;
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(matches!(extended.unwrap(), AuditOutcome::Cosigned(_)));
+        assert_eq!(db.history("files").unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,85 @@
+use clap::Parser;
+use merklefile_auditor::{AuditOutcome, Auditor, AuditorConfig, LogConfig};
+use merklefile_core::error::display_chain;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+/// Exit code when a log was caught forking, rolling back or presenting unsigned heads, as
+/// for `merklefile log head`.
+const LOG_INCONSISTENT: u8 = 8;
+
+#[derive(Parser)]
+#[command(
+    name = "merklefile-auditor",
+    about = "Watch transparency-log servers, cosign their tree heads and alert on forks"
+)]
+struct Cli {
+    /// Audit every log once and exit instead of running on the interval
+    #[arg(long)]
+    once: bool,
+    /// Auditor configuration file: logs, witness key and alerts
+    config: PathBuf,
+}
+
+fn print_outcome(log: &LogConfig, outcome: &AuditOutcome) {
+    match outcome {
+        AuditOutcome::Cosigned(audited) => println!(
+            "{}: cosigned size {} root {}",
+            log.name,
+            audited.head.size,
+            hex::encode(&audited.head.root)
+        ),
+        AuditOutcome::Unchanged { size } => println!("{}: unchanged at size {}", log.name, size),
+        AuditOutcome::Inconsistent { problem } => {
+            eprintln!("{}: INCONSISTENT: {}", log.name, problem)
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let auditor = match AuditorConfig::from_file(&cli.config).and_then(Auditor::new) {
+        Ok(auditor) => auditor,
+        Err(err) => {
+            eprintln!("Error: invalid configuration: {}", display_chain(&err));
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if !cli.once {
+        return match auditor.run(print_outcome).await {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(err) => {
+                eprintln!("Error: {}", display_chain(&err));
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    let outcomes = match auditor.audit_all().await {
+        Ok(outcomes) => outcomes,
+        Err(err) => {
+            eprintln!("Error: {}", display_chain(&err));
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut code = ExitCode::SUCCESS;
+    for (log, outcome) in outcomes {
+        match outcome {
+            Ok(outcome) => {
+                if matches!(outcome, AuditOutcome::Inconsistent { .. }) {
+                    code = ExitCode::from(LOG_INCONSISTENT);
+                }
+                print_outcome(log, &outcome);
+            }
+            Err(err) => {
+                eprintln!("{}: {}", log.name, display_chain(&err));
+                if code == ExitCode::SUCCESS {
+                    code = ExitCode::FAILURE;
+                }
+            }
+        }
+    }
+    code
+}
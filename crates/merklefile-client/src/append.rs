@@ -0,0 +1,76 @@
+use std::io::SeekFrom;
+use std::path::Path;
+use tokio::fs;
+use tokio::io::{self, AsyncReadExt, AsyncSeekExt};
+
+use merklefile_core::append::{AppendProof, AppendState};
+use merklefile_core::error::MerkleError;
+
+/// Re-attests a file that only grows, such as a log, given the state its earlier content
+/// was verified in. Only the bytes appended since, and the partial chunk the earlier content
+/// ended in, are read and hashed. Fails if the file shrank or that partial chunk changed.
+/// Bytes before it are not read again, so rewrites there are only caught by hashing the
+/// whole file, e.g. with `chunk::chunk_tree`.
+pub async fn reattest_appended(
+    path: impl AsRef<Path>,
+    previous: &AppendState,
+) -> io::Result<(AppendState, AppendProof)> {
+    let path = path.as_ref();
+    let not_appended = || MerkleError::NotAppended {
+        path: path.display().to_string(),
+        len: previous.len(),
+    };
+    let mut file = fs::File::open(path).await?;
+    let len = file.metadata().await?.len();
+    if len < previous.len() {
+        return Err(not_appended().into());
+    }
+
+    file.seek(SeekFrom::Start(
+        previous.len() - previous.tail().len() as u64,
+    ))
+    .await?;
+    let mut tail = vec![0; previous.tail().len()];
+    file.read_exact(&mut tail).await?;
+    if tail != previous.tail() {
+        return Err(not_appended().into());
+    }
+    // The file may still be growing; stop where it ended when it was measured
+    let mut appended = Vec::with_capacity((len - previous.len()) as usize);
+    file.take(len - previous.len())
+        .read_to_end(&mut appended)
+        .await?;
+    Ok(previous.append(&appended))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use merklefile_core::chunk;
+
+    #[tokio::test]
+    async fn test_reattesting_reads_only_what_was_appended() {
+        let path =
+            std::env::temp_dir().join(format!("merklefile-append-{}.log", std::process::id()));
+        fs::write(&path, b"first line\n").await.unwrap();
+        let (first, _) = reattest_appended(&path, &AppendState::new(4))
+            .await
+            .unwrap();
+
+        fs::write(&path, b"first line\nsecond line\n")
+            .await
+            .unwrap();
+        let (second, proof) = reattest_appended(&path, &first).await.unwrap();
+        let root = chunk::chunk_tree(b"first line\nsecond line\n", 4).get_root_hash();
+        assert_eq!(second.root(), root);
+        assert!(proof.verify(&first.root(), &root));
+
+        // The partial chunk the verified content ended in was rewritten
+        fs::write(&path, b"first line\nsecond lime\nthird line\n")
+            .await
+            .unwrap();
+        let err = reattest_appended(&path, &second).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        fs::remove_file(&path).await.unwrap();
+    }
+}
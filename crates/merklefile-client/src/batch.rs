@@ -0,0 +1,93 @@
+use tokio::io;
+
+use crate::{Client, ClientMessage, ServerMessage};
+use merklefile_proto::ProtocolError;
+
+/// Several requests sent to the server in one framed message and answered in order.
+///
+/// ```no_run
+/// # async fn example(client: merklefile_client::Client) -> std::io::Result<()> {
+/// let responses = client
+///     .batch()
+///     .download("a.txt")
+///     .proof("a.txt")
+///     .root_hash()
+///     .send()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Batch<'a> {
+    client: &'a Client,
+    requests: Vec<ServerMessage>,
+}
+
+impl<'a> Batch<'a> {
+    pub(super) fn new(client: &'a Client) -> Self {
+        Self {
+            client,
+            requests: Vec::new(),
+        }
+    }
+
+    pub fn download(mut self, filename: &str) -> Self {
+        self.requests.push(ServerMessage::Download {
+            filename: filename.to_string(),
+        });
+        self
+    }
+
+    pub fn proof(mut self, filename: &str) -> Self {
+        self.requests.push(ServerMessage::GetMerkleProof {
+            filename: filename.to_string(),
+        });
+        self
+    }
+
+    pub fn root_hash(mut self) -> Self {
+        self.requests.push(ServerMessage::GetRootHash);
+        self
+    }
+
+    pub fn chunk_hashes(mut self, filename: &str) -> Self {
+        self.requests.push(ServerMessage::GetChunkHashes {
+            filename: filename.to_string(),
+            chunk_size: self.client.config.chunk_size as u64,
+        });
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.requests.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.requests.is_empty()
+    }
+
+    /// Sends the batch and returns one response per request, in the order they were added.
+    /// Failures of individual requests come back as `ClientMessage::Error` entries.
+    pub async fn send(self) -> io::Result<Vec<ClientMessage>> {
+        let expected = self.requests.len();
+        let message = ServerMessage::Batch {
+            requests: self.requests,
+        };
+        let response = self.client.send_server_message(message).await?;
+
+        match response {
+            ClientMessage::Batch { responses } if responses.len() == expected => {
+                eprintln!("Batch of {} requests completed", expected);
+                Ok(responses)
+            }
+            ClientMessage::Error { message } => {
+                eprintln!("Batch request failed: {}", message);
+                Err(ProtocolError::Remote(message).into())
+            }
+            _ => {
+                eprintln!("Unexpected response from server");
+                Err(ProtocolError::UnexpectedResponse.into())
+            }
+        }
+    }
+}
@@ -0,0 +1,178 @@
+//! Blocking client built on `std::net`, enabled with the `sync` feature, for scripts and
+//! plugins that have no async runtime.
+//!
+//! ```no_run
+//! use merklefile_client::blocking;
+//!
+//! # fn main() -> std::io::Result<()> {
+//! let root = blocking::get_root_hash("127.0.0.1:8080")?;
+//! let data = blocking::download_verified("a.txt", &root, "127.0.0.1:8080")?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Failover addresses are ignored: only `server_addr` is contacted.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::net::{TcpStream, ToSocketAddrs};
+
+use crate::{
+    server_error, typed_proof, ClientConfig, ClientMessage, ConsistencyProof, MerkleProof,
+    MultiProof, ServerMessage,
+};
+use merklefile_core::error::MerkleError;
+use merklefile_proto::{self as protocol, ProtocolError};
+
+/// Blocking counterpart of `client::Client`; every call opens one connection.
+#[derive(Debug, Clone, Default)]
+pub struct Client {
+    config: ClientConfig,
+}
+
+impl Client {
+    pub fn new(config: ClientConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn config(&self) -> &ClientConfig {
+        &self.config
+    }
+
+    fn send_server_message(&self, message: &ServerMessage) -> io::Result<ClientMessage> {
+        let mut stream = match self.config.connect_timeout() {
+            Some(timeout) => {
+                let addr = self
+                    .config
+                    .server_addr
+                    .to_socket_addrs()?
+                    .next()
+                    .ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::AddrNotAvailable, "Address did not resolve")
+                    })?;
+                TcpStream::connect_timeout(&addr, timeout)?
+            }
+            None => TcpStream::connect(&self.config.server_addr)?,
+        };
+        stream.set_read_timeout(self.config.request_timeout())?;
+        stream.set_write_timeout(self.config.request_timeout())?;
+
+        protocol::write_frame_blocking(&mut stream, &protocol::encode(message)?)?;
+        let response: ClientMessage =
+            protocol::decode(&protocol::read_frame_blocking(&mut stream)?)?;
+        response.check_proofs()?;
+        Ok(response)
+    }
+
+    /// Uploads files and returns the server's new root hash.
+    pub fn upload_files(&self, client_files: BTreeMap<String, Vec<u8>>) -> io::Result<Vec<u8>> {
+        let message = ServerMessage::Upload {
+            client_files: crate::into_bytes(client_files),
+            dry_run: false,
+            with_proofs: false,
+            on_collision: self.config.on_collision,
+            expect: None,
+        };
+        match self.send_server_message(&message)? {
+            ClientMessage::Success { data } => Ok(Vec::from(data)),
+            ClientMessage::Error { message } => Err(server_error(message)),
+            _ => Err(ProtocolError::UnexpectedResponse.into()),
+        }
+    }
+
+    pub fn download_file(&self, filename: &str) -> io::Result<Vec<u8>> {
+        let message = ServerMessage::Download {
+            filename: filename.to_string(),
+        };
+        match self.send_server_message(&message)? {
+            ClientMessage::Success { data } => Ok(Vec::from(data)),
+            ClientMessage::Error { message } => Err(server_error(message)),
+            _ => Err(ProtocolError::UnexpectedResponse.into()),
+        }
+    }
+
+    pub fn get_merkle_proof(&self, filename: &str) -> io::Result<MerkleProof> {
+        let message = ServerMessage::GetMerkleProof {
+            filename: filename.to_string(),
+        };
+        match self.send_server_message(&message)? {
+            ClientMessage::MerkleProof { proof, tree_size } => typed_proof(&proof, tree_size),
+            ClientMessage::Error { message } => Err(server_error(message)),
+            _ => Err(ProtocolError::UnexpectedResponse.into()),
+        }
+    }
+
+    pub fn get_merkle_proofs(&self, filenames: &[&str]) -> io::Result<MultiProof> {
+        let message = ServerMessage::GetMultiProof {
+            filenames: filenames.iter().map(|name| name.to_string()).collect(),
+        };
+        match self.send_server_message(&message)? {
+            ClientMessage::MultiProof { proof } if proof.leaf_indices.len() == filenames.len() => {
+                Ok(proof)
+            }
+            ClientMessage::Error { message } => Err(server_error(message)),
+            _ => Err(ProtocolError::UnexpectedResponse.into()),
+        }
+    }
+
+    pub fn get_consistency_proof(&self, old_size: u64) -> io::Result<ConsistencyProof> {
+        match self.send_server_message(&ServerMessage::GetConsistencyProof { old_size })? {
+            ClientMessage::ConsistencyProof { proof } if proof.old_size == old_size => Ok(proof),
+            ClientMessage::Error { message } => Err(server_error(message)),
+            _ => Err(ProtocolError::UnexpectedResponse.into()),
+        }
+    }
+
+    pub fn get_root_hash(&self) -> io::Result<Vec<u8>> {
+        match self.send_server_message(&ServerMessage::GetRootHash)? {
+            ClientMessage::Success { data } => Ok(Vec::from(data)),
+            ClientMessage::Error { message } => Err(server_error(message)),
+            _ => Err(ProtocolError::UnexpectedResponse.into()),
+        }
+    }
+
+    /// Downloads a file and its proof, failing with `MerkleError::ProofInvalid` unless the
+    /// proof links the data to `trusted_root`.
+    pub fn download_verified(&self, filename: &str, trusted_root: &[u8]) -> io::Result<Vec<u8>> {
+        let data = self.download_file(filename)?;
+        let proof = self.get_merkle_proof(filename)?;
+        if !proof.verify(trusted_root, &data) {
+            return Err(MerkleError::ProofInvalid {
+                filename: filename.to_string(),
+            }
+            .into());
+        }
+        Ok(data)
+    }
+}
+
+pub fn upload_files(
+    client_files: BTreeMap<String, Vec<u8>>,
+    server_addr: &str,
+) -> io::Result<Vec<u8>> {
+    client_for(server_addr).upload_files(client_files)
+}
+
+pub fn download_file(filename: &str, server_addr: &str) -> io::Result<Vec<u8>> {
+    client_for(server_addr).download_file(filename)
+}
+
+pub fn get_merkle_proof(filename: &str, server_addr: &str) -> io::Result<MerkleProof> {
+    client_for(server_addr).get_merkle_proof(filename)
+}
+
+pub fn get_root_hash(server_addr: &str) -> io::Result<Vec<u8>> {
+    client_for(server_addr).get_root_hash()
+}
+
+pub fn download_verified(
+    filename: &str,
+    trusted_root: &[u8],
+    server_addr: &str,
+) -> io::Result<Vec<u8>> {
+    client_for(server_addr).download_verified(filename, trusted_root)
+}
+
+fn client_for(server_addr: &str) -> Client {
+    Client::new(ClientConfig::new(server_addr))
+}
@@ -0,0 +1,329 @@
+use serde::Deserialize;
+use std::path::Path;
+use std::time::Duration;
+use tokio::io;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::parallel;
+use crate::trust::RootSource;
+use crate::walk::{self, ScanEntry, SpecialFilePolicy, SymlinkPolicy};
+use merklefile_core::chunk;
+use merklefile_core::log::VerifyingKey;
+use merklefile_core::merkle_tree::TreeOptions;
+use merklefile_proto::CollisionPolicy;
+
+/// Client settings, usually loaded from a shared TOML file such as:
+///
+/// ```toml
+/// server_addr = "files.internal:8080"
+/// failover_addrs = ["files-backup.internal:8080"]
+/// connect_timeout_secs = 5
+/// request_timeout_secs = 60
+/// chunk_size = 65536
+/// upload_rate_limit = 10485760
+/// download_rate_limit = 10485760
+/// raw_downloads = true
+/// ignore = ["*.tmp", ".git/*"]
+/// trusted_root_url = "dns:root.files.internal"
+/// symlinks = "target"
+/// special_files = "error"
+/// on_collision = "reject"
+/// log_public_key = "3b6a27bcceb6a42d62a3a8d02a6f0d73653215771de243a63ac048a18b59da29"
+/// ```
+///
+/// Every field is optional and falls back to `ClientConfig::default()`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClientConfig {
+    pub server_addr: String,
+    /// Servers tried in order when `server_addr` cannot be reached or times out. All
+    /// reachable servers must present the same root before any of them is used.
+    pub failover_addrs: Vec<String>,
+    pub connect_timeout_secs: Option<u64>,
+    pub request_timeout_secs: Option<u64>,
+    /// Must match the chunk size used when the file's chunk root was recorded.
+    pub chunk_size: usize,
+    /// Upload bandwidth cap in bytes per second; unlimited when unset.
+    pub upload_rate_limit: Option<u64>,
+    /// Download bandwidth cap in bytes per second; unlimited when unset.
+    pub download_rate_limit: Option<u64>,
+    /// Download files as raw bytes following the response instead of as JSON inside it:
+    /// about a quarter of the bytes on the wire, and none copied through the server's memory
+    /// when it sends them with sendfile. Needs a server of protocol version 4 or later.
+    pub raw_downloads: bool,
+    /// Glob patterns, matched against paths relative to the uploaded directory.
+    pub ignore: Vec<String>,
+    /// Where the root that downloads are verified against is published, independently of
+    /// the file server: `https://...` or `dns:<name>`; see [`RootSource`].
+    pub trusted_root_url: Option<String>,
+    /// Hex Ed25519 public key of a server running as a transparency log; tree heads it
+    /// does not sign are rejected.
+    pub log_public_key: Option<String>,
+    /// Re-hash every file during scans and syncs instead of trusting unchanged size and mtime.
+    pub paranoid: bool,
+    /// Files hashed in parallel during scans and syncs, and chunks of one file hashed in
+    /// parallel when its chunk tree is built; one per core when unset. Lower it to keep
+    /// integrity work from taking every core of a shared host.
+    pub hash_concurrency: Option<usize>,
+    /// How directory scans treat symbolic links.
+    pub symlinks: SymlinkPolicy,
+    /// How directory scans treat FIFOs, sockets and device nodes.
+    pub special_files: SpecialFilePolicy,
+    /// Treat paths that differ only in case as the same path: ignore patterns match
+    /// regardless of case, and a directory scan fails on two such files instead of
+    /// producing a tree that cannot be checked out on Windows. On by default on Windows and
+    /// macOS, whose file systems behave that way.
+    pub case_insensitive_paths: bool,
+    /// Also scan the alternate data streams of files on NTFS, as `file:stream`. Has no
+    /// effect on other file systems.
+    pub alternate_data_streams: bool,
+    /// Bring file names to Unicode NFC before they become tree keys. macOS hands out names
+    /// decomposed (NFD) where Linux keeps whatever bytes were written, so without this the
+    /// same tree can have different roots depending on where it was scanned.
+    pub normalize_unicode: bool,
+    /// Lowercase file names before they become tree keys, for trees shared with
+    /// case-insensitive file systems.
+    pub fold_case: bool,
+    /// What uploads ask the server to do with stored files they would change: `overwrite`,
+    /// `reject` or `autoversion`. The server's own policy applies when unset. Servers before
+    /// protocol version 8 always overwrite.
+    pub on_collision: Option<CollisionPolicy>,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            server_addr: "127.0.0.1:8080".to_string(),
+            failover_addrs: Vec::new(),
+            connect_timeout_secs: None,
+            request_timeout_secs: None,
+            chunk_size: chunk::DEFAULT_CHUNK_SIZE,
+            upload_rate_limit: None,
+            download_rate_limit: None,
+            raw_downloads: false,
+            ignore: Vec::new(),
+            trusted_root_url: None,
+            log_public_key: None,
+            paranoid: false,
+            hash_concurrency: None,
+            symlinks: SymlinkPolicy::default(),
+            special_files: SpecialFilePolicy::default(),
+            case_insensitive_paths: cfg!(any(windows, target_os = "macos")),
+            alternate_data_streams: false,
+            normalize_unicode: true,
+            fold_case: false,
+            on_collision: None,
+        }
+    }
+}
+
+impl ClientConfig {
+    pub fn new(server_addr: &str) -> Self {
+        Self {
+            server_addr: server_addr.to_string(),
+            ..Self::default()
+        }
+    }
+
+    pub fn from_toml(contents: &str) -> io::Result<Self> {
+        let config: Self = toml::from_str(contents)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+        if config.chunk_size == 0 || config.chunk_size > chunk::MAX_CHUNK_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "chunk_size out of range",
+            ));
+        }
+        if config.hash_concurrency == Some(0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "hash_concurrency must be positive",
+            ));
+        }
+        if config.upload_rate_limit == Some(0) || config.download_rate_limit == Some(0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "rate limits must be positive",
+            ));
+        }
+        for pattern in &config.ignore {
+            glob::Pattern::new(pattern)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+        }
+        config.root_source()?;
+        config.log_key()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_toml(&std::fs::read_to_string(path)?)
+    }
+
+    /// The primary server followed by the failover servers, in the order they are tried.
+    pub fn server_addrs(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.server_addr.as_str())
+            .chain(self.failover_addrs.iter().map(String::as_str))
+    }
+
+    pub fn connect_timeout(&self) -> Option<Duration> {
+        self.connect_timeout_secs.map(Duration::from_secs)
+    }
+
+    pub fn request_timeout(&self) -> Option<Duration> {
+        self.request_timeout_secs.map(Duration::from_secs)
+    }
+
+    /// The parsed `trusted_root_url`, if set.
+    pub fn root_source(&self) -> io::Result<Option<RootSource>> {
+        self.trusted_root_url
+            .as_deref()
+            .map(|url| {
+                url.parse()
+                    .map_err(|err: String| io::Error::new(io::ErrorKind::InvalidData, err))
+            })
+            .transpose()
+    }
+
+    /// The parsed `log_public_key`, if set.
+    pub fn log_key(&self) -> io::Result<Option<VerifyingKey>> {
+        let Some(key) = &self.log_public_key else {
+            return Ok(None);
+        };
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "log_public_key is not a hex Ed25519 public key",
+            )
+        };
+        let bytes: [u8; 32] = hex::decode(key)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(invalid)?;
+        VerifyingKey::from_bytes(&bytes)
+            .map(Some)
+            .map_err(|_| invalid())
+    }
+
+    pub fn hash_concurrency(&self) -> usize {
+        self.hash_concurrency
+            .unwrap_or_else(parallel::default_concurrency)
+    }
+
+    /// How the chunk trees of single files are built.
+    pub fn tree_options(&self) -> TreeOptions {
+        TreeOptions {
+            hash_concurrency: self.hash_concurrency(),
+            ..TreeOptions::default()
+        }
+    }
+
+    /// Whether a path relative to an uploaded directory matches one of the ignore patterns.
+    pub fn is_ignored(&self, relative_path: &str) -> bool {
+        let options = self.match_options();
+        self.ignore.iter().any(|pattern| {
+            glob::Pattern::new(pattern)
+                .map(|pattern| pattern.matches_with(relative_path, options))
+                .unwrap_or(false)
+        })
+    }
+
+    /// The tree key for a `/`-separated relative path: empty and `.` components are
+    /// dropped and each name is normalized; see `normalize_name`.
+    pub fn normalize_key(&self, path: &str) -> String {
+        path.split('/')
+            .filter(|component| !component.is_empty() && *component != ".")
+            .map(|component| self.normalize_name(component))
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    /// Applies the Unicode and case normalization to a name or path.
+    pub fn normalize_name(&self, name: &str) -> String {
+        let mut name = if self.normalize_unicode {
+            name.nfc().collect()
+        } else {
+            name.to_string()
+        };
+        if self.fold_case {
+            name = name.to_lowercase();
+            if self.normalize_unicode {
+                // Lowercasing can leave composable sequences behind
+                name = name.nfc().collect();
+            }
+        }
+        name
+    }
+
+    /// How path globs are matched under the case policy.
+    pub fn match_options(&self) -> glob::MatchOptions {
+        glob::MatchOptions {
+            case_sensitive: !self.case_insensitive_paths,
+            ..glob::MatchOptions::new()
+        }
+    }
+
+    /// How a directory scan treats `path` under the symlink and special file policies.
+    pub fn classify(&self, path: &Path) -> io::Result<Option<ScanEntry>> {
+        walk::classify(path, self.symlinks, self.special_files)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partial_config_uses_defaults() {
+        let config = ClientConfig::from_toml(
+            r#"
+            server_addr = "10.0.0.1:9000"
+            ignore = ["*.tmp"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.server_addr, "10.0.0.1:9000");
+        assert_eq!(config.chunk_size, chunk::DEFAULT_CHUNK_SIZE);
+        assert!(config.request_timeout().is_none());
+        assert!(config.is_ignored("notes.tmp"));
+        assert!(!config.is_ignored("notes.txt"));
+
+        let insensitive = ClientConfig {
+            case_insensitive_paths: true,
+            ..config
+        };
+        assert!(insensitive.is_ignored("NOTES.TMP"));
+    }
+
+    #[test]
+    fn test_normalize_key() {
+        let config = ClientConfig::default();
+        // "café" with a combining acute accent, as macOS stores it
+        assert_eq!(
+            config.normalize_key("./docs//cafe\u{301}.txt"),
+            "docs/caf\u{e9}.txt"
+        );
+        let folding = ClientConfig {
+            fold_case: true,
+            ..ClientConfig::default()
+        };
+        assert_eq!(
+            folding.normalize_key("Docs/CAFE\u{301}.TXT"),
+            "docs/caf\u{e9}.txt"
+        );
+        let raw = ClientConfig {
+            normalize_unicode: false,
+            ..ClientConfig::default()
+        };
+        assert_eq!(raw.normalize_key("cafe\u{301}"), "cafe\u{301}");
+    }
+
+    #[test]
+    fn test_invalid_config_is_rejected() {
+        assert!(ClientConfig::from_toml("chunk_size = 0").is_err());
+        assert!(ClientConfig::from_toml("unknown_key = 1").is_err());
+        assert!(ClientConfig::from_toml(r#"ignore = ["[unclosed"]"#).is_err());
+        assert!(ClientConfig::from_toml(r#"symlinks = "ignore""#).is_err());
+        assert!(ClientConfig::from_toml(r#"trusted_root_url = "ftp://x""#).is_err());
+        assert!(ClientConfig::from_toml(r#"log_public_key = "abcd""#).is_err());
+    }
+}
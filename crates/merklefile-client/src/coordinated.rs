@@ -0,0 +1,118 @@
+use std::collections::BTreeMap;
+use tokio::io;
+
+use crate::{into_bytes, server_error, Client, ClientMessage, ServerMessage};
+use merklefile_core::error::MerkleError;
+use merklefile_proto::{self as protocol, ProtocolError};
+
+impl Client {
+    /// Uploads files to every configured server in two phases, so the servers cannot end up
+    /// attesting to different roots after a partial failure. Each server first checks the
+    /// upload and holds it, refusing other uploads meanwhile; only once all of them hold it
+    /// and agree on the root it leads to is it committed everywhere. Otherwise it is
+    /// aborted on the servers that held it and none of them changes. Returns the new root.
+    ///
+    /// A server's prepared upload can still expire before its commit arrives. If the upload
+    /// was then committed on some servers but not on others, this fails with
+    /// `ProtocolError::PartialCommit` naming both, so the caller can bring them back in step.
+    pub async fn upload_files_to_all(
+        &self,
+        client_files: BTreeMap<String, Vec<u8>>,
+    ) -> io::Result<Vec<u8>> {
+        let message = protocol::encode(&ServerMessage::Prepare {
+            client_files: into_bytes(client_files),
+            on_collision: self.config.on_collision,
+            expect: None,
+        })?;
+        let mut prepared: Vec<(&str, u128)> = Vec::new();
+        let mut agreed: Option<Vec<u8>> = None;
+        for addr in self.config.server_addrs() {
+            let result = match self.send_to(addr, &message).await {
+                Ok(ClientMessage::Prepared { transaction, root }) => {
+                    prepared.push((addr, transaction));
+                    match &agreed {
+                        Some(agreed) if *agreed != root => Err(MerkleError::InconsistentRoots {
+                            addr: addr.to_string(),
+                        }
+                        .into()),
+                        _ => {
+                            agreed = Some(root);
+                            Ok(())
+                        }
+                    }
+                }
+                Ok(ClientMessage::Conflict { root, files }) => {
+                    Err(ProtocolError::Conflict { root, files }.into())
+                }
+                Ok(ClientMessage::Error { message }) => Err(server_error(message)),
+                Ok(_) => Err(ProtocolError::UnexpectedResponse.into()),
+                Err(err) => Err(err),
+            };
+            if let Err(err) = result {
+                eprintln!("Failed to prepare upload on {}: {}", addr, err);
+                self.abort_all(&prepared).await;
+                return Err(err);
+            }
+        }
+
+        let (mut committed, mut unconfirmed) = (Vec::new(), Vec::new());
+        let mut failed = None;
+        for (addr, transaction) in &prepared {
+            let message = protocol::encode(&ServerMessage::Commit {
+                transaction: *transaction,
+            })?;
+            let result = match self.send_to(addr, &message).await {
+                Ok(ClientMessage::Success { data }) if Some(&*data) == agreed.as_deref() => Ok(()),
+                Ok(ClientMessage::Success { .. }) => Err(MerkleError::InconsistentRoots {
+                    addr: addr.to_string(),
+                }
+                .into()),
+                Ok(ClientMessage::Error { message }) => Err(server_error(message)),
+                Ok(_) => Err(ProtocolError::UnexpectedResponse.into()),
+                Err(err) => Err(err),
+            };
+            // Keep committing: the servers that can should not be left holding the upload
+            match result {
+                Ok(()) => committed.push(addr.to_string()),
+                Err(err) => {
+                    eprintln!("Failed to commit upload on {}: {}", addr, err);
+                    unconfirmed.push(addr.to_string());
+                    failed.get_or_insert(err);
+                }
+            }
+        }
+        match failed {
+            Some(_) if !committed.is_empty() => Err(ProtocolError::PartialCommit {
+                committed,
+                unconfirmed,
+            }
+            .into()),
+            Some(err) => Err(err),
+            None => {
+                let root = agreed.ok_or(ProtocolError::NoServerReachable)?;
+                eprintln!(
+                    "Files uploaded to all servers. Merkle Root Hash: {:?}",
+                    root
+                );
+                Ok(root)
+            }
+        }
+    }
+
+    /// Releases uploads prepared by `upload_files_to_all`. Failures are only reported; a
+    /// server that cannot be reached drops the upload once it expires.
+    async fn abort_all(&self, prepared: &[(&str, u128)]) {
+        for (addr, transaction) in prepared {
+            let message = ServerMessage::Abort {
+                transaction: *transaction,
+            };
+            let result = match protocol::encode(&message) {
+                Ok(message) => self.send_to(addr, &message).await,
+                Err(err) => Err(err.into()),
+            };
+            if let Err(err) = result {
+                eprintln!("Failed to abort upload on {}: {}", addr, err);
+            }
+        }
+    }
+}
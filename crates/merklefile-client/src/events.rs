@@ -0,0 +1,19 @@
+/// Long-running client operations that report progress events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Upload,
+    Download,
+    DeltaUpload,
+    Repair,
+}
+
+/// Progress of client operations, delivered to every receiver returned by
+/// `Client::subscribe`. `name` is a file name, or the directory for directory uploads.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientEvent {
+    Started { operation: Operation, name: String },
+    FileDone { name: String, bytes: u64 },
+    Verified { name: String },
+    ChunkRepaired { name: String, index: usize },
+    Error { name: String, message: String },
+}
@@ -0,0 +1,117 @@
+use std::path::Path;
+use tokio::io;
+
+use crate::{server_error, Client, ClientMessage, ServerMessage};
+use merklefile_core::error::MerkleError;
+use merklefile_core::log::{self, SignedTreeHead, VerifyingKey};
+use merklefile_proto::ProtocolError;
+
+impl Client {
+    /// Fetches the log server's current signed tree head and checks it against the
+    /// configured `log_public_key`. If `trusted` is the newest head verified before, the
+    /// server must also prove that the new head extends it; a smaller head is a rollback.
+    pub async fn tree_head(&self, trusted: Option<&SignedTreeHead>) -> io::Result<SignedTreeHead> {
+        let key = self.log_key()?;
+        let head = match self.send_server_message(ServerMessage::GetTreeHead).await? {
+            ClientMessage::TreeHead { head } => head,
+            ClientMessage::Error { message } => return Err(server_error(message)),
+            _ => return Err(ProtocolError::UnexpectedResponse.into()),
+        };
+        if !head.verify(&key) {
+            return Err(MerkleError::TreeHeadSignatureInvalid { size: head.size }.into());
+        }
+        if let Some(trusted) = trusted {
+            if head.size < trusted.size {
+                return Err(MerkleError::InconsistentTreeHeads {
+                    old_size: trusted.size,
+                    new_size: head.size,
+                }
+                .into());
+            }
+            self.check_consistency(trusted, &head).await?;
+        }
+        Ok(head)
+    }
+
+    /// Checks a tree head received from a peer (another client or a monitor) against one
+    /// this client verified itself. Either may be the older; the log must prove that the
+    /// newer extends the older, so a server showing different histories to different
+    /// clients is caught as soon as they compare notes.
+    pub async fn check_gossiped_head(
+        &self,
+        ours: &SignedTreeHead,
+        theirs: &SignedTreeHead,
+    ) -> io::Result<()> {
+        if !theirs.verify(&self.log_key()?) {
+            return Err(MerkleError::TreeHeadSignatureInvalid { size: theirs.size }.into());
+        }
+        if theirs.size < ours.size {
+            self.check_consistency(theirs, ours).await
+        } else {
+            self.check_consistency(ours, theirs).await
+        }
+    }
+
+    /// Demands and verifies a proof that `new` extends `old`.
+    async fn check_consistency(
+        &self,
+        old: &SignedTreeHead,
+        new: &SignedTreeHead,
+    ) -> io::Result<()> {
+        let inconsistent = MerkleError::InconsistentTreeHeads {
+            old_size: old.size,
+            new_size: new.size,
+        };
+        if old.size == new.size {
+            return if old.root == new.root {
+                Ok(())
+            } else {
+                Err(inconsistent.into())
+            };
+        }
+        let message = ServerMessage::GetLogConsistency {
+            old_size: old.size,
+            new_size: new.size,
+        };
+        let proof = match self.send_server_message(message).await? {
+            ClientMessage::LogConsistency { proof } => proof,
+            ClientMessage::Error { message } => return Err(server_error(message)),
+            _ => return Err(ProtocolError::UnexpectedResponse.into()),
+        };
+        if log::verify_consistency(old.size, &old.root, new.size, &new.root, &proof) {
+            Ok(())
+        } else {
+            Err(inconsistent.into())
+        }
+    }
+
+    fn log_key(&self) -> io::Result<VerifyingKey> {
+        self.config.log_key()?.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no log_public_key configured to verify tree heads with",
+            )
+        })
+    }
+}
+
+/// Reads a tree head saved with [`save_tree_head`], or `None` if there is no file yet.
+pub fn load_tree_head(path: impl AsRef<Path>) -> io::Result<Option<SignedTreeHead>> {
+    match std::fs::read(path) {
+        Ok(contents) => serde_json::from_slice(&contents)
+            .map(Some)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Saves a tree head as JSON, the form peers exchange them in.
+pub fn save_tree_head(path: impl AsRef<Path>, head: &SignedTreeHead) -> io::Result<()> {
+    let json = serde_json::to_vec_pretty(head).expect("tree head serializes");
+    // Replace the file in one step so a crash never leaves a truncated head behind
+    let path = path.as_ref();
+    let partial = path.with_extension("partial");
+    std::fs::write(&partial, json)?;
+    std::fs::rename(partial, path)
+}
@@ -0,0 +1,1497 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::broadcast;
+
+use merklefile_core::chunk;
+use merklefile_core::error::MerkleError;
+use merklefile_core::merkle_tree;
+use merklefile_core::profile::{self, Stage};
+use merklefile_proto::{self as protocol, Bytes, ProtocolError};
+
+mod append;
+mod batch;
+#[cfg(feature = "sync")]
+pub mod blocking;
+mod config;
+mod coordinated;
+mod events;
+mod gossip;
+mod manifest;
+mod parallel;
+mod repo;
+mod spot_check;
+mod state;
+mod streaming;
+mod throttle;
+mod transcript;
+mod trust;
+mod walk;
+
+pub use append::reattest_appended;
+pub use batch::Batch;
+pub use config::ClientConfig;
+pub use events::{ClientEvent, Operation};
+pub use gossip::{load_tree_head, save_tree_head};
+pub use manifest::{export_manifest, import_manifest, Manifest, ManifestSigner, ManifestVerifier};
+pub use merklefile_core::append::{AppendProof, AppendState};
+pub use merklefile_core::log::SignedTreeHead;
+pub use merklefile_core::merkle_tree::{ConsistencyProof, MerkleProof, MultiProof};
+pub use merklefile_core::transcript::{SignedTranscript, Transcript};
+pub use repo::{Repository, RepositoryKey};
+pub use spot_check::{AuditReport, SpotCheck};
+pub use state::{FileRecord, HashCache, StorageError, SyncState};
+pub use transcript::{load_transcript, save_transcript};
+pub use trust::RootSource;
+pub use walk::{alternate_streams, DirChain, ScanEntry, SpecialFilePolicy, SymlinkPolicy};
+
+pub use merklefile_proto::{
+    ChangeKind, ChunkWithProof, ClientMessage, CollisionPolicy, FileChange, ServerMessage,
+    ServerStats, UploadCondition,
+};
+
+/// Evidence returned by an upload: the new root and a proof for every uploaded file.
+/// Serializable so it can be stored alongside the files it covers.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UploadReceipt {
+    pub root: Vec<u8>,
+    /// Keyed by the name each file is stored under.
+    pub proofs: BTreeMap<String, MerkleProof>,
+    /// Uploaded name to stored name, for files the server stored as a new version instead
+    /// of overwriting (see `CollisionPolicy::AutoVersion`).
+    #[serde(default)]
+    pub stored_as: BTreeMap<String, String>,
+}
+
+/// Result of a dry-run upload: the per-file changes and the root the server would end up with.
+#[derive(Debug, Clone)]
+pub struct UploadPlan {
+    pub changes: Vec<FileChange>,
+    pub root: Vec<u8>,
+}
+
+/// A client bound to one server and configuration. The free functions in this module are
+/// shorthands for a `Client` with the default configuration.
+#[derive(Debug, Clone)]
+pub struct Client {
+    config: ClientConfig,
+    /// Root all servers agreed on, checked before the first request when failing over and
+    /// replaced by the root each upload through this client leads to.
+    agreed_root: Arc<tokio::sync::Mutex<Option<Vec<u8>>>>,
+    /// Servers found to present the agreed root. Any other is checked before it is used, so
+    /// one that was unreachable during the first check, or has not seen an upload since, is
+    /// not trusted unseen.
+    checked: Arc<Mutex<BTreeSet<String>>>,
+    events: broadcast::Sender<ClientEvent>,
+    /// Exchanges recorded since `with_transcript`, shared with clones.
+    transcript: Option<Arc<Mutex<Transcript>>>,
+}
+
+/// Events buffered per subscriber before the slowest one starts lagging.
+const EVENT_CAPACITY: usize = 256;
+
+impl Default for Client {
+    fn default() -> Self {
+        Self::new(ClientConfig::default())
+    }
+}
+
+impl Client {
+    pub fn new(config: ClientConfig) -> Self {
+        Self {
+            config,
+            agreed_root: Arc::default(),
+            checked: Arc::default(),
+            events: broadcast::channel(EVENT_CAPACITY).0,
+            transcript: None,
+        }
+    }
+
+    /// Records every request this client and its clones send from now on, with digests of
+    /// the request and response frames and any root reported, to export with `transcript`.
+    pub fn with_transcript(mut self) -> Self {
+        self.transcript = Some(Arc::default());
+        self
+    }
+
+    /// The exchanges recorded so far, or `None` unless recording was enabled with
+    /// `with_transcript`. Sign it with `Transcript::sign` to hand it to an auditor.
+    pub fn transcript(&self) -> Option<Transcript> {
+        self.transcript
+            .as_ref()
+            .map(|transcript| transcript.lock().expect("transcript lock poisoned").clone())
+    }
+
+    /// Receives progress events from operations started after this call, on this client and
+    /// its clones.
+    pub fn subscribe(&self) -> broadcast::Receiver<ClientEvent> {
+        self.events.subscribe()
+    }
+
+    fn emit(&self, event: ClientEvent) {
+        // Nobody listening is fine
+        let _ = self.events.send(event);
+    }
+
+    fn emit_result<T>(&self, name: &str, result: &io::Result<T>, bytes: impl Fn(&T) -> u64) {
+        match result {
+            Ok(value) => self.emit(ClientEvent::FileDone {
+                name: name.to_string(),
+                bytes: bytes(value),
+            }),
+            Err(err) => self.emit(ClientEvent::Error {
+                name: name.to_string(),
+                message: err.to_string(),
+            }),
+        }
+    }
+
+    pub fn config(&self) -> &ClientConfig {
+        &self.config
+    }
+
+    /// Starts collecting requests to send over a single connection.
+    pub fn batch(&self) -> Batch<'_> {
+        Batch::new(self)
+    }
+
+    /// Sends a message to the first reachable server. With failover addresses configured,
+    /// the servers must first agree on their root before any of them is used, and a server
+    /// that could not be reached then must present that root before it is used later.
+    async fn send_server_message(&self, message: ServerMessage) -> io::Result<ClientMessage> {
+        let mutating = mutates(&message);
+        let message = protocol::encode(&message)?;
+        if self.config.failover_addrs.is_empty() {
+            return self.send_to(&self.config.server_addr, &message).await;
+        }
+
+        let agreed = self.agreed().await?;
+        let mut last_err = None;
+        for addr in self.config.server_addrs() {
+            let checked = match self.is_checked(addr) {
+                true => Ok(()),
+                false => self.check_root(addr, &agreed).await,
+            };
+            match checked {
+                Err(err) if is_unavailable(&err) => {
+                    eprintln!("Server {} unavailable ({}), failing over", addr, err);
+                    last_err = Some(err);
+                    continue;
+                }
+                Err(err) => return Err(err),
+                Ok(()) => {}
+            }
+            match self.send_to(addr, &message).await {
+                Err(err) if is_unavailable(&err) => {
+                    eprintln!("Server {} unavailable ({}), failing over", addr, err);
+                    last_err = Some(err);
+                }
+                Ok(response) if mutating => {
+                    if let Some(root) = changed_root(&response) {
+                        self.root_changed(addr, root).await;
+                    }
+                    return Ok(response);
+                }
+                result => return result,
+            }
+        }
+        Err(last_err.expect("at least one server address is configured"))
+    }
+
+    /// The root the servers agreed on, asking all of them the first time.
+    async fn agreed(&self) -> io::Result<Vec<u8>> {
+        let mut agreed = self.agreed_root.lock().await;
+        if let Some(root) = &*agreed {
+            return Ok(root.clone());
+        }
+        let root = self.check_consistent_roots().await?;
+        *agreed = Some(root.clone());
+        Ok(root)
+    }
+
+    /// Records that an upload through `addr` changed its root. Only `addr` is known to hold
+    /// it, so every other server must present it before it is used again; `None` when the
+    /// new root is not known, so the servers are all asked again.
+    async fn root_changed(&self, addr: &str, root: Option<Vec<u8>>) {
+        let mut agreed = self.agreed_root.lock().await;
+        let mut checked = self.checked.lock().expect("checked servers lock poisoned");
+        checked.clear();
+        if root.is_some() {
+            checked.insert(addr.to_string());
+        }
+        *agreed = root;
+    }
+
+    /// Asks every configured server for its root and fails unless all reachable servers agree.
+    pub async fn check_consistent_roots(&self) -> io::Result<Vec<u8>> {
+        let mut agreed: Option<Vec<u8>> = None;
+        for addr in self.config.server_addrs() {
+            let root = match self.root_of(addr).await {
+                Ok(root) => root,
+                Err(err) if is_unavailable(&err) => continue,
+                Err(err) => return Err(err),
+            };
+            match &agreed {
+                Some(agreed) if *agreed != root => {
+                    return Err(MerkleError::InconsistentRoots {
+                        addr: addr.to_string(),
+                    }
+                    .into());
+                }
+                Some(_) => {}
+                None => agreed = Some(root),
+            }
+            self.mark_checked(addr);
+        }
+        agreed.ok_or_else(|| ProtocolError::NoServerReachable.into())
+    }
+
+    /// Fails unless the server at `addr` presents `agreed` as its root, and remembers it as
+    /// checked if it does.
+    async fn check_root(&self, addr: &str, agreed: &[u8]) -> io::Result<()> {
+        if self.root_of(addr).await? != agreed {
+            return Err(MerkleError::InconsistentRoots {
+                addr: addr.to_string(),
+            }
+            .into());
+        }
+        self.mark_checked(addr);
+        Ok(())
+    }
+
+    async fn root_of(&self, addr: &str) -> io::Result<Vec<u8>> {
+        let message = protocol::encode(&ServerMessage::GetRootHash)?;
+        match self.send_to(addr, &message).await? {
+            ClientMessage::Success { data } => Ok(Vec::from(data)),
+            _ => Err(ProtocolError::UnexpectedResponse.into()),
+        }
+    }
+
+    fn is_checked(&self, addr: &str) -> bool {
+        self.checked
+            .lock()
+            .expect("checked servers lock poisoned")
+            .contains(addr)
+    }
+
+    fn mark_checked(&self, addr: &str) {
+        self.checked
+            .lock()
+            .expect("checked servers lock poisoned")
+            .insert(addr.to_string());
+    }
+
+    async fn send_to(&self, addr: &str, message: &[u8]) -> io::Result<ClientMessage> {
+        match self.config.request_timeout() {
+            Some(timeout) => tokio::time::timeout(timeout, self.exchange(addr, message))
+                .await
+                .map_err(|_| ProtocolError::Timeout("request"))?,
+            None => self.exchange(addr, message).await,
+        }
+    }
+
+    async fn exchange(&self, addr: &str, message: &[u8]) -> io::Result<ClientMessage> {
+        let mut stream = self.connect(addr).await?;
+        let span = profile::span(Stage::SocketWrite).with_bytes(message.len() as u64);
+        stream.write_u64(message.len() as u64).await?;
+        throttle::write_all(&mut stream, message, self.config.upload_rate_limit).await?;
+        stream.flush().await?;
+        drop(span);
+        self.read_response(&mut stream, message).await
+    }
+
+    async fn connect(&self, addr: &str) -> io::Result<TcpStream> {
+        let connect = TcpStream::connect(addr);
+        Ok(match self.config.connect_timeout() {
+            Some(timeout) => tokio::time::timeout(timeout, connect)
+                .await
+                .map_err(|_| ProtocolError::Timeout("connect"))??,
+            None => connect.await?,
+        })
+    }
+
+    /// Reads the response to `request`, and the file after it if it is a `RawFile`.
+    async fn read_response(
+        &self,
+        stream: &mut TcpStream,
+        request: &[u8],
+    ) -> io::Result<ClientMessage> {
+        let mut span = profile::span(Stage::SocketRead);
+        let length = protocol::read_frame_len(stream).await?;
+        let mut buffer = Vec::new();
+        let mut payload = (&mut *stream).take(length);
+        throttle::read_to_end(&mut payload, &mut buffer, self.config.download_rate_limit).await?;
+        if buffer.len() as u64 != length {
+            return Err(ProtocolError::Truncated.into());
+        }
+        span.add_bytes(length);
+        drop(span);
+
+        let response: ClientMessage = protocol::decode(&buffer)?;
+        self.record_exchange(stream, request, &buffer, &response)?;
+        response.check_proofs()?;
+        if let ClientMessage::RawFile { len, hash } = response {
+            return self.read_raw_file(stream, len, &hash).await;
+        }
+        Ok(response)
+    }
+
+    /// Adds an exchange to the transcript, if one is being recorded. A `RawFile` frame holds
+    /// the hash of the file after it, so its digest covers the file too.
+    fn record_exchange(
+        &self,
+        stream: &TcpStream,
+        request: &[u8],
+        response: &[u8],
+        message: &ClientMessage,
+    ) -> io::Result<()> {
+        let Some(transcript) = &self.transcript else {
+            return Ok(());
+        };
+        let root = match message {
+            ClientMessage::UploadReceipt { root, .. } | ClientMessage::UploadPlan { root, .. } => {
+                Some(root.clone())
+            }
+            ClientMessage::TreeHead { head } => Some(head.root.clone()),
+            ClientMessage::Success { data }
+                if request == protocol::encode(&ServerMessage::GetRootHash)? =>
+            {
+                Some(data.to_vec())
+            }
+            _ => None,
+        };
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        let server = stream.peer_addr()?.to_string();
+        transcript
+            .lock()
+            .expect("transcript lock poisoned")
+            .record(timestamp, &server, request, response, root);
+        Ok(())
+    }
+
+    /// Reads the `len` bytes following a `RawFile` frame and hands them back as the
+    /// `Success` a plain download would have returned.
+    async fn read_raw_file(
+        &self,
+        stream: &mut TcpStream,
+        len: u64,
+        hash: &[u8],
+    ) -> io::Result<ClientMessage> {
+        let _span = profile::span(Stage::SocketRead).with_bytes(len);
+        let mut data = Vec::new();
+        let mut raw = stream.take(len);
+        throttle::read_to_end(&mut raw, &mut data, self.config.download_rate_limit).await?;
+        if data.len() as u64 != len {
+            return Err(ProtocolError::Truncated.into());
+        }
+        if Sha256::digest(&data).as_slice() != hash {
+            return Err(ProtocolError::HashMismatch.into());
+        }
+        Ok(ClientMessage::Success { data: data.into() })
+    }
+
+    /// Root of the chunk tree of a single file; record it at upload time to verify later
+    /// `read_range` calls.
+    pub fn compute_chunk_root_hash(&self, data: &[u8]) -> Vec<u8> {
+        chunk::chunk_tree_with(data, self.config.chunk_size, self.config.tree_options())
+            .get_root_hash()
+    }
+
+    /// Lists every file below `dir` as its `/`-separated path relative to `dir` and its full
+    /// path, skipping paths that match the configured ignore patterns. Relative paths are
+    /// normalized into tree keys, and two files with the same key are an error. Symbolic
+    /// links, special files, case and alternate data streams are handled as configured.
+    pub fn scan_dir(&self, dir: impl AsRef<Path>) -> io::Result<BTreeMap<String, PathBuf>> {
+        let mut files = BTreeMap::new();
+        let root = DirChain::default()
+            .enter(dir.as_ref())?
+            .expect("an empty chain contains no directory");
+        let mut pending = vec![(dir.as_ref().to_path_buf(), root)];
+        while let Some((current, chain)) = pending.pop() {
+            for entry in std::fs::read_dir(&current)? {
+                let path = entry?.path();
+                let relative = self.config.normalize_key(
+                    &path
+                        .strip_prefix(dir.as_ref())
+                        .expect("entry lies below the scanned directory")
+                        .components()
+                        .map(|component| component.as_os_str().to_string_lossy())
+                        .collect::<Vec<_>>()
+                        .join("/"),
+                );
+                if self.config.is_ignored(&relative) {
+                    continue;
+                }
+                match self.config.classify(&path)? {
+                    Some(ScanEntry::Dir(_)) => {
+                        if let Some(chain) = chain.enter(&path)? {
+                            pending.push((path, chain));
+                        }
+                    }
+                    Some(ScanEntry::File(_)) => {
+                        if self.config.alternate_data_streams {
+                            for stream in alternate_streams(&path)? {
+                                let mut stream_path = path.clone().into_os_string();
+                                stream_path.push(format!(":{}", stream));
+                                let key =
+                                    format!("{}:{}", relative, self.config.normalize_name(&stream));
+                                insert_unique(&mut files, key, stream_path.into())?;
+                            }
+                        }
+                        insert_unique(&mut files, relative, path)?;
+                    }
+                    None => {}
+                }
+            }
+        }
+        if self.config.case_insensitive_paths {
+            let mut seen = BTreeMap::new();
+            for name in files.keys() {
+                if let Some(other) = seen.insert(name.to_lowercase(), name) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{} and {} differ only in case", other, name),
+                    ));
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    /// Reads every file below `dir`, keyed by its path relative to `dir`; see `scan_dir`.
+    pub fn read_dir(&self, dir: impl AsRef<Path>) -> io::Result<BTreeMap<String, Vec<u8>>> {
+        self.scan_dir(dir)?
+            .into_iter()
+            .map(|(name, path)| Ok((name, self.config.symlinks.read(&path)?)))
+            .collect()
+    }
+
+    /// Uploads the files below `dir` and returns the server's new root hash.
+    pub async fn upload_dir(&self, dir: impl AsRef<Path>) -> io::Result<Vec<u8>> {
+        let dir_name = dir.as_ref().display().to_string();
+        self.emit(ClientEvent::Started {
+            operation: Operation::Upload,
+            name: dir_name.clone(),
+        });
+        let client_files = match self.read_dir(dir) {
+            Ok(client_files) => client_files,
+            Err(err) => {
+                self.emit(ClientEvent::Error {
+                    name: dir_name,
+                    message: err.to_string(),
+                });
+                return Err(err);
+            }
+        };
+        let sizes: Vec<(String, u64)> = client_files
+            .iter()
+            .map(|(name, data)| (name.clone(), data.len() as u64))
+            .collect();
+
+        let result = self.upload_files(client_files).await;
+        match &result {
+            Ok(_) => {
+                for (name, bytes) in sizes {
+                    self.emit(ClientEvent::FileDone { name, bytes });
+                }
+            }
+            Err(_) => self.emit_result(&dir_name, &result, |_| 0),
+        }
+        result
+    }
+
+    /// Uploads files and returns the server's new root hash, as the server reported it in
+    /// answer to the upload.
+    pub async fn upload_files(
+        &self,
+        client_files: BTreeMap<String, Vec<u8>>,
+    ) -> io::Result<Vec<u8>> {
+        self.upload(client_files, None).await
+    }
+
+    /// Uploads files only if the server is still in the state `expect` describes, e.g. each
+    /// file still has the SHA-256 it had when it was downloaded, and returns the new root.
+    /// Otherwise nothing is stored and the error holds a `ProtocolError::Conflict` with
+    /// what changed. Servers older than protocol version 9 upload unconditionally.
+    pub async fn upload_files_if(
+        &self,
+        client_files: BTreeMap<String, Vec<u8>>,
+        expect: UploadCondition,
+    ) -> io::Result<Vec<u8>> {
+        self.upload(client_files, Some(expect)).await
+    }
+
+    /// Uploads files and returns the server's new root hash.
+    async fn upload(
+        &self,
+        client_files: BTreeMap<String, Vec<u8>>,
+        expect: Option<UploadCondition>,
+    ) -> io::Result<Vec<u8>> {
+        let message = ServerMessage::Upload {
+            client_files: into_bytes(client_files),
+            dry_run: false,
+            with_proofs: false,
+            on_collision: self.config.on_collision,
+            expect,
+        };
+        let response = self.send_server_message(message).await?;
+
+        match response {
+            ClientMessage::Success { data } => {
+                eprintln!(
+                    "Files uploaded successfully. Merkle Root Hash from Server: {:?}",
+                    data
+                );
+                Ok(Vec::from(data))
+            }
+            ClientMessage::Conflict { root, files } => {
+                eprintln!(
+                    "Upload refused; files changed on the server: {:?}",
+                    files.keys()
+                );
+                Err(ProtocolError::Conflict { root, files }.into())
+            }
+            ClientMessage::Error { message } => {
+                eprintln!("Failed to upload files: {}", message);
+                Err(server_error(message))
+            }
+            _ => {
+                eprintln!("Unexpected response from server");
+                Err(ProtocolError::UnexpectedResponse.into())
+            }
+        }
+    }
+
+    /// Uploads files and receives, in the same round trip, each file's Merkle proof against
+    /// the new root. Every proof is checked against the uploaded bytes before returning.
+    pub async fn upload_files_with_proofs(
+        &self,
+        client_files: BTreeMap<String, Vec<u8>>,
+    ) -> io::Result<UploadReceipt> {
+        let uploaded = client_files.clone();
+        let message = ServerMessage::Upload {
+            client_files: into_bytes(client_files),
+            dry_run: false,
+            with_proofs: true,
+            on_collision: self.config.on_collision,
+            expect: None,
+        };
+        let response = self.send_server_message(message).await?;
+
+        match response {
+            ClientMessage::UploadReceipt {
+                root,
+                proofs,
+                stored_as,
+                tree_size,
+            } => {
+                let proofs = typed_proofs(proofs, tree_size)?;
+                for (filename, data) in &uploaded {
+                    let stored = stored_as.get(filename).unwrap_or(filename);
+                    let valid = proofs
+                        .get(stored)
+                        .is_some_and(|proof| proof.verify(&root, data));
+                    if !valid {
+                        return Err(MerkleError::ProofInvalid {
+                            filename: filename.clone(),
+                        }
+                        .into());
+                    }
+                }
+                eprintln!(
+                    "Files uploaded with proofs. Merkle Root Hash from Server: {:?}",
+                    root
+                );
+                Ok(UploadReceipt {
+                    root,
+                    proofs,
+                    stored_as,
+                })
+            }
+            ClientMessage::Error { message } => {
+                eprintln!("Failed to upload files: {}", message);
+                Err(server_error(message))
+            }
+            _ => {
+                eprintln!("Unexpected response from server");
+                Err(ProtocolError::UnexpectedResponse.into())
+            }
+        }
+    }
+
+    pub async fn upload_files_dry_run(
+        &self,
+        client_files: BTreeMap<String, Vec<u8>>,
+    ) -> io::Result<UploadPlan> {
+        let message = ServerMessage::Upload {
+            client_files: into_bytes(client_files),
+            dry_run: true,
+            with_proofs: false,
+            on_collision: self.config.on_collision,
+            expect: None,
+        };
+        let response = self.send_server_message(message).await?;
+
+        match response {
+            ClientMessage::UploadPlan { changes, root } => {
+                for change in &changes {
+                    eprintln!(
+                        "{:?} {}: {:?} -> {} bytes",
+                        change.kind, change.filename, change.old_size, change.new_size
+                    );
+                }
+                eprintln!("Merkle Root Hash after upload would be: {:?}", root);
+                Ok(UploadPlan { changes, root })
+            }
+            ClientMessage::Error { message } => {
+                eprintln!("Failed to plan upload: {}", message);
+                Err(server_error(message))
+            }
+            _ => {
+                eprintln!("Unexpected response from server");
+                Err(ProtocolError::UnexpectedResponse.into())
+            }
+        }
+    }
+
+    pub async fn download_file(&self, filename: &str) -> io::Result<Vec<u8>> {
+        self.emit(ClientEvent::Started {
+            operation: Operation::Download,
+            name: filename.to_string(),
+        });
+        let result = self.fetch_file(filename).await;
+        self.emit_result(filename, &result, |data| data.len() as u64);
+        result
+    }
+
+    async fn fetch_file(&self, filename: &str) -> io::Result<Vec<u8>> {
+        let filename = filename.to_string();
+        let message = if self.config.raw_downloads {
+            ServerMessage::DownloadRaw { filename }
+        } else {
+            ServerMessage::Download { filename }
+        };
+        let response = self.send_server_message(message).await?;
+
+        match response {
+            ClientMessage::Success { data } => {
+                eprintln!("File downloaded successfully");
+                Ok(Vec::from(data))
+            }
+            ClientMessage::Error { message } => {
+                eprintln!("Failed to download file: {}", message);
+                Err(server_error(message))
+            }
+            _ => {
+                eprintln!("Unexpected response from server");
+                Err(ProtocolError::UnexpectedResponse.into())
+            }
+        }
+    }
+
+    pub async fn get_merkle_proof(&self, filename: &str) -> io::Result<MerkleProof> {
+        let message = ServerMessage::GetMerkleProof {
+            filename: filename.to_string(),
+        };
+        let response = self.send_server_message(message).await?;
+
+        match response {
+            ClientMessage::MerkleProof { proof, tree_size } => {
+                eprintln!("Merkle Proof fetched successfully");
+                typed_proof(&proof, tree_size)
+            }
+            ClientMessage::Error { message } => {
+                eprintln!("Failed to fetch Merkle proof: {}", message);
+                Err(server_error(message))
+            }
+            _ => {
+                eprintln!("Unexpected response from server");
+                Err(ProtocolError::UnexpectedResponse.into())
+            }
+        }
+    }
+
+    /// Fetches a proof for `filename` in the version of the tree whose root was `root`, e.g.
+    /// one recorded at upload time. The server must still keep that version.
+    pub async fn get_merkle_proof_at(
+        &self,
+        filename: &str,
+        root: &[u8],
+    ) -> io::Result<MerkleProof> {
+        let message = ServerMessage::GetMerkleProofAt {
+            filename: filename.to_string(),
+            root: root.to_vec(),
+        };
+        match self.send_server_message(message).await? {
+            ClientMessage::MerkleProof { proof, tree_size } => typed_proof(&proof, tree_size),
+            ClientMessage::Error { message } => Err(server_error(message)),
+            _ => Err(ProtocolError::UnexpectedResponse.into()),
+        }
+    }
+
+    /// Fetches one proof for all of `filenames`, in one round trip. Siblings shared by their
+    /// paths are sent once, so for files stored near each other it is much smaller than
+    /// their separate proofs. Verify it with the files' contents in the order given.
+    pub async fn get_merkle_proofs(&self, filenames: &[&str]) -> io::Result<MultiProof> {
+        let message = ServerMessage::GetMultiProof {
+            filenames: filenames.iter().map(|name| name.to_string()).collect(),
+        };
+        match self.send_server_message(message).await? {
+            ClientMessage::MultiProof { proof } if proof.leaf_indices.len() == filenames.len() => {
+                Ok(proof)
+            }
+            ClientMessage::Error { message } => Err(server_error(message)),
+            _ => Err(ProtocolError::UnexpectedResponse.into()),
+        }
+    }
+
+    /// Fetches proof that the server's current tree extends the tree of its first `old_size`
+    /// files, e.g. the `tree_size` of a proof in an earlier receipt. Check it with the root
+    /// recorded then and the current one: it only verifies if files were added after all the
+    /// old ones and none of those changed.
+    pub async fn get_consistency_proof(&self, old_size: u64) -> io::Result<ConsistencyProof> {
+        match self
+            .send_server_message(ServerMessage::GetConsistencyProof { old_size })
+            .await?
+        {
+            ClientMessage::ConsistencyProof { proof } if proof.old_size == old_size => Ok(proof),
+            ClientMessage::Error { message } => Err(server_error(message)),
+            _ => Err(ProtocolError::UnexpectedResponse.into()),
+        }
+    }
+
+    /// Fetches the root published at the configured `trusted_root_url`, or `None` if there
+    /// is none. Verify downloads against it rather than against `get_root_hash`, which only
+    /// reports what the server claims.
+    pub async fn published_root(&self) -> io::Result<Option<Vec<u8>>> {
+        match self.config.root_source()? {
+            Some(source) => Ok(Some(source.fetch(self.config.request_timeout()).await?)),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn get_root_hash(&self) -> io::Result<Vec<u8>> {
+        let response = self.send_server_message(ServerMessage::GetRootHash).await?;
+
+        match response {
+            ClientMessage::Success { data } => Ok(Vec::from(data)),
+            ClientMessage::Error { message } => {
+                eprintln!("Failed to fetch root hash: {}", message);
+                Err(server_error(message))
+            }
+            _ => {
+                eprintln!("Unexpected response from server");
+                Err(ProtocolError::UnexpectedResponse.into())
+            }
+        }
+    }
+
+    /// Sizes and counters of the server, for monitoring. Reported by the server, so nothing
+    /// here is verified.
+    pub async fn stats(&self) -> io::Result<ServerStats> {
+        match self.send_server_message(ServerMessage::GetStats).await? {
+            ClientMessage::Stats { stats } => Ok(stats),
+            ClientMessage::Error { message } => Err(server_error(message)),
+            _ => Err(ProtocolError::UnexpectedResponse.into()),
+        }
+    }
+
+    /// Reads `len` bytes at `offset` of a stored file, verifying every covering chunk against
+    /// `chunk_root`.
+    pub async fn read_range(
+        &self,
+        filename: &str,
+        offset: u64,
+        len: u64,
+        chunk_root: &[u8],
+    ) -> io::Result<Vec<u8>> {
+        if len == 0 {
+            return Ok(Vec::new());
+        }
+        let chunks = self
+            .fetch_verified_chunks(filename, offset, len, chunk_root)
+            .await?;
+
+        let chunk_size = self.config.chunk_size as u64;
+        let start = (offset - offset / chunk_size * chunk_size) as usize;
+        let end = start + len as usize;
+        let bytes = chunks.concat();
+        if end > bytes.len() {
+            return Err(ProtocolError::Truncated.into());
+        }
+        eprintln!("Range verified successfully");
+        self.emit(ClientEvent::Verified {
+            name: filename.to_string(),
+        });
+        Ok(bytes[start..end].to_vec())
+    }
+
+    /// Fetches the chunks covering `offset..offset + len`, checking that each one sits at the
+    /// expected index under `chunk_root`.
+    async fn fetch_verified_chunks(
+        &self,
+        filename: &str,
+        offset: u64,
+        len: u64,
+        chunk_root: &[u8],
+    ) -> io::Result<Vec<Vec<u8>>> {
+        let chunk_size = self.config.chunk_size as u64;
+        let message = ServerMessage::ReadRange {
+            filename: filename.to_string(),
+            offset,
+            len,
+            chunk_size,
+        };
+        let response = self.send_server_message(message).await?;
+
+        let chunks = match response {
+            ClientMessage::Chunks { chunks } => chunks,
+            ClientMessage::Error { message } => {
+                eprintln!("Failed to read range: {}", message);
+                return Err(server_error(message));
+            }
+            _ => {
+                eprintln!("Unexpected response from server");
+                return Err(ProtocolError::UnexpectedResponse.into());
+            }
+        };
+
+        let first = offset / chunk_size;
+        let mut verified = Vec::with_capacity(chunks.len());
+        for (expected_index, chunk) in (first..).zip(chunks) {
+            let data = Vec::from(chunk.data);
+            if chunk.index != expected_index
+                || chunk::proof_index(&chunk.proof) as u64 != expected_index
+                || !merkle_tree::MerkleTree::verify_proof(&chunk.proof, chunk_root, &data)
+            {
+                return Err(MerkleError::ChunkInvalid {
+                    index: expected_index,
+                }
+                .into());
+            }
+            verified.push(data);
+        }
+        Ok(verified)
+    }
+
+    pub async fn get_chunk_hashes(&self, filename: &str) -> io::Result<Vec<Vec<u8>>> {
+        let message = ServerMessage::GetChunkHashes {
+            filename: filename.to_string(),
+            chunk_size: self.config.chunk_size as u64,
+        };
+        let response = self.send_server_message(message).await?;
+
+        match response {
+            ClientMessage::ChunkHashes { hashes } => Ok(hashes),
+            ClientMessage::Error { message } => {
+                eprintln!("Failed to fetch chunk hashes: {}", message);
+                Err(server_error(message))
+            }
+            _ => {
+                eprintln!("Unexpected response from server");
+                Err(ProtocolError::UnexpectedResponse.into())
+            }
+        }
+    }
+
+    /// Uploads a new version of a file, sending only the chunks that differ from the stored
+    /// copy. Returns the number of chunks sent.
+    pub async fn upload_file_delta(&self, filename: &str, data: &[u8]) -> io::Result<usize> {
+        self.emit(ClientEvent::Started {
+            operation: Operation::DeltaUpload,
+            name: filename.to_string(),
+        });
+        let result = self.upload_changed_chunks(filename, data).await;
+        self.emit_result(filename, &result, |_| data.len() as u64);
+        result
+    }
+
+    async fn upload_changed_chunks(&self, filename: &str, data: &[u8]) -> io::Result<usize> {
+        let remote_hashes = match self.get_chunk_hashes(filename).await {
+            Ok(hashes) => hashes,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err),
+        };
+
+        let chunk_size = self.config.chunk_size;
+        let chunks: BTreeMap<u64, Bytes> = chunk::split(data, chunk_size)
+            .into_iter()
+            .enumerate()
+            .filter(|(index, chunk)| {
+                remote_hashes.get(*index).map(Vec::as_slice) != Some(&Sha256::digest(chunk)[..])
+            })
+            .map(|(index, chunk)| (index as u64, Bytes::from(chunk)))
+            .collect();
+        let sent = chunks.len();
+
+        let message = ServerMessage::UploadChunks {
+            filename: filename.to_string(),
+            total_len: data.len() as u64,
+            chunk_size: chunk_size as u64,
+            chunks,
+            expect: None,
+        };
+        let response = self.send_server_message(message).await?;
+
+        match response {
+            ClientMessage::Success { data } => {
+                eprintln!(
+                    "Uploaded {} changed chunks. Merkle Root Hash from Server: {:?}",
+                    sent, data
+                );
+                Ok(sent)
+            }
+            ClientMessage::Error { message } => {
+                eprintln!("Failed to upload chunks: {}", message);
+                Err(server_error(message))
+            }
+            _ => {
+                eprintln!("Unexpected response from server");
+                Err(ProtocolError::UnexpectedResponse.into())
+            }
+        }
+    }
+
+    /// Downloads a file and checks it against its chunk root. Chunks that fail verification
+    /// are re-fetched individually, from this server first and then from each replica in
+    /// turn, instead of failing the whole download.
+    pub async fn download_with_repair(
+        &self,
+        filename: &str,
+        chunk_root: &[u8],
+        replicas: &[Client],
+    ) -> io::Result<Vec<u8>> {
+        let data = self.download_file(filename).await?;
+        let result = self.repair(filename, data, chunk_root, replicas).await;
+        match &result {
+            Ok(_) => self.emit(ClientEvent::Verified {
+                name: filename.to_string(),
+            }),
+            Err(err) => self.emit(ClientEvent::Error {
+                name: filename.to_string(),
+                message: err.to_string(),
+            }),
+        }
+        result
+    }
+
+    async fn repair(
+        &self,
+        filename: &str,
+        data: Vec<u8>,
+        chunk_root: &[u8],
+        replicas: &[Client],
+    ) -> io::Result<Vec<u8>> {
+        let chunk_size = self.config.chunk_size;
+        let tree = chunk::chunk_tree_with(&data, chunk_size, self.config.tree_options());
+        if tree.get_root_hash() == chunk_root {
+            return Ok(data);
+        }
+
+        // Find a source whose chunk hash list is consistent with the trusted chunk root
+        let mut trusted_hashes = None;
+        for source in std::iter::once(self).chain(replicas) {
+            if let Ok(hashes) = source.get_chunk_hashes(filename).await {
+                if hashes.is_empty() {
+                    continue;
+                }
+                let tree = merkle_tree::MerkleTree::from_leaf_hashes(hashes.clone());
+                if tree.get_root_hash() == chunk_root {
+                    trusted_hashes = Some(hashes);
+                    break;
+                }
+            }
+        }
+        let trusted_hashes = trusted_hashes.ok_or(MerkleError::NoTrustedChunkHashes)?;
+
+        self.emit(ClientEvent::Started {
+            operation: Operation::Repair,
+            name: filename.to_string(),
+        });
+        let local_chunks = chunk::split(&data, chunk_size);
+        let mut repaired = Vec::new();
+        let mut chunks = Vec::with_capacity(trusted_hashes.len());
+        for (index, trusted_hash) in trusted_hashes.iter().enumerate() {
+            match local_chunks.get(index) {
+                Some(local) if Sha256::digest(local).as_slice() == trusted_hash.as_slice() => {
+                    chunks.push(local.clone());
+                }
+                _ => {
+                    chunks.push(
+                        self.fetch_chunk(filename, index, chunk_root, replicas)
+                            .await?,
+                    );
+                    repaired.push(index);
+                    self.emit(ClientEvent::ChunkRepaired {
+                        name: filename.to_string(),
+                        index,
+                    });
+                }
+            }
+        }
+
+        eprintln!("Repaired chunks {:?} of {}", repaired, filename);
+        Ok(chunks.concat())
+    }
+
+    /// Fetches one verified chunk from the first of this server and `replicas` that serves it.
+    async fn fetch_chunk(
+        &self,
+        filename: &str,
+        index: usize,
+        chunk_root: &[u8],
+        replicas: &[Client],
+    ) -> io::Result<Vec<u8>> {
+        let mut last_err = None;
+        for source in std::iter::once(self).chain(replicas) {
+            let offset = (index * self.config.chunk_size) as u64;
+            match source
+                .fetch_verified_chunks(filename, offset, 1, chunk_root)
+                .await
+            {
+                Ok(mut chunks) if chunks.len() == 1 => return Ok(chunks.remove(0)),
+                Ok(_) => {}
+                Err(err) => last_err = Some(err),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| {
+            MerkleError::ChunkUnrepairable {
+                index: index as u64,
+            }
+            .into()
+        }))
+    }
+
+    /// Hashes every file below `dir`, keyed by relative path. With a cache, files whose size
+    /// and mtime are unchanged reuse their cached hash unless the client is paranoid.
+    pub fn hash_dir(
+        &self,
+        dir: impl AsRef<Path>,
+        cache: Option<&HashCache>,
+    ) -> io::Result<BTreeMap<String, Vec<u8>>> {
+        self.hash_files(self.scan_dir(dir)?, cache)
+    }
+
+    /// Hashes the given files in parallel, keeping their keys; see `hash_dir`.
+    pub fn hash_files(
+        &self,
+        files: BTreeMap<String, PathBuf>,
+        cache: Option<&HashCache>,
+    ) -> io::Result<BTreeMap<String, Vec<u8>>> {
+        let symlinks = self.config.symlinks;
+        let mut entries = Vec::new();
+        for (name, path) in files {
+            let metadata = symlinks.metadata(&path)?;
+            let key = path.to_string_lossy().into_owned();
+            let (size, mtime_ns) = (metadata.len(), mtime_ns(&metadata)?);
+            let cached = match cache {
+                Some(cache) if !self.config.paranoid => cache.get(&key, size, mtime_ns)?,
+                _ => None,
+            };
+            entries.push((name, path, key, size, mtime_ns, cached));
+        }
+
+        // Hash the cache misses on a bounded pool; results come back in scan order
+        let misses: Vec<&PathBuf> = entries
+            .iter()
+            .filter(|entry| entry.5.is_none())
+            .map(|entry| &entry.1)
+            .collect();
+        let mut fresh = parallel::map_bounded(&misses, self.config.hash_concurrency(), |path| {
+            symlinks.leaf_hash(path)
+        })
+        .into_iter();
+
+        let mut hashes = BTreeMap::new();
+        for (name, _, key, size, mtime_ns, cached) in entries {
+            let hash = match cached {
+                Some(hash) => hash,
+                None => {
+                    let hash = fresh.next().expect("one result per cache miss")?;
+                    if let Some(cache) = cache {
+                        cache.insert(&key, size, mtime_ns, &hash)?;
+                    }
+                    hash
+                }
+            };
+            hashes.insert(name, hash);
+        }
+        Ok(hashes)
+    }
+
+    /// The root the server will report once the files below `dir` (and nothing else) are
+    /// uploaded.
+    pub fn dir_root_hash(
+        &self,
+        dir: impl AsRef<Path>,
+        cache: Option<&HashCache>,
+    ) -> io::Result<Vec<u8>> {
+        let hashes = self.hash_dir(dir, cache)?;
+        if hashes.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Directory has no files to hash",
+            ));
+        }
+        Ok(
+            merkle_tree::MerkleTree::from_leaf_hashes(hashes.into_values().collect())
+                .get_root_hash(),
+        )
+    }
+
+    /// Uploads the files below `dir` that changed since the last sync recorded in `state`.
+    /// Files whose size and mtime match their record are not even read, unless the client is
+    /// paranoid.
+    pub async fn sync_dir(
+        &self,
+        dir: impl AsRef<Path>,
+        state: &SyncState,
+    ) -> io::Result<SyncReport> {
+        self.sync(dir, state, false).await
+    }
+
+    /// What `sync_dir` would upload and forget, without contacting the server or changing
+    /// `state`. The report has no root.
+    pub async fn sync_dir_dry_run(
+        &self,
+        dir: impl AsRef<Path>,
+        state: &SyncState,
+    ) -> io::Result<SyncReport> {
+        self.sync(dir, state, true).await
+    }
+
+    async fn sync(
+        &self,
+        dir: impl AsRef<Path>,
+        state: &SyncState,
+        dry_run: bool,
+    ) -> io::Result<SyncReport> {
+        let mut report = SyncReport::default();
+        let mut changed = BTreeMap::new();
+        let mut pending_records = Vec::new();
+        let scanned = self.scan_dir(&dir)?;
+
+        let mut candidates = Vec::new();
+        let symlinks = self.config.symlinks;
+        for (name, path) in &scanned {
+            let metadata = symlinks.metadata(path)?;
+            let size = metadata.len();
+            let mtime_ns = mtime_ns(&metadata)?;
+            let previous = state.get(name)?;
+            if let Some(previous) = previous.as_ref().filter(|_| !self.config.paranoid) {
+                if previous.size == size && previous.mtime_ns == mtime_ns {
+                    report.unchanged.push(name.clone());
+                    continue;
+                }
+            }
+            candidates.push((name.clone(), path.clone(), size, mtime_ns, previous));
+        }
+
+        // Read and hash the candidates on a bounded pool, off the async runtime
+        let paths: Vec<PathBuf> = candidates.iter().map(|entry| entry.1.clone()).collect();
+        let concurrency = self.config.hash_concurrency();
+        let contents = tokio::task::spawn_blocking(move || {
+            parallel::map_bounded(&paths, concurrency, |path| {
+                symlinks.read(path).map(|data| {
+                    let hash = Sha256::digest(&data).to_vec();
+                    (data, hash)
+                })
+            })
+        })
+        .await
+        .map_err(io::Error::other)?;
+
+        for ((name, _, size, mtime_ns, previous), content) in candidates.into_iter().zip(contents) {
+            let (data, content_hash) = content?;
+            match previous {
+                Some(previous) if previous.content_hash == content_hash => {
+                    // Touched but identical; just remember the new mtime
+                    if !dry_run {
+                        state.record(
+                            &name,
+                            &FileRecord {
+                                mtime_ns,
+                                ..previous
+                            },
+                        )?;
+                    }
+                    report.unchanged.push(name);
+                }
+                _ => {
+                    pending_records.push((name.clone(), content_hash, size, mtime_ns));
+                    changed.insert(name, data);
+                }
+            }
+        }
+
+        if dry_run {
+            report.uploaded = changed.into_keys().collect();
+            report.removed = state
+                .paths()?
+                .into_iter()
+                .filter(|name| !scanned.contains_key(name))
+                .collect();
+            eprintln!(
+                "Sync dry run: {} to upload, {} unchanged, {} removed locally",
+                report.uploaded.len(),
+                report.unchanged.len(),
+                report.removed.len()
+            );
+            return Ok(report);
+        }
+
+        if !changed.is_empty() {
+            let root = self.upload(changed, None).await?;
+            for (name, content_hash, size, mtime_ns) in pending_records {
+                let record = FileRecord {
+                    content_hash,
+                    size,
+                    mtime_ns,
+                    root: root.clone(),
+                };
+                state.record(&name, &record)?;
+                report.uploaded.push(name);
+            }
+            report.root = Some(root);
+        }
+
+        // Only once the server has confirmed the upload, so a failed sync leaves every
+        // record in place for the next one
+        for name in state.paths()? {
+            if !scanned.contains_key(&name) {
+                state.remove(&name)?;
+                report.removed.push(name);
+            }
+        }
+
+        eprintln!(
+            "Sync complete: {} uploaded, {} unchanged, {} removed locally",
+            report.uploaded.len(),
+            report.unchanged.len(),
+            report.removed.len()
+        );
+        Ok(report)
+    }
+}
+
+/// Outcome of `Client::sync_dir`, or for `Client::sync_dir_dry_run` what it would be.
+#[derive(Debug, Clone, Default)]
+pub struct SyncReport {
+    pub uploaded: Vec<String>,
+    pub unchanged: Vec<String>,
+    /// Files tracked by the previous sync that no longer exist locally; they stay on the
+    /// server.
+    pub removed: Vec<String>,
+    /// Root reported by the server, if anything was uploaded.
+    pub root: Option<Vec<u8>>,
+}
+
+/// Adds a scanned file under its tree key, failing if another file already normalized to
+/// the same key.
+fn insert_unique(
+    files: &mut BTreeMap<String, PathBuf>,
+    key: String,
+    path: PathBuf,
+) -> io::Result<()> {
+    if let Some(existing) = files.get(&key) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} and {} both become the key {}",
+                existing.display(),
+                path.display(),
+                key
+            ),
+        ));
+    }
+    files.insert(key, path);
+    Ok(())
+}
+
+fn mtime_ns(metadata: &std::fs::Metadata) -> io::Result<i64> {
+    let mtime = metadata
+        .modified()?
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_err(io::Error::other)?;
+    Ok(mtime.as_nanos() as i64)
+}
+
+/// Errors after which the next server in the failover list is tried.
+fn is_unavailable(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::AddrNotAvailable
+            | io::ErrorKind::HostUnreachable
+            | io::ErrorKind::NetworkUnreachable
+            | io::ErrorKind::TimedOut
+    )
+}
+
+/// Whether `message` can change the server's root.
+fn mutates(message: &ServerMessage) -> bool {
+    match message {
+        ServerMessage::Upload { dry_run, .. } => !dry_run,
+        ServerMessage::UploadChunks { .. } | ServerMessage::UploadRaw { .. } => true,
+        ServerMessage::Batch { requests } => requests.iter().any(mutates),
+        _ => false,
+    }
+}
+
+/// The root a successful mutating request left the server at: `Some(root)` if the response
+/// reports it, `Some(None)` if it changed but the response does not say to what, and `None`
+/// if the request was refused and nothing changed.
+fn changed_root(response: &ClientMessage) -> Option<Option<Vec<u8>>> {
+    match response {
+        ClientMessage::Error { .. } | ClientMessage::Conflict { .. } => None,
+        ClientMessage::Success { data } => Some(Some(data.to_vec())),
+        ClientMessage::UploadReceipt { root, .. } => Some(Some(root.clone())),
+        _ => Some(None),
+    }
+}
+
+/// Files as the protocol carries them; the conversion reuses each file's allocation.
+fn into_bytes(files: BTreeMap<String, Vec<u8>>) -> BTreeMap<String, Bytes> {
+    files
+        .into_iter()
+        .map(|(filename, data)| (filename, Bytes::from(data)))
+        .collect()
+}
+
+/// Maps a server error message onto an `io::Error`, keeping "File not found" distinguishable.
+fn server_error(message: String) -> io::Error {
+    ProtocolError::Remote(message).into()
+}
+
+pub fn compute_merkle_root_hash(data: Vec<Vec<u8>>) -> Vec<u8> {
+    let merkle_tree = merkle_tree::MerkleTree::new(data);
+    merkle_tree.get_root_hash()
+}
+
+/// The typed form of a proof the server sent as `(sibling, is_left)` pairs. Servers before
+/// protocol version 12 do not send the tree's size; see `MerkleProof::from_bare_path`.
+pub(crate) fn typed_proof(
+    path: &[(Vec<u8>, bool)],
+    tree_size: Option<u64>,
+) -> io::Result<MerkleProof> {
+    let proof = match tree_size {
+        Some(tree_size) => MerkleProof::from_path(path, tree_size),
+        None => MerkleProof::from_bare_path(path),
+    };
+    proof.ok_or_else(|| ProtocolError::UnexpectedResponse.into())
+}
+
+/// `typed_proof` for every proof of an upload receipt.
+pub(crate) fn typed_proofs(
+    proofs: BTreeMap<String, Vec<(Vec<u8>, bool)>>,
+    tree_size: Option<u64>,
+) -> io::Result<BTreeMap<String, MerkleProof>> {
+    proofs
+        .into_iter()
+        .map(|(filename, path)| Ok((filename, typed_proof(&path, tree_size)?)))
+        .collect()
+}
+
+pub fn verify_merkle_proof(proof: &MerkleProof, root: &[u8], leaf: &[u8]) -> bool {
+    let result = proof.verify(root, leaf);
+    if result {
+        eprintln!("Merkle Proof verified succesfully");
+    }
+    result
+}
+
+/// Root of the chunk tree of a single file, using the default chunk size.
+pub fn compute_chunk_root_hash(data: &[u8]) -> Vec<u8> {
+    Client::default().compute_chunk_root_hash(data)
+}
+
+fn client_for(server_addr: &str) -> Client {
+    Client::new(ClientConfig::new(server_addr))
+}
+
+pub async fn upload_files(
+    client_files: BTreeMap<String, Vec<u8>>,
+    server_addr: &str,
+) -> io::Result<Vec<u8>> {
+    client_for(server_addr).upload_files(client_files).await
+}
+
+pub async fn upload_files_with_proofs(
+    client_files: BTreeMap<String, Vec<u8>>,
+    server_addr: &str,
+) -> io::Result<UploadReceipt> {
+    client_for(server_addr)
+        .upload_files_with_proofs(client_files)
+        .await
+}
+
+pub async fn upload_from_reader(
+    filename: &str,
+    reader: impl AsyncRead + Unpin,
+    len: u64,
+    server_addr: &str,
+) -> io::Result<UploadReceipt> {
+    client_for(server_addr)
+        .upload_from_reader(filename, reader, len)
+        .await
+}
+
+pub async fn upload_files_dry_run(
+    client_files: BTreeMap<String, Vec<u8>>,
+    server_addr: &str,
+) -> io::Result<UploadPlan> {
+    client_for(server_addr)
+        .upload_files_dry_run(client_files)
+        .await
+}
+
+pub async fn download_file(filename: &str, server_addr: &str) -> io::Result<Vec<u8>> {
+    client_for(server_addr).download_file(filename).await
+}
+
+pub async fn get_merkle_proof(filename: &str, server_addr: &str) -> io::Result<MerkleProof> {
+    client_for(server_addr).get_merkle_proof(filename).await
+}
+
+pub async fn get_root_hash(server_addr: &str) -> io::Result<Vec<u8>> {
+    client_for(server_addr).get_root_hash().await
+}
+
+pub async fn audit(server_addr: &str, root: &[u8], sample_size: usize) -> io::Result<AuditReport> {
+    client_for(server_addr).audit(root, sample_size).await
+}
+
+pub async fn read_range(
+    filename: &str,
+    offset: u64,
+    len: u64,
+    chunk_root: &[u8],
+    server_addr: &str,
+) -> io::Result<Vec<u8>> {
+    client_for(server_addr)
+        .read_range(filename, offset, len, chunk_root)
+        .await
+}
+
+pub async fn get_chunk_hashes(filename: &str, server_addr: &str) -> io::Result<Vec<Vec<u8>>> {
+    client_for(server_addr).get_chunk_hashes(filename).await
+}
+
+pub async fn upload_file_delta(
+    filename: &str,
+    data: &[u8],
+    server_addr: &str,
+) -> io::Result<usize> {
+    client_for(server_addr)
+        .upload_file_delta(filename, data)
+        .await
+}
@@ -0,0 +1,395 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use tokio::io;
+
+use crate::{Client, HashCache};
+use merklefile_core::merkle_tree::MerkleTree;
+
+/// First line of every manifest, so a signature over one can never be mistaken for a
+/// signature over anything else.
+const HEADER: &str = "merklefile manifest v1";
+
+/// Namespace SSH signatures over manifests are made in, see `ssh-keygen -Y sign -n`.
+const SSH_NAMESPACE: &str = "merklefile-manifest";
+
+/// A root together with the hash of every file it covers, in a plain-text form that
+/// existing signing tools can sign:
+///
+/// ```text
+/// merklefile manifest v1
+/// root 3b6a27bc...
+/// 9f86d081...  a.txt
+/// 60303ae2...  dir/b.txt
+/// ```
+///
+/// The file lines are in `sha256sum` format, so the manifest also checks out with standard
+/// tools once its header lines are stripped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Manifest {
+    pub root: Vec<u8>,
+    /// SHA-256 of every file, keyed by tree key.
+    pub files: BTreeMap<String, Vec<u8>>,
+}
+
+impl Manifest {
+    /// Builds the manifest of the given file hashes, computing the root they have together.
+    pub fn new(files: BTreeMap<String, Vec<u8>>) -> io::Result<Self> {
+        if files.is_empty() {
+            return Err(invalid("a manifest must list at least one file"));
+        }
+        if let Some(name) = files.keys().find(|name| name.contains(['\n', '\r'])) {
+            return Err(invalid(format!(
+                "{:?} cannot be listed in a manifest: line breaks in file names are not supported",
+                name
+            )));
+        }
+        let root = MerkleTree::from_leaf_hashes(files.values().cloned().collect()).get_root_hash();
+        Ok(Self { root, files })
+    }
+
+    /// Parses a manifest, checking that its root is the root of the files it lists.
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let mut lines = text.lines();
+        if lines.next() != Some(HEADER) {
+            return Err(invalid(format!("not a manifest: expected {:?}", HEADER)));
+        }
+        let root = lines
+            .next()
+            .and_then(|line| line.strip_prefix("root "))
+            .and_then(decode_hash)
+            .ok_or_else(|| invalid("manifest has no valid root line"))?;
+        let mut files = BTreeMap::new();
+        for line in lines {
+            let (hash, name) = line
+                .split_once("  ")
+                .ok_or_else(|| invalid(format!("malformed manifest line {:?}", line)))?;
+            let hash =
+                decode_hash(hash).ok_or_else(|| invalid(format!("malformed hash for {}", name)))?;
+            if files.insert(name.to_string(), hash).is_some() {
+                return Err(invalid(format!("{} is listed twice", name)));
+            }
+        }
+
+        let manifest = Self::new(files)?;
+        if manifest.root != root {
+            return Err(invalid(
+                "manifest root is not the root of the files it lists",
+            ));
+        }
+        Ok(manifest)
+    }
+
+    /// The canonical text form, which is what gets signed.
+    pub fn to_text(&self) -> String {
+        let mut text = format!("{}\nroot {}\n", HEADER, hex::encode(&self.root));
+        for (name, hash) in &self.files {
+            text.push_str(&format!("{}  {}\n", hex::encode(hash), name));
+        }
+        text
+    }
+
+    /// Compares local file hashes with the manifest, describing every file that is missing,
+    /// unexpected or different.
+    pub fn differences(&self, hashes: &BTreeMap<String, Vec<u8>>) -> Vec<String> {
+        let mut differences = Vec::new();
+        for (name, hash) in &self.files {
+            match hashes.get(name) {
+                None => differences.push(format!("missing: {}", name)),
+                Some(local) if local != hash => differences.push(format!("modified: {}", name)),
+                Some(_) => {}
+            }
+        }
+        for name in hashes.keys().filter(|name| !self.files.contains_key(*name)) {
+            differences.push(format!("not in manifest: {}", name));
+        }
+        differences
+    }
+}
+
+/// An existing signing key to sign manifests with. age keys cannot sign, so teams using age
+/// sign with the SSH key they already use as an age identity.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ManifestSigner {
+    /// `gpg --detach-sign`, with the given key or gpg's default key.
+    Gpg { key: Option<String> },
+    /// `ssh-keygen -Y sign` with the private key at this path.
+    Ssh { key: PathBuf },
+}
+
+/// Who a manifest's signature must come from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ManifestVerifier {
+    /// The key with this full fingerprint, or whose subkey has it, found in the given keyring
+    /// or the default one. Any other key in the keyring is refused, however trusted.
+    Gpg {
+        keyring: Option<PathBuf>,
+        fingerprint: String,
+    },
+    /// `identity` as listed in an OpenSSH `allowed_signers` file.
+    Ssh {
+        allowed_signers: PathBuf,
+        identity: String,
+    },
+}
+
+impl ManifestSigner {
+    /// Where the detached signature over the manifest at `path` is written.
+    pub fn signature_path(&self, path: &Path) -> PathBuf {
+        signature_path(path, matches!(self, ManifestSigner::Gpg { .. }))
+    }
+}
+
+impl ManifestVerifier {
+    /// Where the detached signature over the manifest at `path` is looked for.
+    pub fn signature_path(&self, path: &Path) -> PathBuf {
+        signature_path(path, matches!(self, ManifestVerifier::Gpg { .. }))
+    }
+}
+
+fn signature_path(path: &Path, gpg: bool) -> PathBuf {
+    let mut signature = path.as_os_str().to_owned();
+    signature.push(if gpg { ".asc" } else { ".sig" });
+    PathBuf::from(signature)
+}
+
+impl Client {
+    /// The manifest of every file below `dir`, with the root they will have once uploaded.
+    pub fn manifest(
+        &self,
+        dir: impl AsRef<Path>,
+        cache: Option<&HashCache>,
+    ) -> io::Result<Manifest> {
+        Manifest::new(self.hash_dir(dir, cache)?)
+    }
+}
+
+/// Writes `manifest` to `path` and signs it with `signer`, leaving the detached signature
+/// next to it. Returns the signature's path.
+pub fn export_manifest(
+    manifest: &Manifest,
+    path: impl AsRef<Path>,
+    signer: &ManifestSigner,
+) -> io::Result<PathBuf> {
+    let path = path.as_ref();
+    fs::write(path, manifest.to_text())?;
+    let signature = signer.signature_path(path);
+    let mut command = match signer {
+        ManifestSigner::Gpg { key } => {
+            let mut command = Command::new("gpg");
+            command.args(["--batch", "--yes", "--armor", "--detach-sign", "--output"]);
+            command.arg(&signature);
+            if let Some(key) = key {
+                command.args(["--local-user", key]);
+            }
+            command.arg(path);
+            command
+        }
+        ManifestSigner::Ssh { key } => {
+            // ssh-keygen refuses to overwrite an existing signature
+            match fs::remove_file(&signature) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+                _ => {}
+            }
+            let mut command = Command::new("ssh-keygen");
+            command.args(["-q", "-Y", "sign", "-n", SSH_NAMESPACE, "-f"]);
+            command.arg(key).arg(path);
+            command
+        }
+    };
+    run(&mut command, None)?;
+    Ok(signature)
+}
+
+/// Reads the manifest at `path` and verifies its detached signature, returning the manifest
+/// only if the signature checks out.
+pub fn import_manifest(
+    path: impl AsRef<Path>,
+    verifier: &ManifestVerifier,
+) -> io::Result<Manifest> {
+    let path = path.as_ref();
+    let signature = verifier.signature_path(path);
+    // Verify exactly the bytes that are parsed, so the file cannot be swapped in between
+    let contents = fs::read(path)?;
+    let mut command = match verifier {
+        ManifestVerifier::Gpg { keyring, .. } => {
+            let mut command = Command::new("gpg");
+            command.args(["--batch", "--status-fd", "1"]);
+            if let Some(keyring) = keyring {
+                command
+                    .arg("--no-default-keyring")
+                    .arg("--keyring")
+                    .arg(keyring);
+            }
+            command.arg("--verify").arg(&signature).arg("-");
+            command
+        }
+        ManifestVerifier::Ssh {
+            allowed_signers,
+            identity,
+        } => {
+            let mut command = Command::new("ssh-keygen");
+            command.args(["-Y", "verify", "-n", SSH_NAMESPACE, "-f"]);
+            command.arg(allowed_signers).arg("-I").arg(identity);
+            command.arg("-s").arg(&signature);
+            command
+        }
+    };
+    let not_verified = |err: &dyn std::fmt::Display| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("signature on {} does not verify: {}", path.display(), err),
+        )
+    };
+    let status = run(&mut command, Some(&contents)).map_err(|err| not_verified(&err))?;
+    if let ManifestVerifier::Gpg { fingerprint, .. } = verifier {
+        // gpg accepts a signature by any key in the keyring; only the expected one will do
+        if !signed_by(&String::from_utf8_lossy(&status), fingerprint) {
+            return Err(not_verified(&format!(
+                "not signed by the key {}",
+                fingerprint
+            )));
+        }
+    }
+    let text = String::from_utf8(contents).map_err(|_| invalid("manifest is not UTF-8"))?;
+    Manifest::parse(&text)
+}
+
+/// Whether gpg's `--status-fd` output holds a good signature by the key with `fingerprint`,
+/// as either the signing key or the primary key it belongs to.
+fn signed_by(status: &str, fingerprint: &str) -> bool {
+    let expected: String = fingerprint.split_whitespace().collect();
+    status
+        .lines()
+        .filter_map(|line| line.strip_prefix("[GNUPG:] VALIDSIG "))
+        .any(|fields| {
+            // The signing key comes first and the primary key tenth
+            let fields: Vec<&str> = fields.split(' ').collect();
+            [0, 9].iter().any(|&i| {
+                fields
+                    .get(i)
+                    .is_some_and(|key| key.eq_ignore_ascii_case(&expected))
+            })
+        })
+}
+
+/// Runs an external signing tool with `input` on its stdin, turning a failure into an error
+/// carrying its output. Returns what the tool wrote to stdout.
+fn run(command: &mut Command, input: Option<&[u8]>) -> io::Result<Vec<u8>> {
+    let mut child = command
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        // The tool may exit early, e.g. on an unknown key, and close its end of the pipe
+        match stdin.write_all(input) {
+            Err(err) if err.kind() != io::ErrorKind::BrokenPipe => return Err(err),
+            _ => {}
+        }
+    }
+    let output = child.wait_with_output()?;
+    if output.status.success() {
+        Ok(output.stdout)
+    } else {
+        Err(io::Error::other(format!(
+            "{:?} failed: {}",
+            command.get_program(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )))
+    }
+}
+
+fn decode_hash(hash: &str) -> Option<Vec<u8>> {
+    hex::decode(hash).ok().filter(|hash| hash.len() == 32)
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha2::{Digest, Sha256};
+
+    #[test]
+    fn test_gpg_signatures_must_come_from_the_expected_key() {
+        let primary = "A1B2C3D4E5F60718293A4B5C6D7E8F9011223344";
+        let subkey = "0000111122223333444455556666777788889999";
+        let status = format!(
+            "[GNUPG:] NEWSIG\n[GNUPG:] GOODSIG 6D7E8F9011223344 Release\n\
+             [GNUPG:] VALIDSIG {} 2026-01-01 1767225600 0 4 0 22 10 00 {}\n",
+            subkey, primary
+        );
+        assert!(signed_by(&status, primary));
+        assert!(signed_by(&status, &subkey.to_lowercase()));
+        assert!(signed_by(
+            &status,
+            "A1B2 C3D4 E5F6 0718 293A  4B5C 6D7E 8F90 1122 3344"
+        ));
+        assert!(!signed_by(&status, "6D7E8F9011223344"));
+        assert!(!signed_by(&status, &"F".repeat(40)));
+        assert!(!signed_by(
+            "[GNUPG:] GOODSIG 6D7E8F9011223344 Release\n",
+            primary
+        ));
+    }
+
+    #[test]
+    fn test_manifest_round_trip_and_ssh_signature() {
+        let mut files = BTreeMap::new();
+        files.insert("a.txt".to_string(), Sha256::digest(b"alpha").to_vec());
+        files.insert("dir/b.txt".to_string(), Sha256::digest(b"beta").to_vec());
+        let manifest = Manifest::new(files.clone()).unwrap();
+        assert_eq!(Manifest::parse(&manifest.to_text()).unwrap(), manifest);
+        let tampered = manifest
+            .to_text()
+            .replace(&hex::encode(Sha256::digest(b"beta")), &"00".repeat(32));
+        assert!(Manifest::parse(&tampered).is_err());
+
+        files.insert("a.txt".to_string(), Sha256::digest(b"edited").to_vec());
+        files.remove("dir/b.txt");
+        assert_eq!(
+            manifest.differences(&files),
+            vec!["modified: a.txt", "missing: dir/b.txt"]
+        );
+
+        let dir = std::env::temp_dir().join(format!("merklefile-manifest-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let key = dir.join("id_ed25519");
+        let keygen = Command::new("ssh-keygen")
+            .args(["-q", "-t", "ed25519", "-N", "", "-C", "", "-f"])
+            .arg(&key)
+            .status();
+        if !keygen.is_ok_and(|status| status.success()) {
+            // No OpenSSH on this machine; the text format is still covered above
+            fs::remove_dir_all(&dir).unwrap();
+            return;
+        }
+        let public_key = fs::read_to_string(key.with_extension("pub")).unwrap();
+        fs::write(
+            dir.join("allowed_signers"),
+            format!("release@example.com {}", public_key),
+        )
+        .unwrap();
+        let path = dir.join("manifest.txt");
+        export_manifest(&manifest, &path, &ManifestSigner::Ssh { key }).unwrap();
+        let verifier = ManifestVerifier::Ssh {
+            allowed_signers: dir.join("allowed_signers"),
+            identity: "release@example.com".to_string(),
+        };
+        assert_eq!(import_manifest(&path, &verifier).unwrap(), manifest);
+
+        fs::write(&path, tampered).unwrap();
+        let forged = import_manifest(&path, &verifier);
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(forged.is_err());
+    }
+}
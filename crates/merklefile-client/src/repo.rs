@@ -0,0 +1,447 @@
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use tokio::io::{self, AsyncWriteExt};
+
+use merklefile_core::merkle_tree::{MerkleProof, MerkleTree, MultiProof};
+
+/// Version of the layout below, recorded in `config.json`.
+const FORMAT_VERSION: u32 = 1;
+
+/// Length of the random nonce in front of every sealed chunk and index.
+const NONCE_LEN: usize = 24;
+
+/// Key that a repository's chunks and index are encrypted with, with XChaCha20-Poly1305.
+#[derive(Clone, PartialEq, Eq)]
+pub struct RepositoryKey([u8; 32]);
+
+impl RepositoryKey {
+    pub fn generate() -> Self {
+        RepositoryKey(XChaCha20Poly1305::generate_key(&mut OsRng).into())
+    }
+
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        RepositoryKey(bytes)
+    }
+
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.0
+    }
+
+    /// Reads a key written hex-encoded to `path`.
+    pub fn read(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)?;
+        hex::decode(contents.trim())
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(RepositoryKey)
+            .ok_or_else(|| {
+                invalid(format!(
+                    "{} does not hold a hex repository key",
+                    path.display()
+                ))
+            })
+    }
+
+    fn cipher(&self) -> XChaCha20Poly1305 {
+        XChaCha20Poly1305::new(&self.0.into())
+    }
+}
+
+impl fmt::Debug for RepositoryKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("RepositoryKey(..)")
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct RepositoryConfig {
+    version: u32,
+    chunk_size: usize,
+    encrypted: bool,
+}
+
+/// A stored file: its SHA-256, which is its leaf in the repository's tree, and its chunks.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct IndexEntry {
+    hash: String,
+    len: u64,
+    chunks: Vec<String>,
+}
+
+/// A self-contained backup in a local directory, for offline or removable media, that
+/// answers the same questions as a server: files, proofs and the root over all of them.
+/// Files are split into chunks stored once per distinct SHA-256, and the index maps every
+/// filename to its hash and chunks:
+///
+/// ```text
+/// <dir>/config.json                {"version":1,"chunk_size":65536,"encrypted":true}
+/// <dir>/index                      filenames, hashes and chunks, as JSON
+/// <dir>/chunks/9f/9f86d081...
+/// ```
+///
+/// The tree is built over file hashes in filename order, as a server builds it, so a root
+/// recorded from a repository verifies the same files on a server and the other way round.
+/// In an encrypted repository the index and every chunk are sealed with the key, each
+/// behind a random nonce; chunks are still named by the SHA-256 of their plain contents.
+/// Every chunk read back is checked against its name, so damaged media is reported instead
+/// of restored.
+#[derive(Debug)]
+pub struct Repository {
+    dir: PathBuf,
+    chunk_size: usize,
+    key: Option<RepositoryKey>,
+}
+
+impl Repository {
+    /// Creates an empty repository in `dir`, encrypted if a key is given. Fails if `dir`
+    /// already holds one.
+    pub async fn init(
+        dir: impl AsRef<Path>,
+        chunk_size: usize,
+        key: Option<RepositoryKey>,
+    ) -> io::Result<Self> {
+        if chunk_size == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "chunk size must be positive",
+            ));
+        }
+        let repo = Repository {
+            dir: dir.as_ref().to_path_buf(),
+            chunk_size,
+            key,
+        };
+        if fs::try_exists(repo.config_path()).await? {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already holds a repository", repo.dir.display()),
+            ));
+        }
+        fs::create_dir_all(repo.dir.join("chunks")).await?;
+        repo.save_index(&BTreeMap::new()).await?;
+        let config = RepositoryConfig {
+            version: FORMAT_VERSION,
+            chunk_size,
+            encrypted: repo.key.is_some(),
+        };
+        let json = serde_json::to_vec(&config).map_err(io::Error::other)?;
+        write_atomic(&repo.config_path(), &json).await?;
+        Ok(repo)
+    }
+
+    /// Opens the repository in `dir`, with the key it was created with, if any.
+    pub async fn open(dir: impl AsRef<Path>, key: Option<RepositoryKey>) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        let config: RepositoryConfig =
+            serde_json::from_slice(&fs::read(dir.join("config.json")).await?)
+                .map_err(|err| invalid(format!("repository config is invalid: {}", err)))?;
+        if config.version != FORMAT_VERSION {
+            return Err(invalid(format!(
+                "repository format {} is not supported",
+                config.version
+            )));
+        }
+        match (config.encrypted, key.is_some()) {
+            (true, false) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "repository is encrypted; its key is needed",
+                ))
+            }
+            (false, true) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "repository is not encrypted",
+                ))
+            }
+            _ => {}
+        }
+        let repo = Repository {
+            dir,
+            chunk_size: config.chunk_size,
+            key,
+        };
+        // Fails now rather than on first use if the key is wrong
+        repo.load_index().await?;
+        Ok(repo)
+    }
+
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    /// Stores `files`, replacing any stored under the same names.
+    pub async fn upload_files(&self, files: BTreeMap<String, Vec<u8>>) -> io::Result<()> {
+        let mut index = self.load_index().await?;
+        for (filename, data) in files {
+            let mut chunks = Vec::new();
+            for chunk in data.chunks(self.chunk_size) {
+                let id = hex::encode(Sha256::digest(chunk));
+                let path = self.chunk_path(&id);
+                if !fs::try_exists(&path).await? {
+                    fs::create_dir_all(path.parent().expect("chunks are in a directory")).await?;
+                    write_atomic(&path, &self.seal(chunk)?).await?;
+                }
+                chunks.push(id);
+            }
+            let entry = IndexEntry {
+                hash: hex::encode(Sha256::digest(&data)),
+                len: data.len() as u64,
+                chunks,
+            };
+            index.insert(filename, entry);
+        }
+        self.save_index(&index).await?;
+        self.remove_unreferenced_chunks(&index).await
+    }
+
+    /// Reads a stored file back, checking every chunk and the whole file against their
+    /// hashes.
+    pub async fn download_file(&self, filename: &str) -> io::Result<Vec<u8>> {
+        let index = self.load_index().await?;
+        let entry = index.get(filename).ok_or_else(not_found)?;
+        let mut data = Vec::with_capacity(entry.len as usize);
+        for id in &entry.chunks {
+            let chunk = self.open_sealed(&fs::read(self.chunk_path(id)).await?)?;
+            if hex::encode(Sha256::digest(&chunk)) != *id {
+                return Err(invalid(format!("chunk {} of {} is damaged", id, filename)));
+            }
+            data.extend_from_slice(&chunk);
+        }
+        if data.len() as u64 != entry.len || hex::encode(Sha256::digest(&data)) != entry.hash {
+            return Err(invalid(format!(
+                "{} does not match its recorded hash",
+                filename
+            )));
+        }
+        Ok(data)
+    }
+
+    /// Proof for `filename` against the root of the repository.
+    pub async fn get_merkle_proof(&self, filename: &str) -> io::Result<MerkleProof> {
+        let index = self.load_index().await?;
+        let position = index
+            .keys()
+            .position(|name| name == filename)
+            .ok_or_else(not_found)?;
+        tree(&index)?.proof(position).ok_or_else(not_found)
+    }
+
+    /// One proof for all of `filenames` against the root of the repository.
+    pub async fn get_merkle_proofs(&self, filenames: &[&str]) -> io::Result<MultiProof> {
+        let index = self.load_index().await?;
+        let positions = filenames
+            .iter()
+            .map(|filename| index.keys().position(|name| name == filename))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(not_found)?;
+        tree(&index)?
+            .get_proof_for_indices(&positions)
+            .ok_or_else(not_found)
+    }
+
+    pub async fn get_root_hash(&self) -> io::Result<Vec<u8>> {
+        Ok(tree(&self.load_index().await?)?.get_root_hash())
+    }
+
+    /// Names of the stored files.
+    pub async fn filenames(&self) -> io::Result<Vec<String>> {
+        Ok(self.load_index().await?.into_keys().collect())
+    }
+
+    /// Reads back every stored file and returns the names of those that are missing chunks
+    /// or no longer match their hashes.
+    pub async fn check(&self) -> io::Result<Vec<String>> {
+        let mut damaged = Vec::new();
+        for filename in self.filenames().await? {
+            match self.download_file(&filename).await {
+                Ok(_) => {}
+                Err(err)
+                    if matches!(
+                        err.kind(),
+                        io::ErrorKind::InvalidData | io::ErrorKind::NotFound
+                    ) =>
+                {
+                    damaged.push(filename)
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(damaged)
+    }
+
+    async fn load_index(&self) -> io::Result<BTreeMap<String, IndexEntry>> {
+        let sealed = fs::read(self.dir.join("index")).await?;
+        serde_json::from_slice(&self.open_sealed(&sealed)?)
+            .map_err(|err| invalid(format!("repository index is invalid: {}", err)))
+    }
+
+    async fn save_index(&self, index: &BTreeMap<String, IndexEntry>) -> io::Result<()> {
+        let json = serde_json::to_vec(index).map_err(io::Error::other)?;
+        write_atomic(&self.dir.join("index"), &self.seal(&json)?).await
+    }
+
+    /// Deletes the chunks no file refers to any more, such as those of replaced files.
+    async fn remove_unreferenced_chunks(
+        &self,
+        index: &BTreeMap<String, IndexEntry>,
+    ) -> io::Result<()> {
+        let referenced: BTreeSet<&String> =
+            index.values().flat_map(|entry| &entry.chunks).collect();
+        let mut prefixes = fs::read_dir(self.dir.join("chunks")).await?;
+        while let Some(prefix) = prefixes.next_entry().await? {
+            let mut chunks = fs::read_dir(prefix.path()).await?;
+            while let Some(chunk) = chunks.next_entry().await? {
+                let id = chunk.file_name().to_string_lossy().into_owned();
+                if !referenced.contains(&id) {
+                    fs::remove_file(chunk.path()).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn seal(&self, plain: &[u8]) -> io::Result<Vec<u8>> {
+        let Some(key) = &self.key else {
+            return Ok(plain.to_vec());
+        };
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let sealed = key
+            .cipher()
+            .encrypt(&nonce, plain)
+            .map_err(|_| io::Error::other("encryption failed"))?;
+        Ok([nonce.as_slice(), &sealed].concat())
+    }
+
+    fn open_sealed(&self, sealed: &[u8]) -> io::Result<Vec<u8>> {
+        let Some(key) = &self.key else {
+            return Ok(sealed.to_vec());
+        };
+        if sealed.len() < NONCE_LEN {
+            return Err(invalid("sealed data is truncated"));
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        key.cipher()
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .map_err(|_| invalid("data does not decrypt with this key, or was altered"))
+    }
+
+    fn config_path(&self) -> PathBuf {
+        self.dir.join("config.json")
+    }
+
+    fn chunk_path(&self, id: &str) -> PathBuf {
+        self.dir.join("chunks").join(&id[..2]).join(id)
+    }
+}
+
+/// The tree over the stored files, which like a server's holds a single empty leaf when
+/// there are none.
+fn tree(index: &BTreeMap<String, IndexEntry>) -> io::Result<MerkleTree> {
+    let mut leaf_hashes = index
+        .values()
+        .map(|entry| hex::decode(&entry.hash).map_err(|_| invalid("repository index is invalid")))
+        .collect::<io::Result<Vec<_>>>()?;
+    if leaf_hashes.is_empty() {
+        leaf_hashes.push(Sha256::digest([]).to_vec());
+    }
+    Ok(MerkleTree::from_leaf_hashes(leaf_hashes))
+}
+
+/// Writes `data` to `path` through a `.partial` file that is synced and then renamed.
+async fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+    let mut file = fs::File::create(&partial).await?;
+    file.write_all(data).await?;
+    file.sync_data().await?;
+    drop(file);
+    fs::rename(partial, path).await
+}
+
+fn not_found() -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, "File not found")
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::verify_merkle_proof;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("merklefile-repo-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_repository_round_trip_and_proofs() {
+        let dir = temp_dir("round-trip");
+        let key = RepositoryKey::generate();
+        let repo = Repository::init(&dir, 4, Some(key.clone())).await.unwrap();
+        let files = BTreeMap::from([
+            ("a.txt".to_string(), b"abcdabcdab".to_vec()),
+            ("b.txt".to_string(), b"abcd".to_vec()),
+        ]);
+        repo.upload_files(files.clone()).await.unwrap();
+
+        // The same files give the same root as on a server
+        let root = repo.get_root_hash().await.unwrap();
+        assert_eq!(
+            root,
+            crate::compute_merkle_root_hash(files.values().cloned().collect())
+        );
+
+        let repo = Repository::open(&dir, Some(key)).await.unwrap();
+        for (filename, data) in &files {
+            assert_eq!(repo.download_file(filename).await.unwrap(), *data);
+            let proof = repo.get_merkle_proof(filename).await.unwrap();
+            assert!(verify_merkle_proof(&proof, &root, data));
+        }
+        // "abcd" is stored once, "ab" once
+        let chunks: usize = std::fs::read_dir(dir.join("chunks"))
+            .unwrap()
+            .map(|prefix| std::fs::read_dir(prefix.unwrap().path()).unwrap().count())
+            .sum();
+        assert_eq!(chunks, 2);
+
+        assert!(Repository::open(&dir, None).await.is_err());
+        assert!(Repository::open(&dir, Some(RepositoryKey::generate()))
+            .await
+            .is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_damaged_chunks_are_reported() {
+        let dir = temp_dir("damaged");
+        let repo = Repository::init(&dir, 4, None).await.unwrap();
+        let files = BTreeMap::from([
+            ("a.txt".to_string(), b"abcdefgh".to_vec()),
+            ("b.txt".to_string(), b"ijkl".to_vec()),
+        ]);
+        repo.upload_files(files).await.unwrap();
+
+        let id = hex::encode(Sha256::digest(b"efgh"));
+        std::fs::write(repo.chunk_path(&id), b"efgX").unwrap();
+        assert_eq!(
+            repo.download_file("a.txt").await.unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        assert_eq!(repo.check().await.unwrap(), vec!["a.txt".to_string()]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,190 @@
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::BuildHasher;
+use tokio::io;
+
+use crate::{server_error, Client, ClientMessage, ServerMessage};
+use merklefile_proto::ProtocolError;
+
+/// One sampled file and what checking it found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpotCheck {
+    pub filename: String,
+    /// Bytes downloaded, if the server returned the file at all.
+    pub size: Option<u64>,
+    /// Why the file failed, or `None` if its contents and proof match the pinned root.
+    pub problem: Option<String>,
+}
+
+/// Outcome of `Client::audit`: whether a random sample of stored files still matches the
+/// pinned root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditReport {
+    pub root: Vec<u8>,
+    /// Files the server listed, which the sample was drawn from.
+    pub listed: usize,
+    pub checks: Vec<SpotCheck>,
+}
+
+impl AuditReport {
+    pub fn is_clean(&self) -> bool {
+        self.checks.iter().all(|check| check.problem.is_none())
+    }
+}
+
+impl fmt::Display for AuditReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "audited {} of {} files against root {}",
+            self.checks.len(),
+            self.listed,
+            hex::encode(&self.root)
+        )?;
+        for check in &self.checks {
+            match &check.problem {
+                None => writeln!(f, "ok\t{}", check.filename)?,
+                Some(problem) => writeln!(f, "FAIL\t{}\t{}", check.filename, problem)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Client {
+    /// Downloads up to `sample_size` randomly chosen stored files with their proofs and
+    /// checks both against the pinned `root`: a cheap, repeatable check that the server
+    /// still holds what it attests to. Files that fail are reported rather than returned as
+    /// errors; only failing to reach the server or list its files is an error.
+    pub async fn audit(&self, root: &[u8], sample_size: usize) -> io::Result<AuditReport> {
+        let listing = self.list_files().await?;
+        let mut checks = Vec::new();
+        for index in sample(listing.len(), sample_size) {
+            checks.push(self.spot_check(root, &listing, index).await?);
+        }
+        Ok(AuditReport {
+            root: root.to_vec(),
+            listed: listing.len(),
+            checks,
+        })
+    }
+
+    async fn spot_check(
+        &self,
+        root: &[u8],
+        listing: &[String],
+        index: usize,
+    ) -> io::Result<SpotCheck> {
+        let filename = &listing[index];
+        let failed = |size, problem: String| SpotCheck {
+            filename: filename.clone(),
+            size,
+            problem: Some(problem),
+        };
+        let data = match self.fetch_file(filename).await {
+            Ok(data) => data,
+            Err(err) if is_server_fault(&err) => return Ok(failed(None, err.to_string())),
+            Err(err) => return Err(err),
+        };
+        let size = Some(data.len() as u64);
+        let proof = match self.get_merkle_proof(filename).await {
+            Ok(proof) => proof,
+            Err(err) if is_server_fault(&err) => return Ok(failed(size, err.to_string())),
+            Err(err) => return Err(err),
+        };
+
+        if !proof.verify(root, &data) {
+            return Ok(failed(
+                size,
+                "contents or proof do not match the root".to_string(),
+            ));
+        }
+        // Leaves are in filename order, so a listing that hides files shifts the positions
+        // the proofs attest to
+        let position = proof.leaf_index as usize;
+        if position != index || proof.siblings.len() != depth(listing.len()) {
+            return Ok(failed(
+                size,
+                format!(
+                    "proof places it at leaf {} of a tree of depth {}, but it is listed {} of {}",
+                    position,
+                    proof.siblings.len(),
+                    index,
+                    listing.len()
+                ),
+            ));
+        }
+        Ok(SpotCheck {
+            filename: filename.clone(),
+            size,
+            problem: None,
+        })
+    }
+
+    /// Names of every file the server stores, in tree order.
+    pub async fn list_files(&self) -> io::Result<Vec<String>> {
+        match self.send_server_message(ServerMessage::ListFiles).await? {
+            ClientMessage::FileList { filenames } => Ok(filenames),
+            ClientMessage::Error { message } => Err(server_error(message)),
+            _ => Err(ProtocolError::UnexpectedResponse.into()),
+        }
+    }
+}
+
+/// Whether the server answered, with an error or a malformed reply, rather than the request
+/// failing to get through.
+fn is_server_fault(err: &io::Error) -> bool {
+    matches!(
+        err.get_ref()
+            .and_then(|err| err.downcast_ref::<ProtocolError>()),
+        Some(
+            ProtocolError::Remote(_)
+                | ProtocolError::UnexpectedResponse
+                | ProtocolError::Malformed(_)
+                | ProtocolError::ProofTooDeep(_)
+                | ProtocolError::MalformedHash
+        )
+    )
+}
+
+/// Proof length for a tree of `leaves` leaves.
+fn depth(leaves: usize) -> usize {
+    leaves.next_power_of_two().trailing_zeros() as usize
+}
+
+/// Up to `count` distinct indices below `len`, in random order.
+fn sample(len: usize, count: usize) -> Vec<usize> {
+    let mut indices: Vec<usize> = (0..len).collect();
+    let state = RandomState::new();
+    for i in 0..count.min(len) {
+        let j = i + (state.hash_one(i) as usize) % (len - i);
+        indices.swap(i, j);
+    }
+    indices.truncate(count.min(len));
+    indices
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use merklefile_core::merkle_tree::{MerkleProof, MerkleTree};
+
+    #[test]
+    fn test_proofs_reveal_leaf_positions() {
+        let tree = MerkleTree::new((0..5u8).map(|i| vec![i]).collect());
+        for index in 0..5 {
+            let path = tree.get_proof_for(index);
+            let proof = MerkleProof::from_bare_path(&path).unwrap();
+            assert_eq!(proof.leaf_index as usize, index);
+            assert_eq!(proof.siblings.len(), depth(5));
+        }
+        assert_eq!(depth(1), 0);
+
+        let mut picked = sample(10, 4);
+        assert_eq!(picked.len(), 4);
+        picked.sort();
+        picked.dedup();
+        assert_eq!(picked.len(), 4);
+        assert_eq!(sample(3, 10).len(), 3);
+    }
+}
@@ -0,0 +1,238 @@
+use rusqlite::{params, Connection, OptionalExtension};
+use std::io;
+use std::path::Path;
+use thiserror::Error;
+
+use merklefile_core::append::AppendState;
+
+/// Failures of the local SQLite databases (sync state, hash cache, monitor baselines).
+#[derive(Debug, Error)]
+pub enum StorageError {
+    #[error("local state database error")]
+    Database(#[from] rusqlite::Error),
+    #[error("stored append state is invalid")]
+    InvalidAppendState(#[from] serde_json::Error),
+}
+
+impl From<StorageError> for io::Error {
+    fn from(err: StorageError) -> Self {
+        io::Error::other(err)
+    }
+}
+
+/// What the last sync knew about a local file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileRecord {
+    pub content_hash: Vec<u8>,
+    pub size: u64,
+    /// Modification time in nanoseconds since the Unix epoch.
+    pub mtime_ns: i64,
+    /// Root hash the server reported after this version was uploaded.
+    pub root: Vec<u8>,
+}
+
+/// Local SQLite database of per-file sync state, so a sync only re-hashes files whose size
+/// or mtime changed since the previous run.
+#[derive(Debug)]
+pub struct SyncState {
+    conn: Connection,
+}
+
+impl SyncState {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StorageError> {
+        Self::init(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> Result<Self, StorageError> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> Result<Self, StorageError> {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS files (
+                path TEXT PRIMARY KEY,
+                content_hash BLOB NOT NULL,
+                size INTEGER NOT NULL,
+                mtime_ns INTEGER NOT NULL,
+                root BLOB NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS append_states (
+                path TEXT PRIMARY KEY,
+                state TEXT NOT NULL
+            )",
+        )?;
+        Ok(Self { conn })
+    }
+
+    pub fn get(&self, path: &str) -> Result<Option<FileRecord>, StorageError> {
+        self.conn
+            .query_row(
+                "SELECT content_hash, size, mtime_ns, root FROM files WHERE path = ?1",
+                params![path],
+                |row| {
+                    Ok(FileRecord {
+                        content_hash: row.get(0)?,
+                        size: row.get::<_, i64>(1)? as u64,
+                        mtime_ns: row.get(2)?,
+                        root: row.get(3)?,
+                    })
+                },
+            )
+            .optional()
+            .map_err(StorageError::from)
+    }
+
+    pub fn record(&self, path: &str, record: &FileRecord) -> Result<(), StorageError> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO files (path, content_hash, size, mtime_ns, root)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                path,
+                record.content_hash,
+                record.size as i64,
+                record.mtime_ns,
+                record.root
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn remove(&self, path: &str) -> Result<(), StorageError> {
+        self.conn
+            .execute("DELETE FROM files WHERE path = ?1", params![path])?;
+        Ok(())
+    }
+
+    /// How much of the growing file at `path` was verified last, for `reattest_appended`.
+    pub fn append_state(&self, path: &str) -> Result<Option<AppendState>, StorageError> {
+        let state: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT state FROM append_states WHERE path = ?1",
+                params![path],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(state
+            .map(|state| serde_json::from_str(&state))
+            .transpose()?)
+    }
+
+    pub fn record_append_state(&self, path: &str, state: &AppendState) -> Result<(), StorageError> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO append_states (path, state) VALUES (?1, ?2)",
+            params![path, serde_json::to_string(state)?],
+        )?;
+        Ok(())
+    }
+
+    /// Paths of every tracked file, in sorted order.
+    pub fn paths(&self) -> Result<Vec<String>, StorageError> {
+        let mut statement = self.conn.prepare("SELECT path FROM files ORDER BY path")?;
+        let paths = statement
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+        Ok(paths)
+    }
+}
+
+/// Opt-in cache of content hashes keyed by (path, size, mtime), so repeated scans of a
+/// mostly-unchanged tree only re-hash files that were modified.
+#[derive(Debug)]
+pub struct HashCache {
+    conn: Connection,
+}
+
+impl HashCache {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StorageError> {
+        Self::init(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> Result<Self, StorageError> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> Result<Self, StorageError> {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS hash_cache (
+                path TEXT PRIMARY KEY,
+                size INTEGER NOT NULL,
+                mtime_ns INTEGER NOT NULL,
+                hash BLOB NOT NULL
+            )",
+        )?;
+        Ok(Self { conn })
+    }
+
+    /// The cached hash, if the file still has the size and mtime it had when it was hashed.
+    pub fn get(
+        &self,
+        path: &str,
+        size: u64,
+        mtime_ns: i64,
+    ) -> Result<Option<Vec<u8>>, StorageError> {
+        self.conn
+            .query_row(
+                "SELECT hash FROM hash_cache WHERE path = ?1 AND size = ?2 AND mtime_ns = ?3",
+                params![path, size as i64, mtime_ns],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(StorageError::from)
+    }
+
+    pub fn insert(
+        &self,
+        path: &str,
+        size: u64,
+        mtime_ns: i64,
+        hash: &[u8],
+    ) -> Result<(), StorageError> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO hash_cache (path, size, mtime_ns, hash)
+                 VALUES (?1, ?2, ?3, ?4)",
+            params![path, size as i64, mtime_ns, hash],
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_round_trip() {
+        let state = SyncState::open_in_memory().unwrap();
+        let record = FileRecord {
+            content_hash: vec![1; 32],
+            size: 42,
+            mtime_ns: 1_700_000_000_000_000_000,
+            root: vec![2; 32],
+        };
+        state.record("dir/a.txt", &record).unwrap();
+        assert_eq!(state.get("dir/a.txt").unwrap(), Some(record));
+        assert_eq!(state.paths().unwrap(), vec!["dir/a.txt".to_string()]);
+
+        state.remove("dir/a.txt").unwrap();
+        assert_eq!(state.get("dir/a.txt").unwrap(), None);
+    }
+
+    #[test]
+    fn test_append_state_round_trip() {
+        let state = SyncState::open_in_memory().unwrap();
+        assert_eq!(state.append_state("app.log").unwrap(), None);
+        let (verified, _) = AppendState::new(4).append(b"first line\n");
+        state.record_append_state("app.log", &verified).unwrap();
+        assert_eq!(state.append_state("app.log").unwrap(), Some(verified));
+    }
+
+    #[test]
+    fn test_hash_cache_misses_on_changed_metadata() {
+        let cache = HashCache::open_in_memory().unwrap();
+        cache.insert("/data/a.txt", 10, 5, &[9; 32]).unwrap();
+        assert_eq!(cache.get("/data/a.txt", 10, 5).unwrap(), Some(vec![9; 32]));
+        assert_eq!(cache.get("/data/a.txt", 11, 5).unwrap(), None);
+        assert_eq!(cache.get("/data/a.txt", 10, 6).unwrap(), None);
+    }
+}
@@ -0,0 +1,117 @@
+use sha2::{Digest, Sha256};
+use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::{is_unavailable, server_error, throttle, typed_proofs, Client, UploadReceipt};
+use merklefile_core::error::MerkleError;
+use merklefile_core::profile::{self, Stage};
+use merklefile_proto::{self as protocol, ClientMessage, ProtocolError, ServerMessage};
+
+/// Bytes read from the source and sent at a time.
+const STREAM_CHUNK: usize = 64 * 1024;
+
+impl Client {
+    /// Uploads `len` bytes read from `reader`, such as a socket or a pipe, as `filename`
+    /// without ever holding them all in memory. They are sent as they are read, after the
+    /// request frame, and hashed on the way; the server's receipt must prove that leaf hash
+    /// is in its new root. Fails if `reader` ends before `len` bytes. The request timeout
+    /// does not apply, since the upload goes as fast as `reader` does. With failover servers,
+    /// the upload goes to the first reachable one, as `reader` cannot be read twice. Servers
+    /// older than protocol version 6 refuse it.
+    pub async fn upload_from_reader(
+        &self,
+        filename: &str,
+        mut reader: impl AsyncRead + Unpin,
+        len: u64,
+    ) -> io::Result<UploadReceipt> {
+        let (addr, mut stream) = self.connect_to_any().await?;
+        let header = protocol::encode(&ServerMessage::UploadRaw {
+            filename: filename.to_string(),
+            len,
+            on_collision: self.config.on_collision,
+            expect: None,
+        })?;
+        protocol::write_frame(&mut stream, &header).await?;
+
+        let mut span = profile::span(Stage::SocketWrite);
+        let mut hasher = Sha256::new();
+        let mut chunk = vec![0; STREAM_CHUNK];
+        let mut remaining = len;
+        while remaining > 0 {
+            let want = STREAM_CHUNK.min(remaining as usize);
+            let read = reader.read(&mut chunk[..want]).await?;
+            if read == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!(
+                        "{} ended after {} of {} bytes",
+                        filename,
+                        len - remaining,
+                        len
+                    ),
+                ));
+            }
+            hasher.update(&chunk[..read]);
+            throttle::write_all(&mut stream, &chunk[..read], self.config.upload_rate_limit).await?;
+            span.add_bytes(read as u64);
+            remaining -= read as u64;
+        }
+        stream.flush().await?;
+        drop(span);
+        let leaf_hash = hasher.finalize();
+
+        match self.read_response(&mut stream, &header).await? {
+            ClientMessage::UploadReceipt {
+                root,
+                proofs,
+                stored_as,
+                tree_size,
+            } => {
+                let proofs = typed_proofs(proofs, tree_size)?;
+                let stored = stored_as.get(filename).map_or(filename, String::as_str);
+                let valid = proofs
+                    .get(stored)
+                    .is_some_and(|proof| proof.verify_leaf_hash(&root, &leaf_hash));
+                if !valid {
+                    return Err(MerkleError::ProofInvalid {
+                        filename: filename.to_string(),
+                    }
+                    .into());
+                }
+                self.root_changed(addr, Some(root.clone())).await;
+                Ok(UploadReceipt {
+                    root,
+                    proofs,
+                    stored_as,
+                })
+            }
+            ClientMessage::Error { message } => Err(server_error(message)),
+            _ => Err(ProtocolError::UnexpectedResponse.into()),
+        }
+    }
+
+    /// Connects to the first reachable server, once the servers agree on their root if
+    /// failover addresses are configured and the server presents it, as for any request.
+    async fn connect_to_any(&self) -> io::Result<(&str, TcpStream)> {
+        let agreed = match self.config.failover_addrs.is_empty() {
+            true => None,
+            false => Some(self.agreed().await?),
+        };
+        let mut last_err = None;
+        for addr in self.config.server_addrs() {
+            let checked = match &agreed {
+                Some(agreed) if !self.is_checked(addr) => self.check_root(addr, agreed).await,
+                _ => Ok(()),
+            };
+            let connected = match checked {
+                Ok(()) => self.connect(addr).await,
+                Err(err) => Err(err),
+            };
+            match connected {
+                Err(err) if is_unavailable(&err) => last_err = Some(err),
+                result => return result.map(|stream| (addr, stream)),
+            }
+        }
+        Err(last_err.expect("at least one server address is configured"))
+    }
+}
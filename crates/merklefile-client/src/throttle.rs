@@ -0,0 +1,87 @@
+use std::time::Duration;
+use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::time::Instant;
+
+/// Granularity of throttled transfers; small enough to keep the rate smooth.
+const BLOCK_SIZE: usize = 16 * 1024;
+
+/// Paces a transfer so that, on average, no more than `bytes_per_sec` bytes go through.
+struct RateLimiter {
+    bytes_per_sec: u64,
+    start: Instant,
+    transferred: u64,
+}
+
+impl RateLimiter {
+    fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bytes_per_sec: bytes_per_sec.max(1),
+            start: Instant::now(),
+            transferred: 0,
+        }
+    }
+
+    async fn consume(&mut self, bytes: usize) {
+        self.transferred += bytes as u64;
+        let due = Duration::from_secs_f64(self.transferred as f64 / self.bytes_per_sec as f64);
+        tokio::time::sleep_until(self.start + due).await;
+    }
+}
+
+pub(super) async fn write_all<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    data: &[u8],
+    bytes_per_sec: Option<u64>,
+) -> io::Result<()> {
+    let Some(bytes_per_sec) = bytes_per_sec else {
+        return writer.write_all(data).await;
+    };
+    let mut limiter = RateLimiter::new(bytes_per_sec);
+    for block in data.chunks(BLOCK_SIZE) {
+        writer.write_all(block).await?;
+        limiter.consume(block.len()).await;
+    }
+    Ok(())
+}
+
+pub(super) async fn read_to_end<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    buffer: &mut Vec<u8>,
+    bytes_per_sec: Option<u64>,
+) -> io::Result<()> {
+    let Some(bytes_per_sec) = bytes_per_sec else {
+        reader.read_to_end(buffer).await?;
+        return Ok(());
+    };
+    let mut limiter = RateLimiter::new(bytes_per_sec);
+    let mut block = vec![0u8; BLOCK_SIZE];
+    loop {
+        let read = reader.read(&mut block).await?;
+        if read == 0 {
+            return Ok(());
+        }
+        buffer.extend_from_slice(&block[..read]);
+        limiter.consume(read).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_throttled_write_is_paced() {
+        let data = vec![7u8; 64 * 1024];
+        let (mut writer, mut reader) = io::duplex(data.len());
+        let started = Instant::now();
+        write_all(&mut writer, &data, Some(128 * 1024))
+            .await
+            .unwrap();
+        drop(writer);
+        assert!(started.elapsed() >= Duration::from_millis(450));
+
+        let mut received = Vec::new();
+        read_to_end(&mut reader, &mut received, None).await.unwrap();
+        assert_eq!(received, data);
+    }
+}
@@ -0,0 +1,18 @@
+use std::path::Path;
+use tokio::io;
+
+use merklefile_core::transcript::SignedTranscript;
+
+/// Saves a signed transcript as JSON, the form it is handed to the other side or an
+/// auditor in.
+pub fn save_transcript(path: impl AsRef<Path>, transcript: &SignedTranscript) -> io::Result<()> {
+    let json = serde_json::to_vec_pretty(transcript).expect("transcript serializes");
+    std::fs::write(path, json)
+}
+
+/// Loads a transcript saved by `save_transcript`. Its signature is not checked; call
+/// `SignedTranscript::verify` with the key it should be signed with.
+pub fn load_transcript(path: impl AsRef<Path>) -> io::Result<SignedTranscript> {
+    serde_json::from_slice(&std::fs::read(path)?)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
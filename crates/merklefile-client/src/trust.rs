@@ -0,0 +1,185 @@
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::io;
+
+/// Prefix marking the TXT record (or response body) that carries a published root.
+#[cfg(feature = "published-roots")]
+const ROOT_PREFIX: &str = "merklefile-root=";
+
+/// Where a trusted root is published out of band, so downloads are not verified against a
+/// root the file server itself vouches for. A compromised server then has to compromise
+/// the publisher too.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RootSource {
+    /// An HTTPS endpoint whose body is the hex root, e.g. `https://example.com/root.txt`.
+    Https(String),
+    /// A DNS TXT record `merklefile-root=<hex>` on the given name, written `dns:<name>`. The
+    /// zone must be signed: the record is only accepted if DNSSEC validates it up to the DNS
+    /// root, so a forged answer from the network or the resolver is refused.
+    DnsTxt(String),
+}
+
+impl FromStr for RootSource {
+    type Err = String;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        if source.starts_with("https://") {
+            Ok(RootSource::Https(source.to_string()))
+        } else if let Some(name) = source.strip_prefix("dns:").filter(|name| !name.is_empty()) {
+            Ok(RootSource::DnsTxt(name.to_string()))
+        } else {
+            Err(format!(
+                "invalid root source '{}', expected https://... or dns:<name>",
+                source
+            ))
+        }
+    }
+}
+
+impl RootSource {
+    /// Fetches and decodes the published root. Needs the `published-roots` feature.
+    #[cfg(feature = "published-roots")]
+    pub async fn fetch(&self, timeout: Option<Duration>) -> io::Result<Vec<u8>> {
+        match self {
+            RootSource::Https(url) => {
+                let (url, timeout) = (url.clone(), timeout.unwrap_or(Duration::from_secs(30)));
+                let body = tokio::task::spawn_blocking(move || {
+                    https_agent(timeout)
+                        .get(&url)
+                        .call()
+                        .map_err(|err| io::Error::other(format!("{}: {}", url, err)))?
+                        .into_string()
+                })
+                .await
+                .map_err(io::Error::other)??;
+                parse_root(&body)
+            }
+            RootSource::DnsTxt(name) => {
+                let (config, mut options) =
+                    hickory_resolver::system_conf::read_system_conf().map_err(io::Error::other)?;
+                // Answers from unsigned zones, or whose signatures do not validate, fail
+                options.validate = true;
+                let resolver = hickory_resolver::TokioAsyncResolver::tokio(config, options);
+                let lookup = resolver
+                    .txt_lookup(name.as_str())
+                    .await
+                    .map_err(|err| io::Error::other(format!("{}: {}", name, err)))?;
+                let records: Vec<String> = lookup
+                    .iter()
+                    .map(|txt| {
+                        // A record longer than 255 bytes arrives split into strings
+                        txt.txt_data()
+                            .iter()
+                            .map(|part| String::from_utf8_lossy(part))
+                            .collect()
+                    })
+                    .collect();
+                root_from_txt(name, &records)
+            }
+        }
+    }
+
+    /// Fails: this build cannot fetch published roots.
+    #[cfg(not(feature = "published-roots"))]
+    pub async fn fetch(&self, _timeout: Option<Duration>) -> io::Result<Vec<u8>> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "fetching published roots needs the published-roots feature",
+        ))
+    }
+}
+
+/// The agent published roots are fetched with. It refuses plain HTTP, including a redirect
+/// to it, which would otherwise let anyone on the path forge the root.
+#[cfg(feature = "published-roots")]
+fn https_agent(timeout: Duration) -> ureq::Agent {
+    ureq::AgentBuilder::new()
+        .https_only(true)
+        .timeout(timeout)
+        .build()
+}
+
+/// Picks the root out of a name's TXT records, which may hold unrelated entries (SPF and
+/// the like) but must not disagree on the root.
+#[cfg(feature = "published-roots")]
+fn root_from_txt(name: &str, records: &[String]) -> io::Result<Vec<u8>> {
+    let mut roots = records
+        .iter()
+        .filter(|record| record.starts_with(ROOT_PREFIX))
+        .map(|record| parse_root(record))
+        .collect::<io::Result<Vec<_>>>()?;
+    roots.sort();
+    roots.dedup();
+    match roots.len() {
+        1 => Ok(roots.remove(0)),
+        0 => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no {} TXT record on {}", ROOT_PREFIX, name),
+        )),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("conflicting {} TXT records on {}", ROOT_PREFIX, name),
+        )),
+    }
+}
+
+/// Decodes a published root: hex, optionally after `merklefile-root=`.
+#[cfg(feature = "published-roots")]
+fn parse_root(published: &str) -> io::Result<Vec<u8>> {
+    let published = published.trim();
+    let published = published.strip_prefix(ROOT_PREFIX).unwrap_or(published);
+    match hex::decode(published.trim()) {
+        Ok(root) if root.len() == 32 => Ok(root),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "published root is not a hex SHA-256 hash",
+        )),
+    }
+}
+
+#[cfg(all(test, feature = "published-roots"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_published_root_parsing() {
+        let root = "ab".repeat(32);
+        assert_eq!(
+            "dns:root.example.com".parse(),
+            Ok(RootSource::DnsTxt("root.example.com".to_string()))
+        );
+        assert!("http://example.com/root".parse::<RootSource>().is_err());
+        assert_eq!(parse_root(&format!("{}\n", root)).unwrap(), vec![0xab; 32]);
+        assert!(parse_root("abcd").is_err());
+
+        let records = vec![
+            "v=spf1 -all".to_string(),
+            format!("{}{}", ROOT_PREFIX, root),
+        ];
+        assert_eq!(
+            root_from_txt("example.com", &records).unwrap(),
+            vec![0xab; 32]
+        );
+        assert_eq!(
+            root_from_txt("example.com", &records[..1])
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::NotFound
+        );
+        let conflicting = vec![
+            format!("{}{}", ROOT_PREFIX, root),
+            format!("{}{}", ROOT_PREFIX, "cd".repeat(32)),
+        ];
+        assert!(root_from_txt("example.com", &conflicting).is_err());
+    }
+
+    #[test]
+    fn test_published_roots_are_only_fetched_over_https() {
+        // Checked on every hop, so a redirect from HTTPS to plain HTTP fails the same way
+        let err = https_agent(Duration::from_secs(1))
+            .get("http://127.0.0.1:1/root.txt")
+            .call()
+            .unwrap_err();
+        assert_eq!(err.kind(), ureq::ErrorKind::InsecureRequestHttpsOnly);
+    }
+}
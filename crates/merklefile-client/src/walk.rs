@@ -0,0 +1,322 @@
+use merklefile_core::merkle_tree::LeafHasher;
+use serde::Deserialize;
+use std::fs::{File, Metadata};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::io;
+
+/// Further attempts to read a file another process holds open without sharing it.
+const SHARING_RETRIES: u32 = 4;
+
+/// What directory scans and uploads do with a symbolic link.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SymlinkPolicy {
+    /// Treat the link as what it points to: linked directories are descended into (a link
+    /// back into its own ancestors is not) and linked files are hashed by content. A
+    /// dangling link is an error.
+    #[default]
+    Follow,
+    /// Hash the link's target path as if it were the file's content, without touching the
+    /// target, so retargeting the link changes its hash.
+    Target,
+    /// Leave links out.
+    Skip,
+}
+
+impl SymlinkPolicy {
+    /// Metadata of a scanned file: of the link itself under `Target`, otherwise of the file
+    /// it points to.
+    pub fn metadata(self, path: &Path) -> io::Result<Metadata> {
+        match self {
+            SymlinkPolicy::Target => std::fs::symlink_metadata(path),
+            _ => std::fs::metadata(path),
+        }
+    }
+
+    /// Contents of a scanned file; for a link under `Target`, the bytes of its target path.
+    pub fn read(self, path: &Path) -> io::Result<Vec<u8>> {
+        match self.link_target(path)? {
+            Some(target) => Ok(target),
+            None => with_sharing_retries(|| std::fs::read(path)),
+        }
+    }
+
+    /// Leaf hash of what `read` returns, with the file read a buffer at a time rather than
+    /// whole, so hashing a large file takes no more memory than a small one.
+    pub fn leaf_hash(self, path: &Path) -> io::Result<Vec<u8>> {
+        let mut hasher = LeafHasher::new();
+        match self.link_target(path)? {
+            Some(target) => hasher.update(&target),
+            None => {
+                let file = with_sharing_retries(|| File::open(path))?;
+                hasher.read_from(file)?;
+            }
+        }
+        Ok(hasher.finalize())
+    }
+
+    /// The bytes of the target path of a link under `Target`, which stand in for its content.
+    fn link_target(self, path: &Path) -> io::Result<Option<Vec<u8>>> {
+        if self == SymlinkPolicy::Target && std::fs::symlink_metadata(path)?.is_symlink() {
+            return Ok(Some(
+                std::fs::read_link(path)?
+                    .to_string_lossy()
+                    .into_owned()
+                    .into_bytes(),
+            ));
+        }
+        Ok(None)
+    }
+}
+
+/// Opens or reads a file, retrying for a moment while another process holds it open without
+/// sharing it, as editors and virus scanners on Windows briefly do.
+fn with_sharing_retries<T>(mut open: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+    let mut delay = Duration::from_millis(50);
+    for _ in 0..SHARING_RETRIES {
+        match open() {
+            Err(err) if is_sharing_violation(&err) => {
+                std::thread::sleep(delay);
+                delay *= 2;
+            }
+            result => return result,
+        }
+    }
+    open()
+}
+
+#[cfg(windows)]
+fn is_sharing_violation(err: &io::Error) -> bool {
+    use windows_sys::Win32::Foundation::{ERROR_LOCK_VIOLATION, ERROR_SHARING_VIOLATION};
+    matches!(
+        err.raw_os_error().map(|code| code as u32),
+        Some(ERROR_SHARING_VIOLATION | ERROR_LOCK_VIOLATION)
+    )
+}
+
+#[cfg(not(windows))]
+fn is_sharing_violation(_err: &io::Error) -> bool {
+    false
+}
+
+/// Names of a file's alternate data streams, which scans include as `file:stream` when
+/// enabled. Only NTFS has them; elsewhere the list is always empty.
+#[cfg(windows)]
+pub fn alternate_streams(path: &Path) -> io::Result<Vec<String>> {
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::Foundation::{ERROR_HANDLE_EOF, INVALID_HANDLE_VALUE};
+    use windows_sys::Win32::Storage::FileSystem::{
+        FindClose, FindFirstStreamW, FindNextStreamW, FindStreamInfoStandard,
+        WIN32_FIND_STREAM_DATA,
+    };
+
+    let is_end = |err: &io::Error| err.raw_os_error() == Some(ERROR_HANDLE_EOF as i32);
+    let wide: Vec<u16> = path
+        .as_os_str()
+        .encode_wide()
+        .chain(std::iter::once(0))
+        .collect();
+    let mut data = WIN32_FIND_STREAM_DATA::default();
+    // SAFETY: `wide` is NUL-terminated and `data` is the structure FindStreamInfoStandard
+    // fills in
+    let handle = unsafe {
+        FindFirstStreamW(
+            wide.as_ptr(),
+            FindStreamInfoStandard,
+            &mut data as *mut WIN32_FIND_STREAM_DATA as *mut _,
+            0,
+        )
+    };
+    if handle == INVALID_HANDLE_VALUE {
+        let err = io::Error::last_os_error();
+        return if is_end(&err) {
+            Ok(Vec::new())
+        } else {
+            Err(err)
+        };
+    }
+
+    let mut streams = Vec::new();
+    let result = loop {
+        let len = data
+            .cStreamName
+            .iter()
+            .position(|&unit| unit == 0)
+            .unwrap_or(data.cStreamName.len());
+        let name = String::from_utf16_lossy(&data.cStreamName[..len]);
+        // Names look like `:name:$DATA`; the unnamed `::$DATA` stream is the content itself
+        if let Some(stream) = name
+            .strip_prefix(':')
+            .and_then(|name| name.strip_suffix(":$DATA"))
+            .filter(|stream| !stream.is_empty())
+        {
+            streams.push(stream.to_string());
+        }
+        // SAFETY: `handle` came from FindFirstStreamW and has not been closed
+        if unsafe { FindNextStreamW(handle, &mut data as *mut WIN32_FIND_STREAM_DATA as *mut _) }
+            == 0
+        {
+            let err = io::Error::last_os_error();
+            break if is_end(&err) { Ok(streams) } else { Err(err) };
+        }
+    };
+    // SAFETY: as above; the handle is not used again
+    unsafe { FindClose(handle) };
+    result
+}
+
+#[cfg(not(windows))]
+pub fn alternate_streams(_path: &Path) -> io::Result<Vec<String>> {
+    Ok(Vec::new())
+}
+
+impl FromStr for SymlinkPolicy {
+    type Err = String;
+
+    fn from_str(policy: &str) -> Result<Self, Self::Err> {
+        match policy {
+            "follow" => Ok(SymlinkPolicy::Follow),
+            "target" => Ok(SymlinkPolicy::Target),
+            "skip" => Ok(SymlinkPolicy::Skip),
+            _ => Err(format!(
+                "unknown symlink policy '{}', expected follow, target or skip",
+                policy
+            )),
+        }
+    }
+}
+
+/// What directory scans and uploads do with FIFOs, sockets and device nodes, whose
+/// "contents" are not file data: reading a FIFO blocks until a writer appears, and a
+/// device like `/dev/zero` never ends.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SpecialFilePolicy {
+    /// Leave them out.
+    #[default]
+    Skip,
+    /// Fail the scan, for trees that should never contain one.
+    Error,
+}
+
+impl FromStr for SpecialFilePolicy {
+    type Err = String;
+
+    fn from_str(policy: &str) -> Result<Self, Self::Err> {
+        match policy {
+            "skip" => Ok(SpecialFilePolicy::Skip),
+            "error" => Ok(SpecialFilePolicy::Error),
+            _ => Err(format!(
+                "unknown special file policy '{}', expected skip or error",
+                policy
+            )),
+        }
+    }
+}
+
+/// A directory entry as a scan sees it once the policies are applied.
+#[derive(Debug)]
+pub enum ScanEntry {
+    Dir(Metadata),
+    /// A regular file, or a link hashed by its target path.
+    File(Metadata),
+}
+
+/// Decides how a scan treats `path`: as a directory to descend into, a file to hash, or
+/// nothing (`None`) if the policies leave it out.
+pub fn classify(
+    path: &Path,
+    symlinks: SymlinkPolicy,
+    special_files: SpecialFilePolicy,
+) -> io::Result<Option<ScanEntry>> {
+    let link_metadata = std::fs::symlink_metadata(path)?;
+    let metadata = if link_metadata.is_symlink() {
+        match symlinks {
+            SymlinkPolicy::Follow => std::fs::metadata(path)?,
+            SymlinkPolicy::Target => return Ok(Some(ScanEntry::File(link_metadata))),
+            SymlinkPolicy::Skip => return Ok(None),
+        }
+    } else {
+        link_metadata
+    };
+    if metadata.is_dir() {
+        Ok(Some(ScanEntry::Dir(metadata)))
+    } else if metadata.is_file() {
+        Ok(Some(ScanEntry::File(metadata)))
+    } else {
+        match special_files {
+            SpecialFilePolicy::Skip => Ok(None),
+            SpecialFilePolicy::Error => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not a regular file or directory", path.display()),
+            )),
+        }
+    }
+}
+
+/// Canonical paths of the directories a scan descended through to reach the current one, so
+/// a followed link back into one of them is not descended into forever.
+#[derive(Debug, Clone, Default)]
+pub struct DirChain(Vec<PathBuf>);
+
+impl DirChain {
+    /// The chain for the children of `dir`, or `None` if `dir` already lies on this one.
+    pub fn enter(&self, dir: &Path) -> io::Result<Option<DirChain>> {
+        let canonical = std::fs::canonicalize(dir)?;
+        if self.0.contains(&canonical) {
+            return Ok(None);
+        }
+        let mut chain = self.clone();
+        chain.0.push(canonical);
+        Ok(Some(chain))
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+    use std::os::unix::net::UnixListener;
+
+    #[test]
+    fn test_classify_applies_policies() {
+        let dir = std::env::temp_dir().join(format!("merklefile-walk-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("real"), b"contents").unwrap();
+        symlink("real", dir.join("link")).unwrap();
+        symlink(".", dir.join("loop")).unwrap();
+        let _socket = UnixListener::bind(dir.join("socket")).unwrap();
+
+        let is_file = |path: &Path, symlinks| {
+            matches!(
+                classify(path, symlinks, SpecialFilePolicy::Skip).unwrap(),
+                Some(ScanEntry::File(_))
+            )
+        };
+        let link = dir.join("link");
+        let socket = dir.join("socket");
+        let link_is_file = is_file(&link, SymlinkPolicy::Follow);
+        let followed = SymlinkPolicy::Follow.read(&link).unwrap();
+        let target = SymlinkPolicy::Target.read(&link).unwrap();
+        let skipped_link = classify(&link, SymlinkPolicy::Skip, SpecialFilePolicy::Skip).unwrap();
+        let skipped_socket =
+            classify(&socket, SymlinkPolicy::Follow, SpecialFilePolicy::Skip).unwrap();
+        let rejected_socket = classify(&socket, SymlinkPolicy::Follow, SpecialFilePolicy::Error);
+        let root = DirChain::default().enter(&dir).unwrap().unwrap();
+        let looped = root.enter(&dir.join("loop")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(link_is_file);
+        assert_eq!(followed, b"contents");
+        assert_eq!(target, b"real");
+        assert!(skipped_link.is_none());
+        assert!(skipped_socket.is_none());
+        assert_eq!(
+            rejected_socket.unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+        assert!(looped.is_none());
+    }
+}
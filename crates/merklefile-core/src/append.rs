@@ -0,0 +1,271 @@
+//! Rolling verification of files that only grow, such as logs.
+//!
+//! An `AppendState` remembers how much of a file has been verified and just enough of its
+//! chunk tree to extend it: the roots of the largest complete subtrees over its full chunks,
+//! and the bytes of the partial chunk it ends in. Re-attesting the file then hashes only what
+//! was appended, and yields an `AppendProof` that anyone holding both chunk roots can check
+//! to see that the new content extends the old instead of rewriting it.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::slice;
+
+use crate::chunk;
+use crate::merkle_tree::hash_pair;
+
+/// What is known about a growing file after verifying its first `len` bytes.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AppendState {
+    chunk_size: usize,
+    len: u64,
+    /// Roots of the largest complete subtrees over the full chunks, leftmost first: one per
+    /// set bit of the number of full chunks.
+    frontier: Vec<Vec<u8>>,
+    /// The bytes after the last full chunk, fewer than `chunk_size`.
+    tail: Vec<u8>,
+}
+
+impl AppendState {
+    /// The state of an empty file.
+    pub fn new(chunk_size: usize) -> Self {
+        assert!(chunk_size > 0, "chunks cannot be empty");
+        AppendState {
+            chunk_size,
+            len: 0,
+            frontier: Vec::new(),
+            tail: Vec::new(),
+        }
+    }
+
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    /// Bytes verified so far.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The last bytes verified, which an append must leave as they are. They start at
+    /// offset `len() - tail().len()`.
+    pub fn tail(&self) -> &[u8] {
+        &self.tail
+    }
+
+    /// Root of the chunk tree over the bytes verified so far, the same as
+    /// `chunk::chunk_tree` computes from all of them.
+    pub fn root(&self) -> Vec<u8> {
+        root_from(&self.frontier, self.full_chunks(), &self.tail_leaf())
+    }
+
+    /// The state once `appended` is added after the bytes verified so far, and proof that
+    /// the new content extends them. Only `appended` and the partial last chunk are hashed.
+    pub fn append(&self, appended: &[u8]) -> (AppendState, AppendProof) {
+        let mut data = self.tail.clone();
+        data.extend_from_slice(appended);
+        let mut chunks: Vec<&[u8]> = data.chunks(self.chunk_size).collect();
+        if chunks.is_empty() && self.ends_in_partial_chunk() {
+            chunks.push(&[]);
+        }
+
+        let boundary = if self.ends_in_partial_chunk() {
+            chunks[0].to_vec()
+        } else {
+            Vec::new()
+        };
+        let after_boundary = usize::from(self.ends_in_partial_chunk());
+        let proof = AppendProof {
+            chunk_size: self.chunk_size,
+            old_len: self.len,
+            new_len: self.len + appended.len() as u64,
+            frontier: self.frontier.clone(),
+            boundary,
+            appended: chunks[after_boundary..]
+                .iter()
+                .map(|chunk| Sha256::digest(chunk).to_vec())
+                .collect(),
+        };
+
+        let mut next = AppendState {
+            chunk_size: self.chunk_size,
+            len: proof.new_len,
+            frontier: self.frontier.clone(),
+            tail: Vec::new(),
+        };
+        let mut full = self.full_chunks();
+        for chunk in chunks {
+            if chunk.len() < self.chunk_size {
+                next.tail = chunk.to_vec();
+                break;
+            }
+            // Adding leaf `full` completes one subtree per trailing zero of the new count
+            next.frontier.push(Sha256::digest(chunk).to_vec());
+            full += 1;
+            for _ in 0..full.trailing_zeros() {
+                let right = next.frontier.pop().expect("a subtree to merge");
+                let left = next.frontier.pop().expect("a subtree to merge");
+                next.frontier.push(hash_pair(&left, &right).to_vec());
+            }
+        }
+        (next, proof)
+    }
+
+    fn full_chunks(&self) -> usize {
+        (self.len / self.chunk_size as u64) as usize
+    }
+
+    /// Whether the chunk tree has a leaf after the full chunks: the tail's, or the single
+    /// empty chunk of an empty file.
+    fn ends_in_partial_chunk(&self) -> bool {
+        !self.tail.is_empty() || self.len == 0
+    }
+
+    fn tail_leaf(&self) -> Vec<Vec<u8>> {
+        if self.ends_in_partial_chunk() {
+            vec![Sha256::digest(&self.tail).to_vec()]
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+/// Proof that a file of `new_len` bytes begins with the `old_len` bytes it had before, given
+/// the chunk roots of both.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AppendProof {
+    pub chunk_size: usize,
+    pub old_len: u64,
+    pub new_len: u64,
+    /// Roots of the largest complete subtrees over the chunks the old content filled.
+    pub frontier: Vec<Vec<u8>>,
+    /// The chunk the old content ended inside, as it is now, so its old bytes can be seen to
+    /// be a prefix of it; empty if the old content ended on a chunk boundary.
+    pub boundary: Vec<u8>,
+    /// Hashes of the chunks after that one.
+    pub appended: Vec<Vec<u8>>,
+}
+
+impl AppendProof {
+    /// Whether the content with chunk root `new_root` extends the content with chunk root
+    /// `old_root`.
+    pub fn verify(&self, old_root: &[u8], new_root: &[u8]) -> bool {
+        let chunk_size = self.chunk_size as u64;
+        if chunk_size == 0 || self.new_len < self.old_len {
+            return false;
+        }
+        let full = self.old_len / chunk_size;
+        if self.frontier.len() != full.count_ones() as usize {
+            return false;
+        }
+
+        let (mut old_rest, mut new_rest) = (Vec::new(), Vec::new());
+        let kept = (self.old_len % chunk_size) as usize;
+        if kept > 0 || self.old_len == 0 {
+            let boundary_len = (self.new_len - full * chunk_size).min(chunk_size) as usize;
+            if self.boundary.len() != boundary_len {
+                return false;
+            }
+            old_rest.push(Sha256::digest(&self.boundary[..kept]).to_vec());
+            new_rest.push(Sha256::digest(&self.boundary).to_vec());
+        } else if !self.boundary.is_empty() {
+            return false;
+        }
+        new_rest.extend_from_slice(&self.appended);
+        if full as usize + new_rest.len()
+            != chunk::chunk_count(self.new_len as usize, self.chunk_size)
+        {
+            return false;
+        }
+
+        root_from(&self.frontier, full as usize, &old_rest) == old_root
+            && root_from(&self.frontier, full as usize, &new_rest) == new_root
+    }
+}
+
+/// Root of the chunk tree whose first `full` leaves are covered by `frontier` and whose
+/// remaining leaves are `rest`.
+fn root_from(frontier: &[Vec<u8>], full: usize, rest: &[Vec<u8>]) -> Vec<u8> {
+    let mut widths = vec![full + rest.len()];
+    while let Some(&width) = widths.last().filter(|&&width| width > 1) {
+        widths.push(width.div_ceil(2));
+    }
+    let mut subtrees = Subtrees {
+        widths: &widths,
+        full,
+        frontier: frontier.iter(),
+        rest,
+    };
+    subtrees.node(widths.len() - 1, 0)
+}
+
+struct Subtrees<'a> {
+    /// Width of every level, leaves first.
+    widths: &'a [usize],
+    full: usize,
+    frontier: slice::Iter<'a, Vec<u8>>,
+    rest: &'a [Vec<u8>],
+}
+
+impl Subtrees<'_> {
+    /// Node `index` of `level`. Nodes are visited left to right, so the first one found
+    /// entirely over the full chunks is the next root in the frontier.
+    fn node(&mut self, level: usize, index: usize) -> Vec<u8> {
+        if (index + 1) << level <= self.full {
+            return self
+                .frontier
+                .next()
+                .expect("one frontier root per set bit of the full chunks")
+                .clone();
+        }
+        if level == 0 {
+            return self.rest[index - self.full].clone();
+        }
+        let left = self.node(level - 1, 2 * index);
+        // On a level of odd width, the last node is its own sibling
+        let right = match (2 * index + 1).min(self.widths[level - 1] - 1) {
+            right if right == 2 * index => left.clone(),
+            right => self.node(level - 1, right),
+        };
+        hash_pair(&left, &right).to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_appends_match_chunk_trees_and_prove_extension() {
+        let data: Vec<u8> = (0..=255u8).cycle().take(1000).collect();
+        let chunk_size = 7;
+        for old_len in [0, 1, 6, 7, 8, 49, 56, 300] {
+            let (old, _) = AppendState::new(chunk_size).append(&data[..old_len]);
+            let old_root = chunk::chunk_tree(&data[..old_len], chunk_size).get_root_hash();
+            assert_eq!(old.root(), old_root);
+
+            for new_len in [old_len, old_len + 1, old_len + 7, old_len + 13, 1000] {
+                let (new, proof) = old.append(&data[old_len..new_len]);
+                let new_root = chunk::chunk_tree(&data[..new_len], chunk_size).get_root_hash();
+                assert_eq!(new.root(), new_root, "{} -> {}", old_len, new_len);
+                assert!(
+                    proof.verify(&old_root, &new_root),
+                    "{} -> {}",
+                    old_len,
+                    new_len
+                );
+
+                if old_len > 0 && new_len > old_len {
+                    // Content that rewrote the old bytes must not verify
+                    let mut rewritten = data[..new_len].to_vec();
+                    rewritten[old_len - 1] ^= 1;
+                    let rewritten_root = chunk::chunk_tree(&rewritten, chunk_size).get_root_hash();
+                    assert!(!proof.verify(&old_root, &rewritten_root));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use sha2::{Digest, Sha256};
+
+use crate::merkle_tree::{MerkleTree, TreeOptions};
+
+/// Chunk size used when the caller does not pick one; client and server must agree on it.
+pub const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Largest chunk size the server accepts in a request.
+pub const MAX_CHUNK_SIZE: usize = 16 * 1024 * 1024;
+
+/// Splits file contents into fixed-size chunks. An empty file is a single empty chunk,
+/// so every file has a chunk tree.
+pub fn split(data: &[u8], chunk_size: usize) -> Vec<Vec<u8>> {
+    if data.is_empty() {
+        return vec![Vec::new()];
+    }
+    data.chunks(chunk_size).map(<[u8]>::to_vec).collect()
+}
+
+/// Number of chunks `split` produces for a file of `len` bytes.
+pub fn chunk_count(len: usize, chunk_size: usize) -> usize {
+    if len == 0 {
+        1
+    } else {
+        len.div_ceil(chunk_size)
+    }
+}
+
+/// SHA-256 of every chunk, i.e. the leaf hashes of the file's chunk tree.
+pub fn chunk_hashes(data: &[u8], chunk_size: usize) -> Vec<Vec<u8>> {
+    split(data, chunk_size)
+        .iter()
+        .map(|chunk| Sha256::digest(chunk).to_vec())
+        .collect()
+}
+
+/// Builds the Merkle tree over the chunks of a single file.
+pub fn chunk_tree(data: &[u8], chunk_size: usize) -> MerkleTree {
+    chunk_tree_with(data, chunk_size, TreeOptions::default())
+}
+
+/// Like `chunk_tree`, hashing the chunks as `options` allow.
+pub fn chunk_tree_with(data: &[u8], chunk_size: usize, options: TreeOptions) -> MerkleTree {
+    // The chunks are borrowed from `data`, not copied out of it
+    let chunks: Vec<&[u8]> = match data.is_empty() {
+        true => vec![data],
+        false => data.chunks(chunk_size).collect(),
+    };
+    MerkleTree::build_from_slices(&chunks, options)
+}
+
+/// Recovers the leaf index a proof was generated for from its direction flags.
+pub fn proof_index(proof: &[(Vec<u8>, bool)]) -> usize {
+    proof
+        .iter()
+        .enumerate()
+        .filter(|(_, (_, is_left))| *is_left)
+        .map(|(level, _)| 1 << level)
+        .sum()
+}
@@ -0,0 +1,146 @@
+use alloc::string::String;
+#[cfg(feature = "std")]
+use std::error::Error as StdError;
+#[cfg(feature = "std")]
+use std::io;
+use thiserror::Error;
+
+use crate::merkle_tree::HashAlgorithm;
+
+/// Data that does not make a Merkle tree, or does not match the one it is checked against.
+#[derive(Debug, Error)]
+pub enum MerkleError {
+    #[error("a Merkle tree needs at least one leaf")]
+    EmptyInput,
+    #[error("leaf {index} is out of bounds for a tree of {leaf_count} leaves")]
+    IndexOutOfBounds { index: usize, leaf_count: usize },
+    #[error("leaf hash {index} is {len} bytes, not the {expected} of the tree's hash")]
+    LeafHashLength {
+        index: usize,
+        len: usize,
+        expected: usize,
+    },
+    #[error("no valid Merkle proof for {filename}")]
+    ProofInvalid { filename: String },
+    #[error("chunk {index} failed verification")]
+    ChunkInvalid { index: u64 },
+    #[error("chunk {index} could not be repaired from any replica")]
+    ChunkUnrepairable { index: u64 },
+    #[error("no server has chunk hashes matching the chunk root")]
+    NoTrustedChunkHashes,
+    #[error("server {addr} presents a different root")]
+    InconsistentRoots { addr: String },
+    #[error("tree head of size {size} is not signed by the log's key")]
+    TreeHeadSignatureInvalid { size: u64 },
+    #[error("tree head of size {new_size} does not extend the one of size {old_size}")]
+    InconsistentTreeHeads { old_size: u64, new_size: u64 },
+    #[error("{path} no longer begins with the {len} bytes verified before")]
+    NotAppended { path: String, len: u64 },
+}
+
+/// Bytes that are not a valid tree or proof in the interchange format.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum FormatError {
+    #[error("not a merklefile document")]
+    BadMagic,
+    #[error("format version {0} is not supported")]
+    UnsupportedVersion(u8),
+    #[error("document kind {0} is not expected here")]
+    UnexpectedKind(u8),
+    #[error("hash algorithm {0} is not supported")]
+    UnsupportedAlgorithm(u8),
+    #[error("document is hashed with {found}, expected {expected}")]
+    WrongAlgorithm {
+        expected: HashAlgorithm,
+        found: HashAlgorithm,
+    },
+    #[error("leaf encoding {0} is not supported")]
+    UnsupportedLeafEncoding(u8),
+    #[error("hashes of {0} bytes do not match the algorithm")]
+    HashLength(u8),
+    #[error("document ends early")]
+    Truncated,
+    #[error("{0} bytes follow the document")]
+    TrailingBytes(usize),
+    #[error("a tree needs at least one leaf")]
+    NoLeaves,
+    #[error("node {index} of level {level} does not match its children")]
+    NodeMismatch { level: usize, index: usize },
+    #[error("proof does not fit leaf {leaf_index} of a tree of {tree_size} leaves")]
+    ProofShape { leaf_index: u64, tree_size: u64 },
+}
+
+/// Bytes or text that are not a SHA-256 hash.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum HashError {
+    #[error("a hash is 32 bytes, not {0}")]
+    Length(usize),
+    #[error("not a hex hash")]
+    NotHex,
+}
+
+/// A root journal that is not one unbroken chain signed by the expected key. Lines are
+/// counted from 1.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum JournalError {
+    #[error("journal line {line} is not an entry")]
+    Malformed { line: usize },
+    #[error("journal line {line} does not follow the entry before it")]
+    BrokenChain { line: usize },
+    #[error("journal line {line} is not signed by the journal's key")]
+    SignatureInvalid { line: usize },
+    #[error("journal line {line} is older than the entry before it")]
+    TimeWentBack { line: usize },
+}
+
+/// An operation log that does not explain a server's roots. Lines of both the operation
+/// log and the journal are counted from 1.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ReplayError {
+    #[error("operation log line {line} is not an operation")]
+    Malformed { line: usize },
+    #[error("operation log does not start with a snapshot")]
+    NoSnapshot,
+    #[error("operation log line {line} holds files the operations before it do not explain")]
+    UnexplainedSnapshot { line: usize },
+    #[error(
+        "operation log line {line} produces a different root than journal line {journal_line}"
+    )]
+    RootMismatch { line: usize, journal_line: usize },
+    #[error("operation log line {line} produces a root missing from the journal")]
+    MissingRoot { line: usize },
+    #[error("journal line {journal_line} holds a root no logged operation produces")]
+    UnloggedRoot { journal_line: usize },
+    #[error("the current root is not the one the operation log produces")]
+    CurrentRootMismatch,
+}
+
+/// A session transcript whose entries do not chain or whose signature does not hold.
+/// Entries are counted from 0.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum TranscriptError {
+    #[error("transcript entry {entry} does not follow the entry before it")]
+    BrokenChain { entry: usize },
+    #[error("transcript is not signed by the expected key")]
+    SignatureInvalid,
+}
+
+#[cfg(feature = "std")]
+impl From<MerkleError> for io::Error {
+    fn from(err: MerkleError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+/// Formats an error followed by each of its sources, e.g. `failed to bind 0.0.0.0:80: Permission denied`.
+#[cfg(feature = "std")]
+pub fn display_chain(err: &dyn StdError) -> String {
+    let mut message = err.to_string();
+    let mut source = err.source();
+    while let Some(err) = source {
+        message.push_str(": ");
+        message.push_str(&err.to_string());
+        source = err.source();
+    }
+    message
+}
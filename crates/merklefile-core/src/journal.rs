@@ -0,0 +1,195 @@
+//! Hash-chained record of every root a server had. Each entry names the hash of the one
+//! before it and is signed together with it, so entries cannot be removed, reordered or
+//! rewritten without breaking the chain, and only the key holder can add any.
+//!
+//! A journal is a text file with one entry per line:
+//!
+//! ```text
+//! <hex previous entry hash> <size> <timestamp> <hex root> <hex signature>
+//! ```
+//!
+//! The first entry's previous hash is 32 zero bytes. Truncating the journal's latest entries
+//! is only caught by comparing its head with a copy kept elsewhere, such as a published root.
+
+use ed25519_dalek::{Signature, Signer, Verifier};
+use sha2::{Digest, Sha256};
+use std::io;
+use std::path::Path;
+
+use crate::error::JournalError;
+use crate::log::{SigningKey, VerifyingKey};
+
+/// Prefix of the bytes a journal signature covers, so it can never pass for a tree head's.
+const JOURNAL_CONTEXT: &[u8] = b"merklefile root journal v1\n";
+
+/// The server held `size` files with root `root` from `timestamp` (seconds since the Unix
+/// epoch) on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JournalEntry {
+    /// Hash of the entry before, or 32 zero bytes for the first.
+    pub prev: Vec<u8>,
+    pub size: u64,
+    pub timestamp: u64,
+    pub root: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+impl JournalEntry {
+    /// The entry after `prev`, or the first one if there is none, signed with `key`.
+    pub fn sign(
+        prev: Option<&JournalEntry>,
+        size: u64,
+        timestamp: u64,
+        root: Vec<u8>,
+        key: &SigningKey,
+    ) -> Self {
+        let mut entry = JournalEntry {
+            prev: prev.map_or_else(|| vec![0; 32], JournalEntry::hash),
+            size,
+            timestamp,
+            root,
+            signature: Vec::new(),
+        };
+        entry.signature = key.sign(&entry.signed_bytes()).to_bytes().to_vec();
+        entry
+    }
+
+    /// The hash the next entry names as its previous one.
+    pub fn hash(&self) -> Vec<u8> {
+        let mut hasher = Sha256::new();
+        hasher.update(self.signed_bytes());
+        hasher.update(&self.signature);
+        hasher.finalize().to_vec()
+    }
+
+    /// Whether the entry was signed by the holder of `key`.
+    pub fn verify(&self, key: &VerifyingKey) -> bool {
+        let Ok(signature) = Signature::from_slice(&self.signature) else {
+            return false;
+        };
+        key.verify(&self.signed_bytes(), &signature).is_ok()
+    }
+
+    /// The entry as a journal line, without the line break.
+    pub fn to_line(&self) -> String {
+        format!(
+            "{} {} {} {} {}",
+            hex::encode(&self.prev),
+            self.size,
+            self.timestamp,
+            hex::encode(&self.root),
+            hex::encode(&self.signature)
+        )
+    }
+
+    /// Parses a journal line.
+    pub fn parse(line: &str) -> Option<Self> {
+        let fields: Vec<&str> = line.split(' ').collect();
+        let [prev, size, timestamp, root, signature] = fields[..] else {
+            return None;
+        };
+        Some(JournalEntry {
+            prev: hex::decode(prev).ok()?,
+            size: size.parse().ok()?,
+            timestamp: timestamp.parse().ok()?,
+            root: hex::decode(root).ok()?,
+            signature: hex::decode(signature).ok()?,
+        })
+    }
+
+    fn signed_bytes(&self) -> Vec<u8> {
+        let mut bytes = JOURNAL_CONTEXT.to_vec();
+        bytes.extend_from_slice(&self.prev);
+        bytes.extend_from_slice(&self.size.to_be_bytes());
+        bytes.extend_from_slice(&self.timestamp.to_be_bytes());
+        bytes.extend_from_slice(&self.root);
+        bytes
+    }
+}
+
+/// Parses a journal and checks that it is one unbroken chain signed by `key`, with
+/// timestamps that never go back. Returns its entries, oldest first.
+pub fn verify_journal(text: &str, key: &VerifyingKey) -> Result<Vec<JournalEntry>, JournalError> {
+    let mut entries: Vec<JournalEntry> = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        let entry =
+            JournalEntry::parse(line).ok_or(JournalError::Malformed { line: line_number })?;
+        let expected_prev = entries
+            .last()
+            .map_or_else(|| vec![0; 32], JournalEntry::hash);
+        if entry.prev != expected_prev {
+            return Err(JournalError::BrokenChain { line: line_number });
+        }
+        if !entry.verify(key) {
+            return Err(JournalError::SignatureInvalid { line: line_number });
+        }
+        if entries
+            .last()
+            .is_some_and(|last| entry.timestamp < last.timestamp)
+        {
+            return Err(JournalError::TimeWentBack { line: line_number });
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Reads the journal at `path` and verifies it with `verify_journal`.
+pub fn read_journal(path: impl AsRef<Path>, key: &VerifyingKey) -> io::Result<Vec<JournalEntry>> {
+    let text = std::fs::read_to_string(path)?;
+    verify_journal(&text, key).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_journal_chain_detects_tampering() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let mut entries: Vec<JournalEntry> = Vec::new();
+        for size in 1..=4 {
+            let entry = JournalEntry::sign(
+                entries.last(),
+                size,
+                1_700_000_000 + size,
+                vec![size as u8; 32],
+                &key,
+            );
+            entries.push(entry);
+        }
+        let lines: Vec<String> = entries.iter().map(JournalEntry::to_line).collect();
+        let text = lines.join("\n") + "\n";
+        assert_eq!(
+            verify_journal(&text, &key.verifying_key()).unwrap(),
+            entries
+        );
+
+        // Dropping an entry from the middle breaks the chain
+        let dropped = [&lines[..1], &lines[2..]].concat().join("\n");
+        assert_eq!(
+            verify_journal(&dropped, &key.verifying_key()).unwrap_err(),
+            JournalError::BrokenChain { line: 2 }
+        );
+
+        // A rewritten root no longer matches its signature
+        let mut rewritten = entries[2].clone();
+        rewritten.root = vec![9; 32];
+        let text = [lines[0].clone(), lines[1].clone(), rewritten.to_line()].join("\n");
+        assert_eq!(
+            verify_journal(&text, &key.verifying_key()).unwrap_err(),
+            JournalError::SignatureInvalid { line: 3 }
+        );
+
+        let other = SigningKey::from_bytes(&[8; 32]);
+        assert_eq!(
+            verify_journal(&lines[0], &other.verifying_key()).unwrap_err(),
+            JournalError::SignatureInvalid { line: 1 }
+        );
+        assert_eq!(
+            verify_journal("not a journal", &key.verifying_key()).unwrap_err(),
+            JournalError::Malformed { line: 1 }
+        );
+    }
+}
@@ -0,0 +1,24 @@
+//! Merkle tree, chunking and proof verification, without any networking.
+//!
+//! Without the default `std` feature, the crate is `no_std` and needs only `alloc`: it
+//! keeps `merkle_tree`, to build trees and verify proofs, and `error`.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub mod append;
+#[cfg(feature = "std")]
+pub mod chunk;
+pub mod error;
+#[cfg(feature = "std")]
+pub mod journal;
+#[cfg(feature = "std")]
+pub mod log;
+pub mod merkle_tree;
+#[cfg(feature = "std")]
+pub mod operations;
+#[cfg(feature = "std")]
+pub mod profile;
+#[cfg(feature = "std")]
+pub mod transcript;
@@ -0,0 +1,320 @@
+//! Append-only log of file additions, hashed as in RFC 6962 so that a later tree head can be
+//! proven to extend an earlier one.
+//!
+//! Unlike [`MerkleTree`](crate::merkle_tree::MerkleTree), which covers the current file set
+//! in filename order, the log covers every file ever added in the order it was added, and
+//! its leaf and node hashes are domain-separated (`0x00` and `0x01` prefixes).
+
+use ed25519_dalek::{Signature, Signer, Verifier};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io;
+use std::path::Path;
+
+pub use ed25519_dalek::{SigningKey, VerifyingKey};
+
+/// Prefix of the bytes a tree head signature covers, so it cannot be replayed as anything else.
+const TREE_HEAD_CONTEXT: &[u8] = b"merklefile tree head v1\n";
+
+/// Prefix of the bytes a witness cosignature covers, so a witness key's signature can never
+/// pass for the log's own.
+const COSIGNATURE_CONTEXT: &[u8] = b"merklefile cosignature v1\n";
+
+/// The log's leaf for a file added under `filename` with the given contents.
+pub fn entry_hash(filename: &str, data: &[u8]) -> Vec<u8> {
+    entry_hash_of_digest(filename, &Sha256::digest(data))
+}
+
+/// Like `entry_hash`, for a file whose SHA-256 is already known.
+pub fn entry_hash_of_digest(filename: &str, digest: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update([0]);
+    hasher.update((filename.len() as u64).to_be_bytes());
+    hasher.update(filename.as_bytes());
+    hasher.update(digest);
+    hasher.finalize().to_vec()
+}
+
+fn node_hash(left: &[u8], right: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update([1]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().to_vec()
+}
+
+/// Largest power of two smaller than `n`, where the tree over `n > 1` leaves splits.
+fn split(n: usize) -> usize {
+    let mut k = 1;
+    while k * 2 < n {
+        k *= 2;
+    }
+    k
+}
+
+/// Root over the first leaves of the log; the empty log's root is the hash of nothing.
+pub fn root(leaves: &[Vec<u8>]) -> Vec<u8> {
+    match leaves.len() {
+        0 => Sha256::digest([]).to_vec(),
+        1 => leaves[0].clone(),
+        n => {
+            let k = split(n);
+            node_hash(&root(&leaves[..k]), &root(&leaves[k..]))
+        }
+    }
+}
+
+/// Proof that the log's first `old_size` leaves are a prefix of `leaves`. Empty if
+/// `old_size` is 0 or `leaves.len()`, or out of range.
+pub fn consistency_proof(leaves: &[Vec<u8>], old_size: usize) -> Vec<Vec<u8>> {
+    if old_size == 0 || old_size >= leaves.len() {
+        return Vec::new();
+    }
+    let mut proof = Vec::new();
+    subproof(old_size, leaves, true, &mut proof);
+    proof
+}
+
+fn subproof(m: usize, leaves: &[Vec<u8>], complete: bool, proof: &mut Vec<Vec<u8>>) {
+    let n = leaves.len();
+    if m == n {
+        if !complete {
+            proof.push(root(leaves));
+        }
+        return;
+    }
+    let k = split(n);
+    if m <= k {
+        subproof(m, &leaves[..k], complete, proof);
+        proof.push(root(&leaves[k..]));
+    } else {
+        subproof(m - k, &leaves[k..], false, proof);
+        proof.push(root(&leaves[..k]));
+    }
+}
+
+/// Checks that the log of `new_size` leaves with `new_root` extends the one of `old_size`
+/// leaves with `old_root`, following RFC 9162 section 2.1.4.2.
+pub fn verify_consistency(
+    old_size: u64,
+    old_root: &[u8],
+    new_size: u64,
+    new_root: &[u8],
+    proof: &[Vec<u8>],
+) -> bool {
+    if old_size > new_size {
+        return false;
+    }
+    if old_size == new_size {
+        return proof.is_empty() && old_root == new_root;
+    }
+    if old_size == 0 {
+        return proof.is_empty();
+    }
+
+    let mut path: Vec<&[u8]> = proof.iter().map(Vec::as_slice).collect();
+    if old_size.is_power_of_two() {
+        path.insert(0, old_root);
+    }
+    let Some((first, rest)) = path.split_first() else {
+        return false;
+    };
+    let (mut old_node, mut new_node) = (old_size - 1, new_size - 1);
+    while old_node & 1 == 1 {
+        old_node >>= 1;
+        new_node >>= 1;
+    }
+    let (mut old_hash, mut new_hash) = (first.to_vec(), first.to_vec());
+    for hash in rest {
+        if new_node == 0 {
+            return false;
+        }
+        if old_node & 1 == 1 || old_node == new_node {
+            old_hash = node_hash(hash, &old_hash);
+            new_hash = node_hash(hash, &new_hash);
+            while old_node & 1 == 0 && old_node != 0 {
+                old_node >>= 1;
+                new_node >>= 1;
+            }
+        } else {
+            new_hash = node_hash(&new_hash, hash);
+        }
+        old_node >>= 1;
+        new_node >>= 1;
+    }
+    new_node == 0 && old_hash == old_root && new_hash == new_root
+}
+
+/// A log server's signed statement that its log held `size` entries with root `root` at
+/// `timestamp` (seconds since the Unix epoch).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SignedTreeHead {
+    pub size: u64,
+    pub timestamp: u64,
+    pub root: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+impl SignedTreeHead {
+    pub fn sign(size: u64, timestamp: u64, root: Vec<u8>, key: &SigningKey) -> Self {
+        let signature = key
+            .sign(&signed_bytes(size, timestamp, &root))
+            .to_bytes()
+            .to_vec();
+        Self {
+            size,
+            timestamp,
+            root,
+            signature,
+        }
+    }
+
+    /// Whether the head was signed by the holder of `key`.
+    pub fn verify(&self, key: &VerifyingKey) -> bool {
+        let Ok(signature) = Signature::from_slice(&self.signature) else {
+            return false;
+        };
+        key.verify(
+            &signed_bytes(self.size, self.timestamp, &self.root),
+            &signature,
+        )
+        .is_ok()
+    }
+
+    /// A witness's statement, made at `timestamp`, that it verified this head against every
+    /// earlier head of the log it saw.
+    pub fn cosign(&self, key: &SigningKey, timestamp: u64) -> Cosignature {
+        let signature = key
+            .sign(&self.cosigned_bytes(timestamp))
+            .to_bytes()
+            .to_vec();
+        Cosignature {
+            witness: key.verifying_key().to_bytes().to_vec(),
+            timestamp,
+            signature,
+        }
+    }
+
+    fn cosigned_bytes(&self, timestamp: u64) -> Vec<u8> {
+        let mut bytes = COSIGNATURE_CONTEXT.to_vec();
+        bytes.extend_from_slice(&timestamp.to_be_bytes());
+        bytes.extend_from_slice(&signed_bytes(self.size, self.timestamp, &self.root));
+        bytes
+    }
+}
+
+/// A witness's signature over a log's tree head.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Cosignature {
+    /// The witness's public key.
+    pub witness: Vec<u8>,
+    /// When the witness cosigned, in seconds since the Unix epoch.
+    pub timestamp: u64,
+    pub signature: Vec<u8>,
+}
+
+impl Cosignature {
+    /// Whether this is a valid cosignature of `head` by the witness it names.
+    pub fn verify(&self, head: &SignedTreeHead) -> bool {
+        let Ok(witness) = <[u8; 32]>::try_from(self.witness.as_slice()) else {
+            return false;
+        };
+        let (Ok(key), Ok(signature)) = (
+            VerifyingKey::from_bytes(&witness),
+            Signature::from_slice(&self.signature),
+        ) else {
+            return false;
+        };
+        key.verify(&head.cosigned_bytes(self.timestamp), &signature)
+            .is_ok()
+    }
+}
+
+/// Reads a signing key stored as hex, as `merklefile log keygen` writes it.
+pub fn read_signing_key(path: impl AsRef<Path>) -> io::Result<SigningKey> {
+    let path = path.as_ref();
+    let contents = std::fs::read_to_string(path)?;
+    let bytes: [u8; 32] = hex::decode(contents.trim())
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} does not hold a hex signing key", path.display()),
+            )
+        })?;
+    Ok(SigningKey::from_bytes(&bytes))
+}
+
+fn signed_bytes(size: u64, timestamp: u64, root: &[u8]) -> Vec<u8> {
+    let mut bytes = TREE_HEAD_CONTEXT.to_vec();
+    bytes.extend_from_slice(&size.to_be_bytes());
+    bytes.extend_from_slice(&timestamp.to_be_bytes());
+    bytes.extend_from_slice(root);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_consistency_proofs_between_every_pair_of_sizes() {
+        let leaves: Vec<Vec<u8>> = (0..11)
+            .map(|i| entry_hash(&format!("file{}", i), &[i as u8]))
+            .collect();
+        for new_size in 1..=leaves.len() {
+            let new_root = root(&leaves[..new_size]);
+            for old_size in 1..=new_size {
+                let old_root = root(&leaves[..old_size]);
+                let proof = consistency_proof(&leaves[..new_size], old_size);
+                assert!(
+                    verify_consistency(
+                        old_size as u64,
+                        &old_root,
+                        new_size as u64,
+                        &new_root,
+                        &proof
+                    ),
+                    "{} -> {}",
+                    old_size,
+                    new_size
+                );
+                if old_size < new_size {
+                    // A rewritten history must not verify
+                    let forked = root(&[&leaves[..old_size - 1], &[entry_hash("x", b"")]].concat());
+                    assert!(!verify_consistency(
+                        old_size as u64,
+                        &forked,
+                        new_size as u64,
+                        &new_root,
+                        &proof
+                    ));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_tree_head_signature() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let head = SignedTreeHead::sign(3, 1_700_000_000, vec![1; 32], &key);
+        assert!(head.verify(&key.verifying_key()));
+
+        let mut rolled_back = head.clone();
+        rolled_back.size = 2;
+        assert!(!rolled_back.verify(&key.verifying_key()));
+        let other = SigningKey::from_bytes(&[8; 32]);
+        assert!(!head.verify(&other.verifying_key()));
+
+        let cosignature = head.cosign(&other, 1_700_000_060);
+        assert!(cosignature.verify(&head));
+        assert!(!cosignature.verify(&rolled_back));
+        // The witness signed a cosignature, not a tree head
+        let as_head = SignedTreeHead {
+            signature: cosignature.signature.clone(),
+            ..head.clone()
+        };
+        assert!(!as_head.verify(&other.verifying_key()));
+    }
+}
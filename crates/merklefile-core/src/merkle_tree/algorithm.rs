@@ -0,0 +1,103 @@
+use alloc::vec::Vec;
+use core::fmt;
+use serde::{Deserialize, Serialize};
+use sha2::digest::core_api::BlockSizeUser;
+use sha2::{Digest, Sha256, Sha512};
+use sha3::{Keccak256, Sha3_256};
+
+use super::LeafEncoding;
+
+/// The hash function a tree is built with. Proofs, exported trees and protocol messages
+/// name it, so a peer using another one is refused instead of disagreeing on every root.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum HashAlgorithm {
+    #[default]
+    Sha256,
+    Sha512,
+    Sha3_256,
+    /// Only built with the `blake3` feature. Without it, the algorithm is still recognized,
+    /// so documents and peers naming it are refused by name, but proofs of it never verify.
+    Blake3,
+    /// The original Keccak, as Ethereum hashes with it, which differs from SHA3-256 in its
+    /// padding alone.
+    Keccak256,
+}
+
+impl HashAlgorithm {
+    /// Length of its hashes in bytes.
+    pub fn output_len(self) -> usize {
+        match self {
+            HashAlgorithm::Sha256
+            | HashAlgorithm::Sha3_256
+            | HashAlgorithm::Blake3
+            | HashAlgorithm::Keccak256 => 32,
+            HashAlgorithm::Sha512 => 64,
+        }
+    }
+
+    /// Like `MerkleTree::verify_proof`, for a tree built with this algorithm.
+    pub fn verify_proof(self, proof: &[(Vec<u8>, bool)], root: &[u8], leaf: &[u8]) -> bool {
+        self.verify_proof_with(LeafEncoding::Plain, proof, root, leaf)
+    }
+
+    /// Like `verify_proof`, for a tree built with this algorithm and `encoding`.
+    pub fn verify_proof_with(
+        self,
+        encoding: LeafEncoding,
+        proof: &[(Vec<u8>, bool)],
+        root: &[u8],
+        leaf: &[u8],
+    ) -> bool {
+        match self {
+            HashAlgorithm::Sha256 => encoding.check_proof::<Sha256>(proof, root, leaf),
+            HashAlgorithm::Sha512 => encoding.check_proof::<Sha512>(proof, root, leaf),
+            HashAlgorithm::Sha3_256 => encoding.check_proof::<Sha3_256>(proof, root, leaf),
+            #[cfg(feature = "blake3")]
+            HashAlgorithm::Blake3 => encoding.check_proof::<blake3::Hasher>(proof, root, leaf),
+            #[cfg(not(feature = "blake3"))]
+            HashAlgorithm::Blake3 => false,
+            HashAlgorithm::Keccak256 => encoding.check_proof::<Keccak256>(proof, root, leaf),
+        }
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Sha512 => "sha512",
+            HashAlgorithm::Sha3_256 => "sha3-256",
+            HashAlgorithm::Blake3 => "blake3",
+            HashAlgorithm::Keccak256 => "keccak256",
+        })
+    }
+}
+
+/// A hash function a `MerkleTree` can be built with.
+pub trait TreeDigest: Digest + BlockSizeUser + Clone + fmt::Debug + Send + Sync + 'static {
+    const ALGORITHM: HashAlgorithm;
+}
+
+impl TreeDigest for Sha256 {
+    const ALGORITHM: HashAlgorithm = HashAlgorithm::Sha256;
+}
+
+impl TreeDigest for Sha512 {
+    const ALGORITHM: HashAlgorithm = HashAlgorithm::Sha512;
+}
+
+impl TreeDigest for Sha3_256 {
+    const ALGORITHM: HashAlgorithm = HashAlgorithm::Sha3_256;
+}
+
+impl TreeDigest for Keccak256 {
+    const ALGORITHM: HashAlgorithm = HashAlgorithm::Keccak256;
+}
+
+/// BLAKE3 splits a leaf into 1 KiB chunks and hashes them side by side in SIMD lanes, so
+/// large leaves hash several times faster than with SHA-256.
+#[cfg(feature = "blake3")]
+impl TreeDigest for blake3::Hasher {
+    const ALGORITHM: HashAlgorithm = HashAlgorithm::Blake3;
+}
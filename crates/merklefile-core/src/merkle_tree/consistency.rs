@@ -0,0 +1,200 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::slice;
+use serde::{Deserialize, Serialize};
+
+use sha2::Sha256;
+
+use super::LeafEncoding;
+
+/// Proof that a tree of `new_size` leaves begins with the `old_size` leaves of an earlier
+/// one, so its root is an append-only extension of the earlier root, in the manner of the
+/// consistency proofs of RFC 6962.
+///
+/// `hashes` are the roots of the largest complete subtrees over the old leaves, leftmost
+/// first, followed by the nodes of the new tree over the leaves after them that its root is
+/// hashed from, in the order the root is computed. The old root is hashed from the former
+/// alone, and the new root from both. When `old_size` is a power of two the old tree is a
+/// single complete subtree, whose root the verifier already has, so it is left out, and
+/// when the sizes are equal the proof is empty.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ConsistencyProof {
+    pub old_size: u64,
+    pub new_size: u64,
+    pub hashes: Vec<Vec<u8>>,
+}
+
+impl ConsistencyProof {
+    /// Whether the SHA-256 tree of `new_size` leaves with root `new_root` extends the one of
+    /// `old_size` leaves with root `old_root`.
+    pub fn verify(&self, old_root: &[u8], new_root: &[u8]) -> bool {
+        self.verify_with(LeafEncoding::Plain, old_root, new_root)
+    }
+
+    /// Like `verify`, for trees built with `encoding`.
+    pub fn verify_with(&self, encoding: LeafEncoding, old_root: &[u8], new_root: &[u8]) -> bool {
+        if self.old_size == 0 || self.old_size > self.new_size {
+            return false;
+        }
+        if self.old_size == self.new_size {
+            return self.hashes.is_empty() && old_root == new_root;
+        }
+        let subtrees = self.old_size.count_ones() as usize;
+        let (frontier, right) = if self.old_size.is_power_of_two() {
+            (vec![old_root.to_vec()], &self.hashes[..])
+        } else if self.hashes.len() >= subtrees {
+            let (frontier, right) = self.hashes.split_at(subtrees);
+            (frontier.to_vec(), right)
+        } else {
+            return false;
+        };
+
+        let old = Nodes::new(self.old_size, self.old_size, &frontier, &[]).root(encoding);
+        let new = Nodes::new(self.new_size, self.old_size, &frontier, right).root(encoding);
+        old.as_deref() == Some(old_root) && new.as_deref() == Some(new_root)
+    }
+}
+
+/// A node of a tree as `(level, index)`, with the leaves at level 0.
+pub(super) type Position = (usize, u64);
+
+/// Walks a tree of `size` leaves from its root, for a proof of its first `old_size` leaves:
+/// each node entirely over them is the next root in `frontier`, and each node entirely
+/// after them the next node in `right`.
+pub(super) struct Nodes<'a, H> {
+    /// Width of every level, leaves first.
+    widths: Vec<u64>,
+    old_size: u64,
+    frontier: slice::Iter<'a, H>,
+    right: slice::Iter<'a, H>,
+}
+
+impl<'a, H: AsRef<[u8]>> Nodes<'a, H> {
+    pub(super) fn new(size: u64, old_size: u64, frontier: &'a [H], right: &'a [H]) -> Self {
+        let mut widths = vec![size];
+        while let Some(&width) = widths.last().filter(|&&width| width > 1) {
+            widths.push(width.div_ceil(2));
+        }
+        Nodes {
+            widths,
+            old_size,
+            frontier: frontier.iter(),
+            right: right.iter(),
+        }
+    }
+
+    /// The root, or `None` unless the hashes are exactly the ones needed.
+    fn root(mut self, encoding: LeafEncoding) -> Option<Vec<u8>> {
+        let root = self.node(self.widths.len() - 1, 0, encoding)?;
+        let unused = self.frontier.next().is_some() || self.right.next().is_some();
+        (!unused).then_some(root)
+    }
+
+    fn node(&mut self, level: usize, index: u64, encoding: LeafEncoding) -> Option<Vec<u8>> {
+        if self.before(level, index) {
+            return self.frontier.next().map(|hash| hash.as_ref().to_vec());
+        }
+        if self.after(level, index) {
+            return self.right.next().map(|hash| hash.as_ref().to_vec());
+        }
+        let left = self.node(level - 1, 2 * index, encoding)?;
+        // On a level of odd width, the last node is its own sibling
+        let right = match (2 * index + 1).min(self.widths[level - 1] - 1) {
+            right if right == 2 * index => left.clone(),
+            right => self.node(level - 1, right, encoding)?,
+        };
+        Some(encoding.node_hash::<Sha256>(&left, &right).to_vec())
+    }
+
+    /// Whether node `index` of `level` is entirely over the old leaves. Computed in 128 bits,
+    /// since the proof's sizes are not trusted.
+    fn before(&self, level: usize, index: u64) -> bool {
+        (u128::from(index) + 1) << level <= u128::from(self.old_size)
+    }
+
+    /// Whether node `index` of `level` is entirely over the leaves after the old ones.
+    fn after(&self, level: usize, index: u64) -> bool {
+        u128::from(index) << level >= u128::from(self.old_size)
+    }
+
+    /// The nodes a proof is made of, each once in the order `node` takes them: those over
+    /// the old leaves in `frontier` and the others in `right`.
+    pub(super) fn positions(&self) -> (Vec<Position>, Vec<Position>) {
+        let mut positions = (Vec::new(), Vec::new());
+        self.visit(self.widths.len() - 1, 0, &mut positions);
+        positions
+    }
+
+    fn visit(&self, level: usize, index: u64, positions: &mut (Vec<Position>, Vec<Position>)) {
+        if self.before(level, index) {
+            positions.0.push((level, index));
+        } else if self.after(level, index) {
+            positions.1.push((level, index));
+        } else {
+            self.visit(level - 1, 2 * index, positions);
+            let right = (2 * index + 1).min(self.widths[level - 1] - 1);
+            if right != 2 * index {
+                self.visit(level - 1, right, positions);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::merkle_tree::MerkleTree;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn test_consistency_proofs_prove_extension() {
+        let data: Vec<Vec<u8>> = (0..20u8).map(|i| vec![i]).collect();
+        for new_size in 1..=data.len() {
+            let new = MerkleTree::new(data[..new_size].to_vec());
+            let new_root = new.get_root_hash();
+            for old_size in 1..=new_size {
+                let old_root = MerkleTree::new(data[..old_size].to_vec()).get_root_hash();
+                let proof = new.consistency_proof(old_size).unwrap();
+                assert!(
+                    proof.verify(&old_root, &new_root),
+                    "{} -> {}",
+                    old_size,
+                    new_size
+                );
+                // At most one subtree per level of the old tree and one node per level
+                // of the new one
+                assert!(proof.hashes.len() <= 2 * 5);
+            }
+        }
+        let tree = MerkleTree::new(data.clone());
+        assert_eq!(tree.consistency_proof(0), None);
+        assert_eq!(tree.consistency_proof(21), None);
+        assert!(tree.consistency_proof(20).unwrap().hashes.is_empty());
+
+        // A tree whose first leaves changed does not extend the old one
+        let old_root = MerkleTree::new(data[..6].to_vec()).get_root_hash();
+        let mut rewritten = data.clone();
+        rewritten[4] = b"other".to_vec();
+        let tree = MerkleTree::new(rewritten);
+        let proof = tree.consistency_proof(6).unwrap();
+        assert!(!proof.verify(&old_root, &tree.get_root_hash()));
+
+        // Nor does one whose proof has a hash too many or too few, or claims other sizes
+        let tree = MerkleTree::new(data);
+        let new_root = tree.get_root_hash();
+        let proof = tree.consistency_proof(6).unwrap();
+        assert!(proof.verify(&old_root, &new_root));
+        let mut long = proof.clone();
+        long.hashes.push(vec![0; 32]);
+        assert!(!long.verify(&old_root, &new_root));
+        let mut short = proof.clone();
+        short.hashes.pop();
+        assert!(!short.verify(&old_root, &new_root));
+        let mut resized = proof.clone();
+        resized.old_size = 5;
+        assert!(!resized.verify(&old_root, &new_root));
+        let mut huge = proof;
+        huge.new_size = u64::MAX;
+        assert!(!huge.verify(&old_root, &new_root));
+    }
+}
@@ -0,0 +1,272 @@
+use alloc::vec::Vec;
+use core::fmt;
+use hmac::{Mac, SimpleHmac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+#[cfg(feature = "std")]
+use std::io::{self, Read, Write};
+
+use super::{Node, TreeDigest};
+
+/// How the hashes of leaves and nodes are derived from what they cover. Every root and
+/// proof depends on it, so a proof only verifies with the encoding its tree was built with.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum LeafEncoding {
+    /// A leaf's hash is the hash of its bytes, and a node's the hash of its two children
+    /// concatenated. Nothing tells the two apart, so the 64 bytes of a node's children can
+    /// be presented as a leaf that hashes to that node: a second preimage of the root.
+    #[default]
+    Plain,
+    /// As in RFC 6962 and Certificate Transparency, a `0x00` byte is hashed in front of a
+    /// leaf's bytes and a `0x01` byte in front of a node's children, so no node can pass
+    /// for a leaf.
+    DomainSeparated,
+    /// As OpenZeppelin's `MerkleProof` expects, so that with `Keccak256` a tree's proofs
+    /// verify in a smart contract. A leaf's hash is the hash of the hash of its bytes, which
+    /// is 32 bytes long and so cannot be the 64 bytes of a node's children, and a node's the
+    /// hash of its two children in ascending order, so a proof needs no sides: only its
+    /// siblings, from the leaf up.
+    SortedPairs,
+}
+
+impl LeafEncoding {
+    const LEAF_PREFIX: u8 = 0x00;
+    const NODE_PREFIX: u8 = 0x01;
+
+    /// The hash of a leaf with the contents `data`, as trees of this encoding store it. Use
+    /// it to compute what to pass to `push_leaf_hash` and `append_leaf`.
+    pub fn leaf_hash<D: TreeDigest>(self, data: &[u8]) -> Vec<u8> {
+        let mut hasher = LeafHasher::<D>::with_encoding(self);
+        hasher.update(data);
+        hasher.finalize()
+    }
+
+    /// Like `leaf_hash`, for trees whose leaves are hashed with `key`.
+    pub fn keyed_leaf_hash<D: TreeDigest>(self, key: &LeafKey, data: &[u8]) -> Vec<u8> {
+        let mut hasher = LeafHasher::<D>::keyed(self, key);
+        hasher.update(data);
+        hasher.finalize()
+    }
+
+    pub(super) fn node_hash<D: TreeDigest>(self, left: &[u8], right: &[u8]) -> Node<D> {
+        let mut hasher = D::new();
+        let (left, right) = match self {
+            LeafEncoding::Plain => (left, right),
+            LeafEncoding::DomainSeparated => {
+                hasher.update([Self::NODE_PREFIX]);
+                (left, right)
+            }
+            LeafEncoding::SortedPairs => (left.min(right), left.max(right)),
+        };
+        hasher.update(left);
+        hasher.update(right);
+        hasher.finalize()
+    }
+
+    /// Whether `leaf` is in the tree of this encoding and hash function `D` whose root is
+    /// `root`, by the proof's `(sibling, is_left)` pairs from the leaves up.
+    pub fn check_proof<D: TreeDigest>(
+        self,
+        proof: &[(Vec<u8>, bool)],
+        root: &[u8],
+        leaf: &[u8],
+    ) -> bool {
+        self.check_proof_for_leaf_hash::<D>(proof, root, &self.leaf_hash::<D>(leaf))
+    }
+
+    /// Like `check_proof`, for a leaf whose hash in this encoding is already known.
+    pub fn check_proof_for_leaf_hash<D: TreeDigest>(
+        self,
+        proof: &[(Vec<u8>, bool)],
+        root: &[u8],
+        leaf_hash: &[u8],
+    ) -> bool {
+        let mut current_hash = leaf_hash.to_vec();
+
+        for (hash, is_left) in proof {
+            current_hash = if *is_left {
+                self.node_hash::<D>(hash, &current_hash).to_vec()
+            } else {
+                self.node_hash::<D>(&current_hash, hash).to_vec()
+            };
+        }
+
+        current_hash.as_slice() == root
+    }
+}
+
+/// A secret a tree's leaves are hashed with, as the HMAC of their contents under the tree's
+/// hash function, so the leaf of a file cannot be computed without it. Whoever knows a
+/// file's contents but not the key cannot tell from a published root or proof whether the
+/// file is in the tree, nor prepare leaves to match one. Nodes are hashed as without a key,
+/// so proofs of a keyed tree verify by the leaf hash alone.
+///
+/// Neither a tree's exports nor its serialized form hold the key.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct LeafKey([u8; 32]);
+
+impl LeafKey {
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        LeafKey(bytes)
+    }
+
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.0
+    }
+}
+
+impl fmt::Debug for LeafKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("LeafKey(..)")
+    }
+}
+
+/// Hashes a leaf as its contents arrive, so a leaf of many gigabytes never has to be held in
+/// memory at once. Feed it with `update`, or copy a reader into it with `read_from` or
+/// `io::copy`; `finalize` returns the same hash as `LeafEncoding::leaf_hash` of all of it.
+#[derive(Debug, Clone)]
+pub struct LeafHasher<D: TreeDigest = Sha256> {
+    hasher: State<D>,
+    /// Whether what was fed in is hashed once more, as `LeafEncoding::SortedPairs` has it.
+    rehash: bool,
+}
+
+#[derive(Debug, Clone)]
+enum State<D: TreeDigest> {
+    Plain(D),
+    Keyed(SimpleHmac<D>),
+}
+
+impl LeafHasher {
+    /// A hasher for the leaves of plain SHA-256 trees, as `MerkleTree::new` builds them.
+    pub fn new() -> Self {
+        Self::with_encoding(LeafEncoding::Plain)
+    }
+}
+
+impl Default for LeafHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<D: TreeDigest> LeafHasher<D> {
+    /// A hasher for the leaves of trees over `D` built with `encoding`.
+    pub fn with_encoding(encoding: LeafEncoding) -> Self {
+        let mut hasher = LeafHasher {
+            hasher: State::Plain(D::new()),
+            rehash: false,
+        };
+        hasher.prefix(encoding);
+        hasher
+    }
+
+    /// A hasher for the leaves of trees over `D` built with `encoding` and `key`.
+    pub fn keyed(encoding: LeafEncoding, key: &LeafKey) -> Self {
+        let mac = SimpleHmac::<D>::new_from_slice(&key.0).expect("HMAC takes keys of any length");
+        let mut hasher = LeafHasher {
+            hasher: State::Keyed(mac),
+            rehash: false,
+        };
+        hasher.prefix(encoding);
+        hasher
+    }
+
+    fn prefix(&mut self, encoding: LeafEncoding) {
+        match encoding {
+            LeafEncoding::Plain => {}
+            LeafEncoding::DomainSeparated => self.update(&[LeafEncoding::LEAF_PREFIX]),
+            LeafEncoding::SortedPairs => self.rehash = true,
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match &mut self.hasher {
+            State::Plain(hasher) => hasher.update(data),
+            State::Keyed(mac) => mac.update(data),
+        }
+    }
+
+    /// Hashes everything `reader` yields until its end, a buffer at a time, and returns how
+    /// many bytes that was.
+    #[cfg(feature = "std")]
+    pub fn read_from(&mut self, mut reader: impl Read) -> io::Result<u64> {
+        io::copy(&mut reader, self)
+    }
+
+    pub fn finalize(self) -> Vec<u8> {
+        let hash = match self.hasher {
+            State::Plain(hasher) => hasher.finalize(),
+            State::Keyed(mac) => mac.finalize().into_bytes(),
+        };
+        match self.rehash {
+            true => D::digest(hash).to_vec(),
+            false => hash.to_vec(),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<D: TreeDigest> Write for LeafHasher<D> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::format;
+    use sha2::Digest;
+
+    #[test]
+    fn test_streamed_leaves_hash_like_whole_ones() {
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        for encoding in [LeafEncoding::Plain, LeafEncoding::DomainSeparated] {
+            let mut hasher = LeafHasher::<Sha256>::with_encoding(encoding);
+            for piece in data.chunks(4099) {
+                hasher.update(piece);
+            }
+            assert_eq!(hasher.finalize(), encoding.leaf_hash::<Sha256>(&data));
+
+            #[cfg(feature = "std")]
+            {
+                let mut hasher = LeafHasher::<sha2::Sha512>::with_encoding(encoding);
+                assert_eq!(hasher.read_from(&data[..]).unwrap(), data.len() as u64);
+                assert_eq!(hasher.finalize(), encoding.leaf_hash::<sha2::Sha512>(&data));
+            }
+        }
+        assert_eq!(LeafHasher::new().finalize(), Sha256::digest(b"").to_vec());
+    }
+
+    #[test]
+    fn test_keyed_leaves_are_hmacs() {
+        // RFC 4231, test case 2, with the key zero-padded to 32 bytes: HMAC pads it so anyway
+        let mut bytes = [0; 32];
+        bytes[..4].copy_from_slice(b"Jefe");
+        let key = LeafKey::from_bytes(bytes);
+        assert_eq!(
+            hex::encode(
+                LeafEncoding::Plain
+                    .keyed_leaf_hash::<Sha256>(&key, b"what do ya want for nothing?")
+            ),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        let other = LeafKey::from_bytes([1; 32]);
+        for encoding in [LeafEncoding::Plain, LeafEncoding::DomainSeparated] {
+            let keyed = encoding.keyed_leaf_hash::<Sha256>(&key, b"leaf");
+            assert_ne!(keyed, encoding.leaf_hash::<Sha256>(b"leaf"));
+            assert_ne!(keyed, encoding.keyed_leaf_hash::<Sha256>(&other, b"leaf"));
+            let mut hasher = LeafHasher::<Sha256>::keyed(encoding, &key);
+            hasher.update(b"leaf");
+            assert_eq!(hasher.finalize(), keyed);
+        }
+        assert_eq!(format!("{:?}", key), "LeafKey(..)");
+    }
+}
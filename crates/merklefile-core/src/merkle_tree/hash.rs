@@ -0,0 +1,129 @@
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::Deref;
+use core::str::FromStr;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use sha2::digest::Output;
+use sha2::Sha256;
+
+use crate::error::HashError;
+
+/// A SHA-256 hash: a root, a leaf hash or a node of a SHA-256 tree. It is held inline, so
+/// it is `Copy` and costs no allocation, and it cannot be of any other length, so a
+/// truncated hash is refused where it is made rather than failing a proof later.
+///
+/// It dereferences to `[u8]`, so `&hash` goes wherever a hash is taken as bytes, such as
+/// `MerkleTree::verify_proof`. It displays and parses as lowercase hex, and serializes as
+/// the same list of bytes as a `Vec<u8>`, so it can replace one in a message without
+/// changing the message's form.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub struct Hash(pub [u8; 32]);
+
+impl Hash {
+    pub const LEN: usize = 32;
+
+    pub fn to_vec(&self) -> Vec<u8> {
+        self.0.to_vec()
+    }
+}
+
+impl Deref for Hash {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl AsRef<[u8]> for Hash {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<[u8; 32]> for Hash {
+    fn from(bytes: [u8; 32]) -> Self {
+        Hash(bytes)
+    }
+}
+
+impl From<Output<Sha256>> for Hash {
+    fn from(output: Output<Sha256>) -> Self {
+        Hash(output.into())
+    }
+}
+
+impl From<Hash> for Vec<u8> {
+    fn from(hash: Hash) -> Self {
+        hash.to_vec()
+    }
+}
+
+impl TryFrom<&[u8]> for Hash {
+    type Error = HashError;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, HashError> {
+        bytes
+            .try_into()
+            .map(Hash)
+            .map_err(|_| HashError::Length(bytes.len()))
+    }
+}
+
+impl fmt::Display for Hash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&hex::encode(self.0))
+    }
+}
+
+impl fmt::Debug for Hash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Hash({})", self)
+    }
+}
+
+impl FromStr for Hash {
+    type Err = HashError;
+
+    fn from_str(s: &str) -> Result<Self, HashError> {
+        let bytes = hex::decode(s).map_err(|_| HashError::NotHex)?;
+        Hash::try_from(bytes.as_slice())
+    }
+}
+
+impl Serialize for Hash {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.as_slice().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Hash {
+    fn deserialize<De: Deserializer<'de>>(deserializer: De) -> Result<Self, De::Error> {
+        let bytes = Vec::<u8>::deserialize(deserializer)?;
+        Hash::try_from(bytes.as_slice()).map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+    use sha2::Digest;
+
+    #[test]
+    fn test_hashes_parse_and_serialize_like_bytes() {
+        let hash = Hash::from(Sha256::digest(b"abc"));
+        let hex = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        assert_eq!(hash.to_string(), hex);
+        assert_eq!(hex.parse::<Hash>().unwrap(), hash);
+        assert_eq!(hex[..62].parse::<Hash>(), Err(HashError::Length(31)));
+        assert_eq!("xyz".parse::<Hash>(), Err(HashError::NotHex));
+        assert_eq!(Hash::try_from(&hash[..4]), Err(HashError::Length(4)));
+
+        // The same JSON as the bytes of a Vec<u8>
+        let json = serde_json::to_string(&hash).unwrap();
+        assert_eq!(json, serde_json::to_string(&hash.to_vec()).unwrap());
+        assert_eq!(serde_json::from_str::<Hash>(&json).unwrap(), hash);
+        assert!(serde_json::from_str::<Hash>("[1,2,3]").is_err());
+    }
+}
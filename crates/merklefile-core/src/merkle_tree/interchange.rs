@@ -0,0 +1,595 @@
+//! A documented binary format for trees and proofs, so other Merkle libraries and later
+//! versions of this crate can load them. Every document starts with the same header:
+//!
+//! ```text
+//! offset  size  field
+//! 0       4     magic "MRKL"
+//! 4       1     format version, 1
+//! 5       1     kind: 1 tree, 2 proof
+//! 6       1     hash algorithm: 1 SHA-256, 2 SHA-512, 3 SHA3-256, 4 BLAKE3, 5 Keccak-256
+//! 7       1     leaf encoding: 1 plain (a leaf's hash is the hash of its bytes, a node's
+//!               the hash of its two children concatenated, and the last node of a level
+//!               of odd width is paired with itself), 2 domain separated (the same, with a
+//!               0x00 byte hashed in front of a leaf's bytes and 0x01 in front of a node's
+//!               children, as in RFC 6962), 7 sorted pairs (a leaf's hash is the hash of
+//!               the hash of its bytes, a node's the hash of its two children in ascending
+//!               order, as OpenZeppelin's MerkleProof has it); for trees only, 3, 4 and 8 are
+//!               1, 2 and 7 with the last node of a level of odd width carried up unchanged,
+//!               and 5, 6 and 9 with it paired with a hash of all zeroes
+//! 8       1     hash length in bytes: 32, or 64 for SHA-512
+//! ```
+//!
+//! A tree follows with its leaf count as a big-endian u64, then every level from the leaves
+//! up, each node's hash in order. Level widths follow from the leaf count: each level has
+//! half the nodes of the one below, rounded up, and the last level holds the root alone.
+//!
+//! A proof follows with the leaf's index and the tree's leaf count as big-endian u64s, the
+//! root, a step count byte, then one step per level from the leaves up: a side byte, 1 if
+//! the sibling is on the left and 0 if it is on the right, and the sibling's hash.
+//!
+//! Import is strict: unknown versions, algorithms and encodings, trees of another algorithm
+//! than the one asked for, trailing bytes, inner nodes that are not the hash of their
+//! children, and proofs whose shape does not fit the leaf's position are all rejected.
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use super::proof::depth;
+use super::{
+    parent, HashAlgorithm, InnerNodes, LeafEncoding, MerkleTree, Node, OddNode, OnceLock,
+    TreeDigest,
+};
+use crate::error::FormatError;
+
+const MAGIC: &[u8; 4] = b"MRKL";
+const VERSION: u8 = 1;
+const KIND_TREE: u8 = 1;
+const KIND_PROOF: u8 = 2;
+/// The leaf encoding ids, each one less than its position, with the odd node rule they
+/// stand for.
+const LEAF_ENCODINGS: [(LeafEncoding, OddNode); 9] = [
+    (LeafEncoding::Plain, OddNode::Duplicate),
+    (LeafEncoding::DomainSeparated, OddNode::Duplicate),
+    (LeafEncoding::Plain, OddNode::Promote),
+    (LeafEncoding::DomainSeparated, OddNode::Promote),
+    (LeafEncoding::Plain, OddNode::PadWithZero),
+    (LeafEncoding::DomainSeparated, OddNode::PadWithZero),
+    (LeafEncoding::SortedPairs, OddNode::Duplicate),
+    (LeafEncoding::SortedPairs, OddNode::Promote),
+    (LeafEncoding::SortedPairs, OddNode::PadWithZero),
+];
+
+impl MerkleTree {
+    /// Loads a SHA-256 tree written by `export`, or by any writer of the same format,
+    /// checking every inner node against its children.
+    pub fn import(bytes: &[u8]) -> Result<Self, FormatError> {
+        Self::load(bytes)
+    }
+}
+
+impl<D: TreeDigest> MerkleTree<D> {
+    /// The tree in the interchange format, with every level, so a reader can take nodes
+    /// from it instead of rebuilding them.
+    pub fn export(&self) -> Vec<u8> {
+        let inner = self.inner();
+        let nodes = inner.levels.iter().flatten();
+        let hashes = self.leaf_hashes.len() + nodes.clone().count();
+        let mut bytes = header(KIND_TREE, D::ALGORITHM, self.encoding, self.odd_node);
+        bytes.reserve(8 + hashes * D::ALGORITHM.output_len());
+        bytes.extend_from_slice(&(self.leaf_hashes.len() as u64).to_be_bytes());
+        for leaf_hash in self.leaf_hashes.iter() {
+            bytes.extend_from_slice(leaf_hash);
+        }
+        for node in nodes {
+            bytes.extend_from_slice(node);
+        }
+        bytes
+    }
+
+    /// `import` for any hash function. A tree of another hash algorithm is refused; the
+    /// tree keeps the leaf encoding it was written with.
+    pub fn load(bytes: &[u8]) -> Result<Self, FormatError> {
+        let mut reader = Reader::after_header(bytes, KIND_TREE)?;
+        if reader.algorithm != D::ALGORITHM {
+            return Err(FormatError::WrongAlgorithm {
+                expected: D::ALGORITHM,
+                found: reader.algorithm,
+            });
+        }
+        let leaf_count = reader.u64()?;
+        if leaf_count == 0 {
+            return Err(FormatError::NoLeaves);
+        }
+        // Checked against what is left before anything is allocated for it
+        if leaf_count > (reader.remaining() / reader.algorithm.output_len()) as u64 {
+            return Err(FormatError::Truncated);
+        }
+        let leaf_hashes: Vec<Vec<u8>> = (0..leaf_count)
+            .map(|_| reader.hash().map(<[u8]>::to_vec))
+            .collect::<Result<_, _>>()?;
+
+        let (encoding, odd_node) = (reader.encoding, reader.odd_node);
+        let tree = Self::from_checked_levels(leaf_hashes, encoding, odd_node, || reader.hash())?;
+        reader.finish()?;
+        Ok(tree)
+    }
+
+    /// The tree over `leaf_hashes`, with each inner node taken from `next_node`, level by
+    /// level from the leaves up, after checking it against its children.
+    fn from_checked_levels<'a>(
+        leaf_hashes: Vec<Vec<u8>>,
+        encoding: LeafEncoding,
+        odd_node: OddNode,
+        mut next_node: impl FnMut() -> Result<&'a [u8], FormatError>,
+    ) -> Result<Self, FormatError> {
+        let mut levels: Vec<Vec<Node<D>>> = Vec::new();
+        let mut width = leaf_hashes.len();
+        while width > 1 {
+            let mut nodes = Vec::with_capacity(width.div_ceil(2));
+            for index in 0..width.div_ceil(2) {
+                let expected = match levels.last() {
+                    None => parent::<D>(&leaf_hashes, 2 * index, encoding, odd_node),
+                    Some(below) => parent::<D>(below, 2 * index, encoding, odd_node),
+                };
+                if next_node()? != expected.as_slice() {
+                    return Err(FormatError::NodeMismatch {
+                        level: levels.len() + 1,
+                        index,
+                    });
+                }
+                nodes.push(expected);
+            }
+            levels.push(nodes);
+            width = width.div_ceil(2);
+        }
+
+        Ok(MerkleTree {
+            leaf_hashes: Arc::new(leaf_hashes),
+            inner: Arc::new(OnceLock::from(InnerNodes { levels })),
+            encoding,
+            odd_node,
+            leaf_key: None,
+        })
+    }
+}
+
+/// A tree as serde writes it: its hash function, its encoding, and every level from the
+/// leaves up, so it can be read back without building anything. For a compact document of
+/// the same content, use `MerkleTree::export`.
+#[derive(Serialize, Deserialize)]
+struct StoredTree {
+    algorithm: HashAlgorithm,
+    encoding: LeafEncoding,
+    /// Trees stored before it could be chosen duplicate their odd nodes.
+    #[serde(default)]
+    odd_node: OddNode,
+    leaf_hashes: Vec<Vec<u8>>,
+    levels: Vec<Vec<Vec<u8>>>,
+}
+
+impl<D: TreeDigest> Serialize for MerkleTree<D> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        StoredTree {
+            algorithm: D::ALGORITHM,
+            encoding: self.encoding,
+            odd_node: self.odd_node,
+            leaf_hashes: self.leaf_hashes.to_vec(),
+            levels: self
+                .inner()
+                .levels
+                .iter()
+                .map(|level| level.iter().map(|node| node.to_vec()).collect())
+                .collect(),
+        }
+        .serialize(serializer)
+    }
+}
+
+/// Checked as strictly as `MerkleTree::load` checks an exported tree.
+impl<'de, D: TreeDigest> Deserialize<'de> for MerkleTree<D> {
+    fn deserialize<De: Deserializer<'de>>(deserializer: De) -> Result<Self, De::Error> {
+        let stored = StoredTree::deserialize(deserializer)?;
+        Self::from_stored(stored).map_err(de::Error::custom)
+    }
+}
+
+impl<D: TreeDigest> MerkleTree<D> {
+    fn from_stored(stored: StoredTree) -> Result<Self, FormatError> {
+        if stored.algorithm != D::ALGORITHM {
+            return Err(FormatError::WrongAlgorithm {
+                expected: D::ALGORITHM,
+                found: stored.algorithm,
+            });
+        }
+        if stored.leaf_hashes.is_empty() {
+            return Err(FormatError::NoLeaves);
+        }
+        let hashes = stored
+            .leaf_hashes
+            .iter()
+            .chain(stored.levels.iter().flatten());
+        if let Some(hash) = hashes
+            .clone()
+            .find(|hash| hash.len() != D::ALGORITHM.output_len())
+        {
+            return Err(FormatError::HashLength(
+                u8::try_from(hash.len()).unwrap_or(u8::MAX),
+            ));
+        }
+
+        let mut nodes = stored.levels.iter().flatten();
+        let tree = Self::from_checked_levels(
+            stored.leaf_hashes,
+            stored.encoding,
+            stored.odd_node,
+            || {
+                nodes
+                    .next()
+                    .map(Vec::as_slice)
+                    .ok_or(FormatError::Truncated)
+            },
+        )?;
+        match nodes.count() {
+            0 => Ok(tree),
+            trailing => Err(FormatError::TrailingBytes(
+                trailing * D::ALGORITHM.output_len(),
+            )),
+        }
+    }
+}
+
+/// A proof for one leaf, with what is needed to check it and to read it elsewhere: the
+/// leaf's position, the size of the tree, its root and the hash and encoding it was built
+/// with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExportedProof {
+    pub algorithm: HashAlgorithm,
+    pub encoding: LeafEncoding,
+    pub leaf_index: u64,
+    pub tree_size: u64,
+    pub root: Vec<u8>,
+    pub proof: Vec<(Vec<u8>, bool)>,
+}
+
+impl ExportedProof {
+    /// The proof for leaf `index` of `tree`, or `None` if it has no such leaf. Proofs take
+    /// their shape from the leaf's index, so trees that do not pair the last node of a level
+    /// of odd width with itself give `None` too.
+    pub fn from_tree<D: TreeDigest>(tree: &MerkleTree<D>, index: usize) -> Option<Self> {
+        if index >= tree.leaf_hashes().len() || tree.odd_node() != OddNode::Duplicate {
+            return None;
+        }
+        Some(ExportedProof {
+            algorithm: D::ALGORITHM,
+            encoding: tree.encoding(),
+            leaf_index: index as u64,
+            tree_size: tree.leaf_hashes().len() as u64,
+            root: tree.get_root_hash(),
+            proof: tree.get_proof_for(index),
+        })
+    }
+
+    /// Whether `leaf` is the leaf this proof is for, hashed with the proof's algorithm and
+    /// encoding.
+    pub fn verify(&self, leaf: &[u8]) -> bool {
+        self.algorithm
+            .verify_proof_with(self.encoding, &self.proof, &self.root, leaf)
+    }
+
+    /// The proof in the interchange format.
+    pub fn export(&self) -> Vec<u8> {
+        let mut bytes = header(
+            KIND_PROOF,
+            self.algorithm,
+            self.encoding,
+            OddNode::Duplicate,
+        );
+        bytes.extend_from_slice(&self.leaf_index.to_be_bytes());
+        bytes.extend_from_slice(&self.tree_size.to_be_bytes());
+        bytes.extend_from_slice(&self.root);
+        bytes.push(self.proof.len() as u8);
+        for (hash, is_left) in &self.proof {
+            bytes.push(u8::from(*is_left));
+            bytes.extend_from_slice(hash);
+        }
+        bytes
+    }
+
+    /// Loads a proof in the interchange format, checking that it has one step per level
+    /// of its tree, on the sides its leaf's index calls for.
+    pub fn import(bytes: &[u8]) -> Result<Self, FormatError> {
+        let mut reader = Reader::after_header(bytes, KIND_PROOF)?;
+        let leaf_index = reader.u64()?;
+        let tree_size = reader.u64()?;
+        let root = reader.hash()?.to_vec();
+        let steps = reader.u8()?;
+        let shape = FormatError::ProofShape {
+            leaf_index,
+            tree_size,
+        };
+        if leaf_index >= tree_size || u32::from(steps) != depth(tree_size) {
+            return Err(shape);
+        }
+
+        let mut proof = Vec::with_capacity(steps.into());
+        for level in 0..steps {
+            let is_left = match reader.u8()? {
+                0 => false,
+                1 => true,
+                _ => return Err(shape),
+            };
+            if is_left != ((leaf_index >> level) & 1 == 1) {
+                return Err(shape);
+            }
+            proof.push((reader.hash()?.to_vec(), is_left));
+        }
+        let (algorithm, encoding) = (reader.algorithm, reader.encoding);
+        reader.finish()?;
+
+        Ok(ExportedProof {
+            algorithm,
+            encoding,
+            leaf_index,
+            tree_size,
+            root,
+            proof,
+        })
+    }
+}
+
+fn header(
+    kind: u8,
+    algorithm: HashAlgorithm,
+    encoding: LeafEncoding,
+    odd_node: OddNode,
+) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    let encoding = LEAF_ENCODINGS
+        .iter()
+        .position(|&rule| rule == (encoding, odd_node))
+        .expect("every encoding and odd node rule has an id") as u8
+        + 1;
+    bytes.extend_from_slice(&[
+        VERSION,
+        kind,
+        algorithm_id(algorithm),
+        encoding,
+        algorithm.output_len() as u8,
+    ]);
+    bytes
+}
+
+fn algorithm_id(algorithm: HashAlgorithm) -> u8 {
+    match algorithm {
+        HashAlgorithm::Sha256 => 1,
+        HashAlgorithm::Sha512 => 2,
+        HashAlgorithm::Sha3_256 => 3,
+        HashAlgorithm::Blake3 => 4,
+        HashAlgorithm::Keccak256 => 5,
+    }
+}
+
+fn algorithm_from_id(id: u8) -> Option<HashAlgorithm> {
+    match id {
+        1 => Some(HashAlgorithm::Sha256),
+        2 => Some(HashAlgorithm::Sha512),
+        3 => Some(HashAlgorithm::Sha3_256),
+        // Documents this build cannot check are refused as unsupported
+        #[cfg(feature = "blake3")]
+        4 => Some(HashAlgorithm::Blake3),
+        5 => Some(HashAlgorithm::Keccak256),
+        _ => None,
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    /// The algorithm named in the header, which sets the length of every hash.
+    algorithm: HashAlgorithm,
+    encoding: LeafEncoding,
+    odd_node: OddNode,
+}
+
+impl<'a> Reader<'a> {
+    /// Checks the header of a document of `kind` and returns a reader for the rest.
+    fn after_header(bytes: &'a [u8], kind: u8) -> Result<Self, FormatError> {
+        let mut reader = Reader {
+            bytes,
+            algorithm: HashAlgorithm::default(),
+            encoding: LeafEncoding::default(),
+            odd_node: OddNode::default(),
+        };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(FormatError::BadMagic);
+        }
+        match reader.u8()? {
+            VERSION => {}
+            version => return Err(FormatError::UnsupportedVersion(version)),
+        }
+        match reader.u8()? {
+            found if found == kind => {}
+            found => return Err(FormatError::UnexpectedKind(found)),
+        }
+        let id = reader.u8()?;
+        reader.algorithm = algorithm_from_id(id).ok_or(FormatError::UnsupportedAlgorithm(id))?;
+        let id = reader.u8()?;
+        (reader.encoding, reader.odd_node) = match LEAF_ENCODINGS.get((id as usize).wrapping_sub(1))
+        {
+            // Proofs only fit trees that duplicate their odd nodes
+            Some(&(encoding, odd_node)) if kind == KIND_TREE || odd_node == OddNode::Duplicate => {
+                (encoding, odd_node)
+            }
+            _ => return Err(FormatError::UnsupportedLeafEncoding(id)),
+        };
+        match reader.u8()? {
+            len if len as usize == reader.algorithm.output_len() => {}
+            len => return Err(FormatError::HashLength(len)),
+        }
+        Ok(reader)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], FormatError> {
+        if self.bytes.len() < len {
+            return Err(FormatError::Truncated);
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, FormatError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u64(&mut self) -> Result<u64, FormatError> {
+        let bytes = self.take(8)?;
+        Ok(u64::from_be_bytes(bytes.try_into().expect("eight bytes")))
+    }
+
+    fn hash(&mut self) -> Result<&'a [u8], FormatError> {
+        self.take(self.algorithm.output_len())
+    }
+
+    fn remaining(&self) -> usize {
+        self.bytes.len()
+    }
+
+    fn finish(self) -> Result<(), FormatError> {
+        match self.bytes.len() {
+            0 => Ok(()),
+            trailing => Err(FormatError::TrailingBytes(trailing)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+    use alloc::vec;
+    use sha2::Sha512;
+    use sha3::Sha3_256;
+
+    const HASH_LEN: usize = 32;
+
+    #[test]
+    fn test_trees_and_proofs_round_trip_and_are_validated() {
+        for width in [1, 2, 3, 7, 8, 13] {
+            let data: Vec<Vec<u8>> = (0..width).map(|i| vec![i]).collect();
+            let tree = MerkleTree::new(data.clone());
+            let exported = tree.export();
+            let imported = MerkleTree::import(&exported).unwrap();
+            assert_eq!(imported.leaf_hashes(), tree.leaf_hashes());
+            assert_eq!(imported.get_root_hash(), tree.get_root_hash());
+            assert!(imported.debug_validate().is_ok());
+
+            for (index, leaf) in data.iter().enumerate() {
+                let proof = ExportedProof::from_tree(&tree, index).unwrap();
+                let imported = ExportedProof::import(&proof.export()).unwrap();
+                assert_eq!(imported, proof);
+                assert!(imported.verify(leaf));
+            }
+        }
+
+        let tree = MerkleTree::new(vec![vec![1], vec![2], vec![3]]);
+        let exported = tree.export();
+        let mut tampered = exported.clone();
+        // The first inner node, right after the header, the leaf count and three leaves
+        tampered[9 + 8 + 3 * HASH_LEN] ^= 1;
+        assert_eq!(
+            MerkleTree::import(&tampered).unwrap_err(),
+            FormatError::NodeMismatch { level: 1, index: 0 }
+        );
+        assert_eq!(
+            MerkleTree::import(&exported[..exported.len() - 1]).unwrap_err(),
+            FormatError::Truncated
+        );
+        assert_eq!(
+            MerkleTree::import(&[exported.as_slice(), &[0]].concat()).unwrap_err(),
+            FormatError::TrailingBytes(1)
+        );
+        let mut unknown = exported.clone();
+        unknown[6] = 9;
+        assert_eq!(
+            MerkleTree::import(&unknown).unwrap_err(),
+            FormatError::UnsupportedAlgorithm(9)
+        );
+
+        let proof = ExportedProof::from_tree(&tree, 2).unwrap().export();
+        assert_eq!(
+            MerkleTree::import(&proof).unwrap_err(),
+            FormatError::UnexpectedKind(KIND_PROOF)
+        );
+        // Flipping the side of the first step no longer fits leaf 2
+        let mut flipped = proof.clone();
+        flipped[9 + 8 + 8 + HASH_LEN + 1] = 1;
+        assert!(matches!(
+            ExportedProof::import(&flipped).unwrap_err(),
+            FormatError::ProofShape { .. }
+        ));
+    }
+
+    #[test]
+    fn test_trees_round_trip_through_serde() {
+        for width in [1, 2, 5, 8] {
+            let data: Vec<Vec<u8>> = (0..width).map(|i| vec![i]).collect();
+            let tree = MerkleTree::new(data);
+            let json = serde_json::to_string(&tree).unwrap();
+            let restored: MerkleTree = serde_json::from_str(&json).unwrap();
+            assert!(!restored.is_dirty());
+            assert_eq!(restored.leaf_hashes(), tree.leaf_hashes());
+            assert_eq!(restored.get_root_hash(), tree.get_root_hash());
+            assert!(restored.debug_validate().is_ok());
+        }
+
+        let tree = MerkleTree::new((0..5u8).map(|i| vec![i]).collect());
+        let stored = |tree: &MerkleTree| serde_json::to_value(tree).unwrap();
+        let mut tampered = stored(&tree);
+        tampered["levels"][1][0][0] = 0.into();
+        let err = serde_json::from_value::<MerkleTree>(tampered).unwrap_err();
+        assert!(err.to_string().contains("node 0 of level 2"), "{}", err);
+        let mut short = stored(&tree);
+        short["levels"][2].as_array_mut().unwrap().pop();
+        assert!(serde_json::from_value::<MerkleTree>(short).is_err());
+        let mut extra = stored(&tree);
+        extra["levels"][2]
+            .as_array_mut()
+            .unwrap()
+            .push(tree.get_root_hash().into());
+        assert!(serde_json::from_value::<MerkleTree>(extra).is_err());
+        assert!(serde_json::from_value::<MerkleTree<Sha512>>(stored(&tree)).is_err());
+    }
+
+    #[test]
+    fn test_documents_name_their_hash_algorithm() {
+        let data: Vec<Vec<u8>> = (0..5u8).map(|i| vec![i]).collect();
+        let sha512 = MerkleTree::<Sha512>::build(data.clone());
+        let imported = MerkleTree::<Sha512>::load(&sha512.export()).unwrap();
+        assert_eq!(imported.get_root_hash(), sha512.get_root_hash());
+        assert_eq!(imported.get_root_hash().len(), 64);
+        assert_eq!(
+            MerkleTree::<Sha3_256>::load(&sha512.export()).unwrap_err(),
+            FormatError::WrongAlgorithm {
+                expected: HashAlgorithm::Sha3_256,
+                found: HashAlgorithm::Sha512,
+            }
+        );
+        assert!(matches!(
+            MerkleTree::import(&sha512.export()).unwrap_err(),
+            FormatError::WrongAlgorithm { .. }
+        ));
+
+        let sha3 = MerkleTree::<Sha3_256>::build(data.clone());
+        let proof = ExportedProof::from_tree(&sha3, 3).unwrap();
+        let imported = ExportedProof::import(&proof.export()).unwrap();
+        assert_eq!(imported.algorithm, HashAlgorithm::Sha3_256);
+        assert!(imported.verify(&data[3]));
+        // The same proof read as SHA-256 does not verify
+        let relabeled = ExportedProof {
+            algorithm: HashAlgorithm::Sha256,
+            ..imported
+        };
+        assert!(!relabeled.verify(&data[3]));
+    }
+}
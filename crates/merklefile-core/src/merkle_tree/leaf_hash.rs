@@ -0,0 +1,380 @@
+//! Leaf hashing for tree construction, dispatched at runtime to the fastest SHA-256 the CPU
+//! supports. Many small files mean many short, independent SHA-256 computations, which a
+//! single-stream implementation runs one after another. On CPUs with AVX2 but without the
+//! SHA extensions, leaves are instead hashed eight at a time, one per 32-bit SIMD lane.
+
+use alloc::collections::BTreeMap;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+#[cfg(feature = "std")]
+use std::sync::OnceLock;
+
+use super::{HashAlgorithm, LeafEncoding, LeafKey, TreeDigest};
+#[cfg(feature = "std")]
+use crate::profile::{self, Stage};
+
+/// Messages hashed side by side, one per 32-bit lane of a 256-bit vector.
+const LANES: usize = 8;
+
+#[cfg(all(target_arch = "x86_64", feature = "std"))]
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+#[cfg(all(target_arch = "x86_64", feature = "std"))]
+const H0: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// How SHA-256 is computed on this CPU.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum HashBackend {
+    /// The x86 SHA extensions (SHA-NI).
+    ShaNi,
+    /// The ARMv8 SHA-2 instructions of the NEON crypto extensions.
+    ArmSha2,
+    /// Eight leaves at a time in AVX2 lanes, for x86 CPUs without SHA-NI.
+    Avx2MultiBuffer,
+    /// Portable code, without hardware acceleration.
+    Portable,
+}
+
+impl fmt::Display for HashBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            HashBackend::ShaNi => "sha-ni",
+            HashBackend::ArmSha2 => "arm-sha2",
+            HashBackend::Avx2MultiBuffer => "avx2-multi-buffer",
+            HashBackend::Portable => "portable",
+        })
+    }
+}
+
+/// The backend in use on this CPU.
+impl Default for HashBackend {
+    fn default() -> Self {
+        hash_backend()
+    }
+}
+
+/// The SHA-256 backend leaves are hashed with, detected once per process. With SHA-NI or
+/// the ARMv8 SHA-2 instructions, hashing one leaf at a time is fastest; `sha2` uses them
+/// whenever the CPU has them.
+#[cfg(feature = "std")]
+pub fn hash_backend() -> HashBackend {
+    static BACKEND: OnceLock<HashBackend> = OnceLock::new();
+    *BACKEND.get_or_init(|| {
+        #[cfg(target_arch = "x86_64")]
+        {
+            // The same features `sha2` requires before it uses SHA-NI
+            if is_x86_feature_detected!("sha")
+                && is_x86_feature_detected!("sse2")
+                && is_x86_feature_detected!("ssse3")
+                && is_x86_feature_detected!("sse4.1")
+            {
+                return HashBackend::ShaNi;
+            }
+            if is_x86_feature_detected!("avx2") {
+                return HashBackend::Avx2MultiBuffer;
+            }
+        }
+        #[cfg(target_arch = "aarch64")]
+        {
+            if std::arch::is_aarch64_feature_detected!("sha2") {
+                return HashBackend::ArmSha2;
+            }
+        }
+        HashBackend::Portable
+    })
+}
+
+/// Without `std`, the CPU's features cannot be detected, so the portable backend is used.
+#[cfg(not(feature = "std"))]
+pub fn hash_backend() -> HashBackend {
+    HashBackend::Portable
+}
+
+/// Hash of every leaf, in order, keyed with `key` if there is one. Only plain, unkeyed
+/// SHA-256 has a multi-buffer path; other hashes and encodings take one leaf at a time.
+pub(crate) fn hash_leaves<D: TreeDigest, L: AsRef<[u8]>>(
+    leaves: &[L],
+    encoding: LeafEncoding,
+    key: Option<&LeafKey>,
+) -> Vec<Vec<u8>> {
+    #[cfg(feature = "std")]
+    let _span = profile::span(Stage::HashLeaves)
+        .with_bytes(leaves.iter().map(|leaf| leaf.as_ref().len() as u64).sum());
+    if D::ALGORITHM == HashAlgorithm::Sha256
+        && encoding == LeafEncoding::Plain
+        && key.is_none()
+        && leaves.len() >= LANES
+        && hash_backend() == HashBackend::Avx2MultiBuffer
+    {
+        hash_leaves_multi_buffer(leaves)
+    } else {
+        leaves
+            .iter()
+            .map(|leaf| match key {
+                Some(key) => encoding.keyed_leaf_hash::<D>(key, leaf.as_ref()),
+                None => encoding.leaf_hash::<D>(leaf.as_ref()),
+            })
+            .collect()
+    }
+}
+
+/// Hash of every leaf, in order, on at most `threads` threads, each hashing one
+/// contiguous run of the leaves.
+#[cfg(feature = "std")]
+pub(crate) fn hash_leaves_on<D: TreeDigest, L: AsRef<[u8]> + Sync>(
+    leaves: &[L],
+    threads: usize,
+    encoding: LeafEncoding,
+    key: Option<&LeafKey>,
+) -> Vec<Vec<u8>> {
+    let threads = threads.clamp(1, leaves.len().max(1));
+    // Browsers have no threads to spawn
+    if threads == 1 || cfg!(target_arch = "wasm32") {
+        return hash_leaves::<D, L>(leaves, encoding, key);
+    }
+    let run = leaves.len().div_ceil(threads);
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = leaves
+            .chunks(run)
+            .map(|run| scope.spawn(|| hash_leaves::<D, L>(run, encoding, key)))
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("hashing does not panic"))
+            .collect()
+    })
+}
+
+/// Without `std` there are no threads to spawn, so every leaf is hashed on the calling
+/// thread.
+#[cfg(not(feature = "std"))]
+pub(crate) fn hash_leaves_on<D: TreeDigest, L: AsRef<[u8]> + Sync>(
+    leaves: &[L],
+    _threads: usize,
+    encoding: LeafEncoding,
+    key: Option<&LeafKey>,
+) -> Vec<Vec<u8>> {
+    hash_leaves::<D, L>(leaves, encoding, key)
+}
+
+fn hash_leaves_multi_buffer<L: AsRef<[u8]>>(leaves: &[L]) -> Vec<Vec<u8>> {
+    // Lanes run in lockstep, so only leaves spanning the same number of blocks share a group
+    let mut by_blocks: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    for (index, leaf) in leaves.iter().enumerate() {
+        by_blocks
+            .entry(block_count(leaf.as_ref().len()))
+            .or_default()
+            .push(index);
+    }
+
+    let mut hashes = vec![Vec::new(); leaves.len()];
+    for indices in by_blocks.values() {
+        let mut groups = indices.chunks_exact(LANES);
+        for group in &mut groups {
+            let messages: [&[u8]; LANES] =
+                core::array::from_fn(|lane| leaves[group[lane]].as_ref());
+            for (lane, digest) in hash_group(messages).into_iter().enumerate() {
+                hashes[group[lane]] = digest.to_vec();
+            }
+        }
+        for &index in groups.remainder() {
+            hashes[index] = Sha256::digest(leaves[index].as_ref()).to_vec();
+        }
+    }
+    hashes
+}
+
+/// Hashes eight messages of the same block count, one per lane of 256-bit AVX2 vectors.
+#[cfg(all(target_arch = "x86_64", feature = "std"))]
+fn hash_group(messages: [&[u8]; LANES]) -> [[u8; 32]; LANES] {
+    assert!(is_x86_feature_detected!("avx2"));
+    // SAFETY: the CPU supports AVX2, checked just above
+    unsafe { avx2::hash_group(messages) }
+}
+
+#[cfg(not(all(target_arch = "x86_64", feature = "std")))]
+fn hash_group(_messages: [&[u8]; LANES]) -> [[u8; 32]; LANES] {
+    unreachable!("multi-buffer hashing needs AVX2")
+}
+
+/// Blocks a message of `len` bytes takes once padded: a 1 bit, zeros and a 64-bit length.
+fn block_count(len: usize) -> usize {
+    (len + 9).div_ceil(64)
+}
+
+/// The blocks after the message's last full block: its remaining bytes and the padding.
+#[cfg(all(target_arch = "x86_64", feature = "std"))]
+fn padded_tail(message: &[u8]) -> Vec<u8> {
+    let full = message.len() / 64 * 64;
+    let mut tail = message[full..].to_vec();
+    tail.push(0x80);
+    let padded = (block_count(message.len()) * 64) - full;
+    tail.resize(padded - 8, 0);
+    tail.extend_from_slice(&((message.len() as u64) * 8).to_be_bytes());
+    tail
+}
+
+#[cfg(all(target_arch = "x86_64", feature = "std"))]
+mod avx2 {
+    use super::{block_count, padded_tail, H0, K, LANES};
+    use core::arch::x86_64::*;
+
+    macro_rules! rotr {
+        ($x:expr, $bits:literal) => {
+            _mm256_or_si256(
+                _mm256_srli_epi32::<$bits>($x),
+                _mm256_slli_epi32::<{ 32 - $bits }>($x),
+            )
+        };
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub(super) fn hash_group(messages: [&[u8]; LANES]) -> [[u8; 32]; LANES] {
+        let blocks = block_count(messages[0].len());
+        debug_assert!(messages
+            .iter()
+            .all(|message| block_count(message.len()) == blocks));
+        let tails: [Vec<u8>; LANES] = core::array::from_fn(|lane| padded_tail(messages[lane]));
+
+        let mut state: [__m256i; 8] =
+            core::array::from_fn(|word| _mm256_set1_epi32(H0[word] as i32));
+        for block in 0..blocks {
+            let blocks: [&[u8]; LANES] = core::array::from_fn(|lane| {
+                let message = messages[lane];
+                let full = message.len() / 64;
+                if block < full {
+                    &message[block * 64..(block + 1) * 64]
+                } else {
+                    &tails[lane][(block - full) * 64..(block - full + 1) * 64]
+                }
+            });
+            compress(&mut state, blocks);
+        }
+
+        let mut words = [[0u32; LANES]; 8];
+        for (word, vector) in words.iter_mut().zip(state) {
+            // SAFETY: `word` is 32 bytes, the width of the vector
+            unsafe { _mm256_storeu_si256(word.as_mut_ptr().cast(), vector) };
+        }
+        core::array::from_fn(|lane| {
+            let mut digest = [0; 32];
+            for (bytes, word) in digest.chunks_exact_mut(4).zip(&words) {
+                bytes.copy_from_slice(&word[lane].to_be_bytes());
+            }
+            digest
+        })
+    }
+
+    #[target_feature(enable = "avx2")]
+    fn compress(state: &mut [__m256i; 8], blocks: [&[u8]; LANES]) {
+        let word = |t: usize| {
+            let words: [i32; LANES] = core::array::from_fn(|lane| {
+                let bytes = &blocks[lane][t * 4..t * 4 + 4];
+                i32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+            });
+            // SAFETY: `words` is 32 bytes, the width of the vector
+            unsafe { _mm256_loadu_si256(words.as_ptr().cast()) }
+        };
+        let mut w = [_mm256_setzero_si256(); 64];
+        for (t, slot) in w.iter_mut().take(16).enumerate() {
+            *slot = word(t);
+        }
+        for t in 16..64 {
+            let (w15, w2) = (w[t - 15], w[t - 2]);
+            let s0 = _mm256_xor_si256(
+                _mm256_xor_si256(rotr!(w15, 7), rotr!(w15, 18)),
+                _mm256_srli_epi32::<3>(w15),
+            );
+            let s1 = _mm256_xor_si256(
+                _mm256_xor_si256(rotr!(w2, 17), rotr!(w2, 19)),
+                _mm256_srli_epi32::<10>(w2),
+            );
+            w[t] = _mm256_add_epi32(
+                _mm256_add_epi32(w[t - 16], s0),
+                _mm256_add_epi32(w[t - 7], s1),
+            );
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = *state;
+        for t in 0..64 {
+            let s1 = _mm256_xor_si256(_mm256_xor_si256(rotr!(e, 6), rotr!(e, 11)), rotr!(e, 25));
+            let ch = _mm256_xor_si256(_mm256_and_si256(e, f), _mm256_andnot_si256(e, g));
+            let t1 = _mm256_add_epi32(
+                _mm256_add_epi32(h, s1),
+                _mm256_add_epi32(ch, _mm256_add_epi32(_mm256_set1_epi32(K[t] as i32), w[t])),
+            );
+            let s0 = _mm256_xor_si256(_mm256_xor_si256(rotr!(a, 2), rotr!(a, 13)), rotr!(a, 22));
+            let maj = _mm256_xor_si256(
+                _mm256_and_si256(a, b),
+                _mm256_and_si256(c, _mm256_xor_si256(a, b)),
+            );
+            h = g;
+            g = f;
+            f = e;
+            e = _mm256_add_epi32(d, t1);
+            d = c;
+            c = b;
+            b = a;
+            a = _mm256_add_epi32(t1, _mm256_add_epi32(s0, maj));
+        }
+
+        for (word, value) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *word = _mm256_add_epi32(*word, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(all(target_arch = "x86_64", feature = "std"))]
+    #[test]
+    fn test_multi_buffer_matches_sha256() {
+        if !is_x86_feature_detected!("avx2") {
+            return;
+        }
+        // Lengths around every padding boundary, several of each so they fill whole groups
+        let leaves: Vec<Vec<u8>> = [0, 1, 55, 56, 63, 64, 65, 119, 120, 128, 1000]
+            .iter()
+            .flat_map(|&len| (0..9u8).map(move |seed| vec![seed; len]))
+            .collect();
+        let expected: Vec<Vec<u8>> = leaves
+            .iter()
+            .map(|leaf| Sha256::digest(leaf).to_vec())
+            .collect();
+        assert_eq!(hash_leaves_multi_buffer(&leaves), expected);
+        assert_eq!(
+            hash_leaves::<Sha256, _>(&leaves, LeafEncoding::Plain, None),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_hash_backend_matches_cpu() {
+        let backend = hash_backend();
+        assert_eq!(backend, hash_backend());
+        #[cfg(all(target_arch = "x86_64", feature = "std"))]
+        assert_eq!(
+            backend == HashBackend::ShaNi,
+            is_x86_feature_detected!("sha") && is_x86_feature_detected!("sse4.1")
+        );
+        #[cfg(not(feature = "std"))]
+        assert_eq!(backend, HashBackend::Portable);
+    }
+}
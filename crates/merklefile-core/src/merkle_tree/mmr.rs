@@ -0,0 +1,239 @@
+use alloc::vec::Vec;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::hash_pair;
+
+/// A Merkle Mountain Range: an append-only list of leaves, hashed as a row of perfect
+/// binary trees whose sizes are the powers of two in the number of leaves, largest first.
+/// Appending a leaf merges equal trees at the end of the row and never touches any node
+/// before them, so nothing is rebalanced and every node, once hashed, stays as it is.
+///
+/// The root bags the peaks, the roots of those trees, from the right: the last two are
+/// hashed together, then the one before them with that, and so on. A range of one tree has
+/// its peak as root, and an empty range the SHA-256 of nothing.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MerkleMountainRange {
+    /// The nodes of every complete subtree, by height: node `i` of height `h` is over leaves
+    /// `i * 2^h` to `(i + 1) * 2^h`, and its children are nodes `2i` and `2i + 1` of `h - 1`.
+    heights: Vec<Vec<Vec<u8>>>,
+}
+
+/// A proof that a leaf is in a range of `size` leaves: the siblings on its path up to the
+/// peak of its tree, from the leaf up, then the other peaks, left to right. The side of
+/// each sibling, and where its peak goes among the others, follow from `leaf_index` and
+/// `size`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MmrProof {
+    pub leaf_index: u64,
+    pub size: u64,
+    pub siblings: Vec<Vec<u8>>,
+    pub peaks: Vec<Vec<u8>>,
+}
+
+/// The peaks of a range of `size` leaves, left to right, as `(height, first leaf)`.
+fn peak_positions(size: u64) -> Vec<(u32, u64)> {
+    let mut positions = Vec::with_capacity(size.count_ones() as usize);
+    let mut start = 0;
+    for height in (0..u64::BITS).rev() {
+        if size & (1 << height) != 0 {
+            positions.push((height, start));
+            start += 1 << height;
+        }
+    }
+    positions
+}
+
+/// The root of a range with `peaks`, bagged from the right.
+fn bag(peaks: &[Vec<u8>]) -> Vec<u8> {
+    match peaks.split_last() {
+        None => Sha256::digest(b"").to_vec(),
+        Some((last, rest)) => rest
+            .iter()
+            .rev()
+            .fold(last.clone(), |bag, peak| hash_pair(peak, &bag).to_vec()),
+    }
+}
+
+impl MerkleMountainRange {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A range of the leaves hashed from `data`, in order.
+    pub fn from_leaves<L: AsRef<[u8]>>(data: &[L]) -> Self {
+        let mut range = Self::new();
+        for leaf in data {
+            range.append(leaf.as_ref());
+        }
+        range
+    }
+
+    /// Hashes `data` and appends it as the last leaf, returning its index.
+    pub fn append(&mut self, data: &[u8]) -> u64 {
+        self.append_leaf_hash(Sha256::digest(data).to_vec())
+    }
+
+    /// Like `append`, for a leaf whose hash is already known. Hashes one node per tree it
+    /// merges, at most `log2(n)`, and none on average more than two.
+    pub fn append_leaf_hash(&mut self, leaf_hash: Vec<u8>) -> u64 {
+        let index = self.len();
+        let mut node = leaf_hash;
+        let mut height = 0;
+        loop {
+            if height == self.heights.len() {
+                self.heights.push(Vec::new());
+            }
+            let level = &mut self.heights[height];
+            level.push(node);
+            if level.len() % 2 == 1 {
+                return index;
+            }
+            node = hash_pair(&level[level.len() - 2], &level[level.len() - 1]).to_vec();
+            height += 1;
+        }
+    }
+
+    /// Number of leaves.
+    pub fn len(&self) -> u64 {
+        self.heights.first().map_or(0, |leaves| leaves.len() as u64)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The roots of the trees of the range, left to right, largest first.
+    pub fn peaks(&self) -> Vec<Vec<u8>> {
+        peak_positions(self.len())
+            .into_iter()
+            .map(|(height, start)| {
+                self.heights[height as usize][(start >> height) as usize].clone()
+            })
+            .collect()
+    }
+
+    pub fn get_root_hash(&self) -> Vec<u8> {
+        bag(&self.peaks())
+    }
+
+    /// The proof for leaf `index`, or `None` if there is no such leaf.
+    pub fn proof(&self, index: u64) -> Option<MmrProof> {
+        let size = self.len();
+        if index >= size {
+            return None;
+        }
+        let positions = peak_positions(size);
+        let (height, _) = positions
+            .iter()
+            .copied()
+            .find(|&(height, start)| index < start + (1 << height))?;
+
+        let mut siblings = Vec::with_capacity(height as usize);
+        let mut node = index;
+        for level in &self.heights[..height as usize] {
+            siblings.push(level[(node ^ 1) as usize].clone());
+            node /= 2;
+        }
+        let mut peaks = self.peaks();
+        let mine = positions
+            .iter()
+            .position(|&(peak_height, _)| peak_height == height)?;
+        peaks.remove(mine);
+        Some(MmrProof {
+            leaf_index: index,
+            size,
+            siblings,
+            peaks,
+        })
+    }
+}
+
+impl MmrProof {
+    /// Whether `leaf` is leaf `leaf_index` of a range of `size` leaves whose root is `root`.
+    pub fn verify(&self, root: &[u8], leaf: &[u8]) -> bool {
+        self.verify_leaf_hash(root, &Sha256::digest(leaf))
+    }
+
+    /// Like `verify`, for a leaf whose hash is already known.
+    pub fn verify_leaf_hash(&self, root: &[u8], leaf_hash: &[u8]) -> bool {
+        self.root(leaf_hash)
+            .is_some_and(|computed| computed == root)
+    }
+
+    /// The root the leaf, its siblings and the other peaks bag up to, or `None` if the proof
+    /// does not fit a range of `size` leaves.
+    fn root(&self, leaf_hash: &[u8]) -> Option<Vec<u8>> {
+        if self.leaf_index >= self.size {
+            return None;
+        }
+        let positions = peak_positions(self.size);
+        let mine = positions
+            .iter()
+            .position(|&(height, start)| self.leaf_index < start + (1 << height))?;
+        let (height, start) = positions[mine];
+        if self.siblings.len() != height as usize || self.peaks.len() != positions.len() - 1 {
+            return None;
+        }
+
+        let mut node = leaf_hash.to_vec();
+        let mut index = self.leaf_index - start;
+        for sibling in &self.siblings {
+            node = match index % 2 {
+                1 => hash_pair(sibling, &node),
+                _ => hash_pair(&node, sibling),
+            }
+            .to_vec();
+            index /= 2;
+        }
+        let mut peaks = self.peaks.clone();
+        peaks.insert(mine, node);
+        Some(bag(&peaks))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn test_mountain_range_proofs() {
+        let mut range = MerkleMountainRange::new();
+        assert_eq!(range.get_root_hash(), Sha256::digest(b"").to_vec());
+        assert_eq!(range.proof(0), None);
+
+        let data: Vec<Vec<u8>> = (0..40u8).map(|i| vec![i]).collect();
+        for (i, leaf) in data.iter().enumerate() {
+            assert_eq!(range.append(leaf), i as u64);
+            let size = i as u64 + 1;
+            assert_eq!(range.len(), size);
+            assert_eq!(range.peaks().len(), size.count_ones() as usize);
+            let root = range.get_root_hash();
+            for (index, leaf) in data[..=i].iter().enumerate() {
+                let proof = range.proof(index as u64).unwrap();
+                assert!(proof.verify(&root, leaf), "size {} leaf {}", size, index);
+                assert!(!proof.verify(&root, b"other"));
+            }
+        }
+        assert_eq!(MerkleMountainRange::from_leaves(&data), range);
+
+        // A range of a power of two leaves is one tree, with the root of a MerkleTree
+        let tree = crate::merkle_tree::MerkleTree::new(data[..32].to_vec());
+        let prefix = MerkleMountainRange::from_leaves(&data[..32]);
+        assert_eq!(prefix.get_root_hash(), tree.get_root_hash());
+
+        // Proofs that do not fit their range are refused
+        let root = range.get_root_hash();
+        let proof = range.proof(35).unwrap();
+        let mut moved = proof.clone();
+        moved.leaf_index = 34;
+        assert!(!moved.verify(&root, &data[35]));
+        let mut resized = proof.clone();
+        resized.size = 41;
+        assert!(!resized.verify(&root, &data[35]));
+        let mut short = proof;
+        short.peaks.pop();
+        assert!(!short.verify(&root, &data[35]));
+    }
+}
@@ -0,0 +1,1638 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ops::Range;
+use sha2::digest::Output;
+use sha2::{Digest, Sha256};
+#[cfg(feature = "std")]
+use std::sync::OnceLock;
+
+use crate::error::MerkleError;
+#[cfg(feature = "std")]
+use crate::profile::{self, Stage};
+
+/// Without `std` there is no lock to build the nodes under, and a tree is only ever used
+/// from the thread that has it.
+#[cfg(not(feature = "std"))]
+use core::cell::OnceCell as OnceLock;
+
+mod algorithm;
+mod consistency;
+mod encoding;
+mod hash;
+pub mod interchange;
+mod leaf_hash;
+mod mmr;
+mod multiproof;
+#[cfg(feature = "std")]
+mod persistent;
+mod preset;
+mod proof;
+#[cfg(feature = "std")]
+mod sparse;
+
+pub use algorithm::{HashAlgorithm, TreeDigest};
+pub use consistency::ConsistencyProof;
+pub use encoding::{LeafEncoding, LeafHasher, LeafKey};
+pub use hash::Hash;
+pub use interchange::ExportedProof;
+pub use leaf_hash::{hash_backend, HashBackend};
+pub use mmr::{MerkleMountainRange, MmrProof};
+pub use multiproof::{MultiProof, RangeProof};
+#[cfg(feature = "std")]
+pub use persistent::{NodeCache, PersistentTree};
+pub use preset::{HashFunction, OddNode, TreePreset};
+pub use proof::MerkleProof;
+#[cfg(feature = "std")]
+pub use sparse::{sparse_key, SparseKey, SparseMerkleTree, SparseProof, SPARSE_DEPTH};
+
+/// An inner node: the hash of its two children.
+type Node<D> = Output<D>;
+
+/// A binary Merkle tree over leaf hashes, built with the hash function `D`.
+///
+/// Leaves and inner nodes are reference-counted, so cloning a tree or taking a `snapshot`
+/// copies two pointers. Adding a leaf copies the leaves, and the nodes above them if they
+/// are built, only if a snapshot still shares them.
+///
+/// SHA-256 trees are built with `new` and its siblings. A default type parameter does not
+/// take part in inference, so trees over another hash are built with the generic
+/// counterparts, e.g. `MerkleTree::<Sha512>::build`.
+#[derive(Debug, Clone)]
+pub struct MerkleTree<D: TreeDigest = Sha256> {
+    leaf_hashes: Arc<Vec<Vec<u8>>>,
+    /// The nodes above the leaves, built when first needed and replaced when a leaf is added.
+    /// Snapshots share them, so whichever copy needs them first builds them for all.
+    inner: Arc<OnceLock<InnerNodes<D>>>,
+    encoding: LeafEncoding,
+    odd_node: OddNode,
+    /// The key the leaves of data are hashed with, if any, for `update_leaf`.
+    leaf_key: Option<LeafKey>,
+}
+
+/// An immutable view of a `MerkleTree` as it was when taken. Readers can compute roots and
+/// proofs from it while the tree it came from goes on changing, without holding any lock.
+#[derive(Debug, Clone)]
+pub struct TreeSnapshot<D: TreeDigest = Sha256> {
+    tree: MerkleTree<D>,
+}
+
+impl TreeSnapshot {
+    pub fn root(&self) -> Hash {
+        self.tree.root()
+    }
+}
+
+impl<D: TreeDigest> TreeSnapshot<D> {
+    pub fn get_root_hash(&self) -> Vec<u8> {
+        self.tree.get_root_hash()
+    }
+
+    pub fn get_proof_for(&self, index: usize) -> Vec<(Vec<u8>, bool)> {
+        self.tree.get_proof_for(index)
+    }
+
+    pub fn try_get_proof_for(&self, index: usize) -> Result<Vec<(Vec<u8>, bool)>, MerkleError> {
+        self.tree.try_get_proof_for(index)
+    }
+
+    pub fn proof(&self, index: usize) -> Option<MerkleProof> {
+        self.tree.proof(index)
+    }
+
+    pub fn get_proof_for_indices(&self, indices: &[usize]) -> Option<MultiProof> {
+        self.tree.get_proof_for_indices(indices)
+    }
+
+    pub fn get_range_proof(&self, range: Range<usize>) -> Option<RangeProof> {
+        self.tree.get_range_proof(range)
+    }
+
+    pub fn consistency_proof(&self, old_size: usize) -> Option<ConsistencyProof> {
+        self.tree.consistency_proof(old_size)
+    }
+
+    /// Hashes of the leaves, in leaf order.
+    pub fn leaf_hashes(&self) -> &[Vec<u8>] {
+        self.tree.leaf_hashes()
+    }
+
+    pub fn leaf_count(&self) -> usize {
+        self.tree.leaf_count()
+    }
+
+    pub fn depth(&self) -> usize {
+        self.tree.depth()
+    }
+
+    pub fn leaf_hash(&self, index: usize) -> Option<&[u8]> {
+        self.tree.leaf_hash(index)
+    }
+
+    pub fn leaves(&self) -> impl ExactSizeIterator<Item = &[u8]> + '_ {
+        self.tree.leaves()
+    }
+
+    pub fn levels(&self) -> impl Iterator<Item = Vec<&[u8]>> + '_ {
+        self.tree.levels()
+    }
+
+    pub fn debug_validate(&self) -> Result<(), Vec<String>> {
+        self.tree.debug_validate()
+    }
+}
+
+/// How a tree is built from the data of its leaves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TreeOptions {
+    /// Most threads the leaves are hashed on at once. At 1, the default, they are hashed on
+    /// the calling thread, so tree construction on a shared host never takes every core.
+    pub hash_concurrency: usize,
+    /// How leaves and nodes are hashed. `LeafEncoding::Plain` by default, as every tree
+    /// before it could be chosen was built.
+    pub encoding: LeafEncoding,
+    /// The secret leaves are hashed with, if any. None by default.
+    pub leaf_key: Option<LeafKey>,
+    /// What a level of odd width does with its last node. `OddNode::Duplicate` by default,
+    /// as every tree before it could be chosen was built.
+    pub odd_node: OddNode,
+}
+
+impl Default for TreeOptions {
+    fn default() -> Self {
+        TreeOptions {
+            hash_concurrency: 1,
+            encoding: LeafEncoding::Plain,
+            leaf_key: None,
+            odd_node: OddNode::Duplicate,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct InnerNodes<D: TreeDigest> {
+    /// The nodes above the leaves, one allocation per level from the leaves up, so building
+    /// a tree costs one allocation per level instead of one per node, and a leaf can be
+    /// appended without moving the levels above it. The children of node `i` of a level are
+    /// nodes `2i` and `2i + 1` of the level below; on a level of odd width, the last node
+    /// has only the one, which the tree's `OddNode` completes.
+    levels: Vec<Vec<Node<D>>>,
+}
+
+impl MerkleTree {
+    pub fn new(data: Vec<Vec<u8>>) -> Self {
+        Self::build(data)
+    }
+
+    /// Like `new`, but returns `MerkleError::EmptyInput` for no leaves instead of panicking.
+    pub fn try_new(data: Vec<Vec<u8>>) -> Result<Self, MerkleError> {
+        Self::try_build(data)
+    }
+
+    /// Like `new`, hashing the leaves as `options` allow.
+    pub fn with_options(data: Vec<Vec<u8>>, options: TreeOptions) -> Self {
+        Self::build_with_options(data, options)
+    }
+
+    /// Like `new`, for leaves borrowed from wherever they are kept, such as the values of a
+    /// map of files or the chunks of one buffer, so none of them is copied.
+    pub fn from_slices<L: AsRef<[u8]> + Sync>(data: &[L]) -> Self {
+        Self::build_from_slices(data, TreeOptions::default())
+    }
+
+    /// The root as a `Hash`, which unlike `get_root_hash` allocates nothing. The root of a
+    /// tree of one leaf is that leaf's hash, so this panics on a tree built from a single
+    /// leaf hash that is not 32 bytes, which no SHA-256 tree has.
+    pub fn root(&self) -> Hash {
+        match self.inner().levels.last() {
+            Some(top) => top[0].into(),
+            None => Hash::try_from(self.leaf_hashes[0].as_slice())
+                .expect("leaves of SHA-256 trees are SHA-256 hashes"),
+        }
+    }
+
+    /// Builds a tree from already hashed leaves, e.g. a list of chunk hashes received from a peer.
+    pub fn from_leaf_hashes(leaf_hashes: Vec<Vec<u8>>) -> Self {
+        Self::build_from_leaf_hashes(leaf_hashes)
+    }
+
+    /// Like `from_leaf_hashes`, for hashes that cannot be trusted to be well formed, such as
+    /// those received from a peer: refuses an empty list and any hash that is not 32 bytes.
+    pub fn try_from_leaf_hashes(leaf_hashes: Vec<Vec<u8>>) -> Result<Self, MerkleError> {
+        Self::try_build_from_leaf_hashes(leaf_hashes)
+    }
+
+    /// Records the leaves without hashing anything above them. The tree is built by the
+    /// first call that needs it, such as `get_root_hash`, and kept until a leaf is added, so
+    /// bulk ingestion through `push_leaf_hash` pays for one build instead of one per leaf.
+    pub fn lazy(leaf_hashes: Vec<Vec<u8>>) -> Self {
+        Self::build_lazy(leaf_hashes)
+    }
+
+    #[allow(dead_code)]
+    pub fn verify_proof(proof: &[(Vec<u8>, bool)], root: &[u8], leaf: &[u8]) -> bool {
+        Self::check_proof(proof, root, leaf)
+    }
+
+    /// Like `verify_proof`, for a leaf whose hash is already known, e.g. because it was
+    /// hashed while it was streamed.
+    pub fn verify_proof_for_leaf_hash(
+        proof: &[(Vec<u8>, bool)],
+        root: &[u8],
+        leaf_hash: &[u8],
+    ) -> bool {
+        Self::check_proof_for_leaf_hash(proof, root, leaf_hash)
+    }
+}
+
+impl<D: TreeDigest> MerkleTree<D> {
+    /// `new` for any hash function.
+    pub fn build(data: Vec<Vec<u8>>) -> Self {
+        Self::build_with_options(data, TreeOptions::default())
+    }
+
+    /// `try_new` for any hash function.
+    pub fn try_build(data: Vec<Vec<u8>>) -> Result<Self, MerkleError> {
+        if data.is_empty() {
+            return Err(MerkleError::EmptyInput);
+        }
+        Ok(Self::build(data))
+    }
+
+    /// `with_options` for any hash function.
+    pub fn build_with_options(data: Vec<Vec<u8>>, options: TreeOptions) -> Self {
+        Self::build_from_slices(&data, options)
+    }
+
+    /// `from_slices` for any hash function, hashing the leaves as `options` allow.
+    pub fn build_from_slices<L: AsRef<[u8]> + Sync>(data: &[L], options: TreeOptions) -> Self {
+        let leaf_hashes = leaf_hash::hash_leaves_on::<D, L>(
+            data,
+            options.hash_concurrency,
+            options.encoding,
+            options.leaf_key.as_ref(),
+        );
+        let mut tree = Self::build_lazy(leaf_hashes)
+            .with_encoding(options.encoding)
+            .with_odd_node(options.odd_node);
+        tree.leaf_key = options.leaf_key;
+        tree.inner();
+        tree
+    }
+
+    /// `from_leaf_hashes` for any hash function.
+    pub fn build_from_leaf_hashes(leaf_hashes: Vec<Vec<u8>>) -> Self {
+        Self::build_from_encoded_leaf_hashes(leaf_hashes, LeafEncoding::Plain)
+    }
+
+    /// `try_from_leaf_hashes` for any hash function, whose hashes must be as long as `D`'s.
+    pub fn try_build_from_leaf_hashes(leaf_hashes: Vec<Vec<u8>>) -> Result<Self, MerkleError> {
+        if leaf_hashes.is_empty() {
+            return Err(MerkleError::EmptyInput);
+        }
+        let expected = <D as Digest>::output_size();
+        if let Some((index, hash)) = leaf_hashes
+            .iter()
+            .enumerate()
+            .find(|(_, hash)| hash.len() != expected)
+        {
+            return Err(MerkleError::LeafHashLength {
+                index,
+                len: hash.len(),
+                expected,
+            });
+        }
+        Ok(Self::build_from_leaf_hashes(leaf_hashes))
+    }
+
+    /// Like `build_from_leaf_hashes`, for leaves hashed with `encoding` (see
+    /// `LeafEncoding::leaf_hash`), whose nodes are then hashed with it too.
+    pub fn build_from_encoded_leaf_hashes(
+        leaf_hashes: Vec<Vec<u8>>,
+        encoding: LeafEncoding,
+    ) -> Self {
+        let tree = Self::build_lazy(leaf_hashes).with_encoding(encoding);
+        tree.inner();
+        tree
+    }
+
+    /// `lazy` for any hash function.
+    pub fn build_lazy(leaf_hashes: Vec<Vec<u8>>) -> Self {
+        assert!(
+            !leaf_hashes.is_empty(),
+            "a Merkle tree needs at least one leaf"
+        );
+        Self {
+            leaf_hashes: Arc::new(leaf_hashes),
+            inner: Arc::default(),
+            encoding: LeafEncoding::Plain,
+            odd_node: OddNode::Duplicate,
+            leaf_key: None,
+        }
+    }
+
+    /// The tree with its nodes hashed with `encoding`, rebuilt when next needed if that is
+    /// not the encoding it had. Its leaf hashes are kept as they are.
+    fn with_encoding(mut self, encoding: LeafEncoding) -> Self {
+        if encoding != self.encoding {
+            self.encoding = encoding;
+            self.inner = Arc::default();
+        }
+        self
+    }
+
+    /// The tree with the last node of each level of odd width completed as `odd_node` has
+    /// it, rebuilt when next needed if that is not how it was. Its leaf hashes are kept as
+    /// they are.
+    pub fn with_odd_node(mut self, odd_node: OddNode) -> Self {
+        if odd_node != self.odd_node {
+            self.odd_node = odd_node;
+            self.inner = Arc::default();
+        }
+        self
+    }
+
+    /// The tree with `key` as the key `update_leaf` hashes data with, for a tree built from
+    /// leaf hashes keyed with it, or loaded from an export, which does not hold the key. The
+    /// leaf hashes already in the tree are kept as they are.
+    pub fn with_leaf_key(mut self, key: LeafKey) -> Self {
+        self.leaf_key = Some(key);
+        self
+    }
+
+    /// Whether the tree's leaves are hashed with a key it knows.
+    pub fn is_keyed(&self) -> bool {
+        self.leaf_key.is_some()
+    }
+
+    /// The hash function the tree is built with.
+    pub fn algorithm(&self) -> HashAlgorithm {
+        D::ALGORITHM
+    }
+
+    /// How the tree's leaves and nodes are hashed.
+    pub fn encoding(&self) -> LeafEncoding {
+        self.encoding
+    }
+
+    /// What each level of odd width does with its last node.
+    pub fn odd_node(&self) -> OddNode {
+        self.odd_node
+    }
+
+    /// Adds a leaf after the existing ones. The tree above the leaves is rebuilt when next
+    /// needed, not now.
+    pub fn push_leaf_hash(&mut self, leaf_hash: Vec<u8>) {
+        Arc::make_mut(&mut self.leaf_hashes).push(leaf_hash);
+        self.inner = Arc::default();
+    }
+
+    /// Adds a leaf after the existing ones and rehashes only the nodes above it, one per
+    /// level, so the root is current again in O(log n). On a tree that is not built yet, it
+    /// only records the leaf, like `push_leaf_hash`.
+    pub fn append_leaf(&mut self, leaf_hash: Vec<u8>) {
+        self.extend_leaves([leaf_hash]);
+    }
+
+    /// Like `append_leaf` for each of `leaf_hashes` in turn.
+    pub fn extend_leaves(&mut self, leaf_hashes: impl IntoIterator<Item = Vec<u8>>) {
+        let leaves = Arc::make_mut(&mut self.leaf_hashes);
+        match Arc::make_mut(&mut self.inner).get_mut() {
+            Some(inner) => {
+                for leaf_hash in leaf_hashes {
+                    leaves.push(leaf_hash);
+                    inner.append(leaves, self.encoding, self.odd_node);
+                }
+            }
+            None => {
+                leaves.extend(leaf_hashes);
+                self.inner = Arc::default();
+            }
+        }
+    }
+
+    /// Replaces leaf `index` with the leaf of `data` and rehashes only the nodes above it,
+    /// one per level, returning the hash it replaced, or `None`, leaving the tree as it is,
+    /// if there is no such leaf.
+    pub fn update_leaf(&mut self, index: usize, data: &[u8]) -> Option<Vec<u8>> {
+        let leaf_hash = match &self.leaf_key {
+            Some(key) => self.encoding.keyed_leaf_hash::<D>(key, data),
+            None => self.encoding.leaf_hash::<D>(data),
+        };
+        self.update_leaf_hash(index, leaf_hash)
+    }
+
+    /// Like `update_leaf`, for a leaf whose hash is already known. On a tree that is not
+    /// built yet, it only records the leaf.
+    pub fn update_leaf_hash(&mut self, index: usize, leaf_hash: Vec<u8>) -> Option<Vec<u8>> {
+        if index >= self.leaf_hashes.len() {
+            return None;
+        }
+        let leaves = Arc::make_mut(&mut self.leaf_hashes);
+        let old = core::mem::replace(&mut leaves[index], leaf_hash);
+        if let Some(inner) = Arc::make_mut(&mut self.inner).get_mut() {
+            inner.update(leaves, index, self.encoding, self.odd_node);
+        }
+        Some(old)
+    }
+
+    /// Removes leaf `index`, shifting the leaves after it one place left, and returns its
+    /// hash. Only the nodes over the leaves from `index` on are rehashed, so the subtrees
+    /// left of it, and the proofs within them up to where they meet the rest, are kept.
+    /// `None`, leaving the tree as it is, if there is no such leaf or it is the only one.
+    pub fn remove_leaf(&mut self, index: usize) -> Option<Vec<u8>> {
+        if index >= self.leaf_hashes.len() || self.leaf_hashes.len() == 1 {
+            return None;
+        }
+        let leaves = Arc::make_mut(&mut self.leaf_hashes);
+        let removed = leaves.remove(index);
+        if let Some(inner) = Arc::make_mut(&mut self.inner).get_mut() {
+            inner.rehash_from(leaves, index, self.encoding, self.odd_node);
+        }
+        Some(removed)
+    }
+
+    /// Replaces leaf `index` with a tombstone, a hash of all zeroes that no data hashes to,
+    /// and returns the hash it replaced. Unlike `remove_leaf`, every other leaf keeps its
+    /// index, so the server's file positions, and the shape of every proof, stay as they
+    /// are. `None` if there is no such leaf.
+    pub fn tombstone_leaf(&mut self, index: usize) -> Option<Vec<u8>> {
+        self.update_leaf_hash(index, vec![0; <D as Digest>::output_size()])
+    }
+
+    /// Whether leaf `index` was replaced with a tombstone.
+    pub fn is_tombstone(&self, index: usize) -> bool {
+        self.leaf_hashes
+            .get(index)
+            .is_some_and(|hash| hash.iter().all(|&byte| byte == 0))
+    }
+
+    /// The tree as it is now, for readers that should not block or be blocked by writers.
+    pub fn snapshot(&self) -> TreeSnapshot<D> {
+        TreeSnapshot { tree: self.clone() }
+    }
+
+    /// Whether leaves were recorded since the tree above them was last built.
+    pub fn is_dirty(&self) -> bool {
+        self.inner.get().is_none()
+    }
+
+    fn inner(&self) -> &InnerNodes<D> {
+        self.inner
+            .get_or_init(|| Self::build_tree(&self.leaf_hashes, self.encoding, self.odd_node))
+    }
+
+    fn build_tree(leaves: &[Vec<u8>], encoding: LeafEncoding, odd_node: OddNode) -> InnerNodes<D> {
+        #[cfg(feature = "std")]
+        let _span = profile::span(Stage::BuildTree);
+        let mut levels: Vec<Vec<Node<D>>> = Vec::new();
+        let mut width = leaves.len();
+        while width > 1 {
+            let mut level = Vec::with_capacity(width.div_ceil(2));
+            for i in (0..width).step_by(2) {
+                level.push(match levels.last() {
+                    None => parent::<D>(leaves, i, encoding, odd_node),
+                    Some(below) => parent::<D>(below, i, encoding, odd_node),
+                });
+            }
+            levels.push(level);
+            width = width.div_ceil(2);
+        }
+        InnerNodes { levels }
+    }
+
+    pub fn get_root_hash(&self) -> Vec<u8> {
+        match self.inner().levels.last() {
+            Some(top) => top[0].to_vec(),
+            None => self.leaf_hashes[0].clone(),
+        }
+    }
+
+    /// The siblings of leaf `index` from the leaves up, each with whether it is on the left.
+    /// They are read from the stored levels, one per level, so a proof costs O(log n) and
+    /// hashes nothing unless leaves were recorded since the tree was last built. The proof
+    /// is empty both for the one leaf of a tree of one and for a leaf the tree does not have;
+    /// `try_get_proof_for` tells the two apart.
+    pub fn get_proof_for(&self, index: usize) -> Vec<(Vec<u8>, bool)> {
+        if index >= self.leaf_hashes.len() {
+            return Vec::new();
+        }
+
+        let inner = self.inner();
+        #[cfg(feature = "std")]
+        let _span = profile::span(Stage::Proof);
+        let mut proof = Vec::with_capacity(inner.levels.len());
+        let mut index = index;
+        // The top level is the root, which has no sibling
+        for depth in 0..inner.levels.len() {
+            let width = match depth {
+                0 => self.leaf_hashes.len(),
+                _ => inner.level(depth).len(),
+            };
+            let sibling = match (index ^ 1 < width, self.odd_node) {
+                (true, _) => Some(self.node_ref(depth, index ^ 1).to_vec()),
+                (false, OddNode::Duplicate) => Some(self.node_ref(depth, index).to_vec()),
+                // Carried up unchanged, with nothing to hash it with
+                (false, OddNode::Promote) => None,
+                (false, OddNode::PadWithZero) => Some(vec![0; <D as Digest>::output_size()]),
+            };
+            if let Some(sibling) = sibling {
+                proof.push((sibling, index % 2 == 1));
+            }
+            index /= 2;
+        }
+
+        proof
+    }
+
+    /// Like `get_proof_for`, but returns `MerkleError::IndexOutOfBounds` for a leaf the tree
+    /// does not have.
+    pub fn try_get_proof_for(&self, index: usize) -> Result<Vec<(Vec<u8>, bool)>, MerkleError> {
+        if index >= self.leaf_hashes.len() {
+            return Err(MerkleError::IndexOutOfBounds {
+                index,
+                leaf_count: self.leaf_hashes.len(),
+            });
+        }
+        Ok(self.get_proof_for(index))
+    }
+
+    /// The proof for leaf `index` as a `MerkleProof`, or `None` if there is no such leaf.
+    /// A `MerkleProof` takes its shape from the leaf's index alone, which does not say where
+    /// a node was promoted, so trees built with `OddNode::Promote` give `None` as well; use
+    /// `get_proof_for` for those.
+    pub fn proof(&self, index: usize) -> Option<MerkleProof> {
+        if index >= self.leaf_hashes.len() || self.odd_node == OddNode::Promote {
+            return None;
+        }
+        Some(MerkleProof {
+            leaf_index: index as u64,
+            tree_size: self.leaf_hashes.len() as u64,
+            siblings: self
+                .get_proof_for(index)
+                .into_iter()
+                .map(|(sibling, _)| sibling)
+                .collect(),
+        })
+    }
+
+    /// One proof for the leaves at `indices`, in the order given, or `None` if there are
+    /// none or one is not in the tree. Each sibling is included once, and none that is itself
+    /// on the path of one of the leaves, so the proof is never longer than the separate
+    /// proofs together and much shorter for leaves close to each other. Its verifier pairs
+    /// the last node of a level of odd width with itself, so trees built with another
+    /// `OddNode` give `None`.
+    pub fn get_proof_for_indices(&self, indices: &[usize]) -> Option<MultiProof> {
+        let size = self.leaf_hashes.len();
+        if indices.is_empty()
+            || indices.iter().any(|&index| index >= size)
+            || self.odd_node != OddNode::Duplicate
+        {
+            return None;
+        }
+
+        #[cfg(feature = "std")]
+        let _span = profile::span(Stage::Proof);
+        let mut known = indices.to_vec();
+        known.sort_unstable();
+        known.dedup();
+        let mut siblings = Vec::new();
+        let mut width = size;
+        let mut depth = 0;
+        while width > 1 {
+            let mut parents = Vec::with_capacity(known.len());
+            let mut nodes = known.iter().peekable();
+            while let Some(&index) = nodes.next() {
+                // Known siblings, and the last node of an odd level paired with itself, are
+                // left for the verifier to compute
+                let sibling = (index ^ 1).min(width - 1);
+                if nodes.peek() == Some(&&sibling) {
+                    nodes.next();
+                } else if sibling != index {
+                    siblings.push(self.node(depth, sibling));
+                }
+                parents.push(index / 2);
+            }
+            known = parents;
+            width = width.div_ceil(2);
+            depth += 1;
+        }
+
+        Some(MultiProof {
+            leaf_indices: indices.iter().map(|&index| index as u64).collect(),
+            tree_size: size as u64,
+            siblings,
+        })
+    }
+
+    /// One proof for the leaves in `range`, or `None` if it is empty or runs past the last
+    /// leaf. Only the siblings along the two edges of the range are needed, at most two per
+    /// level however many leaves it covers.
+    pub fn get_range_proof(&self, range: Range<usize>) -> Option<RangeProof> {
+        if range.is_empty() {
+            return None;
+        }
+        let indices: Vec<usize> = range.clone().collect();
+        let proof = self.get_proof_for_indices(&indices)?;
+        Some(RangeProof {
+            start: range.start as u64,
+            end: range.end as u64,
+            tree_size: proof.tree_size,
+            siblings: proof.siblings,
+        })
+    }
+
+    /// Proof that this tree begins with the first `old_size` of its leaves, so its root
+    /// extends the root the tree had when it had only those. `None` unless `old_size` is
+    /// between 1 and the number of leaves, and the tree pairs the last node of a level of
+    /// odd width with itself, as the verifier does.
+    pub fn consistency_proof(&self, old_size: usize) -> Option<ConsistencyProof> {
+        let size = self.leaf_hashes.len();
+        if old_size == 0 || old_size > size || self.odd_node != OddNode::Duplicate {
+            return None;
+        }
+
+        #[cfg(feature = "std")]
+        let _span = profile::span(Stage::Proof);
+        let mut hashes = Vec::new();
+        if old_size < size {
+            let nodes = consistency::Nodes::<Vec<u8>>::new(size as u64, old_size as u64, &[], &[]);
+            let (frontier, right) = nodes.positions();
+            // The old root is the one subtree of a tree whose size is a power of two
+            let skip = usize::from(old_size.is_power_of_two());
+            hashes = frontier
+                .into_iter()
+                .skip(skip)
+                .chain(right)
+                .map(|(level, index)| self.node(level, index as usize))
+                .collect();
+        }
+        Some(ConsistencyProof {
+            old_size: old_size as u64,
+            new_size: size as u64,
+            hashes,
+        })
+    }
+
+    /// Indices of the leaves that differ between this tree and `other`, in order: those
+    /// whose hashes differ, and those only one of the trees has. The trees are walked from
+    /// the root down, and a subtree whose node is the same in both is not entered, so trees
+    /// that differ in a few leaves are compared in O(k log n) for k differing leaves.
+    pub fn diff(&self, other: &MerkleTree<D>) -> Vec<usize> {
+        let common = self.leaf_hashes.len().min(other.leaf_hashes.len());
+        let size = self.leaf_hashes.len().max(other.leaf_hashes.len());
+        let top = self.inner().levels.len().max(other.inner().levels.len());
+        let mut differing = Vec::new();
+        // Nodes as (depth, index), the node covering leaves index * 2^depth on
+        let mut pending = vec![(top, 0)];
+        while let Some((depth, index)) = pending.pop() {
+            let first = index << depth;
+            let end = ((index + 1) << depth).min(size);
+            if first >= common {
+                // Leaves only the larger tree has
+                differing.extend(first..end);
+            } else if depth == 0 {
+                if self.leaf_hashes[index] != other.leaf_hashes[index] {
+                    differing.push(index);
+                }
+            } else if end > common || self.node_ref(depth, index) != other.node_ref(depth, index) {
+                // A node over leaves past the end of either tree hashes differently in each
+                pending.push((depth - 1, 2 * index + 1));
+                pending.push((depth - 1, 2 * index));
+            }
+        }
+        differing
+    }
+
+    /// Like `node`, without copying it. Depth 0 is the leaves.
+    fn node_ref(&self, depth: usize, index: usize) -> &[u8] {
+        match depth {
+            0 => &self.leaf_hashes[index],
+            _ => &self.inner().level(depth)[index],
+        }
+    }
+
+    /// Node `index` of level `depth`, with the leaves at depth 0.
+    fn node(&self, depth: usize, index: usize) -> Vec<u8> {
+        self.node_ref(depth, index).to_vec()
+    }
+
+    /// Hashes of the leaves, in leaf order.
+    pub fn leaf_hashes(&self) -> &[Vec<u8>] {
+        &self.leaf_hashes
+    }
+
+    /// Number of leaves.
+    pub fn leaf_count(&self) -> usize {
+        self.leaf_hashes.len()
+    }
+
+    /// Levels above the leaves: the length of a proof for a leaf of a full tree, and 0 for a
+    /// tree of one leaf. The same for every `OddNode`, as each halves a level rounding up.
+    pub fn depth(&self) -> usize {
+        proof::depth(self.leaf_hashes.len() as u64) as usize
+    }
+
+    /// The hash of leaf `index`, or `None` if there is no such leaf.
+    pub fn leaf_hash(&self, index: usize) -> Option<&[u8]> {
+        self.leaf_hashes.get(index).map(Vec::as_slice)
+    }
+
+    /// Hashes of the leaves, in leaf order.
+    pub fn leaves(&self) -> impl ExactSizeIterator<Item = &[u8]> + '_ {
+        self.leaf_hashes.iter().map(Vec::as_slice)
+    }
+
+    /// Every level from the leaves up to the root, each as its nodes in order, building the
+    /// tree first if it is not built yet. There are `depth() + 1` of them, the last one the
+    /// root alone.
+    pub fn levels(&self) -> impl Iterator<Item = Vec<&[u8]>> + '_ {
+        let above = self.inner().levels.iter();
+        core::iter::once(self.leaves().collect())
+            .chain(above.map(|level| level.iter().map(Node::<D>::as_slice).collect()))
+    }
+
+    /// Recomputes the tree from its leaves and lists every broken invariant, for tests and
+    /// self-checks. Far too slow to call on every operation.
+    pub fn debug_validate(&self) -> Result<(), Vec<String>> {
+        let mut violations = Vec::new();
+        if self.leaf_hashes.is_empty() {
+            violations.push("tree has no leaves".to_string());
+            return Err(violations);
+        }
+        for (index, hash) in self.leaf_hashes.iter().enumerate() {
+            if hash.len() != <D as Digest>::output_size() {
+                violations.push(format!("leaf {} hash is {} bytes", index, hash.len()));
+            }
+        }
+
+        // Each level holds half the nodes of the one it was built from, rounded up
+        let inner = self.inner();
+        let mut previous = self.leaf_hashes.len();
+        for depth in 1..=inner.levels.len() {
+            let (len, expected_len) = (inner.level(depth).len(), previous.div_ceil(2));
+            if len != expected_len {
+                violations.push(format!(
+                    "level {} has {} nodes, expected {}",
+                    depth, len, expected_len
+                ));
+            }
+            previous = len;
+        }
+        if previous != 1 {
+            violations.push(format!("top level has {} nodes", previous));
+        }
+        let root = Self::build_lazy(self.leaf_hashes.to_vec())
+            .with_encoding(self.encoding)
+            .with_odd_node(self.odd_node)
+            .get_root_hash();
+        if root != self.get_root_hash() {
+            violations.push(format!(
+                "root {} does not match the root {} recomputed from the leaves",
+                hex::encode(self.get_root_hash()),
+                hex::encode(&root)
+            ));
+        }
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+
+    /// `verify_proof` for trees over any hash function. Proofs of trees of another
+    /// encoding are checked with `LeafEncoding::check_proof`.
+    pub fn check_proof(proof: &[(Vec<u8>, bool)], root: &[u8], leaf: &[u8]) -> bool {
+        LeafEncoding::Plain.check_proof::<D>(proof, root, leaf)
+    }
+
+    /// `verify_proof_for_leaf_hash` for trees over any hash function.
+    pub fn check_proof_for_leaf_hash(
+        proof: &[(Vec<u8>, bool)],
+        root: &[u8],
+        leaf_hash: &[u8],
+    ) -> bool {
+        LeafEncoding::Plain.check_proof_for_leaf_hash::<D>(proof, root, leaf_hash)
+    }
+}
+
+impl<D: TreeDigest> InnerNodes<D> {
+    /// The nodes of level `depth` above the leaves, starting at 1.
+    fn level(&self, depth: usize) -> &[Node<D>] {
+        &self.levels[depth - 1]
+    }
+
+    /// Rehashes the nodes above leaf `index` of `leaves`, whose hash changed.
+    fn update(
+        &mut self,
+        leaves: &[Vec<u8>],
+        index: usize,
+        encoding: LeafEncoding,
+        odd_node: OddNode,
+    ) {
+        let mut index = index;
+        for depth in 0..self.levels.len() {
+            let left = index & !1;
+            let node = match depth {
+                0 => parent::<D>(leaves, left, encoding, odd_node),
+                _ => parent::<D>(&self.levels[depth - 1], left, encoding, odd_node),
+            };
+            index /= 2;
+            self.levels[depth][index] = node;
+        }
+    }
+
+    /// Rehashes the nodes over `leaves` from leaf `first` on, after those leaves changed or
+    /// moved, and drops the levels and nodes that `leaves` no longer fill.
+    fn rehash_from(
+        &mut self,
+        leaves: &[Vec<u8>],
+        first: usize,
+        encoding: LeafEncoding,
+        odd_node: OddNode,
+    ) {
+        let mut width = leaves.len();
+        let mut first = first;
+        let mut depth = 0;
+        while width > 1 {
+            let parent_width = width.div_ceil(2);
+            first /= 2;
+            let mut level = core::mem::take(&mut self.levels[depth]);
+            level.truncate(first);
+            for index in first..parent_width {
+                level.push(match depth {
+                    0 => parent::<D>(leaves, 2 * index, encoding, odd_node),
+                    _ => parent::<D>(&self.levels[depth - 1], 2 * index, encoding, odd_node),
+                });
+            }
+            self.levels[depth] = level;
+            width = parent_width;
+            depth += 1;
+        }
+        self.levels.truncate(depth);
+    }
+
+    /// Updates the nodes for `leaves`, the leaves they were built over and one more. Only
+    /// the last node of each level is above the new leaf, so only those are rehashed, and a
+    /// level is added once the top one has two nodes.
+    fn append(&mut self, leaves: &[Vec<u8>], encoding: LeafEncoding, odd_node: OddNode) {
+        let mut width = leaves.len();
+        let mut depth = 0;
+        while width > 1 {
+            // The last node's children are the last two of the level below, or only the
+            // last one if that level is of odd width
+            let index = (width - 1) / 2;
+            let node = match depth {
+                0 => parent::<D>(leaves, 2 * index, encoding, odd_node),
+                _ => parent::<D>(&self.levels[depth - 1], 2 * index, encoding, odd_node),
+            };
+            if depth == self.levels.len() {
+                self.levels.push(Vec::new());
+            }
+            let level = &mut self.levels[depth];
+            if index == level.len() {
+                level.push(node);
+            } else {
+                level[index] = node;
+            }
+            width = width.div_ceil(2);
+            depth += 1;
+        }
+    }
+}
+
+/// The parent of node `left` of `below`, a whole level, and of the node after it. The last
+/// node of a level of odd width has no node after it, and is completed as `odd_node` has it.
+fn parent<D: TreeDigest>(
+    below: &[impl AsRef<[u8]>],
+    left: usize,
+    encoding: LeafEncoding,
+    odd_node: OddNode,
+) -> Node<D> {
+    let node = below[left].as_ref();
+    match (below.get(left + 1), odd_node) {
+        (Some(right), _) => encoding.node_hash::<D>(node, right.as_ref()),
+        (None, OddNode::Duplicate) => encoding.node_hash::<D>(node, node),
+        (None, OddNode::Promote) => Node::<D>::clone_from_slice(node),
+        (None, OddNode::PadWithZero) => encoding.node_hash::<D>(node, &Node::<D>::default()),
+    }
+}
+
+/// A plain SHA-256 node, for the structures built on SHA-256 trees alone.
+pub(crate) fn hash_pair(left: &[u8], right: &[u8]) -> [u8; 32] {
+    LeafEncoding::Plain.node_hash::<Sha256>(left, right).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sha2::Sha256;
+
+    #[test]
+    fn test_merkle_tree_single_node() {
+        let data = vec![vec![1, 2, 3, 4]];
+        let merkle_tree = MerkleTree::new(data.clone());
+        let root_hash = Sha256::digest(&data[0]).to_vec();
+        assert_eq!(merkle_tree.get_root_hash(), root_hash);
+        assert_eq!(merkle_tree.root().to_vec(), root_hash);
+    }
+
+    #[test]
+    fn test_fallible_constructors_and_proofs() {
+        assert!(matches!(
+            MerkleTree::try_new(Vec::new()),
+            Err(MerkleError::EmptyInput)
+        ));
+        assert!(matches!(
+            MerkleTree::try_from_leaf_hashes(Vec::new()),
+            Err(MerkleError::EmptyInput)
+        ));
+        let hashes = vec![vec![0; 32], vec![1; 31]];
+        assert!(matches!(
+            MerkleTree::try_from_leaf_hashes(hashes),
+            Err(MerkleError::LeafHashLength {
+                index: 1,
+                len: 31,
+                expected: 32
+            })
+        ));
+        assert!(MerkleTree::<sha2::Sha512>::try_build_from_leaf_hashes(vec![vec![0; 64]]).is_ok());
+
+        // The one leaf of a tree of one has an empty proof; a missing leaf has none at all
+        let single = MerkleTree::try_new(vec![vec![1]]).unwrap();
+        assert_eq!(single.try_get_proof_for(0).unwrap(), Vec::new());
+        let err = single.try_get_proof_for(1).unwrap_err();
+        assert!(matches!(
+            err,
+            MerkleError::IndexOutOfBounds {
+                index: 1,
+                leaf_count: 1
+            }
+        ));
+        assert_eq!(
+            err.to_string(),
+            "leaf 1 is out of bounds for a tree of 1 leaves"
+        );
+
+        let data: Vec<Vec<u8>> = (0..5u8).map(|i| vec![i]).collect();
+        let tree = MerkleTree::try_new(data.clone()).unwrap();
+        assert_eq!(tree.get_root_hash(), MerkleTree::new(data).get_root_hash());
+        assert_eq!(tree.try_get_proof_for(4).unwrap(), tree.get_proof_for(4));
+        assert!(tree.snapshot().try_get_proof_for(5).is_err());
+    }
+
+    #[test]
+    fn test_merkle_tree_multiple_nodes() {
+        let data = vec![vec![1, 2, 3, 4], vec![5, 6, 7, 8]];
+        let merkle_tree = MerkleTree::new(data);
+
+        let leaf1_hash = Sha256::digest([1, 2, 3, 4]).to_vec();
+        let leaf2_hash = Sha256::digest([5, 6, 7, 8]).to_vec();
+        let mut hasher = Sha256::new();
+        hasher.update(leaf1_hash);
+        hasher.update(leaf2_hash);
+        let root_hash = hasher.finalize().to_vec();
+
+        assert_eq!(merkle_tree.get_root_hash(), root_hash);
+        assert_eq!(merkle_tree.root().to_vec(), root_hash);
+        assert_eq!(merkle_tree.snapshot().root(), merkle_tree.root());
+    }
+
+    #[test]
+    fn test_from_leaf_hashes_matches_new() {
+        let data = vec![vec![1], vec![2], vec![3]];
+        let leaf_hashes = data
+            .iter()
+            .map(|leaf| Sha256::digest(leaf).to_vec())
+            .collect();
+        assert_eq!(
+            MerkleTree::from_leaf_hashes(leaf_hashes).get_root_hash(),
+            MerkleTree::new(data).get_root_hash()
+        );
+    }
+
+    #[test]
+    fn test_borrowed_leaves_match_new() {
+        let data: Vec<Vec<u8>> = (0..21u8).map(|i| vec![i; i as usize]).collect();
+        let tree = MerkleTree::new(data.clone());
+        let borrowed: Vec<&[u8]> = data.iter().map(Vec::as_slice).collect();
+        assert_eq!(
+            MerkleTree::from_slices(&borrowed).get_root_hash(),
+            tree.get_root_hash()
+        );
+        assert_eq!(
+            MerkleTree::from_slices(&data).get_root_hash(),
+            tree.get_root_hash()
+        );
+        let options = TreeOptions {
+            hash_concurrency: 4,
+            ..TreeOptions::default()
+        };
+        let bounded = MerkleTree::<Sha256>::build_from_slices(&borrowed, options);
+        assert_eq!(bounded.leaf_hashes(), tree.leaf_hashes());
+    }
+
+    #[test]
+    fn test_bounded_hashing_matches_new() {
+        let data: Vec<Vec<u8>> = (0..37u8).map(|i| vec![i; i as usize * 3]).collect();
+        let tree = MerkleTree::new(data.clone());
+        for hash_concurrency in [1, 2, 5, 64] {
+            let options = TreeOptions {
+                hash_concurrency,
+                ..TreeOptions::default()
+            };
+            let bounded = MerkleTree::with_options(data.clone(), options);
+            assert_eq!(bounded.leaf_hashes(), tree.leaf_hashes());
+            assert_eq!(bounded.get_root_hash(), tree.get_root_hash());
+        }
+    }
+
+    #[test]
+    fn test_arena_matches_level_by_level_construction() {
+        for width in 1..=20u8 {
+            let data: Vec<Vec<u8>> = (0..width).map(|i| vec![i]).collect();
+            let mut level: Vec<Vec<u8>> = data
+                .iter()
+                .map(|leaf| Sha256::digest(leaf).to_vec())
+                .collect();
+            while level.len() > 1 {
+                level = level
+                    .chunks(2)
+                    .map(|pair| hash_pair(&pair[0], pair.last().unwrap()).to_vec())
+                    .collect();
+            }
+            let tree = MerkleTree::new(data.clone());
+            assert_eq!(tree.get_root_hash(), level[0], "width {}", width);
+            assert!(tree.debug_validate().is_ok());
+            for (index, leaf) in data.iter().enumerate() {
+                let proof = tree.get_proof_for(index);
+                assert!(MerkleTree::verify_proof(&proof, &level[0], leaf));
+            }
+        }
+    }
+
+    #[test]
+    fn test_lazy_tree_builds_once_on_demand() {
+        let data: Vec<Vec<u8>> = (0..100u8).map(|i| vec![i]).collect();
+        let mut tree = MerkleTree::lazy(vec![Sha256::digest(&data[0]).to_vec()]);
+        for leaf in &data[1..] {
+            tree.push_leaf_hash(Sha256::digest(leaf).to_vec());
+        }
+        assert!(tree.is_dirty());
+        let expected = MerkleTree::new(data.clone());
+        assert_eq!(tree.get_root_hash(), expected.get_root_hash());
+        assert!(!tree.is_dirty());
+        assert_eq!(tree.get_proof_for(42), expected.get_proof_for(42));
+
+        tree.push_leaf_hash(Sha256::digest([100]).to_vec());
+        assert!(tree.is_dirty());
+        let mut data = data;
+        data.push(vec![100]);
+        assert_eq!(tree.get_root_hash(), MerkleTree::new(data).get_root_hash());
+        assert!(tree.debug_validate().is_ok());
+    }
+
+    #[test]
+    fn test_appended_leaves_match_a_rebuilt_tree() {
+        let leaf = |i: u8| Sha256::digest([i]).to_vec();
+        let mut tree = MerkleTree::from_leaf_hashes(vec![leaf(0)]);
+        for width in 2..=40u8 {
+            let snapshot = tree.snapshot();
+            tree.append_leaf(leaf(width - 1));
+            assert!(!tree.is_dirty());
+            let rebuilt = MerkleTree::from_leaf_hashes((0..width).map(leaf).collect());
+            assert_eq!(
+                tree.get_root_hash(),
+                rebuilt.get_root_hash(),
+                "width {}",
+                width
+            );
+            assert!(tree.debug_validate().is_ok());
+            for index in 0..width as usize {
+                assert_eq!(tree.get_proof_for(index), rebuilt.get_proof_for(index));
+            }
+            // Snapshots sharing the nodes keep the tree they were taken of
+            assert_eq!(snapshot.leaf_hashes().len(), width as usize - 1);
+            assert!(snapshot.debug_validate().is_ok());
+        }
+
+        tree.extend_leaves((40..100).map(leaf));
+        let rebuilt = MerkleTree::from_leaf_hashes((0..100).map(leaf).collect());
+        assert_eq!(tree.get_root_hash(), rebuilt.get_root_hash());
+
+        let mut lazy = MerkleTree::lazy(vec![leaf(0)]);
+        lazy.extend_leaves((1..100).map(leaf));
+        assert!(lazy.is_dirty());
+        assert_eq!(lazy.get_root_hash(), rebuilt.get_root_hash());
+    }
+
+    #[test]
+    fn test_updated_leaves_match_a_rebuilt_tree() {
+        let leaf = |i: u8| Sha256::digest([i]).to_vec();
+        for width in 1..=20u8 {
+            let mut leaves: Vec<Vec<u8>> = (0..width).map(leaf).collect();
+            let mut tree = MerkleTree::from_leaf_hashes(leaves.clone());
+            for index in 0..width as usize {
+                let snapshot = tree.snapshot();
+                let old = tree.update_leaf(index, &[100 + index as u8]);
+                assert_eq!(old, Some(leaves[index].clone()));
+                leaves[index] = leaf(100 + index as u8);
+                assert!(!tree.is_dirty());
+                let rebuilt = MerkleTree::from_leaf_hashes(leaves.clone());
+                assert_eq!(tree.get_root_hash(), rebuilt.get_root_hash(), "{}", width);
+                assert!(tree.debug_validate().is_ok());
+                assert!(snapshot.debug_validate().is_ok());
+                assert_ne!(snapshot.get_root_hash(), tree.get_root_hash());
+            }
+            assert_eq!(tree.update_leaf_hash(width as usize, leaf(0)), None);
+        }
+
+        let mut lazy = MerkleTree::lazy((0..10).map(leaf).collect());
+        lazy.update_leaf_hash(3, leaf(30));
+        assert!(lazy.is_dirty());
+        let mut leaves: Vec<Vec<u8>> = (0..10).map(leaf).collect();
+        leaves[3] = leaf(30);
+        assert_eq!(
+            lazy.get_root_hash(),
+            MerkleTree::from_leaf_hashes(leaves).get_root_hash()
+        );
+    }
+
+    #[test]
+    fn test_removed_leaves_match_a_rebuilt_tree() {
+        let leaf = |i: u8| Sha256::digest([i]).to_vec();
+        for width in 2..=20u8 {
+            for index in 0..width as usize {
+                let mut leaves: Vec<Vec<u8>> = (0..width).map(leaf).collect();
+                let mut tree = MerkleTree::from_leaf_hashes(leaves.clone());
+                let snapshot = tree.snapshot();
+                assert_eq!(tree.remove_leaf(index), Some(leaves.remove(index)));
+                assert!(!tree.is_dirty());
+                let rebuilt = MerkleTree::from_leaf_hashes(leaves);
+                assert_eq!(
+                    tree.get_root_hash(),
+                    rebuilt.get_root_hash(),
+                    "width {} index {}",
+                    width,
+                    index
+                );
+                assert!(tree.debug_validate().is_ok());
+                assert_eq!(snapshot.leaf_hashes().len(), width as usize);
+                assert!(snapshot.debug_validate().is_ok());
+            }
+        }
+
+        let mut tree = MerkleTree::from_leaf_hashes(vec![leaf(0), leaf(1)]);
+        assert_eq!(tree.remove_leaf(2), None);
+        tree.remove_leaf(0);
+        assert_eq!(tree.remove_leaf(0), None);
+        assert_eq!(tree.get_root_hash(), leaf(1));
+
+        // Tombstones keep every other leaf where it was
+        let data: Vec<Vec<u8>> = (0..7u8).map(|i| vec![i]).collect();
+        let mut tree = MerkleTree::new(data.clone());
+        let proof = tree.get_proof_for(5);
+        assert_eq!(tree.tombstone_leaf(2), Some(leaf(2)));
+        assert!(tree.is_tombstone(2));
+        assert!(!tree.is_tombstone(5));
+        assert_eq!(tree.leaf_hashes().len(), 7);
+        let root = tree.get_root_hash();
+        assert_eq!(tree.get_proof_for(5).len(), proof.len());
+        assert!(MerkleTree::verify_proof(
+            &tree.get_proof_for(5),
+            &root,
+            &data[5]
+        ));
+        assert_eq!(tree.tombstone_leaf(7), None);
+    }
+
+    #[test]
+    fn test_diff_finds_the_differing_leaves() {
+        let leaf = |i: usize| Sha256::digest(i.to_be_bytes()).to_vec();
+        let tree = |leaves: &[Vec<u8>]| MerkleTree::from_leaf_hashes(leaves.to_vec());
+        let base: Vec<Vec<u8>> = (0..37).map(leaf).collect();
+        assert!(tree(&base).diff(&tree(&base)).is_empty());
+
+        let mut changed = base.clone();
+        for index in [0, 5, 6, 36] {
+            changed[index] = leaf(1000 + index);
+        }
+        assert_eq!(tree(&base).diff(&tree(&changed)), vec![0, 5, 6, 36]);
+        assert_eq!(tree(&changed).diff(&tree(&base)), vec![0, 5, 6, 36]);
+
+        // Leaves only one tree has differ too, whichever is larger
+        for size in [1, 2, 16, 17, 32, 36] {
+            let mut shorter = base[..size].to_vec();
+            shorter[0] = leaf(2000);
+            let expected: Vec<usize> = core::iter::once(0).chain(size..37).collect();
+            assert_eq!(tree(&base).diff(&tree(&shorter)), expected, "{}", size);
+            assert_eq!(tree(&shorter).diff(&tree(&base)), expected, "{}", size);
+        }
+    }
+
+    #[test]
+    fn test_trees_over_other_hashes() {
+        use sha2::Sha512;
+        use sha3::Sha3_256;
+
+        let data: Vec<Vec<u8>> = (0..11u8).map(|i| vec![i]).collect();
+        let mut tree = MerkleTree::<Sha512>::build(data.clone());
+        assert_eq!(tree.algorithm(), HashAlgorithm::Sha512);
+        let root = tree.get_root_hash();
+        assert_eq!(root.len(), 64);
+        assert!(tree.debug_validate().is_ok());
+        for (index, leaf) in data.iter().enumerate() {
+            let proof = tree.get_proof_for(index);
+            assert!(MerkleTree::<Sha512>::check_proof(&proof, &root, leaf));
+            assert!(HashAlgorithm::Sha512.verify_proof(&proof, &root, leaf));
+            assert!(!MerkleTree::verify_proof(&proof, &root, leaf));
+        }
+        tree.append_leaf(Sha512::digest([11]).to_vec());
+        let mut data = data;
+        data.push(vec![11]);
+        assert_eq!(
+            tree.get_root_hash(),
+            MerkleTree::<Sha512>::build(data.clone()).get_root_hash()
+        );
+
+        let sha3 = MerkleTree::<Sha3_256>::build(data.clone());
+        assert_eq!(sha3.get_root_hash().len(), 32);
+        assert_ne!(sha3.get_root_hash(), MerkleTree::new(data).get_root_hash());
+    }
+
+    #[test]
+    fn test_domain_separated_trees() {
+        let options = TreeOptions {
+            encoding: LeafEncoding::DomainSeparated,
+            ..TreeOptions::default()
+        };
+        let separated = LeafEncoding::DomainSeparated;
+
+        // Trees of a power of two leaves have the shape of RFC 6962's, and so its roots
+        let data: Vec<Vec<u8>> = (0..8u8).map(|i| vec![i * 0x10]).collect();
+        for size in [1, 2, 4, 8] {
+            let tree = MerkleTree::with_options(data[..size].to_vec(), options);
+            assert_eq!(tree.encoding(), separated);
+            assert_eq!(
+                tree.get_root_hash(),
+                TreePreset::RFC6962.root(&data[..size]),
+                "{}",
+                size
+            );
+        }
+
+        let data: Vec<Vec<u8>> = (0..11u8).map(|i| vec![i]).collect();
+        let mut tree = MerkleTree::with_options(data.clone(), options);
+        let root = tree.get_root_hash();
+        assert_ne!(root, MerkleTree::new(data.clone()).get_root_hash());
+        assert!(tree.debug_validate().is_ok());
+        for (index, leaf) in data.iter().enumerate() {
+            let proof = tree.proof(index).unwrap();
+            assert!(proof.verify_with(separated, &root, leaf));
+            assert!(!proof.verify(&root, leaf));
+            assert!(HashAlgorithm::Sha256.verify_proof_with(separated, &proof.path(), &root, leaf));
+        }
+        let proof = tree.get_proof_for_indices(&[9, 2]).unwrap();
+        assert!(proof.verify_with(separated, &root, &[&data[9], &data[2]]));
+        assert!(!proof.verify(&root, &[&data[9], &data[2]]));
+
+        let old_root = tree.get_root_hash();
+        tree.append_leaf(separated.leaf_hash::<Sha256>(&[11]));
+        let mut data = data;
+        data.push(vec![11]);
+        let rebuilt = MerkleTree::with_options(data.clone(), options);
+        assert_eq!(tree.get_root_hash(), rebuilt.get_root_hash());
+        let proof = tree.consistency_proof(11).unwrap();
+        assert!(proof.verify_with(separated, &old_root, &tree.get_root_hash()));
+
+        let imported = MerkleTree::import(&tree.export()).unwrap();
+        assert_eq!(imported.encoding(), separated);
+        assert_eq!(imported.get_root_hash(), tree.get_root_hash());
+        let exported = ExportedProof::import(&ExportedProof::from_tree(&tree, 3).unwrap().export());
+        assert!(exported.unwrap().verify(&data[3]));
+
+        // The children of a node do not pass for a leaf that hashes to it
+        let pair = MerkleTree::new(data[..2].to_vec());
+        let children = pair.leaf_hashes().concat();
+        assert!(MerkleTree::verify_proof(
+            &[],
+            &pair.get_root_hash(),
+            &children
+        ));
+        let pair = MerkleTree::with_options(data[..2].to_vec(), options);
+        let mut prefixed = vec![0x01];
+        prefixed.extend_from_slice(&pair.leaf_hashes().concat());
+        for forged in [pair.leaf_hashes().concat(), prefixed] {
+            assert!(!separated.check_proof::<Sha256>(&[], &pair.get_root_hash(), &forged));
+        }
+    }
+
+    #[test]
+    fn test_odd_node_strategies() {
+        let data: Vec<Vec<u8>> = (0..11u8).map(|i| vec![i]).collect();
+        let zero_padded = TreePreset {
+            odd_node: OddNode::PadWithZero,
+            ..TreePreset::MERKLEFILE
+        };
+        for (odd_node, preset) in [
+            (OddNode::Duplicate, TreePreset::MERKLEFILE),
+            (OddNode::Promote, TreePreset::RS_MERKLE),
+            (OddNode::PadWithZero, zero_padded),
+        ] {
+            let options = TreeOptions {
+                odd_node,
+                ..TreeOptions::default()
+            };
+            let mut tree = MerkleTree::with_options(data.clone(), options);
+            assert_eq!(tree.odd_node(), odd_node);
+            let root = tree.get_root_hash();
+            assert_eq!(root, preset.root(&data), "{:?}", odd_node);
+            assert!(tree.debug_validate().is_ok());
+            for (index, leaf) in data.iter().enumerate() {
+                let proof = tree.get_proof_for(index);
+                assert!(MerkleTree::verify_proof(&proof, &root, leaf));
+                assert!(!MerkleTree::verify_proof(&proof, &root, b"other"));
+            }
+            assert_eq!(tree.proof(3).is_some(), odd_node != OddNode::Promote);
+
+            // A repeated last leaf changes the root unless it is duplicated
+            let mut repeated = data.clone();
+            repeated.push(data[10].clone());
+            let repeated = MerkleTree::with_options(repeated, options);
+            assert_eq!(
+                repeated.get_root_hash() == root,
+                odd_node == OddNode::Duplicate
+            );
+
+            // The strategy is kept as the tree changes, and through storage
+            tree.append_leaf(Sha256::digest([11]).to_vec());
+            tree.update_leaf(4, b"four");
+            tree.remove_leaf(0);
+            let mut changed = data.clone();
+            changed.push(vec![11]);
+            changed[4] = b"four".to_vec();
+            changed.remove(0);
+            let rebuilt = MerkleTree::with_options(changed, options);
+            assert_eq!(tree.get_root_hash(), rebuilt.get_root_hash());
+            assert!(tree.debug_validate().is_ok());
+            let imported = MerkleTree::import(&tree.export()).unwrap();
+            assert_eq!(imported.odd_node(), odd_node);
+            assert_eq!(imported.get_root_hash(), tree.get_root_hash());
+            let json = serde_json::to_string(&tree).unwrap();
+            let restored: MerkleTree = serde_json::from_str(&json).unwrap();
+            assert_eq!(restored.odd_node(), odd_node);
+            assert_eq!(
+                ExportedProof::from_tree(&tree, 0).is_some(),
+                odd_node == OddNode::Duplicate
+            );
+        }
+    }
+
+    #[test]
+    fn test_sorted_pair_trees_verify_like_openzeppelin() {
+        use sha3::Keccak256;
+
+        // OpenZeppelin's MerkleProof.processProof, with the siblings alone
+        fn process_proof(siblings: &[Vec<u8>], leaf: &[u8]) -> Vec<u8> {
+            siblings.iter().fold(leaf.to_vec(), |node, sibling| {
+                let (a, b) = (node.as_slice().min(sibling), node.as_slice().max(sibling));
+                Keccak256::digest([a, b].concat()).to_vec()
+            })
+        }
+
+        assert_eq!(
+            hex::encode(Keccak256::digest(b"")),
+            "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470"
+        );
+        let sorted = LeafEncoding::SortedPairs;
+        let data: Vec<Vec<u8>> = (0..13u8).map(|i| vec![i; 3]).collect();
+        for odd_node in [OddNode::Duplicate, OddNode::Promote] {
+            let options = TreeOptions {
+                encoding: sorted,
+                odd_node,
+                ..TreeOptions::default()
+            };
+            let tree = MerkleTree::<Keccak256>::build_with_options(data.clone(), options);
+            assert_eq!(tree.algorithm(), HashAlgorithm::Keccak256);
+            let root = tree.get_root_hash();
+            assert!(tree.debug_validate().is_ok());
+            for (index, leaf) in data.iter().enumerate() {
+                let leaf_hash = Keccak256::digest(Keccak256::digest(leaf)).to_vec();
+                assert_eq!(tree.leaf_hashes()[index], leaf_hash);
+                let proof = tree.get_proof_for(index);
+                let siblings: Vec<Vec<u8>> = proof.iter().map(|(hash, _)| hash.clone()).collect();
+                assert_eq!(process_proof(&siblings, &leaf_hash), root);
+                assert!(HashAlgorithm::Keccak256.verify_proof_with(sorted, &proof, &root, leaf));
+                assert!(!HashAlgorithm::Keccak256.verify_proof(&proof, &root, leaf));
+            }
+
+            let imported = MerkleTree::<Keccak256>::load(&tree.export()).unwrap();
+            assert_eq!(imported.encoding(), sorted);
+            assert_eq!(imported.get_root_hash(), root);
+        }
+
+        let tree = MerkleTree::<Keccak256>::build_with_options(
+            data.clone(),
+            TreeOptions {
+                encoding: sorted,
+                ..TreeOptions::default()
+            },
+        );
+        let exported =
+            ExportedProof::import(&ExportedProof::from_tree(&tree, 12).unwrap().export());
+        assert!(exported.unwrap().verify(&data[12]));
+    }
+
+    #[test]
+    fn test_trees_can_be_inspected() {
+        let data: Vec<Vec<u8>> = (0..5u8).map(|i| vec![i]).collect();
+        let tree = MerkleTree::lazy(
+            data.iter()
+                .map(|leaf| Sha256::digest(leaf).to_vec())
+                .collect(),
+        );
+        assert_eq!(tree.leaf_count(), 5);
+        assert_eq!(tree.depth(), 3);
+        assert_eq!(tree.leaf_hash(4), Some(Sha256::digest([4]).as_slice()));
+        assert_eq!(tree.leaf_hash(5), None);
+        assert!(tree
+            .leaves()
+            .eq(tree.leaf_hashes().iter().map(Vec::as_slice)));
+
+        let levels: Vec<Vec<&[u8]>> = tree.levels().collect();
+        assert_eq!(levels.len(), tree.depth() + 1);
+        assert_eq!(
+            levels.iter().map(Vec::len).collect::<Vec<_>>(),
+            [5, 3, 2, 1]
+        );
+        let root = tree.get_root_hash();
+        assert_eq!(levels[3], [root.as_slice()]);
+        assert_eq!(
+            levels[1][2],
+            hash_pair(levels[0][4], levels[0][4]).as_slice()
+        );
+        for (index, _) in data.iter().enumerate() {
+            assert_eq!(tree.get_proof_for(index).len(), tree.depth());
+        }
+
+        let single = MerkleTree::new(data[..1].to_vec());
+        assert_eq!(single.depth(), 0);
+        assert_eq!(single.levels().count(), 1);
+        let snapshot = tree.snapshot();
+        assert_eq!(snapshot.leaf_count(), 5);
+        assert_eq!(snapshot.levels().last().unwrap(), [root.as_slice()]);
+    }
+
+    #[test]
+    fn test_keyed_trees() {
+        let key = LeafKey::from_bytes([7; 32]);
+        let options = TreeOptions {
+            leaf_key: Some(key),
+            // More than a multi-buffer batch, which only unkeyed leaves take
+            hash_concurrency: 2,
+            ..TreeOptions::default()
+        };
+        let data: Vec<Vec<u8>> = (0..20u8).map(|i| vec![i]).collect();
+        let mut tree = MerkleTree::with_options(data.clone(), options);
+        assert!(tree.is_keyed());
+        let root = tree.get_root_hash();
+        assert_ne!(root, MerkleTree::new(data.clone()).get_root_hash());
+
+        // Proofs verify by the keyed leaf hash, and not by the contents alone
+        for (index, leaf) in data.iter().enumerate() {
+            let proof = tree.get_proof_for(index);
+            let leaf_hash = LeafEncoding::Plain.keyed_leaf_hash::<Sha256>(&key, leaf);
+            assert!(MerkleTree::verify_proof_for_leaf_hash(
+                &proof, &root, &leaf_hash
+            ));
+            assert!(!MerkleTree::verify_proof(&proof, &root, leaf));
+        }
+
+        // Updated leaves are hashed with the key too, also once it is given back to a tree
+        // built from leaf hashes
+        tree.update_leaf(3, b"new");
+        let mut data = data;
+        data[3] = b"new".to_vec();
+        let rebuilt = MerkleTree::with_options(data, options);
+        assert_eq!(tree.get_root_hash(), rebuilt.get_root_hash());
+        let mut restored =
+            MerkleTree::from_leaf_hashes(tree.leaf_hashes().to_vec()).with_leaf_key(key);
+        restored.update_leaf(4, b"newer");
+        tree.update_leaf(4, b"newer");
+        assert_eq!(restored.get_root_hash(), tree.get_root_hash());
+    }
+
+    #[cfg(feature = "blake3")]
+    #[test]
+    fn test_blake3_trees() {
+        let data: Vec<Vec<u8>> = vec![vec![1; 5000], vec![2], vec![3]];
+        let tree = MerkleTree::<blake3::Hasher>::build(data.clone());
+        let leaves: Vec<blake3::Hash> = data.iter().map(|leaf| blake3::hash(leaf)).collect();
+        let node = |left: &blake3::Hash, right: &blake3::Hash| {
+            blake3::hash(&[*left.as_bytes(), *right.as_bytes()].concat())
+        };
+        let root = node(&node(&leaves[0], &leaves[1]), &node(&leaves[2], &leaves[2]));
+        assert_eq!(tree.get_root_hash(), root.as_bytes());
+        assert_eq!(tree.algorithm(), HashAlgorithm::Blake3);
+
+        let proof = ExportedProof::from_tree(&tree, 2).unwrap();
+        let imported = ExportedProof::import(&proof.export()).unwrap();
+        assert!(imported.verify(&data[2]));
+        let loaded = MerkleTree::<blake3::Hasher>::load(&tree.export()).unwrap();
+        assert_eq!(loaded.get_root_hash(), tree.get_root_hash());
+    }
+
+    #[test]
+    fn test_snapshots_keep_their_version() {
+        let data: Vec<Vec<u8>> = (0..10u8).map(|i| vec![i]).collect();
+        let mut tree = MerkleTree::new(data.clone());
+        let snapshot = tree.snapshot();
+        let root = snapshot.get_root_hash();
+
+        tree.push_leaf_hash(Sha256::digest([10]).to_vec());
+        assert_ne!(tree.get_root_hash(), root);
+        assert_eq!(snapshot.get_root_hash(), root);
+        assert_eq!(snapshot.leaf_hashes().len(), 10);
+        for (index, leaf) in data.iter().enumerate() {
+            let proof = snapshot.get_proof_for(index);
+            assert!(MerkleTree::verify_proof(&proof, &root, leaf));
+        }
+
+        // A snapshot of a dirty tree builds the nodes once, for the tree too
+        tree.push_leaf_hash(Sha256::digest([11]).to_vec());
+        let dirty = tree.snapshot();
+        assert!(tree.is_dirty());
+        dirty.get_root_hash();
+        assert!(!tree.is_dirty());
+    }
+
+    #[test]
+    fn test_proof_generation_and_verification() {
+        let data = vec![
+            vec![1, 2, 3, 4],
+            vec![5, 6, 7, 8],
+            vec![9, 10, 11, 12],
+            vec![13, 14, 15, 16],
+            vec![17, 18, 19, 20],
+        ];
+        let merkle_tree = MerkleTree::new(data.clone());
+        let root_hash = merkle_tree.get_root_hash();
+
+        for (i, leaf_data) in data.iter().enumerate() {
+            let proof = merkle_tree.get_proof_for(i);
+            let verification_result = MerkleTree::verify_proof(&proof, &root_hash, leaf_data);
+            assert!(
+                verification_result,
+                "Proof verification failed for leaf at index {}",
+                i
+            );
+        }
+    }
+
+    #[test]
+    fn test_invalid_proof_verification() {
+        let data = vec![vec![1], vec![2], vec![3], vec![4]];
+        let tree = MerkleTree::new(data.clone());
+
+        // Test with an invalid index
+        let invalid_index = 10;
+        let proof = tree.get_proof_for(invalid_index);
+        assert!(proof.is_empty(), "Proof should be empty for invalid index");
+
+        // Test with a valid index but modified proof
+        let index = 2;
+        let mut proof = tree.get_proof_for(index);
+        proof[0].0[0] ^= 1; // Modify the proof slightly
+        let root_hash = tree.get_root_hash();
+        let leaf_hash = Sha256::digest(&data[index]);
+        assert!(
+            !MerkleTree::verify_proof(&proof, &root_hash, &leaf_hash),
+            "Proof verification should fail for modified proof"
+        );
+    }
+
+    #[test]
+    fn test_debug_validate_detects_tampering() {
+        let tree = MerkleTree::new(vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()]);
+        assert!(tree.debug_validate().is_ok());
+
+        let mut tampered = tree.clone();
+        Arc::make_mut(&mut tampered.leaf_hashes)[1] = Sha256::digest(b"x").to_vec();
+        let violations = tampered.debug_validate().unwrap_err();
+        assert_eq!(violations.len(), 1);
+        assert!(violations[0].starts_with("root"));
+
+        let mut truncated = tree;
+        Arc::make_mut(&mut truncated.leaf_hashes)[0].truncate(4);
+        assert_eq!(truncated.debug_validate().unwrap_err().len(), 2);
+
+        let mut short = MerkleTree::new((0u8..5).map(|i| vec![i]).collect());
+        Arc::make_mut(&mut short.inner).get_mut().unwrap().levels[0].pop();
+        let violations = short.debug_validate().unwrap_err();
+        assert_eq!(violations[0], "level 1 has 2 nodes, expected 3");
+    }
+}
@@ -0,0 +1,261 @@
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use super::LeafEncoding;
+
+/// A proof that several leaves are in a tree at once. Paths of leaves close to each other
+/// meet below the root, and a node on one leaf's path is not needed as a sibling for
+/// another, so the proof holds each sibling hash once, and none that the leaves themselves
+/// determine.
+///
+/// `siblings` are in the order the verifier needs them: level by level from the leaves up,
+/// and from left to right within a level. Leaves are given in the order of `leaf_indices`,
+/// which is the order they were asked for in and may repeat an index.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MultiProof {
+    pub leaf_indices: Vec<u64>,
+    pub tree_size: u64,
+    pub siblings: Vec<Vec<u8>>,
+}
+
+impl MultiProof {
+    /// Whether each of `leaves` is at the matching entry of `leaf_indices` in a SHA-256 tree
+    /// of `tree_size` leaves whose root is `root`.
+    pub fn verify<L: AsRef<[u8]>>(&self, root: &[u8], leaves: &[L]) -> bool {
+        self.verify_with(LeafEncoding::Plain, root, leaves)
+    }
+
+    /// Like `verify`, for a tree built with `encoding`.
+    pub fn verify_with<L: AsRef<[u8]>>(
+        &self,
+        encoding: LeafEncoding,
+        root: &[u8],
+        leaves: &[L],
+    ) -> bool {
+        let leaf_hashes: Vec<Vec<u8>> = leaves
+            .iter()
+            .map(|leaf| encoding.leaf_hash::<Sha256>(leaf.as_ref()))
+            .collect();
+        self.root(&leaf_hashes, encoding)
+            .is_some_and(|computed| computed == root)
+    }
+
+    /// Like `verify`, for leaves whose hashes are already known.
+    pub fn verify_leaf_hashes(&self, root: &[u8], leaf_hashes: &[Vec<u8>]) -> bool {
+        self.root(leaf_hashes, LeafEncoding::Plain)
+            .is_some_and(|computed| computed == root)
+    }
+
+    /// The root the leaves and the siblings hash up to, or `None` if the proof does not fit
+    /// a tree of `tree_size` leaves: a leaf outside it, an index given two different leaves,
+    /// or too few or too many siblings.
+    fn root(&self, leaf_hashes: &[Vec<u8>], encoding: LeafEncoding) -> Option<Vec<u8>> {
+        let hash_pair = |left: &[u8], right: &[u8]| encoding.node_hash::<Sha256>(left, right);
+        if leaf_hashes.len() != self.leaf_indices.len() || leaf_hashes.is_empty() {
+            return None;
+        }
+        let mut by_index = BTreeMap::new();
+        for (&index, hash) in self.leaf_indices.iter().zip(leaf_hashes) {
+            if index >= self.tree_size {
+                return None;
+            }
+            if by_index
+                .insert(index, hash.clone())
+                .is_some_and(|other| &other != hash)
+            {
+                return None;
+            }
+        }
+
+        let mut known: Vec<(u64, Vec<u8>)> = by_index.into_iter().collect();
+        let mut siblings = self.siblings.iter();
+        let mut width = self.tree_size;
+        while width > 1 {
+            let mut parents = Vec::with_capacity(known.len());
+            let mut nodes = known.into_iter().peekable();
+            while let Some((index, hash)) = nodes.next() {
+                // The last node of a level of odd width is paired with itself
+                let sibling = (index ^ 1).min(width - 1);
+                let parent = if sibling == index {
+                    hash_pair(&hash, &hash)
+                } else if nodes.peek().is_some_and(|(next, _)| *next == sibling) {
+                    let (_, right) = nodes.next()?;
+                    hash_pair(&hash, &right)
+                } else if index % 2 == 1 {
+                    hash_pair(siblings.next()?, &hash)
+                } else {
+                    hash_pair(&hash, siblings.next()?)
+                };
+                parents.push((index / 2, parent.to_vec()));
+            }
+            known = parents;
+            width = width.div_ceil(2);
+        }
+
+        if siblings.next().is_some() {
+            return None;
+        }
+        known.pop().map(|(_, root)| root)
+    }
+}
+
+/// A proof that the leaves from `start` up to `end` are in a tree, in that order: a
+/// `MultiProof` of every leaf of the range, holding only the siblings along the range's
+/// two edges, which does not list the range's leaf indices one by one.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RangeProof {
+    pub start: u64,
+    pub end: u64,
+    pub tree_size: u64,
+    pub siblings: Vec<Vec<u8>>,
+}
+
+impl RangeProof {
+    /// Whether `leaves` are the leaves from `start` up to `end` of a SHA-256 tree of
+    /// `tree_size` leaves whose root is `root`.
+    pub fn verify<L: AsRef<[u8]>>(&self, root: &[u8], leaves: &[L]) -> bool {
+        self.verify_with(LeafEncoding::Plain, root, leaves)
+    }
+
+    /// Like `verify`, for a tree built with `encoding`.
+    pub fn verify_with<L: AsRef<[u8]>>(
+        &self,
+        encoding: LeafEncoding,
+        root: &[u8],
+        leaves: &[L],
+    ) -> bool {
+        self.end.checked_sub(self.start) == Some(leaves.len() as u64)
+            && self.multiproof().verify_with(encoding, root, leaves)
+    }
+
+    /// Like `verify`, for leaves whose hashes are already known.
+    pub fn verify_leaf_hashes(&self, root: &[u8], leaf_hashes: &[Vec<u8>]) -> bool {
+        self.end.checked_sub(self.start) == Some(leaf_hashes.len() as u64)
+            && self.multiproof().verify_leaf_hashes(root, leaf_hashes)
+    }
+
+    fn multiproof(&self) -> MultiProof {
+        MultiProof {
+            leaf_indices: (self.start..self.end).collect(),
+            tree_size: self.tree_size,
+            siblings: self.siblings.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::merkle_tree::MerkleTree;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn test_multiproofs_verify_several_leaves() {
+        for size in 1..=17usize {
+            let data: Vec<Vec<u8>> = (0..size as u8).map(|i| vec![i]).collect();
+            let tree = MerkleTree::new(data.clone());
+            let root = tree.get_root_hash();
+            for indices in [
+                vec![0],
+                vec![size - 1],
+                (0..size).collect(),
+                vec![size / 2, 0],
+            ] {
+                let proof = tree.get_proof_for_indices(&indices).unwrap();
+                let leaves: Vec<&[u8]> = indices.iter().map(|&i| data[i].as_slice()).collect();
+                assert!(proof.verify(&root, &leaves), "size {} {:?}", size, indices);
+
+                let mut swapped = leaves.clone();
+                swapped[0] = b"other";
+                assert!(!proof.verify(&root, &swapped));
+            }
+        }
+
+        let data: Vec<Vec<u8>> = (0..16u8).map(|i| vec![i]).collect();
+        let tree = MerkleTree::new(data.clone());
+        let root = tree.get_root_hash();
+
+        // Siblings shared by the paths are sent once, and the two halves of a pair not at all
+        let proof = tree.get_proof_for_indices(&[4, 5, 6, 7]).unwrap();
+        assert_eq!(proof.siblings.len(), 2);
+        let separate: usize = [4, 5, 6, 7]
+            .iter()
+            .map(|&i| tree.get_proof_for(i).len())
+            .sum();
+        assert_eq!(separate, 16);
+        let all: Vec<usize> = (0..16).collect();
+        assert!(tree
+            .get_proof_for_indices(&all)
+            .unwrap()
+            .siblings
+            .is_empty());
+
+        // An index asked for twice must be given the same leaf both times
+        let proof = tree.get_proof_for_indices(&[3, 3]).unwrap();
+        assert!(proof.verify(&root, &[&data[3], &data[3]]));
+        assert!(!proof.verify(&root, &[&data[3], &data[2]]));
+
+        // Missing, extra and misplaced parts are refused
+        let proof = tree.get_proof_for_indices(&[1, 9]).unwrap();
+        let leaves = [&data[1], &data[9]];
+        assert!(proof.verify(&root, &leaves));
+        assert!(!proof.verify(&root, &leaves[..1]));
+        let mut short = proof.clone();
+        short.siblings.pop();
+        assert!(!short.verify(&root, &leaves));
+        let mut long = proof.clone();
+        long.siblings.push(vec![0; 32]);
+        assert!(!long.verify(&root, &leaves));
+        let mut moved = proof.clone();
+        moved.leaf_indices = vec![0, 9];
+        assert!(!moved.verify(&root, &leaves));
+        let mut outside = proof;
+        outside.leaf_indices = vec![1, 16];
+        assert!(!outside.verify(&root, &leaves));
+
+        assert_eq!(tree.get_proof_for_indices(&[]), None);
+        assert_eq!(tree.get_proof_for_indices(&[2, 16]), None);
+    }
+
+    #[test]
+    fn test_range_proofs_verify_runs_of_leaves() {
+        for size in 1..=17usize {
+            let data: Vec<Vec<u8>> = (0..size as u8).map(|i| vec![i]).collect();
+            let tree = MerkleTree::new(data.clone());
+            let root = tree.get_root_hash();
+            for start in 0..size {
+                for end in start + 1..=size {
+                    let proof = tree.get_range_proof(start..end).unwrap();
+                    assert!(
+                        proof.verify(&root, &data[start..end]),
+                        "{} {}..{}",
+                        size,
+                        start,
+                        end
+                    );
+                    // Each edge of the range needs at most one sibling per level
+                    assert!(proof.siblings.len() <= 2 * tree.get_proof_for(0).len());
+                }
+            }
+        }
+
+        let data: Vec<Vec<u8>> = (0..16u8).map(|i| vec![i]).collect();
+        let tree = MerkleTree::new(data.clone());
+        let root = tree.get_root_hash();
+        let proof = tree.get_range_proof(4..8).unwrap();
+        assert_eq!(proof.siblings.len(), 2);
+        assert!(!proof.verify(&root, &data[4..7]));
+        assert!(!proof.verify(&root, &data[5..9]));
+        let mut shifted = proof.clone();
+        (shifted.start, shifted.end) = (5, 9);
+        assert!(!shifted.verify(&root, &data[4..8]));
+        let mut reversed = proof;
+        (reversed.start, reversed.end) = (8, 4);
+        assert!(!reversed.verify(&root, &data[4..8]));
+
+        assert_eq!(tree.get_range_proof(3..3), None);
+        assert_eq!(tree.get_range_proof(15..17), None);
+    }
+}
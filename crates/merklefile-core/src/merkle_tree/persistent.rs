@@ -0,0 +1,388 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, Weak};
+
+use super::{hash_pair, MerkleProof};
+
+/// Entries a `NodeCache` starts with before it first drops entries of freed subtrees.
+const MIN_PRUNE_AT: usize = 1024;
+
+/// A node of a `PersistentTree`, shared by every version that contains it.
+#[derive(Debug)]
+enum Node {
+    Leaf(Vec<u8>),
+    /// `right` is missing on the right edge of a level of odd width, where the left child is
+    /// its own sibling, as in `MerkleTree`.
+    Branch {
+        hash: [u8; 32],
+        left: Arc<Node>,
+        right: Option<Arc<Node>>,
+    },
+}
+
+impl Node {
+    fn hash(&self) -> &[u8] {
+        match self {
+            Node::Leaf(hash) => hash,
+            Node::Branch { hash, .. } => hash,
+        }
+    }
+
+    fn branch(left: Arc<Node>, right: Option<Arc<Node>>) -> Arc<Node> {
+        let hash = Self::branch_hash(&left, right.as_ref());
+        Arc::new(Node::Branch { hash, left, right })
+    }
+
+    fn branch_hash(left: &Arc<Node>, right: Option<&Arc<Node>>) -> [u8; 32] {
+        hash_pair(left.hash(), right.unwrap_or(left).hash())
+    }
+
+    /// A subtree of `height` levels holding only `leaf`, as its leftmost leaf.
+    fn path(height: u32, leaf: Vec<u8>) -> Arc<Node> {
+        (0..height).fold(Arc::new(Node::Leaf(leaf)), |node, _| {
+            Node::branch(node, None)
+        })
+    }
+}
+
+/// Subtrees by content, so trees built through the same cache hold each distinct subtree
+/// once however many of them contain it: versions of one tree, or the trees of many
+/// namespaces with overlapping files.
+///
+/// Only weak references are kept; a subtree is freed once no tree uses it, and its entry
+/// is dropped as the cache grows.
+#[derive(Debug)]
+pub struct NodeCache {
+    nodes: Mutex<CacheEntries>,
+}
+
+#[derive(Debug)]
+struct CacheEntries {
+    /// Keyed by root hash, height and number of leaves. The hash alone is not enough: a lone
+    /// leaf paired with itself hashes like two equal leaves.
+    by_key: HashMap<(Vec<u8>, u32, usize), Weak<Node>>,
+    prune_at: usize,
+}
+
+impl Default for NodeCache {
+    fn default() -> Self {
+        NodeCache {
+            nodes: Mutex::new(CacheEntries {
+                by_key: HashMap::new(),
+                prune_at: MIN_PRUNE_AT,
+            }),
+        }
+    }
+}
+
+impl NodeCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of distinct subtrees, leaves included, that some tree still uses.
+    pub fn len(&self) -> usize {
+        let nodes = self.nodes.lock().unwrap();
+        nodes
+            .by_key
+            .values()
+            .filter(|node| node.strong_count() > 0)
+            .count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The cached subtree with this content, or `node`, which is cached from now on.
+    fn intern(&self, key: (Vec<u8>, u32, usize), node: impl FnOnce() -> Arc<Node>) -> Arc<Node> {
+        let mut nodes = self.nodes.lock().unwrap();
+        if let Some(cached) = nodes.by_key.get(&key).and_then(Weak::upgrade) {
+            return cached;
+        }
+        if nodes.by_key.len() >= nodes.prune_at {
+            nodes.by_key.retain(|_, node| node.strong_count() > 0);
+            nodes.prune_at = (2 * nodes.by_key.len()).max(MIN_PRUNE_AT);
+        }
+        let node = node();
+        nodes.by_key.insert(key, Arc::downgrade(&node));
+        node
+    }
+}
+
+/// One version of a Merkle tree with the same shape, root and proofs as a `MerkleTree` of
+/// the same leaves, built so that versions derived from it share every subtree they did
+/// not change.
+///
+/// `set` and `push` return a new version and leave this one as it was, at the cost of the
+/// O(log n) nodes on the path to the changed leaf. Keeping many versions for historical
+/// proofs then costs memory in proportion to the changes between them rather than to the
+/// size of each.
+#[derive(Debug, Clone)]
+pub struct PersistentTree {
+    root: Arc<Node>,
+    len: usize,
+    /// Levels above the leaves.
+    height: u32,
+}
+
+impl PersistentTree {
+    pub fn from_leaf_hashes(leaf_hashes: Vec<Vec<u8>>) -> Self {
+        Self::build(leaf_hashes, None)
+    }
+
+    /// Like `from_leaf_hashes`, but reuses every subtree `cache` already holds and adds the
+    /// new ones to it.
+    pub fn from_leaf_hashes_in(leaf_hashes: Vec<Vec<u8>>, cache: &NodeCache) -> Self {
+        Self::build(leaf_hashes, Some(cache))
+    }
+
+    fn build(leaf_hashes: Vec<Vec<u8>>, cache: Option<&NodeCache>) -> Self {
+        assert!(
+            !leaf_hashes.is_empty(),
+            "a Merkle tree needs at least one leaf"
+        );
+        let len = leaf_hashes.len();
+        // Every node with the number of leaves below it
+        let mut level: Vec<(Arc<Node>, usize)> = leaf_hashes
+            .into_iter()
+            .map(|hash| {
+                let leaf = match cache {
+                    Some(cache) => {
+                        cache.intern((hash.clone(), 0, 1), || Arc::new(Node::Leaf(hash)))
+                    }
+                    None => Arc::new(Node::Leaf(hash)),
+                };
+                (leaf, 1)
+            })
+            .collect();
+        let mut height = 0;
+        while level.len() > 1 {
+            height += 1;
+            let mut nodes = level.into_iter();
+            let mut above = Vec::with_capacity(nodes.len().div_ceil(2));
+            while let Some((left, left_len)) = nodes.next() {
+                let (right, right_len) = nodes.next().unzip();
+                let len = left_len + right_len.unwrap_or(0);
+                let node = match cache {
+                    Some(cache) => {
+                        let hash = Node::branch_hash(&left, right.as_ref());
+                        cache.intern((hash.to_vec(), height, len), || {
+                            Arc::new(Node::Branch { hash, left, right })
+                        })
+                    }
+                    None => Node::branch(left, right),
+                };
+                above.push((node, len));
+            }
+            level = above;
+        }
+        let (root, _) = level.pop().expect("at least one leaf");
+        Self { root, len, height }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Always false: a tree has at least one leaf.
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    pub fn get_root_hash(&self) -> Vec<u8> {
+        self.root.hash().to_vec()
+    }
+
+    /// Proof for the leaf at `index`, in the form `MerkleTree::get_proof_for` returns; empty
+    /// if there is no such leaf.
+    pub fn get_proof_for(&self, index: usize) -> Vec<(Vec<u8>, bool)> {
+        if index >= self.len {
+            return Vec::new();
+        }
+        let mut proof = Vec::with_capacity(self.height as usize);
+        let mut node = &self.root;
+        for level in (0..self.height).rev() {
+            let Node::Branch { left, right, .. } = node.as_ref() else {
+                unreachable!("leaves are only found at the bottom");
+            };
+            if index >> level & 1 == 0 {
+                let sibling = right.as_ref().unwrap_or(left);
+                proof.push((sibling.hash().to_vec(), false));
+                node = left;
+            } else {
+                proof.push((left.hash().to_vec(), true));
+                node = right.as_ref().expect("leaf index is within the tree");
+            }
+        }
+        proof.reverse();
+        proof
+    }
+
+    /// The proof for leaf `index` as a `MerkleProof`, or `None` if there is no such leaf.
+    pub fn proof(&self, index: usize) -> Option<MerkleProof> {
+        if index >= self.len {
+            return None;
+        }
+        Some(MerkleProof {
+            leaf_index: index as u64,
+            tree_size: self.len as u64,
+            siblings: self
+                .get_proof_for(index)
+                .into_iter()
+                .map(|(sibling, _)| sibling)
+                .collect(),
+        })
+    }
+
+    /// A new version with the leaf at `index` replaced by `leaf_hash`.
+    ///
+    /// # Panics
+    ///
+    /// If there is no leaf at `index`.
+    pub fn set(&self, index: usize, leaf_hash: Vec<u8>) -> Self {
+        assert!(
+            index < self.len,
+            "leaf {} is out of range for a tree of {} leaves",
+            index,
+            self.len
+        );
+        Self {
+            root: Self::replace(&self.root, self.height, index, leaf_hash),
+            ..self.clone()
+        }
+    }
+
+    /// A new version with `leaf_hash` added after the last leaf.
+    pub fn push(&self, leaf_hash: Vec<u8>) -> Self {
+        if self.len == 1 << self.height {
+            // Full: the old tree becomes the left half of one a level taller
+            let right = Node::path(self.height, leaf_hash);
+            return Self {
+                root: Node::branch(Arc::clone(&self.root), Some(right)),
+                len: self.len + 1,
+                height: self.height + 1,
+            };
+        }
+        Self {
+            root: Self::replace(&self.root, self.height, self.len, leaf_hash),
+            len: self.len + 1,
+            height: self.height,
+        }
+    }
+
+    /// Copies the path from `node`, `height` levels above the leaves, down to leaf `index`,
+    /// with that leaf set to `leaf_hash` and created if it is just past the last one.
+    fn replace(node: &Arc<Node>, height: u32, index: usize, leaf_hash: Vec<u8>) -> Arc<Node> {
+        let Node::Branch { left, right, .. } = node.as_ref() else {
+            return Arc::new(Node::Leaf(leaf_hash));
+        };
+        let level = height - 1;
+        if index >> level & 1 == 0 {
+            let left = Self::replace(left, level, index, leaf_hash);
+            Node::branch(left, right.clone())
+        } else {
+            let right = match right {
+                Some(right) => Self::replace(right, level, index, leaf_hash),
+                None => Node::path(level, leaf_hash),
+            };
+            Node::branch(Arc::clone(left), Some(right))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merkle_tree::MerkleTree;
+    use sha2::{Digest, Sha256};
+
+    fn leaf(i: u32) -> Vec<u8> {
+        Sha256::digest(i.to_be_bytes()).to_vec()
+    }
+
+    fn assert_matches(tree: &PersistentTree, leaves: &[Vec<u8>]) {
+        let expected = MerkleTree::from_leaf_hashes(leaves.to_vec());
+        assert_eq!(tree.len(), leaves.len());
+        assert_eq!(tree.get_root_hash(), expected.get_root_hash());
+        for index in 0..=leaves.len() {
+            assert_eq!(tree.get_proof_for(index), expected.get_proof_for(index));
+        }
+    }
+
+    #[test]
+    fn test_versions_match_rebuilt_trees() {
+        let mut leaves = vec![leaf(0)];
+        let mut tree = PersistentTree::from_leaf_hashes(leaves.clone());
+        let mut versions = vec![(tree.clone(), leaves.clone())];
+        for i in 1..20 {
+            tree = tree.push(leaf(i));
+            leaves.push(leaf(i));
+            assert_matches(&tree, &leaves);
+            assert_matches(&PersistentTree::from_leaf_hashes(leaves.clone()), &leaves);
+
+            let index = (i as usize * 7) % leaves.len();
+            tree = tree.set(index, leaf(100 + i));
+            leaves[index] = leaf(100 + i);
+            assert_matches(&tree, &leaves);
+            versions.push((tree.clone(), leaves.clone()));
+        }
+        // Deriving new versions never changed the old ones
+        for (version, leaves) in &versions {
+            assert_matches(version, leaves);
+        }
+    }
+
+    #[test]
+    fn test_cached_trees_share_identical_subtrees() {
+        let cache = NodeCache::new();
+        let leaves: Vec<Vec<u8>> = (0..8).map(leaf).collect();
+        let tree = PersistentTree::from_leaf_hashes_in(leaves.clone(), &cache);
+        assert_matches(&tree, &leaves);
+        assert_eq!(cache.len(), 8 + 4 + 2 + 1);
+
+        let mut other = leaves.clone();
+        other[7] = leaf(100);
+        let other_tree = PersistentTree::from_leaf_hashes_in(other.clone(), &cache);
+        assert_matches(&other_tree, &other);
+        // Only the new leaf and the path above it were added
+        assert_eq!(cache.len(), 15 + 4);
+        let (
+            Node::Branch { left, .. },
+            Node::Branch {
+                left: other_left, ..
+            },
+        ) = (tree.root.as_ref(), other_tree.root.as_ref())
+        else {
+            panic!("a tree of 8 leaves has a branch at the root");
+        };
+        assert!(Arc::ptr_eq(left, other_left));
+
+        // A lone leaf paired with itself is not mistaken for two equal leaves
+        let lone = PersistentTree::from_leaf_hashes_in(vec![leaf(0); 3], &cache);
+        assert_matches(&lone, &[leaf(0), leaf(0), leaf(0)]);
+        drop((tree, other_tree, lone));
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_versions_share_unchanged_subtrees() {
+        let tree = PersistentTree::from_leaf_hashes((0..8).map(leaf).collect());
+        let changed = tree.set(0, leaf(100));
+        let (
+            Node::Branch {
+                left: old_left,
+                right: old_right,
+                ..
+            },
+            Node::Branch { left, right, .. },
+        ) = (tree.root.as_ref(), changed.root.as_ref())
+        else {
+            panic!("a tree of 8 leaves has a branch at the root");
+        };
+        assert!(!Arc::ptr_eq(old_left, left));
+        assert!(Arc::ptr_eq(
+            old_right.as_ref().unwrap(),
+            right.as_ref().unwrap()
+        ));
+    }
+}
@@ -0,0 +1,229 @@
+use alloc::vec::Vec;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// The hash a preset applies to leaves and nodes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashFunction {
+    Sha256,
+    /// SHA-256 of the SHA-256, as in Bitcoin.
+    DoubleSha256,
+}
+
+/// What a level of odd width does with its last node. A `MerkleTree` is built with one of
+/// these through `TreeOptions::odd_node`, and a preset names the one another
+/// implementation uses.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum OddNode {
+    /// Pair it with itself, as merklefile and Bitcoin do. A tree whose last leaf is
+    /// repeated then has the same root as the tree without the repeat, so leaves `[a, b, c]`
+    /// and `[a, b, c, c]` cannot be told apart by their roots.
+    #[default]
+    Duplicate,
+    /// Carry it up to the next level unchanged, as `rs_merkle` does. This gives the same
+    /// shape as RFC 6962, whose left subtree always covers the largest power of two leaves.
+    Promote,
+    /// Pair it with a hash of all zeroes, so a repeated last leaf changes the root.
+    PadWithZero,
+}
+
+/// The rules another Merkle implementation builds its trees by, so a root it recorded can
+/// be reproduced from the same leaves while migrating to merklefile.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TreePreset {
+    pub hash: HashFunction,
+    /// Byte hashed in front of a leaf's data, to tell leaves from nodes.
+    pub leaf_prefix: Option<u8>,
+    /// Byte hashed in front of a node's two children.
+    pub node_prefix: Option<u8>,
+    pub odd_node: OddNode,
+}
+
+impl TreePreset {
+    /// merklefile's own trees.
+    pub const MERKLEFILE: TreePreset = TreePreset {
+        hash: HashFunction::Sha256,
+        leaf_prefix: None,
+        node_prefix: None,
+        odd_node: OddNode::Duplicate,
+    };
+
+    /// `rs_merkle` with its `Sha256` algorithm, given leaves hashed with `Sha256::hash`.
+    pub const RS_MERKLE: TreePreset = TreePreset {
+        hash: HashFunction::Sha256,
+        leaf_prefix: None,
+        node_prefix: None,
+        odd_node: OddNode::Promote,
+    };
+
+    /// Certificate Transparency's Merkle tree hash (RFC 6962), as in `ct-merkle` and Trillian.
+    pub const RFC6962: TreePreset = TreePreset {
+        hash: HashFunction::Sha256,
+        leaf_prefix: Some(0x00),
+        node_prefix: Some(0x01),
+        odd_node: OddNode::Promote,
+    };
+
+    /// Bitcoin block Merkle roots. Its leaves are transaction ids, which are already hashes,
+    /// so build from them with `root_from_leaf_hashes`, in internal byte order: the reverse
+    /// of how block explorers display them.
+    pub const BITCOIN: TreePreset = TreePreset {
+        hash: HashFunction::DoubleSha256,
+        leaf_prefix: None,
+        node_prefix: None,
+        odd_node: OddNode::Duplicate,
+    };
+
+    /// Every preset, with its name.
+    pub const ALL: [(&'static str, TreePreset); 4] = [
+        ("merklefile", TreePreset::MERKLEFILE),
+        ("rs_merkle", TreePreset::RS_MERKLE),
+        ("rfc6962", TreePreset::RFC6962),
+        ("bitcoin", TreePreset::BITCOIN),
+    ];
+
+    /// The preset named `name` in `ALL`.
+    pub fn named(name: &str) -> Option<TreePreset> {
+        Self::ALL
+            .iter()
+            .find(|(preset_name, _)| *preset_name == name)
+            .map(|(_, preset)| *preset)
+    }
+
+    /// The name of the first preset that builds `root` from `leaves`, to find out how a
+    /// recorded root was made.
+    pub fn identify(leaves: &[impl AsRef<[u8]>], root: &[u8]) -> Option<&'static str> {
+        Self::ALL
+            .iter()
+            .find(|(_, preset)| preset.root(leaves) == root)
+            .map(|(name, _)| *name)
+    }
+
+    pub fn leaf_hash(&self, data: &[u8]) -> Vec<u8> {
+        self.digest(self.leaf_prefix, &[data])
+    }
+
+    pub fn node_hash(&self, left: &[u8], right: &[u8]) -> Vec<u8> {
+        self.digest(self.node_prefix, &[left, right])
+    }
+
+    /// The root over the leaves' data. An empty tree's root is the hash of no bytes, as in
+    /// RFC 6962.
+    pub fn root(&self, leaves: &[impl AsRef<[u8]>]) -> Vec<u8> {
+        let leaf_hashes = leaves
+            .iter()
+            .map(|leaf| self.leaf_hash(leaf.as_ref()))
+            .collect();
+        self.root_from_leaf_hashes(leaf_hashes)
+    }
+
+    /// The root over leaves that are already hashed.
+    pub fn root_from_leaf_hashes(&self, leaf_hashes: Vec<Vec<u8>>) -> Vec<u8> {
+        let mut level = leaf_hashes;
+        if level.is_empty() {
+            return self.digest(None, &[]);
+        }
+        while level.len() > 1 {
+            level = level
+                .chunks(2)
+                .map(|pair| match (pair, self.odd_node) {
+                    ([left, right], _) => self.node_hash(left, right),
+                    ([last], OddNode::Duplicate) => self.node_hash(last, last),
+                    ([last], OddNode::Promote) => last.clone(),
+                    ([last], OddNode::PadWithZero) => self.node_hash(last, &[0; 32]),
+                    _ => unreachable!("chunks of two"),
+                })
+                .collect();
+        }
+        level.swap_remove(0)
+    }
+
+    fn digest(&self, prefix: Option<u8>, parts: &[&[u8]]) -> Vec<u8> {
+        let mut hasher = Sha256::new();
+        if let Some(prefix) = prefix {
+            hasher.update([prefix]);
+        }
+        for part in parts {
+            hasher.update(part);
+        }
+        let hash = hasher.finalize();
+        match self.hash {
+            HashFunction::Sha256 => hash.to_vec(),
+            HashFunction::DoubleSha256 => Sha256::digest(hash).to_vec(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merkle_tree::MerkleTree;
+    use alloc::vec;
+
+    #[test]
+    fn test_presets_reproduce_known_roots() {
+        for width in 1..=9u8 {
+            let data: Vec<Vec<u8>> = (0..width).map(|i| vec![i]).collect();
+            assert_eq!(
+                TreePreset::MERKLEFILE.root(&data),
+                MerkleTree::new(data.clone()).get_root_hash()
+            );
+        }
+
+        // Test vectors of the Certificate Transparency reference implementation
+        let leaves: Vec<Vec<u8>> = [
+            "",
+            "00",
+            "10",
+            "2021",
+            "3031",
+            "40414243",
+            "5051525354555657",
+            "606162636465666768696a6b6c6d6e6f",
+        ]
+        .iter()
+        .map(|leaf| hex::decode(leaf).unwrap())
+        .collect();
+        let roots = [
+            (
+                0,
+                "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            ),
+            (
+                1,
+                "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d",
+            ),
+            (
+                2,
+                "fac54203e7cc696cf0dfcb42c92a1d9dbaf70ad9e621f4bd8d98662f00e3c125",
+            ),
+            (
+                3,
+                "aeb6bcfe274b70a14fb067a5e5578264db0fa9b51af5e0ba159158f329e06e77",
+            ),
+            (
+                8,
+                "5dc9da79a70659a9ad559cb701ded9a2ab9d823aad2f4960cfe370eff4604328",
+            ),
+        ];
+        for (size, root) in roots {
+            let root = hex::decode(root).unwrap();
+            assert_eq!(TreePreset::RFC6962.root(&leaves[..size]), root, "{}", size);
+            if size > 0 {
+                // Every preset agrees on the empty tree
+                assert_eq!(
+                    TreePreset::identify(&leaves[..size], &root),
+                    Some("rfc6962")
+                );
+            }
+        }
+
+        // Promoting the odd node leaves it unhashed until the level it is paired on
+        let preset = TreePreset::RS_MERKLE;
+        let hashes: Vec<Vec<u8>> = (0..3u8).map(|i| preset.leaf_hash(&[i])).collect();
+        let expected = preset.node_hash(&preset.node_hash(&hashes[0], &hashes[1]), &hashes[2]);
+        assert_eq!(preset.root_from_leaf_hashes(hashes), expected);
+        assert_eq!(TreePreset::named("bitcoin"), Some(TreePreset::BITCOIN));
+    }
+}
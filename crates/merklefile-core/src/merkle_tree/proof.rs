@@ -0,0 +1,283 @@
+use alloc::vec::Vec;
+use serde::{Deserialize, Serialize};
+
+use sha2::Sha256;
+
+use super::{LeafEncoding, MerkleTree};
+
+/// A proof that a leaf is in a tree: the leaf's position, the number of leaves in the tree,
+/// and the sibling of every node on the path from the leaf to the root, from the leaves up.
+/// Which side each sibling is on follows from the leaf's index, so it is not stored.
+///
+/// It serializes as a struct of those three fields. It also deserializes from the bare list
+/// of `(sibling, is_left)` pairs that older receipts and servers wrote; see `from_bare_path`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(try_from = "Stored")]
+pub struct MerkleProof {
+    pub leaf_index: u64,
+    pub tree_size: u64,
+    pub siblings: Vec<Vec<u8>>,
+}
+
+impl MerkleProof {
+    /// The typed form of `path`, a list of `(sibling, is_left)` pairs from the leaves up, for
+    /// a tree of `tree_size` leaves. The leaf's index is read from the sides. `None` if the
+    /// path does not fit a leaf of such a tree.
+    pub fn from_path(path: &[(Vec<u8>, bool)], tree_size: u64) -> Option<Self> {
+        let proof = MerkleProof {
+            leaf_index: path_index(path)?,
+            tree_size,
+            siblings: path.iter().map(|(sibling, _)| sibling.clone()).collect(),
+        };
+        proof.fits().then_some(proof)
+    }
+
+    /// Like `from_path`, for a path whose tree size was not recorded, as in the proofs of
+    /// servers before protocol version 12 and the receipts they issued. The proof records
+    /// the smallest tree its path fits.
+    pub fn from_bare_path(path: &[(Vec<u8>, bool)]) -> Option<Self> {
+        let smallest = match path.len() {
+            0 => 1,
+            levels => 1u64.checked_shl(levels as u32 - 1)? + 1,
+        };
+        Self::from_path(path, smallest.max(path_index(path)? + 1))
+    }
+
+    /// The proof as `(sibling, is_left)` pairs from the leaves up, as `MerkleTree::verify_proof`
+    /// and the wire protocol take it.
+    pub fn path(&self) -> Vec<(Vec<u8>, bool)> {
+        self.siblings
+            .iter()
+            .enumerate()
+            .map(|(level, sibling)| (sibling.clone(), (self.leaf_index >> level) & 1 == 1))
+            .collect()
+    }
+
+    /// Whether `leaf` is at `leaf_index` of a SHA-256 tree of `tree_size` leaves whose root
+    /// is `root`.
+    pub fn verify(&self, root: &[u8], leaf: &[u8]) -> bool {
+        self.verify_with(LeafEncoding::Plain, root, leaf)
+    }
+
+    /// Like `verify`, for a tree built with `encoding`. The proof does not say which
+    /// encoding its tree has, so that it cannot claim a weaker one than the verifier expects.
+    pub fn verify_with(&self, encoding: LeafEncoding, root: &[u8], leaf: &[u8]) -> bool {
+        let leaf_hash = encoding.leaf_hash::<Sha256>(leaf);
+        verify_at(
+            encoding,
+            &leaf_hash,
+            self.leaf_index,
+            self.tree_size,
+            &self.siblings,
+            root,
+        )
+    }
+
+    /// Like `verify`, for a leaf whose hash is already known.
+    pub fn verify_leaf_hash(&self, root: &[u8], leaf_hash: &[u8]) -> bool {
+        verify_at(
+            LeafEncoding::Plain,
+            leaf_hash,
+            self.leaf_index,
+            self.tree_size,
+            &self.siblings,
+            root,
+        )
+    }
+
+    /// Whether there is one sibling per level of the tree, and the leaf is in it.
+    fn fits(&self) -> bool {
+        fits(self.leaf_index, self.tree_size, self.siblings.len())
+    }
+}
+
+impl MerkleTree {
+    /// Whether `leaf` is leaf `index` of a SHA-256 tree of `tree_size` leaves whose root is
+    /// `root`, by the siblings of its path from the leaves up. Unlike `verify_proof`, which
+    /// hashes each sibling on the side its flag names, the sides are read from `index`, so
+    /// whoever sent the proof cannot choose them, and the leaf cannot be proven at any
+    /// position but `index`.
+    pub fn verify_proof_at(
+        leaf: &[u8],
+        index: u64,
+        tree_size: u64,
+        proof: &[Vec<u8>],
+        root: &[u8],
+    ) -> bool {
+        let leaf_hash = LeafEncoding::Plain.leaf_hash::<Sha256>(leaf);
+        verify_at(
+            LeafEncoding::Plain,
+            &leaf_hash,
+            index,
+            tree_size,
+            proof,
+            root,
+        )
+    }
+}
+
+/// Whether a path of `siblings` fits leaf `index` of a tree of `tree_size` leaves: one
+/// sibling per level, and the leaf in the tree.
+fn fits(index: u64, tree_size: u64, siblings: usize) -> bool {
+    index < tree_size && siblings as u32 == depth(tree_size)
+}
+
+/// Whether `siblings`, each on the side `index` puts it, hash `leaf_hash` up to `root`.
+fn verify_at(
+    encoding: LeafEncoding,
+    leaf_hash: &[u8],
+    index: u64,
+    tree_size: u64,
+    siblings: &[Vec<u8>],
+    root: &[u8],
+) -> bool {
+    if !fits(index, tree_size, siblings.len()) {
+        return false;
+    }
+    let mut node = leaf_hash.to_vec();
+    for (level, sibling) in siblings.iter().enumerate() {
+        node = match (index >> level) & 1 {
+            1 => encoding.node_hash::<Sha256>(sibling, &node),
+            _ => encoding.node_hash::<Sha256>(&node, sibling),
+        }
+        .to_vec();
+    }
+    node == root
+}
+
+/// Levels above the leaves in a tree of `size` leaves.
+pub(super) fn depth(size: u64) -> u32 {
+    size.checked_next_power_of_two()
+        .map_or(u64::BITS, u64::trailing_zeros)
+}
+
+/// The index a path leads up from: each step whose sibling is on the left is a 1 bit.
+fn path_index(path: &[(Vec<u8>, bool)]) -> Option<u64> {
+    if path.len() > u64::BITS as usize {
+        return None;
+    }
+    Some(
+        path.iter()
+            .enumerate()
+            .filter(|(_, (_, is_left))| *is_left)
+            .map(|(level, _)| 1 << level)
+            .sum(),
+    )
+}
+
+/// Either form a proof has been stored in.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Stored {
+    Typed {
+        leaf_index: u64,
+        tree_size: u64,
+        siblings: Vec<Vec<u8>>,
+    },
+    Path(Vec<(Vec<u8>, bool)>),
+}
+
+impl TryFrom<Stored> for MerkleProof {
+    type Error = &'static str;
+
+    fn try_from(stored: Stored) -> Result<Self, Self::Error> {
+        match stored {
+            Stored::Typed {
+                leaf_index,
+                tree_size,
+                siblings,
+            } => Ok(MerkleProof {
+                leaf_index,
+                tree_size,
+                siblings,
+            }),
+            Stored::Path(path) => {
+                Self::from_bare_path(&path).ok_or("proof path does not fit any tree")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn test_typed_proofs_match_paths() {
+        let data: Vec<Vec<u8>> = (0..7u8).map(|i| vec![i]).collect();
+        let tree = MerkleTree::new(data.clone());
+        let root = tree.get_root_hash();
+        for (index, leaf) in data.iter().enumerate() {
+            let proof = tree.proof(index).unwrap();
+            assert_eq!(proof.leaf_index, index as u64);
+            assert_eq!(proof.tree_size, 7);
+            assert_eq!(proof.path(), tree.get_proof_for(index));
+            assert_eq!(
+                MerkleProof::from_path(&proof.path(), 7),
+                Some(proof.clone())
+            );
+            assert!(proof.verify(&root, leaf));
+            assert!(!proof.verify(&root, b"other"));
+
+            let json = serde_json::to_string(&proof).unwrap();
+            assert!(json.contains("\"leaf_index\""));
+            assert_eq!(serde_json::from_str::<MerkleProof>(&json).unwrap(), proof);
+        }
+        assert_eq!(tree.proof(7), None);
+
+        // Sides follow from the index, so the same siblings do not prove another position
+        for (index, leaf) in data.iter().enumerate() {
+            let siblings = tree.proof(index).unwrap().siblings;
+            assert!(MerkleTree::verify_proof_at(
+                leaf,
+                index as u64,
+                7,
+                &siblings,
+                &root
+            ));
+            assert!(!MerkleTree::verify_proof_at(
+                leaf,
+                index as u64 ^ 1,
+                7,
+                &siblings,
+                &root
+            ));
+            assert!(!MerkleTree::verify_proof_at(
+                leaf,
+                index as u64,
+                9,
+                &siblings,
+                &root
+            ));
+            assert!(!MerkleTree::verify_proof_at(
+                leaf,
+                index as u64,
+                7,
+                &siblings[1..],
+                &root
+            ));
+        }
+
+        // A proof claiming another tree size than its path fits is refused
+        let mut resized = tree.proof(2).unwrap();
+        assert_eq!(MerkleProof::from_path(&resized.path(), 9), None);
+        resized.tree_size = 9;
+        assert!(!resized.verify(&root, &data[2]));
+
+        // Bare paths, as older servers sent them, still load
+        let path = tree.get_proof_for(6);
+        let bare = serde_json::to_string(&path).unwrap();
+        let loaded: MerkleProof = serde_json::from_str(&bare).unwrap();
+        assert_eq!(loaded.leaf_index, 6);
+        assert_eq!(loaded.tree_size, 7);
+        assert!(loaded.verify(&root, &data[6]));
+        assert_eq!(
+            MerkleProof::from_bare_path(&tree.get_proof_for(1))
+                .unwrap()
+                .tree_size,
+            5
+        );
+        assert_eq!(MerkleProof::from_bare_path(&[]).unwrap().tree_size, 1);
+    }
+}
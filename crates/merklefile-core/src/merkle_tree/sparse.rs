@@ -0,0 +1,265 @@
+use alloc::collections::BTreeMap;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::sync::OnceLock;
+
+use super::{hash_pair, Hash};
+
+/// Levels of a sparse tree: one per bit of a SHA-256 key.
+pub const SPARSE_DEPTH: usize = 256;
+
+/// A SHA-256 key, and the path from the root of a sparse tree to the leaf it names.
+pub type SparseKey = Hash;
+
+/// A Merkle tree with a leaf for every possible SHA-256 hash, almost all of them empty. A
+/// file's leaf is the one at `sparse_key(filename)`, so it stays where it is as files are
+/// added and removed around it, and a proof for it stays the same shape for as long as the
+/// file is stored. A proof for a key whose leaf is empty shows that no file of that name is
+/// in the tree.
+///
+/// Only the leaves of stored files are kept. Empty leaves are all zeroes, and every empty
+/// subtree of a level has the same root, so those are computed once for all trees.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SparseMerkleTree {
+    leaves: BTreeMap<SparseKey, Hash>,
+}
+
+/// A proof for one key of a sparse tree, that its leaf holds a file or that it is empty.
+///
+/// Siblings run from the leaf up, but only those of non-empty subtrees are included:
+/// `non_empty` has a bit per level, least significant bit of its first byte for the level
+/// of the leaf, set when the sibling at that level is in `siblings`. The others are the
+/// roots of empty subtrees, which the verifier computes. A proof in a tree of `n` files
+/// holds about `log2(n)` siblings rather than 256.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SparseProof {
+    pub non_empty: Vec<u8>,
+    pub siblings: Vec<Hash>,
+}
+
+/// The key of `filename`'s leaf in a sparse tree.
+pub fn sparse_key(filename: &str) -> SparseKey {
+    Sha256::digest(filename.as_bytes()).into()
+}
+
+/// The root of an empty subtree of `height` levels, for each height up to `SPARSE_DEPTH`.
+fn empty_roots() -> &'static [Hash; SPARSE_DEPTH + 1] {
+    static EMPTY: OnceLock<[Hash; SPARSE_DEPTH + 1]> = OnceLock::new();
+    EMPTY.get_or_init(|| {
+        let mut roots = [Hash::default(); SPARSE_DEPTH + 1];
+        for height in 1..=SPARSE_DEPTH {
+            roots[height] = Hash(hash_pair(&roots[height - 1], &roots[height - 1]));
+        }
+        roots
+    })
+}
+
+/// Bit `depth` of `key`, counted from the root: whether the path goes right at that depth.
+fn bit(key: &SparseKey, depth: usize) -> bool {
+    key[depth / 8] & (0x80 >> (depth % 8)) != 0
+}
+
+impl SparseMerkleTree {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A tree holding each of `files`, as `(filename, contents)`.
+    pub fn from_files<'a>(files: impl IntoIterator<Item = (&'a str, &'a [u8])>) -> Self {
+        let mut tree = Self::new();
+        for (filename, contents) in files {
+            tree.insert(filename, contents);
+        }
+        tree
+    }
+
+    /// Stores `contents` as `filename`, replacing the file stored under that name, if any.
+    pub fn insert(&mut self, filename: &str, contents: &[u8]) {
+        self.insert_leaf_hash(filename, Sha256::digest(contents).into());
+    }
+
+    /// Like `insert`, for contents whose hash is already known.
+    pub fn insert_leaf_hash(&mut self, filename: &str, leaf_hash: Hash) {
+        self.leaves.insert(sparse_key(filename), leaf_hash);
+    }
+
+    /// Empties `filename`'s leaf, returning whether a file was stored there.
+    pub fn remove(&mut self, filename: &str) -> bool {
+        self.leaves.remove(&sparse_key(filename)).is_some()
+    }
+
+    /// The hash of the contents stored as `filename`, if any.
+    pub fn leaf_hash(&self, filename: &str) -> Option<&Hash> {
+        self.leaves.get(&sparse_key(filename))
+    }
+
+    /// Number of files stored.
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    pub fn get_root_hash(&self) -> Vec<u8> {
+        self.root().to_vec()
+    }
+
+    pub fn root(&self) -> Hash {
+        let leaves: Vec<_> = self.leaves.iter().map(|(k, v)| (*k, *v)).collect();
+        Self::subtree_root(&leaves, 0)
+    }
+
+    /// The proof for `filename`'s leaf, whether a file is stored there or not.
+    pub fn proof(&self, filename: &str) -> SparseProof {
+        let key = sparse_key(filename);
+        let leaves: Vec<_> = self.leaves.iter().map(|(k, v)| (*k, *v)).collect();
+        let mut path = Vec::with_capacity(SPARSE_DEPTH);
+        let mut below = &leaves[..];
+        for depth in 0..SPARSE_DEPTH {
+            // Leaves are in key order, so those under the left child come first
+            let split = below.partition_point(|(other, _)| !bit(other, depth));
+            let (left, right) = below.split_at(split);
+            let (on_path, sibling) = if bit(&key, depth) {
+                (right, left)
+            } else {
+                (left, right)
+            };
+            path.push((!sibling.is_empty()).then(|| Self::subtree_root(sibling, depth + 1)));
+            below = on_path;
+        }
+
+        let mut proof = SparseProof {
+            non_empty: vec![0; SPARSE_DEPTH / 8],
+            siblings: Vec::new(),
+        };
+        for (level, sibling) in path.into_iter().rev().enumerate() {
+            if let Some(sibling) = sibling {
+                proof.non_empty[level / 8] |= 1 << (level % 8);
+                proof.siblings.push(sibling);
+            }
+        }
+        proof
+    }
+
+    /// The root of the subtree at `depth` holding `leaves`, all of whose keys share the path
+    /// down to it.
+    fn subtree_root(leaves: &[(SparseKey, Hash)], depth: usize) -> Hash {
+        match leaves {
+            [] => empty_roots()[SPARSE_DEPTH - depth],
+            [(_, leaf)] if depth == SPARSE_DEPTH => *leaf,
+            _ => {
+                let split = leaves.partition_point(|(key, _)| !bit(key, depth));
+                let (left, right) = leaves.split_at(split);
+                Hash(hash_pair(
+                    &Self::subtree_root(left, depth + 1),
+                    &Self::subtree_root(right, depth + 1),
+                ))
+            }
+        }
+    }
+}
+
+impl SparseProof {
+    /// Whether, in the sparse tree whose root is `root`, `filename` holds `contents`, or
+    /// with `None`, whether no file of that name is stored.
+    pub fn verify(&self, root: &[u8], filename: &str, contents: Option<&[u8]>) -> bool {
+        let leaf_hash: Option<Hash> = contents.map(|contents| Sha256::digest(contents).into());
+        self.verify_leaf_hash(root, filename, leaf_hash.as_ref())
+    }
+
+    /// Like `verify`, for contents whose hash is already known.
+    pub fn verify_leaf_hash(&self, root: &[u8], filename: &str, leaf_hash: Option<&Hash>) -> bool {
+        self.root(&sparse_key(filename), leaf_hash)
+            .is_some_and(|computed| computed.as_ref() == root)
+    }
+
+    /// The root the leaf at `key` and the siblings hash up to, or `None` if the proof has
+    /// the wrong number of siblings for its bits.
+    fn root(&self, key: &SparseKey, leaf_hash: Option<&Hash>) -> Option<Hash> {
+        let present = |level: usize| self.non_empty[level / 8] & (1 << (level % 8)) != 0;
+        if self.non_empty.len() != SPARSE_DEPTH / 8
+            || (0..SPARSE_DEPTH).filter(|&level| present(level)).count() != self.siblings.len()
+        {
+            return None;
+        }
+
+        let mut node = leaf_hash.copied().unwrap_or(empty_roots()[0]);
+        let mut siblings = self.siblings.iter();
+        for level in 0..SPARSE_DEPTH {
+            let sibling = match present(level) {
+                true => siblings.next()?,
+                false => &empty_roots()[level],
+            };
+            node = Hash(match bit(key, SPARSE_DEPTH - 1 - level) {
+                true => hash_pair(sibling, &node),
+                false => hash_pair(&node, sibling),
+            });
+        }
+        Some(node)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sparse_proofs_of_membership_and_absence() {
+        let empty = SparseMerkleTree::new();
+        assert_eq!(empty.root(), empty_roots()[SPARSE_DEPTH]);
+        let proof = empty.proof("a.txt");
+        assert!(proof.siblings.is_empty());
+        assert!(proof.verify(&empty.get_root_hash(), "a.txt", None));
+
+        let names: Vec<String> = (0..20).map(|i| format!("file{}.txt", i)).collect();
+        let mut tree =
+            SparseMerkleTree::from_files(names.iter().map(|name| (name.as_str(), name.as_bytes())));
+        let root = tree.get_root_hash();
+        for name in &names {
+            let proof = tree.proof(name);
+            assert!(proof.verify(&root, name, Some(name.as_bytes())));
+            assert!(!proof.verify(&root, name, Some(b"other")));
+            assert!(!proof.verify(&root, name, None));
+            assert!(proof.siblings.len() < 20);
+        }
+        let absent = tree.proof("missing.txt");
+        assert!(absent.verify(&root, "missing.txt", None));
+        assert!(!absent.verify(&root, "missing.txt", Some(b"")));
+        assert!(!absent.verify(&root, "file0.txt", None));
+
+        // Adding and removing files leaves the other files' leaves where they were
+        let proof = tree.proof("file3.txt");
+        tree.insert("new.txt", b"new");
+        assert_ne!(tree.get_root_hash(), root);
+        assert_eq!(
+            tree.proof("file3.txt").non_empty.len(),
+            proof.non_empty.len()
+        );
+        assert!(tree.remove("new.txt"));
+        assert!(!tree.remove("new.txt"));
+        assert_eq!(tree.get_root_hash(), root);
+        assert_eq!(tree.proof("file3.txt"), proof);
+
+        // The root depends on the files alone, not the order they were added in
+        let reversed = SparseMerkleTree::from_files(
+            names
+                .iter()
+                .rev()
+                .map(|name| (name.as_str(), name.as_bytes())),
+        );
+        assert_eq!(reversed.get_root_hash(), root);
+
+        // Malformed proofs are refused
+        let mut short = proof.clone();
+        short.siblings.pop();
+        assert!(!short.verify(&root, "file3.txt", Some(b"file3.txt")));
+        let mut flipped = proof.clone();
+        flipped.non_empty[31] ^= 0x80;
+        assert!(!flipped.verify(&root, "file3.txt", Some(b"file3.txt")));
+        let mut truncated = proof;
+        truncated.non_empty.pop();
+        assert!(!truncated.verify(&root, "file3.txt", Some(b"file3.txt")));
+    }
+}
@@ -0,0 +1,300 @@
+//! Append-only record of every change a server made to its files. Replaying it from its
+//! first snapshot recomputes every root the server had, so they can be checked against the
+//! server's root journal and its current root: if all of them match, the present state is
+//! explained entirely by logged operations.
+//!
+//! An operation log is a text file with one operation per line:
+//!
+//! ```text
+//! snapshot <timestamp> [<hex filename>=<hex leaf hash> ...]
+//! upload <timestamp> <hex filename>=<hex leaf hash> [<hex filename>=<hex leaf hash> ...]
+//! ```
+//!
+//! A `snapshot` lists every file the server held when it started, and an `upload` the files
+//! one upload added or replaced. Filenames are hex so they may hold any character. Each
+//! upload produces one root, which is journaled; snapshots produce none.
+
+use std::collections::BTreeMap;
+
+use crate::error::ReplayError;
+use crate::journal::JournalEntry;
+use crate::merkle_tree::MerkleTree;
+
+/// What an operation did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperationKind {
+    /// The server started holding exactly these files.
+    Snapshot,
+    /// These files were added or replaced.
+    Upload,
+}
+
+/// One line of an operation log: files by name, with their leaf hashes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Operation {
+    pub kind: OperationKind,
+    pub timestamp: u64,
+    pub files: BTreeMap<String, Vec<u8>>,
+}
+
+impl Operation {
+    /// The operation as a log line, without the line break.
+    pub fn to_line(&self) -> String {
+        let kind = match self.kind {
+            OperationKind::Snapshot => "snapshot",
+            OperationKind::Upload => "upload",
+        };
+        let mut line = format!("{} {}", kind, self.timestamp);
+        for (filename, hash) in &self.files {
+            line.push_str(&format!(" {}={}", hex::encode(filename), hex::encode(hash)));
+        }
+        line
+    }
+
+    /// Parses an operation log line.
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.split(' ');
+        let kind = match fields.next()? {
+            "snapshot" => OperationKind::Snapshot,
+            "upload" => OperationKind::Upload,
+            _ => return None,
+        };
+        let timestamp = fields.next()?.parse().ok()?;
+        let mut files = BTreeMap::new();
+        for field in fields {
+            let (filename, hash) = field.split_once('=')?;
+            let filename = String::from_utf8(hex::decode(filename).ok()?).ok()?;
+            files.insert(filename, hex::decode(hash).ok()?);
+        }
+        if kind == OperationKind::Upload && files.is_empty() {
+            return None;
+        }
+        Some(Operation {
+            kind,
+            timestamp,
+            files,
+        })
+    }
+}
+
+/// A root an upload produced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplayedRoot {
+    /// Line of the upload in the operation log.
+    pub line: usize,
+    pub timestamp: u64,
+    /// Files held after the upload.
+    pub size: u64,
+    pub root: Vec<u8>,
+}
+
+/// The outcome of replaying an operation log.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Replay {
+    /// Roots of every upload, oldest first.
+    pub roots: Vec<ReplayedRoot>,
+    /// Files held after the last operation, by name, with their leaf hashes.
+    pub files: BTreeMap<String, Vec<u8>>,
+}
+
+impl Replay {
+    /// Root of the files held after the last operation. A server holding no files serves
+    /// the root of a single empty leaf.
+    pub fn root(&self) -> Vec<u8> {
+        root_of(&self.files).unwrap_or_else(|| MerkleTree::new(vec![vec![]]).get_root_hash())
+    }
+
+    /// Checks that the roots of the replayed uploads are the journal's roots, in the same
+    /// order, with none missing on either side.
+    pub fn check_journal(&self, journal: &[JournalEntry]) -> Result<(), ReplayError> {
+        for (index, replayed) in self.roots.iter().enumerate() {
+            let journal_line = index + 1;
+            match journal.get(index) {
+                Some(entry) if entry.root == replayed.root && entry.size == replayed.size => {}
+                Some(_) => {
+                    return Err(ReplayError::RootMismatch {
+                        line: replayed.line,
+                        journal_line,
+                    })
+                }
+                None => {
+                    return Err(ReplayError::MissingRoot {
+                        line: replayed.line,
+                    })
+                }
+            }
+        }
+        if journal.len() > self.roots.len() {
+            return Err(ReplayError::UnloggedRoot {
+                journal_line: self.roots.len() + 1,
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks that `root`, the server's current root, is the one the log ends in.
+    pub fn check_current(&self, root: &[u8]) -> Result<(), ReplayError> {
+        if root == self.root() {
+            Ok(())
+        } else {
+            Err(ReplayError::CurrentRootMismatch)
+        }
+    }
+}
+
+/// Replays an operation log from its first snapshot, recomputing the root after every
+/// upload. Every later snapshot, taken when the server restarted, must hold exactly the
+/// files the operations before it left; anything else changed them without being logged.
+pub fn replay(text: &str) -> Result<Replay, ReplayError> {
+    let mut replay: Option<Replay> = None;
+    for (index, line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        let operation =
+            Operation::parse(line).ok_or(ReplayError::Malformed { line: line_number })?;
+        match (operation.kind, &mut replay) {
+            (OperationKind::Snapshot, None) => {
+                replay = Some(Replay {
+                    roots: Vec::new(),
+                    files: operation.files,
+                })
+            }
+            (OperationKind::Snapshot, Some(replay)) => {
+                if operation.files != replay.files {
+                    return Err(ReplayError::UnexplainedSnapshot { line: line_number });
+                }
+            }
+            (OperationKind::Upload, None) => return Err(ReplayError::NoSnapshot),
+            (OperationKind::Upload, Some(replay)) => {
+                replay.files.extend(operation.files);
+                replay.roots.push(ReplayedRoot {
+                    line: line_number,
+                    timestamp: operation.timestamp,
+                    size: replay.files.len() as u64,
+                    root: root_of(&replay.files).expect("an upload adds at least one file"),
+                });
+            }
+        }
+    }
+    replay.ok_or(ReplayError::NoSnapshot)
+}
+
+/// Root of the tree a server builds over `files`, in filename order; `None` if there are
+/// none.
+fn root_of(files: &BTreeMap<String, Vec<u8>>) -> Option<Vec<u8>> {
+    if files.is_empty() {
+        return None;
+    }
+    Some(MerkleTree::from_leaf_hashes(files.values().cloned().collect()).get_root_hash())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log::SigningKey;
+
+    fn operation(kind: OperationKind, timestamp: u64, files: &[(&str, u8)]) -> Operation {
+        Operation {
+            kind,
+            timestamp,
+            files: files
+                .iter()
+                .map(|(filename, hash)| (filename.to_string(), vec![*hash; 32]))
+                .collect(),
+        }
+    }
+
+    fn log(operations: &[Operation]) -> String {
+        let lines: Vec<String> = operations.iter().map(Operation::to_line).collect();
+        lines.join("\n") + "\n"
+    }
+
+    #[test]
+    fn test_operation_lines_round_trip() {
+        let upload = operation(OperationKind::Upload, 7, &[("dir/a b=c.txt", 1), ("b", 2)]);
+        assert_eq!(Operation::parse(&upload.to_line()), Some(upload));
+        let empty = operation(OperationKind::Snapshot, 7, &[]);
+        assert_eq!(empty.to_line(), "snapshot 7");
+        assert_eq!(Operation::parse("snapshot 7"), Some(empty));
+        assert_eq!(Operation::parse("upload 7"), None);
+        assert_eq!(Operation::parse("delete 7 61=01"), None);
+        assert_eq!(Operation::parse("upload 7 zz=01"), None);
+    }
+
+    #[test]
+    fn test_replay_explains_journal_and_current_root() {
+        let text = log(&[
+            operation(OperationKind::Snapshot, 1, &[("a", 1)]),
+            operation(OperationKind::Upload, 2, &[("b", 2)]),
+            operation(OperationKind::Snapshot, 3, &[("a", 1), ("b", 2)]),
+            operation(OperationKind::Upload, 4, &[("a", 3)]),
+        ]);
+        let replay = replay(&text).unwrap();
+        assert_eq!(replay.roots.len(), 2);
+        assert_eq!(replay.roots[1].line, 4);
+        assert_eq!(replay.roots[1].size, 2);
+        let expected = MerkleTree::from_leaf_hashes(vec![vec![3; 32], vec![2; 32]]);
+        assert_eq!(replay.root(), expected.get_root_hash());
+        assert_eq!(replay.check_current(&expected.get_root_hash()), Ok(()));
+        assert_eq!(
+            replay.check_current(&[0; 32]),
+            Err(ReplayError::CurrentRootMismatch)
+        );
+
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let mut journal: Vec<JournalEntry> = Vec::new();
+        for replayed in &replay.roots {
+            let entry = JournalEntry::sign(
+                journal.last(),
+                replayed.size,
+                replayed.timestamp,
+                replayed.root.clone(),
+                &key,
+            );
+            journal.push(entry);
+        }
+        assert_eq!(replay.check_journal(&journal), Ok(()));
+        assert_eq!(
+            replay.check_journal(&journal[..1]),
+            Err(ReplayError::MissingRoot { line: 4 })
+        );
+        let mut rewritten = journal.clone();
+        rewritten[0].root = vec![9; 32];
+        assert_eq!(
+            replay.check_journal(&rewritten),
+            Err(ReplayError::RootMismatch {
+                line: 2,
+                journal_line: 1
+            })
+        );
+        let extra = JournalEntry::sign(journal.last(), 3, 5, vec![9; 32], &key);
+        journal.push(extra);
+        assert_eq!(
+            replay.check_journal(&journal),
+            Err(ReplayError::UnloggedRoot { journal_line: 3 })
+        );
+    }
+
+    #[test]
+    fn test_replay_detects_unlogged_changes() {
+        // Files changed while the server was down
+        let text = log(&[
+            operation(OperationKind::Snapshot, 1, &[("a", 1)]),
+            operation(OperationKind::Snapshot, 2, &[("a", 9)]),
+        ]);
+        assert_eq!(
+            replay(&text),
+            Err(ReplayError::UnexplainedSnapshot { line: 2 })
+        );
+        let text = log(&[operation(OperationKind::Upload, 1, &[("a", 1)])]);
+        assert_eq!(replay(&text), Err(ReplayError::NoSnapshot));
+        assert_eq!(replay(""), Err(ReplayError::NoSnapshot));
+        assert_eq!(
+            replay("snapshot 1\nnot an operation\n"),
+            Err(ReplayError::Malformed { line: 2 })
+        );
+        assert_eq!(
+            replay("snapshot 1\n").unwrap().root(),
+            MerkleTree::new(vec![vec![]]).get_root_hash()
+        );
+    }
+}
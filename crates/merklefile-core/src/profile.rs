@@ -0,0 +1,135 @@
+//! Timing hooks around the stages of work that dominate a profile: hashing, tree building,
+//! proofs, (de)serialization and socket I/O. Embedders install a `Profiler` once, at startup,
+//! to feed their own metrics or profilers. Without one, a span costs a single atomic load.
+//!
+//! Every stage has a stable dotted name, e.g. `merklefile.tree.build`, suitable as a
+//! metric name or as a frame in a flamegraph built from the recorded spans.
+
+use std::fmt;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+/// A stage of work timed by the installed `Profiler`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Stage {
+    /// Hashing leaf data: whole files, or chunks of one.
+    HashLeaves,
+    /// Building the inner nodes of a tree from its leaf hashes.
+    BuildTree,
+    /// Collecting the proof for one leaf.
+    Proof,
+    /// Encoding a message for the wire.
+    Serialize,
+    /// Decoding a message from the wire.
+    Deserialize,
+    /// Reading a frame from a socket.
+    SocketRead,
+    /// Writing a frame, or a raw file, to a socket.
+    SocketWrite,
+}
+
+impl Stage {
+    /// The stage's documented name.
+    pub fn name(self) -> &'static str {
+        match self {
+            Stage::HashLeaves => "merklefile.hash.leaves",
+            Stage::BuildTree => "merklefile.tree.build",
+            Stage::Proof => "merklefile.tree.proof",
+            Stage::Serialize => "merklefile.proto.serialize",
+            Stage::Deserialize => "merklefile.proto.deserialize",
+            Stage::SocketRead => "merklefile.io.read",
+            Stage::SocketWrite => "merklefile.io.write",
+        }
+    }
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Receives a record of every finished span.
+pub trait Profiler: Send + Sync {
+    /// `stage` took `elapsed` and processed `bytes` bytes, or 0 where no bytes apply, as for
+    /// building trees and proofs.
+    fn record(&self, stage: Stage, elapsed: Duration, bytes: u64);
+}
+
+static PROFILER: OnceLock<Box<dyn Profiler>> = OnceLock::new();
+
+/// Installs the process-wide profiler. Only the first call has an effect; later ones hand
+/// their profiler back.
+pub fn set_profiler(profiler: Box<dyn Profiler>) -> Result<(), Box<dyn Profiler>> {
+    PROFILER.set(profiler)
+}
+
+/// Starts timing `stage`, which is recorded when the returned span is dropped. Hold it
+/// across `.await`s to time asynchronous work.
+pub fn span(stage: Stage) -> Span {
+    Span {
+        stage,
+        start: PROFILER.get().map(|_| Instant::now()),
+        bytes: 0,
+    }
+}
+
+/// A stage being timed; see `span`.
+#[must_use = "a span is recorded when dropped"]
+#[derive(Debug)]
+pub struct Span {
+    stage: Stage,
+    start: Option<Instant>,
+    bytes: u64,
+}
+
+impl Span {
+    /// Counts `bytes` more bytes as processed by this span.
+    pub fn add_bytes(&mut self, bytes: u64) {
+        self.bytes += bytes;
+    }
+
+    /// Sets the bytes processed by this span.
+    pub fn with_bytes(mut self, bytes: u64) -> Self {
+        self.bytes = bytes;
+        self
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        if let (Some(start), Some(profiler)) = (self.start, PROFILER.get()) {
+            profiler.record(self.stage, start.elapsed(), self.bytes);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merkle_tree::MerkleTree;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<(Stage, u64)>>);
+
+    impl Profiler for &'static Recorder {
+        fn record(&self, stage: Stage, _elapsed: Duration, bytes: u64) {
+            self.0.lock().unwrap().push((stage, bytes));
+        }
+    }
+
+    #[test]
+    fn test_installed_profiler_sees_tree_stages() {
+        let recorder: &'static Recorder = Box::leak(Box::default());
+        assert!(set_profiler(Box::new(recorder)).is_ok());
+        assert!(set_profiler(Box::new(recorder)).is_err());
+
+        let tree = MerkleTree::new(vec![vec![0; 100], vec![1; 28]]);
+        tree.get_proof_for(1);
+        let records = recorder.0.lock().unwrap();
+        assert!(records.contains(&(Stage::HashLeaves, 128)));
+        assert!(records.iter().any(|(stage, _)| *stage == Stage::BuildTree));
+        assert!(records.iter().any(|(stage, _)| *stage == Stage::Proof));
+    }
+}
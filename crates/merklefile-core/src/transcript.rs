@@ -0,0 +1,187 @@
+//! Signed record of a client session, to settle what a server actually returned.
+//!
+//! Every exchange becomes an entry holding the SHA-256 of the request frame and of the
+//! response frame, and any root the response reported. Entries are hash-chained like the
+//! root journal's, and signing a transcript signs the hash of its last entry, so a signed
+//! transcript can be checked entry by entry against the frames either side kept, and no
+//! entry can be dropped or altered without breaking the signature.
+
+use ed25519_dalek::{Signature, Signer, Verifier};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::error::TranscriptError;
+use crate::log::{SigningKey, VerifyingKey};
+
+/// Prefix of the bytes a transcript signature covers, so it can never pass for a tree
+/// head's or a journal entry's.
+const TRANSCRIPT_CONTEXT: &[u8] = b"merklefile session transcript v1\n";
+
+/// One request to `server` at `timestamp` (seconds since the Unix epoch) and its response.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TranscriptEntry {
+    /// Hash of the entry before, or 32 zero bytes for the first.
+    pub prev: Vec<u8>,
+    pub timestamp: u64,
+    pub server: String,
+    /// SHA-256 of the request frame.
+    pub request: Vec<u8>,
+    /// SHA-256 of the response frame.
+    pub response: Vec<u8>,
+    /// The root the response reported, if it reported one.
+    pub root: Option<Vec<u8>>,
+}
+
+impl TranscriptEntry {
+    /// The hash the next entry names as its previous one.
+    pub fn hash(&self) -> Vec<u8> {
+        let mut hasher = Sha256::new();
+        hasher.update(&self.prev);
+        hasher.update(self.timestamp.to_be_bytes());
+        hasher.update((self.server.len() as u64).to_be_bytes());
+        hasher.update(self.server.as_bytes());
+        hasher.update(&self.request);
+        hasher.update(&self.response);
+        match &self.root {
+            Some(root) => {
+                hasher.update([1]);
+                hasher.update((root.len() as u64).to_be_bytes());
+                hasher.update(root);
+            }
+            None => hasher.update([0]),
+        }
+        hasher.finalize().to_vec()
+    }
+}
+
+/// The exchanges of a session so far, oldest first.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Transcript {
+    entries: Vec<TranscriptEntry>,
+}
+
+impl Transcript {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends the exchange of `request` for `response` with `server`.
+    pub fn record(
+        &mut self,
+        timestamp: u64,
+        server: &str,
+        request: &[u8],
+        response: &[u8],
+        root: Option<Vec<u8>>,
+    ) {
+        let entry = TranscriptEntry {
+            prev: self.head(),
+            timestamp,
+            server: server.to_string(),
+            request: Sha256::digest(request).to_vec(),
+            response: Sha256::digest(response).to_vec(),
+            root,
+        };
+        self.entries.push(entry);
+    }
+
+    pub fn entries(&self) -> &[TranscriptEntry] {
+        &self.entries
+    }
+
+    /// Hash of the last entry, or 32 zero bytes if there is none.
+    pub fn head(&self) -> Vec<u8> {
+        self.entries
+            .last()
+            .map_or_else(|| vec![0; 32], TranscriptEntry::hash)
+    }
+
+    /// The transcript signed by the holder of `key`, ready to export.
+    pub fn sign(&self, key: &SigningKey) -> SignedTranscript {
+        let signature = key.sign(&signed_bytes(&self.head(), self.entries.len()));
+        SignedTranscript {
+            entries: self.entries.clone(),
+            signer: key.verifying_key().to_bytes().to_vec(),
+            signature: signature.to_bytes().to_vec(),
+        }
+    }
+}
+
+/// A transcript with the signature of whoever recorded it. Serializable, so it can be
+/// handed to the other side or an auditor.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SignedTranscript {
+    pub entries: Vec<TranscriptEntry>,
+    /// Public key of the signer, for reference; `verify` takes the key to trust.
+    pub signer: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+impl SignedTranscript {
+    /// Checks that the entries form one unbroken chain, signed as a whole by the holder
+    /// of `key`.
+    pub fn verify(&self, key: &VerifyingKey) -> Result<(), TranscriptError> {
+        let mut head = vec![0; 32];
+        for (index, entry) in self.entries.iter().enumerate() {
+            if entry.prev != head {
+                return Err(TranscriptError::BrokenChain { entry: index });
+            }
+            head = entry.hash();
+        }
+        let signature = Signature::from_slice(&self.signature)
+            .map_err(|_| TranscriptError::SignatureInvalid)?;
+        key.verify(&signed_bytes(&head, self.entries.len()), &signature)
+            .map_err(|_| TranscriptError::SignatureInvalid)
+    }
+}
+
+fn signed_bytes(head: &[u8], count: usize) -> Vec<u8> {
+    let mut bytes = TRANSCRIPT_CONTEXT.to_vec();
+    bytes.extend_from_slice(&(count as u64).to_be_bytes());
+    bytes.extend_from_slice(head);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signed_transcript_detects_tampering() {
+        let key = SigningKey::from_bytes(&[3; 32]);
+        let mut transcript = Transcript::new();
+        transcript.record(1_700_000_000, "a:1", b"request 1", b"response 1", None);
+        transcript.record(
+            1_700_000_001,
+            "a:1",
+            b"request 2",
+            b"response 2",
+            Some(vec![5; 32]),
+        );
+        transcript.record(1_700_000_002, "b:2", b"request 3", b"response 3", None);
+        let signed = transcript.sign(&key);
+        assert_eq!(signed.verify(&key.verifying_key()), Ok(()));
+
+        // A different root than the one the server reported
+        let mut altered = signed.clone();
+        altered.entries[1].root = Some(vec![6; 32]);
+        assert_eq!(
+            altered.verify(&key.verifying_key()),
+            Err(TranscriptError::BrokenChain { entry: 2 })
+        );
+
+        // Dropping the last entry keeps the chain but not the signature
+        let mut truncated = signed.clone();
+        truncated.entries.pop();
+        assert_eq!(
+            truncated.verify(&key.verifying_key()),
+            Err(TranscriptError::SignatureInvalid)
+        );
+
+        let other = SigningKey::from_bytes(&[4; 32]);
+        assert_eq!(
+            signed.verify(&other.verifying_key()),
+            Err(TranscriptError::SignatureInvalid)
+        );
+    }
+}
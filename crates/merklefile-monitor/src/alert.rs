@@ -0,0 +1,357 @@
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::report::{Report, ReportFormat};
+
+/// Where and how often to send integrity alerts, loaded from TOML such as:
+///
+/// ```toml
+/// min_interval_secs = 300
+///
+/// [webhook]
+/// url = "https://hooks.example.com/merklefile"
+/// headers = { Authorization = "Bearer ..." }
+///
+/// [email]
+/// smtp_host = "smtp.example.com"
+/// username = "alerts"
+/// password = "..."
+/// from = "merklefile@example.com"
+/// to = ["security@example.com"]
+/// ```
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct AlertConfig {
+    pub webhook: Option<WebhookConfig>,
+    pub email: Option<EmailConfig>,
+    /// Further attempts per channel after a failed delivery.
+    pub retries: u32,
+    /// Delay before the first retry; doubled for every further one.
+    pub retry_delay_secs: u64,
+    /// At most one alert is sent per this many seconds. Alerts raised in between are
+    /// counted and mentioned in the next one, so a flapping check cannot flood anyone.
+    pub min_interval_secs: u64,
+}
+
+impl Default for AlertConfig {
+    fn default() -> Self {
+        Self {
+            webhook: None,
+            email: None,
+            retries: 3,
+            retry_delay_secs: 5,
+            min_interval_secs: 300,
+        }
+    }
+}
+
+impl AlertConfig {
+    pub fn from_toml(contents: &str) -> io::Result<Self> {
+        toml::from_str(contents)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_toml(&std::fs::read_to_string(path)?)
+    }
+}
+
+/// A generic webhook: the alert is POSTed as JSON.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookConfig {
+    pub url: String,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    /// Upgrade a plain connection with STARTTLS (port 587 by default).
+    #[default]
+    Starttls,
+    /// TLS from the first byte (port 465 by default).
+    Tls,
+    /// No encryption, e.g. for a relay on localhost (port 25 by default).
+    None,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct EmailConfig {
+    pub smtp_host: String,
+    pub smtp_port: Option<u16>,
+    #[serde(default)]
+    pub security: SmtpSecurity,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub from: String,
+    pub to: Vec<String>,
+}
+
+/// Something worth waking someone up for.
+#[derive(Debug, Clone)]
+pub struct Alert {
+    pub subject: String,
+    /// Plain-text body, used for email.
+    pub text: String,
+    /// Structured body, POSTed to the webhook.
+    pub json: Value,
+}
+
+impl Alert {
+    /// Files changed under a monitored baseline.
+    pub fn integrity_violation(baseline: &str, report: &Report) -> Self {
+        let json: Value = serde_json::from_str(&report.render(ReportFormat::Json))
+            .expect("rendered report is valid JSON");
+        Self {
+            subject: format!(
+                "merklefile: {} files changed under baseline {}",
+                report.changes.len(),
+                baseline
+            ),
+            text: report.render(ReportFormat::Text),
+            json: json!({
+                "event": "integrity_violation",
+                "baseline": baseline,
+                "report": json,
+            }),
+        }
+    }
+
+    /// A server's self-check found its tree and stored files out of step.
+    pub fn self_check_failed(server: &str, violations: &[String]) -> Self {
+        Self {
+            subject: format!("merklefile: self-check failed on {}", server),
+            text: violations.join("\n"),
+            json: json!({
+                "event": "self_check_failed",
+                "server": server,
+                "violations": violations,
+            }),
+        }
+    }
+
+    /// A transparency log presented a head that is unsigned, rolled back or forked.
+    pub fn log_inconsistent(log: &str, server: &str, problem: &str) -> Self {
+        Self {
+            subject: format!("merklefile: log {} on {} is inconsistent", log, server),
+            text: problem.to_string(),
+            json: json!({
+                "event": "log_inconsistent",
+                "log": log,
+                "server": server,
+                "problem": problem,
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct RateState {
+    last_sent: Option<Instant>,
+    suppressed: u64,
+}
+
+/// Delivers alerts to the configured channels with retries and rate limiting.
+#[derive(Debug)]
+pub struct Alerter {
+    config: AlertConfig,
+    state: Mutex<RateState>,
+}
+
+impl Alerter {
+    pub fn new(config: AlertConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(RateState::default()),
+        }
+    }
+
+    /// Sends `alert` to every channel, unless one was sent less than `min_interval_secs`
+    /// ago. Returns whether it was sent; fails if any channel still failed after retries.
+    pub async fn send(&self, alert: Alert) -> io::Result<bool> {
+        let suppressed = {
+            let mut state = self.state.lock().expect("alert state lock poisoned");
+            let min_interval = Duration::from_secs(self.config.min_interval_secs);
+            if state
+                .last_sent
+                .is_some_and(|last_sent| last_sent.elapsed() < min_interval)
+            {
+                state.suppressed += 1;
+                return Ok(false);
+            }
+            state.last_sent = Some(Instant::now());
+            std::mem::take(&mut state.suppressed)
+        };
+
+        let mut alert = alert;
+        if suppressed > 0 {
+            alert.text.push_str(&format!(
+                "\n\n{} earlier alerts were suppressed by rate limiting",
+                suppressed
+            ));
+            alert.json["suppressed"] = json!(suppressed);
+        }
+
+        let mut result = Ok(true);
+        if let Some(webhook) = &self.config.webhook {
+            let (webhook, body) = (webhook.clone(), alert.json.clone());
+            if let Err(err) = self
+                .with_retries(move || post_webhook(&webhook, &body))
+                .await
+            {
+                result = Err(err);
+            }
+        }
+        if let Some(email) = &self.config.email {
+            let (email, alert) = (email.clone(), alert.clone());
+            if let Err(err) = self.with_retries(move || send_email(&email, &alert)).await {
+                result = Err(err);
+            }
+        }
+        result
+    }
+
+    /// Runs the blocking delivery `attempt` off the async runtime, retrying with
+    /// exponential backoff.
+    async fn with_retries(
+        &self,
+        attempt: impl Fn() -> io::Result<()> + Send + Sync + 'static,
+    ) -> io::Result<()> {
+        let attempt = std::sync::Arc::new(attempt);
+        let mut delay = Duration::from_secs(self.config.retry_delay_secs);
+        let mut retries_left = self.config.retries;
+        loop {
+            let attempt = std::sync::Arc::clone(&attempt);
+            let result = tokio::task::spawn_blocking(move || attempt())
+                .await
+                .map_err(io::Error::other)?;
+            match result {
+                Ok(()) => return Ok(()),
+                Err(err) if retries_left == 0 => return Err(err),
+                Err(err) => {
+                    eprintln!("Alert delivery failed, retrying in {:?}: {}", delay, err);
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                    retries_left -= 1;
+                }
+            }
+        }
+    }
+}
+
+fn post_webhook(webhook: &WebhookConfig, body: &Value) -> io::Result<()> {
+    let mut request = ureq::post(&webhook.url).timeout(Duration::from_secs(30));
+    for (name, value) in &webhook.headers {
+        request = request.set(name, value);
+    }
+    request
+        .send_json(body)
+        .map(|_| ())
+        .map_err(|err| io::Error::other(format!("webhook {}: {}", webhook.url, err)))
+}
+
+fn send_email(email: &EmailConfig, alert: &Alert) -> io::Result<()> {
+    let invalid = |err: String| io::Error::new(io::ErrorKind::InvalidInput, err);
+    let mut message = Message::builder()
+        .from(
+            email
+                .from
+                .parse::<Mailbox>()
+                .map_err(|err| invalid(err.to_string()))?,
+        )
+        .subject(&alert.subject);
+    for to in &email.to {
+        message = message.to(to
+            .parse::<Mailbox>()
+            .map_err(|err| invalid(err.to_string()))?);
+    }
+    let message = message
+        .body(alert.text.clone())
+        .map_err(|err| invalid(err.to_string()))?;
+
+    let smtp_error = |err: lettre::transport::smtp::Error| {
+        io::Error::other(format!("SMTP {}: {}", email.smtp_host, err))
+    };
+    let mut transport = match email.security {
+        SmtpSecurity::Starttls => SmtpTransport::starttls_relay(&email.smtp_host),
+        SmtpSecurity::Tls => SmtpTransport::relay(&email.smtp_host),
+        SmtpSecurity::None => Ok(SmtpTransport::builder_dangerous(&email.smtp_host).port(25)),
+    }
+    .map_err(smtp_error)?;
+    if let Some(port) = email.smtp_port {
+        transport = transport.port(port);
+    }
+    if let (Some(username), Some(password)) = (&email.username, &email.password) {
+        transport = transport.credentials(Credentials::new(username.clone(), password.clone()));
+    }
+    transport
+        .timeout(Some(Duration::from_secs(30)))
+        .build()
+        .send(&message)
+        .map(|_| ())
+        .map_err(smtp_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_webhook_receives_json_and_is_rate_limited() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let receiver = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                    content_length = value.trim().parse().unwrap();
+                }
+                if line == "\r\n" {
+                    break;
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            reader
+                .get_mut()
+                .write_all(b"HTTP/1.1 204 No Content\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+            serde_json::from_slice::<Value>(&body).unwrap()
+        });
+
+        let alerter = Alerter::new(AlertConfig {
+            webhook: Some(WebhookConfig {
+                url,
+                headers: BTreeMap::new(),
+            }),
+            retries: 0,
+            ..AlertConfig::default()
+        });
+        let violations = vec!["tree: leaf 0 mismatch".to_string()];
+        let alert = Alert::self_check_failed("127.0.0.1:8080", &violations);
+        assert!(alerter.send(alert.clone()).await.unwrap());
+        // Within min_interval_secs of the first, so not delivered
+        assert!(!alerter.send(alert).await.unwrap());
+
+        let received = receiver.join().unwrap();
+        assert_eq!(received["event"], "self_check_failed");
+        assert_eq!(received["violations"][0], "tree: leaf 0 mismatch");
+    }
+}
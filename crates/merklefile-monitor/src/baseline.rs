@@ -0,0 +1,166 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+use merklefile_core::merkle_tree::MerkleTree;
+
+use crate::report::{Change, ChangeKind, Report};
+use crate::rules::ScanPath;
+
+/// What a baseline's tree leaves commit to.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LeafEncoding {
+    /// The content hash alone, as on the server.
+    #[default]
+    Content,
+    /// The content hash together with permissions, owner, group, mtime and symlink target,
+    /// so a `chmod 4755` on an unchanged binary changes the root.
+    Metadata,
+}
+
+impl LeafEncoding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LeafEncoding::Content => "content",
+            LeafEncoding::Metadata => "metadata",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "content" => Some(LeafEncoding::Content),
+            "metadata" => Some(LeafEncoding::Metadata),
+            _ => None,
+        }
+    }
+}
+
+/// What a scan recorded about one file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct FileEntry {
+    /// Content hash.
+    pub hash: Vec<u8>,
+    pub size: u64,
+    /// Modification time in nanoseconds since the Unix epoch.
+    pub mtime_ns: i64,
+    /// Permission and file type bits, as in `st_mode`; the file attributes on Windows.
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    /// Where the path points, if it is a symbolic link.
+    pub link_target: Option<String>,
+}
+
+impl FileEntry {
+    /// The file's leaf in a baseline tree with the given encoding.
+    pub fn leaf(&self, encoding: LeafEncoding) -> Vec<u8> {
+        match encoding {
+            LeafEncoding::Content => self.hash.clone(),
+            LeafEncoding::Metadata => {
+                let mut hasher = Sha256::new();
+                // Domain-separated from content hashes, which are hashes of raw file bytes
+                hasher.update(b"merklefile-metadata-leaf-v1");
+                hasher.update(&self.hash);
+                hasher.update(self.mode.to_be_bytes());
+                hasher.update(self.uid.to_be_bytes());
+                hasher.update(self.gid.to_be_bytes());
+                hasher.update(self.mtime_ns.to_be_bytes());
+                if let Some(target) = &self.link_target {
+                    hasher.update((target.len() as u64).to_be_bytes());
+                    hasher.update(target.as_bytes());
+                }
+                hasher.finalize().to_vec()
+            }
+        }
+    }
+
+    fn metadata_matches(&self, other: &FileEntry) -> bool {
+        (
+            self.mode,
+            self.uid,
+            self.gid,
+            self.mtime_ns,
+            &self.link_target,
+        ) == (
+            other.mode,
+            other.uid,
+            other.gid,
+            other.mtime_ns,
+            &other.link_target,
+        )
+    }
+}
+
+/// A recorded state of the monitored paths: every file's content hash and metadata, and the
+/// root of the Merkle tree over their leaves.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Baseline {
+    /// Paths that were scanned and their rules, so later checks can rescan the same set.
+    pub paths: Vec<ScanPath>,
+    pub encoding: LeafEncoding,
+    pub root: Vec<u8>,
+    /// Every file, keyed by full path.
+    pub files: BTreeMap<String, FileEntry>,
+}
+
+impl Baseline {
+    pub fn new(
+        paths: Vec<ScanPath>,
+        encoding: LeafEncoding,
+        files: BTreeMap<String, FileEntry>,
+    ) -> Self {
+        // An empty scan gets the same root as an empty server
+        let root = if files.is_empty() {
+            MerkleTree::new(vec![vec![]]).get_root_hash()
+        } else {
+            MerkleTree::from_leaf_hashes(files.values().map(|entry| entry.leaf(encoding)).collect())
+                .get_root_hash()
+        };
+        Self {
+            paths,
+            encoding,
+            root,
+            files,
+        }
+    }
+
+    /// Everything that differs between this baseline and a later scan.
+    pub fn compare(&self, current: &Baseline) -> Report {
+        let mut changes = BTreeMap::new();
+        for (path, old) in &self.files {
+            let kind = match current.files.get(path) {
+                None => ChangeKind::Removed,
+                Some(new) if new.hash != old.hash => ChangeKind::Modified,
+                Some(new)
+                    if self.encoding == LeafEncoding::Metadata && !new.metadata_matches(old) =>
+                {
+                    ChangeKind::MetadataChanged
+                }
+                Some(_) => continue,
+            };
+            let change = Change {
+                kind,
+                old: Some(old.clone()),
+                new: current.files.get(path).cloned(),
+            };
+            changes.insert(path.clone(), change);
+        }
+        for (path, new) in &current.files {
+            if !self.files.contains_key(path) {
+                let change = Change {
+                    kind: ChangeKind::Added,
+                    old: None,
+                    new: Some(new.clone()),
+                };
+                changes.insert(path.clone(), change);
+            }
+        }
+
+        Report {
+            old_root: self.root.clone(),
+            new_root: current.root.clone(),
+            changes,
+        }
+    }
+}
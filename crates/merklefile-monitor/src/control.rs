@@ -0,0 +1,288 @@
+use merklefile_core::merkle_tree::HashBackend;
+use serde::Serialize;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::Notify;
+
+use crate::daemon::ScanOutcome;
+
+/// Commands accepted on the monitor daemon's control socket, one per line. Each is answered
+/// with one line: `ok`, `error: ...`, or for `status` a JSON [`DaemonStatus`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlCommand {
+    Status,
+    /// Scan right away, even while paused, instead of waiting for the schedule.
+    ScanNow,
+    /// Skip scheduled scans until resumed.
+    Pause,
+    Resume,
+    /// Record a fresh baseline from the current state of the paths, replacing the stored
+    /// one, e.g. after a reviewed change.
+    RotateBaseline,
+}
+
+impl ControlCommand {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ControlCommand::Status => "status",
+            ControlCommand::ScanNow => "scan-now",
+            ControlCommand::Pause => "pause",
+            ControlCommand::Resume => "resume",
+            ControlCommand::RotateBaseline => "rotate-baseline",
+        }
+    }
+}
+
+impl FromStr for ControlCommand {
+    type Err = String;
+
+    fn from_str(command: &str) -> Result<Self, Self::Err> {
+        match command {
+            "status" => Ok(ControlCommand::Status),
+            "scan-now" => Ok(ControlCommand::ScanNow),
+            "pause" => Ok(ControlCommand::Pause),
+            "resume" => Ok(ControlCommand::Resume),
+            "rotate-baseline" => Ok(ControlCommand::RotateBaseline),
+            _ => Err(format!(
+                "unknown command '{}', expected status, scan-now, pause, resume or \
+                 rotate-baseline",
+                command
+            )),
+        }
+    }
+}
+
+/// What the daemon is doing, as reported by the `status` command.
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct DaemonStatus {
+    pub paused: bool,
+    pub scanning: bool,
+    /// Runs completed since the daemon started.
+    pub scans: u64,
+    /// When the last run finished, in seconds since the Unix epoch.
+    pub last_scan_at: Option<u64>,
+    /// Summary of the last run's outcome.
+    pub last_result: Option<String>,
+    /// When the next scheduled scan is due, in seconds since the Unix epoch.
+    pub next_scan_at: Option<u64>,
+    /// How files are hashed on this machine, to confirm hardware acceleration is in use.
+    pub hash_backend: HashBackend,
+}
+
+/// A run asked for by a control command rather than the schedule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Request {
+    ScanNow,
+    RotateBaseline,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    status: DaemonStatus,
+    scan_requested: bool,
+    rotate_requested: bool,
+}
+
+/// State shared between the daemon's scan loop and its control socket.
+#[derive(Debug, Default)]
+pub(crate) struct Control {
+    state: Mutex<State>,
+    wake: Notify,
+}
+
+impl Control {
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().expect("control state lock poisoned")
+    }
+
+    pub(crate) fn handle(&self, command: ControlCommand) -> String {
+        let mut state = self.state();
+        match command {
+            ControlCommand::Status => {
+                return serde_json::to_string(&state.status).expect("status serializes")
+            }
+            ControlCommand::ScanNow => state.scan_requested = true,
+            ControlCommand::Pause => state.status.paused = true,
+            ControlCommand::Resume => state.status.paused = false,
+            ControlCommand::RotateBaseline => state.rotate_requested = true,
+        }
+        self.wake.notify_one();
+        "ok".to_string()
+    }
+
+    /// Waits until a control command has been handled since the last call.
+    pub(crate) async fn woken(&self) {
+        self.wake.notified().await
+    }
+
+    /// Takes the pending requested run, rotations first.
+    pub(crate) fn take_request(&self) -> Option<Request> {
+        let mut state = self.state();
+        if std::mem::take(&mut state.rotate_requested) {
+            Some(Request::RotateBaseline)
+        } else if std::mem::take(&mut state.scan_requested) {
+            Some(Request::ScanNow)
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn is_paused(&self) -> bool {
+        self.state().status.paused
+    }
+
+    pub(crate) fn scheduled(&self, at: SystemTime) {
+        self.state().status.next_scan_at = Some(unix_secs(at));
+    }
+
+    pub(crate) fn started(&self) {
+        self.state().status.scanning = true;
+    }
+
+    pub(crate) fn finished(&self, outcome: &io::Result<ScanOutcome>) {
+        let mut state = self.state();
+        let status = &mut state.status;
+        status.scanning = false;
+        status.scans += 1;
+        status.last_scan_at = Some(unix_secs(SystemTime::now()));
+        status.last_result = Some(match outcome {
+            Ok(ScanOutcome::Recorded(root)) => format!("recorded baseline {}", hex::encode(root)),
+            Ok(ScanOutcome::Checked(report)) if report.is_clean() => "clean".to_string(),
+            Ok(ScanOutcome::Checked(report)) => format!("{} changes", report.changes.len()),
+            Ok(ScanOutcome::Skipped) => "skipped".to_string(),
+            Err(err) => format!("failed: {}", err),
+        });
+    }
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+/// Sends one command to the daemon listening on `path` and returns its answer.
+#[cfg(unix)]
+pub async fn send_command(path: impl AsRef<Path>, command: ControlCommand) -> io::Result<String> {
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    let mut stream = tokio::net::UnixStream::connect(path).await?;
+    stream
+        .write_all(format!("{}\n", command.as_str()).as_bytes())
+        .await?;
+    let mut answer = String::new();
+    BufReader::new(stream).read_line(&mut answer).await?;
+    Ok(answer.trim_end().to_string())
+}
+
+#[cfg(not(unix))]
+pub async fn send_command(_path: impl AsRef<Path>, _command: ControlCommand) -> io::Result<String> {
+    Err(unsupported())
+}
+
+/// Binds the control socket, replacing a stale socket file left by a daemon that died but
+/// refusing to take over from one still listening. Only the owner may connect.
+#[cfg(unix)]
+pub(crate) fn bind(path: &Path) -> io::Result<tokio::net::UnixListener> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", path.display()),
+            ));
+        }
+        if std::os::unix::net::UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("another daemon is listening on {}", path.display()),
+            ));
+        }
+        std::fs::remove_file(path)?;
+    }
+    let listener = tokio::net::UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+/// Answers control connections until the listener fails. Each connection may send any
+/// number of commands.
+#[cfg(unix)]
+pub(crate) async fn serve(listener: tokio::net::UnixListener, control: std::sync::Arc<Control>) {
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                eprintln!("Control socket failed: {}", err);
+                return;
+            }
+        };
+        let control = std::sync::Arc::clone(&control);
+        tokio::spawn(async move {
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                let answer = match line.trim().parse() {
+                    Ok(command) => control.handle(command),
+                    Err(err) => format!("error: {}", err),
+                };
+                if writer
+                    .write_all(format!("{}\n", answer).as_bytes())
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "control sockets are only supported on Unix",
+    )
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_control_socket_commands() {
+        let path =
+            std::env::temp_dir().join(format!("merklefile-control-{}.sock", std::process::id()));
+        let control = Arc::new(Control::default());
+        tokio::spawn(serve(bind(&path).unwrap(), Arc::clone(&control)));
+        // A second daemon must not steal the socket
+        assert!(bind(&path).is_err());
+
+        assert_eq!(
+            send_command(&path, ControlCommand::Pause).await.unwrap(),
+            "ok"
+        );
+        send_command(&path, ControlCommand::ScanNow).await.unwrap();
+        send_command(&path, ControlCommand::RotateBaseline)
+            .await
+            .unwrap();
+        let status = send_command(&path, ControlCommand::Status).await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let status: serde_json::Value = serde_json::from_str(&status).unwrap();
+        assert_eq!(status["paused"], true);
+        assert!(control.is_paused());
+        assert_eq!(control.take_request(), Some(Request::RotateBaseline));
+        assert_eq!(control.take_request(), Some(Request::ScanNow));
+        assert_eq!(control.take_request(), None);
+        assert!("reboot".parse::<ControlCommand>().is_err());
+    }
+}
@@ -0,0 +1,306 @@
+use serde::Deserialize;
+use std::collections::hash_map::RandomState;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::hash::BuildHasher;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use merklefile_client::Client;
+
+use crate::alert::{Alert, AlertConfig, Alerter};
+use crate::baseline::LeafEncoding;
+use crate::control::{self, Control, Request};
+use crate::db::BaselineDb;
+use crate::report::{Report, ReportFormat};
+use crate::rules::ScanPath;
+use crate::schedule::{CronExpr, Schedule};
+use crate::Monitor;
+
+/// Settings for `merklefile monitor daemon`, loaded from a TOML file such as:
+///
+/// ```toml
+/// paths = ["/etc", { path = "/", exclude = ["proc", "sys"], one_file_system = true }]
+/// db = "/var/lib/merklefile/monitor.db"
+/// baseline = "default"
+/// cron = "*/30 * * * *"     # or: interval_secs = 1800
+/// jitter_secs = 120
+/// push = true
+/// format = "json"
+/// encoding = "metadata"
+/// control_socket = "/run/merklefile/monitor.sock"
+///
+/// [alerts]
+/// webhook = { url = "https://hooks.example.com/merklefile" }
+/// ```
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct MonitorConfig {
+    /// Bare paths, or tables with `path` and its [`ScanRules`](crate::ScanRules).
+    pub paths: Vec<ScanPath>,
+    #[serde(default = "default_db")]
+    pub db: PathBuf,
+    /// Name of the baseline to check against; recorded on the first run if missing.
+    #[serde(default = "default_baseline")]
+    pub baseline: String,
+    /// Seconds between the end of one scan and the start of the next.
+    pub interval_secs: Option<u64>,
+    /// Five-field cron expression, in UTC; an alternative to `interval_secs`.
+    pub cron: Option<String>,
+    /// Each scan is delayed by a random amount up to this, so a fleet of hosts sharing a
+    /// schedule does not hit its disks (and the server) at the same moment.
+    #[serde(default)]
+    pub jitter_secs: u64,
+    /// File locked for the duration of a scan; defaults to the database path plus `.lock`.
+    pub lock_file: Option<PathBuf>,
+    /// Upload the baseline root to the server when it is first recorded.
+    #[serde(default)]
+    pub push: bool,
+    /// What a recorded baseline's leaves commit to; `"metadata"` also covers permissions,
+    /// ownership, mtime and symlink targets. An existing baseline keeps its own encoding.
+    #[serde(default)]
+    pub encoding: LeafEncoding,
+    /// How reports of changes are printed.
+    #[serde(default)]
+    pub format: ReportFormat,
+    /// Notify someone when a scan finds changes.
+    pub alerts: Option<AlertConfig>,
+    /// Unix socket on which the daemon accepts [`ControlCommand`](crate::ControlCommand)s, so it can be managed
+    /// without a restart.
+    pub control_socket: Option<PathBuf>,
+}
+
+fn default_db() -> PathBuf {
+    PathBuf::from("merklefile-monitor.db")
+}
+
+fn default_baseline() -> String {
+    "default".to_string()
+}
+
+impl MonitorConfig {
+    pub fn from_toml(contents: &str) -> io::Result<Self> {
+        let config: Self = toml::from_str(contents)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+        config.schedule()?;
+        for scan_path in &config.paths {
+            scan_path.rules.validate()?;
+        }
+        Ok(config)
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_toml(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn schedule(&self) -> io::Result<Schedule> {
+        match (self.interval_secs, &self.cron) {
+            (Some(0), None) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "interval_secs must be positive",
+            )),
+            (Some(secs), None) => Ok(Schedule::Interval(Duration::from_secs(secs))),
+            (None, Some(expr)) => Ok(Schedule::Cron(CronExpr::parse(expr)?)),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "exactly one of interval_secs and cron must be set",
+            )),
+        }
+    }
+
+    pub fn lock_file(&self) -> PathBuf {
+        self.lock_file.clone().unwrap_or_else(|| {
+            let mut path = self.db.clone().into_os_string();
+            path.push(".lock");
+            PathBuf::from(path)
+        })
+    }
+}
+
+/// An exclusive lock held for the duration of a scan, so scheduled runs never overlap each
+/// other or a second daemon on the same database. The operating system releases it if the
+/// process dies.
+#[derive(Debug)]
+pub struct ScanLock {
+    _file: File,
+}
+
+impl ScanLock {
+    /// Takes the lock, or returns `None` if another scan holds it.
+    pub fn try_acquire(path: impl AsRef<Path>) -> io::Result<Option<Self>> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)?;
+        match file.try_lock() {
+            Ok(()) => Ok(Some(Self { _file: file })),
+            Err(TryLockError::WouldBlock) => Ok(None),
+            Err(TryLockError::Error(err)) => Err(err),
+        }
+    }
+}
+
+/// What one scheduled run did.
+#[derive(Debug)]
+pub enum ScanOutcome {
+    /// No baseline existed yet, so this run recorded one with the given root.
+    Recorded(Vec<u8>),
+    Checked(Report),
+    /// Another scan held the lock.
+    Skipped,
+}
+
+/// Runs monitor scans unattended on a schedule.
+#[derive(Debug)]
+pub struct Daemon {
+    monitor: Monitor,
+    config: MonitorConfig,
+    schedule: Schedule,
+    alerter: Option<Alerter>,
+}
+
+impl Daemon {
+    pub fn new(client: Client, config: MonitorConfig) -> io::Result<Self> {
+        Ok(Self {
+            monitor: Monitor::new(client, config.paths.clone()).with_encoding(config.encoding),
+            schedule: config.schedule()?,
+            alerter: config.alerts.clone().map(Alerter::new),
+            config,
+        })
+    }
+
+    pub fn config(&self) -> &MonitorConfig {
+        &self.config
+    }
+
+    /// Scans forever, passing each outcome to `on_scan` and alerting on changes. A failed
+    /// scan is logged and retried at the next scheduled time. With a control socket
+    /// configured, commands on it can also pause scheduled scans or trigger runs early.
+    pub async fn run(&self, mut on_scan: impl FnMut(ScanOutcome)) -> io::Result<()> {
+        let control = Arc::new(Control::default());
+        if let Some(path) = &self.config.control_socket {
+            self.listen(path, &control)?;
+        }
+        let jitter = Duration::from_secs(self.config.jitter_secs);
+        loop {
+            let delay = self.schedule.delay_from(SystemTime::now())? + random_delay(jitter);
+            control.scheduled(SystemTime::now() + delay);
+            let deadline = tokio::time::Instant::now() + delay;
+            let request = loop {
+                tokio::select! {
+                    _ = tokio::time::sleep_until(deadline) => break None,
+                    _ = control.woken() => {
+                        if let Some(request) = control.take_request() {
+                            break Some(request);
+                        }
+                    }
+                }
+            };
+            if request.is_none() && control.is_paused() {
+                continue;
+            }
+
+            control.started();
+            let outcome = match request {
+                Some(Request::RotateBaseline) => self.rotate_baseline().await,
+                _ => self.run_once().await,
+            };
+            control.finished(&outcome);
+            match outcome {
+                Ok(ScanOutcome::Checked(report)) if !report.is_clean() => {
+                    if let Some(alerter) = &self.alerter {
+                        let alert = Alert::integrity_violation(&self.config.baseline, &report);
+                        if let Err(err) = alerter.send(alert).await {
+                            eprintln!("Alert failed: {}", err);
+                        }
+                    }
+                    on_scan(ScanOutcome::Checked(report))
+                }
+                Ok(outcome) => on_scan(outcome),
+                Err(err) => eprintln!("Scan failed: {}", err),
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    fn listen(&self, path: &Path, control: &Arc<Control>) -> io::Result<()> {
+        tokio::spawn(control::serve(control::bind(path)?, Arc::clone(control)));
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn listen(&self, _path: &Path, _control: &Arc<Control>) -> io::Result<()> {
+        Err(control::unsupported())
+    }
+
+    /// Checks the paths against the configured baseline, recording it first if missing.
+    pub async fn run_once(&self) -> io::Result<ScanOutcome> {
+        let Some(_lock) = ScanLock::try_acquire(self.config.lock_file())? else {
+            return Ok(ScanOutcome::Skipped);
+        };
+        let mut db = BaselineDb::open(&self.config.db)?;
+        match db.load(&self.config.baseline)? {
+            Some(baseline) => Ok(ScanOutcome::Checked(self.monitor.check(&baseline)?)),
+            None => self.record(&mut db).await,
+        }
+    }
+
+    /// Records the current state of the paths as the configured baseline, replacing the
+    /// stored one.
+    pub async fn rotate_baseline(&self) -> io::Result<ScanOutcome> {
+        let Some(_lock) = ScanLock::try_acquire(self.config.lock_file())? else {
+            return Ok(ScanOutcome::Skipped);
+        };
+        self.record(&mut BaselineDb::open(&self.config.db)?).await
+    }
+
+    async fn record(&self, db: &mut BaselineDb) -> io::Result<ScanOutcome> {
+        let baseline = self.monitor.scan()?;
+        db.save(&self.config.baseline, &baseline)?;
+        if self.config.push {
+            self.monitor.push(&baseline, &self.config.baseline).await?;
+        }
+        Ok(ScanOutcome::Recorded(baseline.root))
+    }
+}
+
+/// A uniformly random duration up to `max`, at millisecond granularity.
+fn random_delay(max: Duration) -> Duration {
+    if max.is_zero() {
+        return Duration::ZERO;
+    }
+    let random = RandomState::new().hash_one(SystemTime::now());
+    Duration::from_millis(random % (max.as_millis() as u64 + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_requires_one_schedule() {
+        let config = MonitorConfig::from_toml("paths = [\"/etc\"]\ncron = \"0 * * * *\"").unwrap();
+        assert_eq!(
+            config.lock_file(),
+            PathBuf::from("merklefile-monitor.db.lock")
+        );
+        assert!(MonitorConfig::from_toml("paths = [\"/etc\"]").is_err());
+        assert!(MonitorConfig::from_toml(
+            "paths = [\"/etc\"]\ninterval_secs = 60\ncron = \"0 * * * *\""
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_scan_lock_is_exclusive() {
+        let path = std::env::temp_dir().join(format!("merklefile-lock-{}", std::process::id()));
+        let lock = ScanLock::try_acquire(&path).unwrap();
+        assert!(lock.is_some());
+        assert!(ScanLock::try_acquire(&path).unwrap().is_none());
+        drop(lock);
+        assert!(ScanLock::try_acquire(&path).unwrap().is_some());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,264 @@
+use rusqlite::types::Type;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use merklefile_client::StorageError;
+
+use crate::baseline::{Baseline, FileEntry, LeafEncoding};
+use crate::rules::{ScanPath, ScanRules};
+
+/// Summary of a stored baseline, as listed by [`BaselineDb::list`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BaselineInfo {
+    pub name: String,
+    /// When the baseline was saved, in seconds since the Unix epoch.
+    pub created_at: i64,
+    pub root: Vec<u8>,
+    pub file_count: u64,
+}
+
+/// Local SQLite database of named baselines, so a scan can be compared against any of them.
+#[derive(Debug)]
+pub struct BaselineDb {
+    conn: Connection,
+}
+
+impl BaselineDb {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StorageError> {
+        Self::init(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> Result<Self, StorageError> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> Result<Self, StorageError> {
+        conn.execute_batch(
+            "PRAGMA foreign_keys = ON;
+            CREATE TABLE IF NOT EXISTS baselines (
+                name TEXT PRIMARY KEY,
+                created_at INTEGER NOT NULL,
+                encoding TEXT NOT NULL,
+                root BLOB NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS baseline_paths (
+                baseline TEXT NOT NULL REFERENCES baselines(name) ON DELETE CASCADE,
+                position INTEGER NOT NULL,
+                path TEXT NOT NULL,
+                exclude TEXT NOT NULL,
+                max_depth INTEGER,
+                one_file_system INTEGER NOT NULL,
+                max_file_size INTEGER,
+                PRIMARY KEY (baseline, position)
+            );
+            CREATE TABLE IF NOT EXISTS baseline_files (
+                baseline TEXT NOT NULL REFERENCES baselines(name) ON DELETE CASCADE,
+                path TEXT NOT NULL,
+                hash BLOB NOT NULL,
+                size INTEGER NOT NULL,
+                mtime_ns INTEGER NOT NULL,
+                mode INTEGER NOT NULL,
+                uid INTEGER NOT NULL,
+                gid INTEGER NOT NULL,
+                link_target TEXT,
+                PRIMARY KEY (baseline, path)
+            )",
+        )?;
+        Ok(Self { conn })
+    }
+
+    /// Stores `baseline` under `name`, replacing any baseline of that name.
+    pub fn save(&mut self, name: &str, baseline: &Baseline) -> Result<(), StorageError> {
+        let created_at = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs() as i64)
+            .unwrap_or(0);
+
+        let tx = self.conn.transaction()?;
+        tx.execute("DELETE FROM baselines WHERE name = ?1", params![name])?;
+        tx.execute(
+            "INSERT INTO baselines (name, created_at, encoding, root) VALUES (?1, ?2, ?3, ?4)",
+            params![name, created_at, baseline.encoding.as_str(), baseline.root],
+        )?;
+        for (position, scan_path) in baseline.paths.iter().enumerate() {
+            let rules = &scan_path.rules;
+            tx.execute(
+                "INSERT INTO baseline_paths (baseline, position, path, exclude, max_depth,
+                     one_file_system, max_file_size)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    name,
+                    position as i64,
+                    scan_path.path.to_string_lossy(),
+                    rules.exclude.join("\n"),
+                    rules.max_depth.map(|depth| depth as i64),
+                    rules.one_file_system,
+                    rules.max_file_size.map(|size| size as i64)
+                ],
+            )?;
+        }
+        for (path, entry) in &baseline.files {
+            tx.execute(
+                "INSERT INTO baseline_files (baseline, path, hash, size, mtime_ns, mode, uid,
+                     gid, link_target)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                params![
+                    name,
+                    path,
+                    entry.hash,
+                    entry.size as i64,
+                    entry.mtime_ns,
+                    entry.mode,
+                    entry.uid,
+                    entry.gid,
+                    entry.link_target
+                ],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn load(&self, name: &str) -> Result<Option<Baseline>, StorageError> {
+        let found: Option<(String, Vec<u8>)> = self
+            .conn
+            .query_row(
+                "SELECT encoding, root FROM baselines WHERE name = ?1",
+                params![name],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        let Some((encoding, root)) = found else {
+            return Ok(None);
+        };
+        let encoding = LeafEncoding::parse(&encoding).ok_or_else(|| {
+            rusqlite::Error::FromSqlConversionFailure(
+                0,
+                Type::Text,
+                format!("unknown leaf encoding '{}'", encoding).into(),
+            )
+        })?;
+
+        let mut statement = self.conn.prepare(
+            "SELECT path, exclude, max_depth, one_file_system, max_file_size
+             FROM baseline_paths WHERE baseline = ?1 ORDER BY position",
+        )?;
+        let paths = statement
+            .query_map(params![name], |row| {
+                let exclude: String = row.get(1)?;
+                Ok(ScanPath {
+                    path: PathBuf::from(row.get::<_, String>(0)?),
+                    rules: ScanRules {
+                        // Patterns are stored newline-separated
+                        exclude: exclude.lines().map(str::to_string).collect(),
+                        max_depth: row.get::<_, Option<i64>>(2)?.map(|depth| depth as usize),
+                        one_file_system: row.get(3)?,
+                        max_file_size: row.get::<_, Option<i64>>(4)?.map(|size| size as u64),
+                    },
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut statement = self.conn.prepare(
+            "SELECT path, hash, size, mtime_ns, mode, uid, gid, link_target
+                 FROM baseline_files WHERE baseline = ?1",
+        )?;
+        let files = statement
+            .query_map(params![name], |row| {
+                let entry = FileEntry {
+                    hash: row.get(1)?,
+                    size: row.get::<_, i64>(2)? as u64,
+                    mtime_ns: row.get(3)?,
+                    mode: row.get(4)?,
+                    uid: row.get(5)?,
+                    gid: row.get(6)?,
+                    link_target: row.get(7)?,
+                };
+                Ok((row.get(0)?, entry))
+            })?
+            .collect::<Result<BTreeMap<_, _>, _>>()?;
+
+        Ok(Some(Baseline {
+            paths,
+            encoding,
+            root,
+            files,
+        }))
+    }
+
+    /// Every stored baseline, oldest first.
+    pub fn list(&self) -> Result<Vec<BaselineInfo>, StorageError> {
+        let mut statement = self.conn.prepare(
+            "SELECT name, created_at, root,
+                 (SELECT COUNT(*) FROM baseline_files WHERE baseline = name)
+             FROM baselines ORDER BY created_at, name",
+        )?;
+        let baselines = statement
+            .query_map([], |row| {
+                Ok(BaselineInfo {
+                    name: row.get(0)?,
+                    created_at: row.get(1)?,
+                    root: row.get(2)?,
+                    file_count: row.get::<_, i64>(3)? as u64,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(baselines)
+    }
+
+    /// Deletes a baseline; returns whether it existed.
+    pub fn remove(&self, name: &str) -> Result<bool, StorageError> {
+        let deleted = self
+            .conn
+            .execute("DELETE FROM baselines WHERE name = ?1", params![name])?;
+        Ok(deleted > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_baseline_round_trip() {
+        let mut db = BaselineDb::open_in_memory().unwrap();
+        let mut files = BTreeMap::new();
+        files.insert(
+            "/etc/hosts".to_string(),
+            FileEntry {
+                hash: vec![7; 32],
+                size: 20,
+                mtime_ns: 1_700_000_000_000_000_000,
+                mode: 0o100644,
+                uid: 0,
+                gid: 0,
+                link_target: Some("/etc/hosts.real".to_string()),
+            },
+        );
+        let scan_path = ScanPath {
+            path: PathBuf::from("/etc"),
+            rules: ScanRules {
+                exclude: vec!["*.bak".to_string(), "ssl".to_string()],
+                max_depth: Some(3),
+                one_file_system: true,
+                max_file_size: None,
+            },
+        };
+        let baseline = Baseline::new(vec![scan_path], LeafEncoding::Metadata, files);
+
+        db.save("etc", &baseline).unwrap();
+        db.save("etc", &baseline).unwrap();
+        assert_eq!(db.load("etc").unwrap(), Some(baseline.clone()));
+        let listed = db.list().unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(
+            (listed[0].root.clone(), listed[0].file_count),
+            (baseline.root, 1)
+        );
+
+        assert!(db.remove("etc").unwrap());
+        assert_eq!(db.load("etc").unwrap(), None);
+        assert!(db.list().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,206 @@
+//! File integrity monitoring: record a baseline of local paths, then report every file that
+//! was added, removed or modified since.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+use merklefile_client::{Client, SymlinkPolicy};
+
+mod alert;
+mod baseline;
+mod control;
+mod daemon;
+mod db;
+mod report;
+mod rules;
+mod schedule;
+
+pub use alert::{Alert, AlertConfig, Alerter, EmailConfig, SmtpSecurity, WebhookConfig};
+pub use baseline::{Baseline, FileEntry, LeafEncoding};
+pub use control::{send_command, ControlCommand, DaemonStatus};
+pub use daemon::{Daemon, MonitorConfig, ScanLock, ScanOutcome};
+pub use db::{BaselineDb, BaselineInfo};
+pub use report::{Change, ChangeKind, Report, ReportFormat};
+pub use rules::{ScanPath, ScanRules};
+pub use schedule::{CronExpr, Schedule};
+
+/// Scans a fixed set of files and directories, each under its own scan rules, hashing with
+/// the client's configuration (ignore patterns, symlink and special file policies, hashing
+/// concurrency).
+#[derive(Debug, Clone)]
+pub struct Monitor {
+    client: Client,
+    paths: Vec<ScanPath>,
+    encoding: LeafEncoding,
+}
+
+impl Monitor {
+    pub fn new(client: Client, paths: Vec<ScanPath>) -> Self {
+        Self {
+            client,
+            paths,
+            encoding: LeafEncoding::Content,
+        }
+    }
+
+    /// Sets what the leaves of new baselines commit to; checks always use the encoding of
+    /// the baseline they compare against.
+    pub fn with_encoding(mut self, encoding: LeafEncoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    pub fn paths(&self) -> &[ScanPath] {
+        &self.paths
+    }
+
+    /// Hashes every monitored file into a new baseline.
+    pub fn scan(&self) -> io::Result<Baseline> {
+        self.scan_with(self.encoding)
+    }
+
+    fn scan_with(&self, encoding: LeafEncoding) -> io::Result<Baseline> {
+        let mut listed = BTreeMap::new();
+        for scan_path in &self.paths {
+            scan_path.rules.validate()?;
+            listed.extend(scan_path.walk(self.client.config())?);
+        }
+        let mut files = BTreeMap::new();
+        let symlinks = self.client.config().symlinks;
+        for (path, hash) in self.client.hash_files(listed, None)? {
+            let entry = file_entry(Path::new(&path), symlinks, hash)?;
+            files.insert(path, entry);
+        }
+        Ok(Baseline::new(self.paths.clone(), encoding, files))
+    }
+
+    /// Scans the monitored paths and compares them against `baseline`.
+    pub fn check(&self, baseline: &Baseline) -> io::Result<Report> {
+        Ok(baseline.compare(&self.scan_with(baseline.encoding)?))
+    }
+
+    /// Uploads the baseline root as `monitor/<name>.root`, so the server's tree attests to
+    /// it, and returns the server's new root.
+    pub async fn push(&self, baseline: &Baseline, name: &str) -> io::Result<Vec<u8>> {
+        let mut files = BTreeMap::new();
+        files.insert(
+            format!("monitor/{}.root", name),
+            hex::encode(&baseline.root).into_bytes(),
+        );
+        self.client.upload_files(files).await?;
+        self.client.get_root_hash().await
+    }
+}
+
+fn file_entry(path: &Path, symlinks: SymlinkPolicy, hash: Vec<u8>) -> io::Result<FileEntry> {
+    let metadata = symlinks.metadata(path)?;
+    let mtime = metadata
+        .modified()?
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_err(io::Error::other)?;
+    let link_target = if std::fs::symlink_metadata(path)?.is_symlink() {
+        Some(std::fs::read_link(path)?.to_string_lossy().into_owned())
+    } else {
+        None
+    };
+    let (mode, uid, gid) = ownership(&metadata);
+    Ok(FileEntry {
+        hash,
+        size: metadata.len(),
+        mtime_ns: mtime.as_nanos() as i64,
+        mode,
+        uid,
+        gid,
+        link_target,
+    })
+}
+
+#[cfg(unix)]
+fn ownership(metadata: &std::fs::Metadata) -> (u32, u32, u32) {
+    use std::os::unix::fs::MetadataExt;
+    (metadata.mode(), metadata.uid(), metadata.gid())
+}
+
+/// On Windows the NTFS attributes (read-only, hidden, system, ...) stand in for the mode;
+/// there are no numeric owners.
+#[cfg(windows)]
+fn ownership(metadata: &std::fs::Metadata) -> (u32, u32, u32) {
+    use std::os::windows::fs::MetadataExt;
+    (metadata.file_attributes(), 0, 0)
+}
+
+/// Without Unix permissions, only the read-only flag is recorded, as the owner write bit.
+#[cfg(not(any(unix, windows)))]
+fn ownership(metadata: &std::fs::Metadata) -> (u32, u32, u32) {
+    let mode = if metadata.permissions().readonly() {
+        0o444
+    } else {
+        0o644
+    };
+    (mode, 0, 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_reports_changes_with_evidence() {
+        let dir = std::env::temp_dir().join(format!("merklefile-monitor-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("etc")).unwrap();
+        std::fs::write(dir.join("etc/passwd"), b"root:x:0:0").unwrap();
+        std::fs::write(dir.join("etc/hosts"), b"127.0.0.1 localhost").unwrap();
+
+        let monitor = Monitor::new(Client::default(), vec![dir.clone().into()]);
+        let baseline = monitor.scan().unwrap();
+        assert_eq!(baseline.files.len(), 2);
+        assert!(monitor.check(&baseline).unwrap().is_clean());
+
+        std::fs::write(dir.join("etc/passwd"), b"root:x:0:0\nevil:x:0:0").unwrap();
+        std::fs::remove_file(dir.join("etc/hosts")).unwrap();
+        std::fs::write(dir.join("etc/shadow"), b"evil:$6$").unwrap();
+        let report = monitor.check(&baseline).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let kinds: Vec<_> = report.changes.values().map(|change| change.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![ChangeKind::Removed, ChangeKind::Modified, ChangeKind::Added]
+        );
+        let (path, modified) = report.changes.iter().nth(1).unwrap();
+        assert!(path.ends_with("etc/passwd"));
+        assert_eq!(modified.old.as_ref(), baseline.files.get(path));
+        assert_eq!(modified.new.as_ref().unwrap().size, 21);
+        assert_ne!(report.old_root, report.new_root);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_metadata_encoding_catches_chmod() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("merklefile-chmod-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let binary = dir.join("su");
+        std::fs::write(&binary, b"\x7fELF").unwrap();
+        std::fs::set_permissions(&binary, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let content_only = Monitor::new(Client::default(), vec![dir.clone().into()]);
+        let with_metadata = content_only.clone().with_encoding(LeafEncoding::Metadata);
+        let content_baseline = content_only.scan().unwrap();
+        let metadata_baseline = with_metadata.scan().unwrap();
+        assert_ne!(content_baseline.root, metadata_baseline.root);
+
+        std::fs::set_permissions(&binary, std::fs::Permissions::from_mode(0o4755)).unwrap();
+        let content_report = content_only.check(&content_baseline).unwrap();
+        let metadata_report = with_metadata.check(&metadata_baseline).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(content_report.is_clean());
+        let change = &metadata_report.changes[binary.to_str().unwrap()];
+        assert_eq!(change.kind, ChangeKind::MetadataChanged);
+        assert_eq!(change.new.as_ref().unwrap().mode & 0o7777, 0o4755);
+        assert_ne!(metadata_report.old_root, metadata_report.new_root);
+    }
+}
@@ -0,0 +1,286 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::baseline::FileEntry;
+
+/// How a [`Report`] is rendered: text for people, JSON and CSV for SIEM pipelines.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportFormat {
+    #[default]
+    Text,
+    Json,
+    Csv,
+}
+
+impl FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "text" => Ok(ReportFormat::Text),
+            "json" => Ok(ReportFormat::Json),
+            "csv" => Ok(ReportFormat::Csv),
+            _ => Err(format!(
+                "unknown report format '{}', expected text, json or csv",
+                value
+            )),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Modified,
+    /// Same content, but permissions, owner, group, mtime or symlink target changed; only
+    /// reported for baselines with [`LeafEncoding::Metadata`](crate::LeafEncoding).
+    #[serde(rename = "metadata")]
+    MetadataChanged,
+}
+
+impl ChangeKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ChangeKind::Added => "added",
+            ChangeKind::Removed => "removed",
+            ChangeKind::Modified => "modified",
+            ChangeKind::MetadataChanged => "metadata",
+        }
+    }
+}
+
+/// One file that differs from the baseline, with the recorded and current entries as
+/// evidence.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub kind: ChangeKind,
+    /// The file as recorded in the baseline; `None` if it was added.
+    pub old: Option<FileEntry>,
+    /// The file as found now; `None` if it was removed.
+    pub new: Option<FileEntry>,
+}
+
+/// Outcome of comparing a scan against a baseline.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    pub old_root: Vec<u8>,
+    pub new_root: Vec<u8>,
+    /// Changed files, keyed by path.
+    pub changes: BTreeMap<String, Change>,
+}
+
+impl Report {
+    pub fn is_clean(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    pub fn render(&self, format: ReportFormat) -> String {
+        match format {
+            ReportFormat::Text => self.to_string(),
+            ReportFormat::Json => self.to_json(),
+            ReportFormat::Csv => self.to_csv(),
+        }
+    }
+
+    /// A JSON object with hex-encoded hashes and roots, one entry per changed file.
+    fn to_json(&self) -> String {
+        let changes: Vec<_> = self
+            .changes
+            .iter()
+            .map(|(path, change)| {
+                json!({
+                    "path": path,
+                    "kind": change.kind,
+                    "old_hash": change.old.as_ref().map(|entry| hex::encode(&entry.hash)),
+                    "new_hash": change.new.as_ref().map(|entry| hex::encode(&entry.hash)),
+                    "old_size": change.old.as_ref().map(|entry| entry.size),
+                    "new_size": change.new.as_ref().map(|entry| entry.size),
+                    "old_mtime_ns": change.old.as_ref().map(|entry| entry.mtime_ns),
+                    "new_mtime_ns": change.new.as_ref().map(|entry| entry.mtime_ns),
+                    "old_mode": change.old.as_ref().map(|entry| format!("{:o}", entry.mode)),
+                    "new_mode": change.new.as_ref().map(|entry| format!("{:o}", entry.mode)),
+                    "old_owner": change.old.as_ref().map(|entry| [entry.uid, entry.gid]),
+                    "new_owner": change.new.as_ref().map(|entry| [entry.uid, entry.gid]),
+                    "old_link_target": change.old.as_ref().and_then(|entry| entry.link_target.as_ref()),
+                    "new_link_target": change.new.as_ref().and_then(|entry| entry.link_target.as_ref()),
+                })
+            })
+            .collect();
+        json!({
+            "clean": self.is_clean(),
+            "old_root": hex::encode(&self.old_root),
+            "new_root": hex::encode(&self.new_root),
+            "changes": changes,
+        })
+        .to_string()
+    }
+
+    /// One row per changed file, each carrying both roots so rows stand on their own once
+    /// ingested; a clean report is just the header.
+    fn to_csv(&self) -> String {
+        let mut csv = String::from(
+            "path,kind,old_hash,new_hash,old_size,new_size,old_mode,new_mode,old_root,new_root\n",
+        );
+        let hash = |entry: &Option<FileEntry>| {
+            entry
+                .as_ref()
+                .map(|entry| hex::encode(&entry.hash))
+                .unwrap_or_default()
+        };
+        let size = |entry: &Option<FileEntry>| {
+            entry
+                .as_ref()
+                .map(|entry| entry.size.to_string())
+                .unwrap_or_default()
+        };
+        let mode = |entry: &Option<FileEntry>| {
+            entry
+                .as_ref()
+                .map(|entry| format!("{:o}", entry.mode))
+                .unwrap_or_default()
+        };
+        for (path, change) in &self.changes {
+            csv.push_str(&format!(
+                "{},{},{},{},{},{},{},{},{},{}\n",
+                csv_field(path),
+                change.kind.as_str(),
+                hash(&change.old),
+                hash(&change.new),
+                size(&change.old),
+                size(&change.new),
+                mode(&change.old),
+                mode(&change.new),
+                hex::encode(&self.old_root),
+                hex::encode(&self.new_root)
+            ));
+        }
+        csv
+    }
+}
+
+/// Quotes a CSV field if it contains a delimiter, quote or line break (RFC 4180).
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// One line per change with the old and new content hashes (or metadata, for metadata
+/// changes), then the old and new roots.
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hash = |entry: &Option<FileEntry>| match entry {
+            Some(entry) => hex::encode(&entry.hash),
+            None => "-".to_string(),
+        };
+        let metadata = |entry: &Option<FileEntry>| match entry {
+            Some(entry) => {
+                let mut described = format!(
+                    "mode {:o} owner {}:{} mtime {}",
+                    entry.mode, entry.uid, entry.gid, entry.mtime_ns
+                );
+                if let Some(target) = &entry.link_target {
+                    described.push_str(&format!(" link {}", target));
+                }
+                described
+            }
+            None => "-".to_string(),
+        };
+        for (path, change) in &self.changes {
+            if change.kind == ChangeKind::MetadataChanged {
+                writeln!(
+                    f,
+                    "{:<8} {} {} -> {}",
+                    change.kind.as_str(),
+                    path,
+                    metadata(&change.old),
+                    metadata(&change.new)
+                )?;
+                continue;
+            }
+            writeln!(
+                f,
+                "{:<8} {} {} -> {}",
+                change.kind.as_str(),
+                path,
+                hash(&change.old),
+                hash(&change.new)
+            )?;
+        }
+        write!(
+            f,
+            "root {} -> {}",
+            hex::encode(&self.old_root),
+            hex::encode(&self.new_root)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report() -> Report {
+        let entry = |byte| FileEntry {
+            hash: vec![byte; 32],
+            size: 3,
+            mtime_ns: 0,
+            mode: 0o100644,
+            uid: 0,
+            gid: 0,
+            link_target: None,
+        };
+        let mut changes = BTreeMap::new();
+        changes.insert(
+            "/etc/a,b".to_string(),
+            Change {
+                kind: ChangeKind::Modified,
+                old: Some(entry(1)),
+                new: Some(entry(2)),
+            },
+        );
+        changes.insert(
+            "/etc/new".to_string(),
+            Change {
+                kind: ChangeKind::Added,
+                old: None,
+                new: Some(entry(3)),
+            },
+        );
+        Report {
+            old_root: vec![0xaa; 32],
+            new_root: vec![0xbb; 32],
+            changes,
+        }
+    }
+
+    #[test]
+    fn test_render_json() {
+        let rendered: serde_json::Value =
+            serde_json::from_str(&report().render(ReportFormat::Json)).unwrap();
+        assert_eq!(rendered["clean"], false);
+        assert_eq!(rendered["new_root"], hex::encode([0xbb; 32]));
+        assert_eq!(rendered["changes"][0]["kind"], "modified");
+        assert_eq!(rendered["changes"][0]["old_hash"], hex::encode([1; 32]));
+        assert_eq!(rendered["changes"][0]["new_mode"], "100644");
+        assert_eq!(rendered["changes"][1]["old_hash"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn test_render_csv_quotes_paths() {
+        let rendered = report().render(ReportFormat::Csv);
+        let lines: Vec<_> = rendered.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].starts_with("\"/etc/a,b\",modified,"));
+        assert!(lines[2].starts_with("/etc/new,added,,"));
+        assert!(lines[2].ends_with(&hex::encode([0xbb; 32])));
+    }
+}
@@ -0,0 +1,262 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::PathBuf;
+
+use merklefile_client::{alternate_streams, ClientConfig, DirChain, ScanEntry};
+
+/// Limits on what a scan of one path covers, so scanning `/` does not drown in `/proc`,
+/// `/sys` and container overlay noise.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScanRules {
+    /// Glob patterns matched against paths relative to the scanned path. A matching
+    /// directory is not descended into, so `proc` skips all of `/proc`.
+    pub exclude: Vec<String>,
+    /// How many directory levels below the scanned path to descend; `0` covers only the
+    /// files directly inside it.
+    pub max_depth: Option<usize>,
+    /// Stay on the file system of the scanned path instead of crossing mount points.
+    pub one_file_system: bool,
+    /// Skip files larger than this many bytes.
+    pub max_file_size: Option<u64>,
+}
+
+impl ScanRules {
+    pub fn validate(&self) -> io::Result<()> {
+        for pattern in &self.exclude {
+            glob::Pattern::new(pattern)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+        }
+        Ok(())
+    }
+
+    fn is_excluded(&self, relative_path: &str, options: glob::MatchOptions) -> bool {
+        self.exclude.iter().any(|pattern| {
+            glob::Pattern::new(pattern)
+                .map(|pattern| pattern.matches_with(relative_path, options))
+                .unwrap_or(false)
+        })
+    }
+}
+
+/// A file or directory to monitor and the rules for scanning it. In a monitor
+/// configuration it is either a bare path or a table with the rules alongside `path`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(from = "ScanPathSpec")]
+pub struct ScanPath {
+    pub path: PathBuf,
+    #[serde(flatten)]
+    pub rules: ScanRules,
+}
+
+impl From<PathBuf> for ScanPath {
+    fn from(path: PathBuf) -> Self {
+        Self {
+            path,
+            rules: ScanRules::default(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ScanPathSpec {
+    Bare(PathBuf),
+    WithRules(ScanPathTable),
+}
+
+// Spelled out rather than flattened, since `deny_unknown_fields` does not see through
+// `#[serde(flatten)]`
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScanPathTable {
+    path: PathBuf,
+    #[serde(default)]
+    exclude: Vec<String>,
+    max_depth: Option<usize>,
+    #[serde(default)]
+    one_file_system: bool,
+    max_file_size: Option<u64>,
+}
+
+impl From<ScanPathSpec> for ScanPath {
+    fn from(spec: ScanPathSpec) -> Self {
+        match spec {
+            ScanPathSpec::Bare(path) => path.into(),
+            ScanPathSpec::WithRules(table) => Self {
+                path: table.path,
+                rules: ScanRules {
+                    exclude: table.exclude,
+                    max_depth: table.max_depth,
+                    one_file_system: table.one_file_system,
+                    max_file_size: table.max_file_size,
+                },
+            },
+        }
+    }
+}
+
+impl ScanPath {
+    /// Lists the files this path covers under its rules, keyed by full path. Paths the
+    /// client's ignore patterns match are skipped too, and symbolic links and special files
+    /// are handled as its policies say.
+    pub(crate) fn walk(&self, config: &ClientConfig) -> io::Result<BTreeMap<String, PathBuf>> {
+        let mut files = BTreeMap::new();
+        // A link to the scanned directory itself is followed whatever the symlink policy,
+        // since it was named explicitly
+        let root_metadata = std::fs::metadata(&self.path)?;
+        if !root_metadata.is_dir() {
+            if let Some(ScanEntry::File(metadata)) = config.classify(&self.path)? {
+                if self.within_size_limit(&metadata) {
+                    let key = config.normalize_name(&self.path.to_string_lossy());
+                    files.insert(key, self.path.clone());
+                }
+            }
+            return Ok(files);
+        }
+
+        let root = DirChain::default()
+            .enter(&self.path)?
+            .expect("an empty chain contains no directory");
+        let mut pending = vec![(self.path.clone(), 0, root)];
+        while let Some((current, depth, chain)) = pending.pop() {
+            for entry in std::fs::read_dir(&current)? {
+                let path = entry?.path();
+                let relative = config.normalize_key(
+                    &path
+                        .strip_prefix(&self.path)
+                        .expect("entry lies below the scanned directory")
+                        .components()
+                        .map(|component| component.as_os_str().to_string_lossy())
+                        .collect::<Vec<_>>()
+                        .join("/"),
+                );
+                if self.rules.is_excluded(&relative, config.match_options())
+                    || config.is_ignored(&relative)
+                {
+                    continue;
+                }
+                match config.classify(&path)? {
+                    Some(ScanEntry::Dir(metadata)) => {
+                        let below_limit = self.rules.max_depth.is_none_or(|max| depth < max);
+                        if below_limit && !self.crosses_mount(&root_metadata, &metadata) {
+                            if let Some(chain) = chain.enter(&path)? {
+                                pending.push((path, depth + 1, chain));
+                            }
+                        }
+                    }
+                    Some(ScanEntry::File(metadata)) if self.within_size_limit(&metadata) => {
+                        if config.alternate_data_streams {
+                            for stream in alternate_streams(&path)? {
+                                let mut stream_path = path.clone().into_os_string();
+                                stream_path.push(format!(":{}", stream));
+                                let stream_path = PathBuf::from(stream_path);
+                                files.insert(
+                                    stream_path.to_string_lossy().into_owned(),
+                                    stream_path,
+                                );
+                            }
+                        }
+                        let key = config.normalize_name(&path.to_string_lossy());
+                        insert_unique(&mut files, key, path)?;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    fn within_size_limit(&self, metadata: &std::fs::Metadata) -> bool {
+        self.rules
+            .max_file_size
+            .is_none_or(|max| metadata.len() <= max)
+    }
+
+    #[cfg(unix)]
+    fn crosses_mount(&self, root: &std::fs::Metadata, dir: &std::fs::Metadata) -> bool {
+        use std::os::unix::fs::MetadataExt;
+        self.rules.one_file_system && root.dev() != dir.dev()
+    }
+
+    #[cfg(not(unix))]
+    fn crosses_mount(&self, _root: &std::fs::Metadata, _dir: &std::fs::Metadata) -> bool {
+        false
+    }
+}
+
+/// Adds a listed file, failing rather than letting a file whose name normalizes the same
+/// way (say, an NFD twin) take the place of another.
+fn insert_unique(
+    files: &mut BTreeMap<String, PathBuf>,
+    key: String,
+    path: PathBuf,
+) -> io::Result<()> {
+    if let Some(existing) = files.get(&key) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} and {} both normalize to {}",
+                existing.display(),
+                path.display(),
+                key
+            ),
+        ));
+    }
+    files.insert(key, path);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_walk_applies_rules() {
+        let dir = std::env::temp_dir().join(format!("merklefile-rules-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("proc/1")).unwrap();
+        std::fs::create_dir_all(dir.join("etc/ssh")).unwrap();
+        std::fs::write(dir.join("proc/1/status"), b"running").unwrap();
+        std::fs::write(dir.join("etc/hosts"), b"127.0.0.1 localhost").unwrap();
+        std::fs::write(dir.join("etc/ssh/sshd_config"), b"PermitRootLogin no").unwrap();
+        std::fs::write(dir.join("big.img"), vec![0; 4096]).unwrap();
+
+        let scan = ScanPath {
+            path: dir.clone(),
+            rules: ScanRules {
+                exclude: vec!["proc".to_string()],
+                max_depth: Some(1),
+                one_file_system: true,
+                max_file_size: Some(1024),
+            },
+        };
+        let config = ClientConfig::default();
+        let files = scan.walk(&config).unwrap();
+        let unscoped = ScanPath::from(dir.clone()).walk(&config).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let relative: Vec<_> = files
+            .keys()
+            .map(|path| path.strip_prefix(dir.to_str().unwrap()).unwrap())
+            .collect();
+        assert_eq!(relative, vec!["/etc/hosts"]);
+        assert_eq!(unscoped.len(), 4);
+    }
+
+    #[test]
+    fn test_scan_path_accepts_bare_paths_and_tables() {
+        #[derive(Deserialize)]
+        struct Paths {
+            paths: Vec<ScanPath>,
+        }
+        let parsed: Paths = toml::from_str(
+            r#"paths = ["/etc", { path = "/", exclude = ["proc", "sys"], one_file_system = true }]"#,
+        )
+        .unwrap();
+        assert_eq!(parsed.paths[0], ScanPath::from(PathBuf::from("/etc")));
+        assert_eq!(parsed.paths[1].rules.exclude, vec!["proc", "sys"]);
+        assert!(parsed.paths[1].rules.one_file_system);
+        assert!(toml::from_str::<Paths>(r#"paths = [{ path = "/", exlude = ["proc"] }]"#).is_err());
+    }
+}
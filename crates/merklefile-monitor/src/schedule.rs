@@ -0,0 +1,203 @@
+use std::io;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// When the monitor daemon runs its scans.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Schedule {
+    /// A fixed delay between the end of one scan and the start of the next.
+    Interval(Duration),
+    /// A five-field cron expression, evaluated in UTC.
+    Cron(CronExpr),
+}
+
+impl Schedule {
+    /// Time to wait, from `now`, until the next scan is due.
+    pub fn delay_from(&self, now: SystemTime) -> io::Result<Duration> {
+        match self {
+            Schedule::Interval(interval) => Ok(*interval),
+            Schedule::Cron(expr) => {
+                let now = now.duration_since(UNIX_EPOCH).map_err(io::Error::other)?;
+                let next = expr.next_after(now.as_secs()).ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidInput, "cron expression never fires")
+                })?;
+                Ok(Duration::from_secs(next) - now)
+            }
+        }
+    }
+}
+
+/// A parsed `minute hour day-of-month month day-of-week` expression. Each field accepts
+/// `*`, numbers, ranges (`1-5`), steps (`*/15`, `0-30/10`) and comma-separated lists.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronExpr {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Whether day-of-month or day-of-week was restricted; as in cron, a day matches if
+    /// either restricted field matches.
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+impl CronExpr {
+    pub fn parse(expr: &str) -> io::Result<Self> {
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(invalid(expr, "expected five fields"));
+        };
+        // Sunday may be written as 0 or 7
+        let mut weekdays = parse_field(weekday, 0, 7).ok_or_else(|| invalid(expr, weekday))?;
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1;
+        }
+        Ok(Self {
+            minutes: parse_field(minute, 0, 59).ok_or_else(|| invalid(expr, minute))?,
+            hours: parse_field(hour, 0, 23).ok_or_else(|| invalid(expr, hour))?,
+            days: parse_field(day, 1, 31).ok_or_else(|| invalid(expr, day))?,
+            months: parse_field(month, 1, 12).ok_or_else(|| invalid(expr, month))?,
+            weekdays,
+            days_restricted: day != "*",
+            weekdays_restricted: weekday != "*",
+        })
+    }
+
+    /// The first whole minute strictly after `secs` (seconds since the Unix epoch) that
+    /// matches, searching up to five years ahead.
+    pub fn next_after(&self, secs: u64) -> Option<u64> {
+        let mut minute = secs / 60 + 1;
+        let limit = minute + 5 * 366 * 24 * 60;
+        while minute < limit {
+            let days = minute / (24 * 60);
+            let (_, month, day) = civil_from_days(days as i64);
+            // 1970-01-01 was a Thursday
+            let weekday = (days + 4) % 7;
+            if !self.day_matches(month, day, weekday) {
+                minute = (days + 1) * 24 * 60;
+                continue;
+            }
+            let hour = minute / 60 % 24;
+            if self.hours & (1 << hour) == 0 {
+                minute = (minute / 60 + 1) * 60;
+                continue;
+            }
+            if self.minutes & (1 << (minute % 60)) != 0 {
+                return Some(minute * 60);
+            }
+            minute += 1;
+        }
+        None
+    }
+
+    fn day_matches(&self, month: u32, day: u32, weekday: u64) -> bool {
+        if self.months & (1 << month) == 0 {
+            return false;
+        }
+        let day_ok = self.days & (1 << day) != 0;
+        let weekday_ok = self.weekdays & (1 << weekday) != 0;
+        match (self.days_restricted, self.weekdays_restricted) {
+            (true, true) => day_ok || weekday_ok,
+            _ => day_ok && weekday_ok,
+        }
+    }
+}
+
+fn invalid(expr: &str, reason: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid cron expression '{}': {}", expr, reason),
+    )
+}
+
+/// Parses one cron field into a bitmask of the values it selects.
+fn parse_field(field: &str, min: u64, max: u64) -> Option<u64> {
+    let mut mask = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u64>().ok().filter(|step| *step > 0)?),
+            None => (part, 1),
+        };
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (start.parse().ok()?, end.parse().ok()?)
+        } else {
+            let value = range.parse().ok()?;
+            // `5/10` means from 5 to the end in steps of 10
+            (value, if part.contains('/') { max } else { value })
+        };
+        if start < min || end > max || start > end {
+            return None;
+        }
+        for value in (start..=end).step_by(step as usize) {
+            mask |= 1 << value;
+        }
+    }
+    Some(mask)
+}
+
+/// Converts days since the Unix epoch to a (year, month, day) date in the proleptic
+/// Gregorian calendar.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2024-03-01 00:00:00 UTC, a Friday
+    const MARCH_1_2024: u64 = 1_709_251_200;
+
+    #[test]
+    fn test_cron_next_after() {
+        let every_quarter_hour = CronExpr::parse("*/15 * * * *").unwrap();
+        assert_eq!(
+            every_quarter_hour.next_after(MARCH_1_2024 + 60),
+            Some(MARCH_1_2024 + 15 * 60)
+        );
+
+        let nightly = CronExpr::parse("30 2 * * *").unwrap();
+        assert_eq!(
+            nightly.next_after(MARCH_1_2024),
+            Some(MARCH_1_2024 + (2 * 60 + 30) * 60)
+        );
+
+        // The next Monday is March 4th
+        let mondays = CronExpr::parse("0 9 * * 1").unwrap();
+        assert_eq!(
+            mondays.next_after(MARCH_1_2024),
+            Some(MARCH_1_2024 + (3 * 24 + 9) * 3600)
+        );
+
+        assert_eq!(CronExpr::parse("0 0 30 2 *").unwrap().next_after(0), None);
+    }
+
+    #[test]
+    fn test_cron_rejects_malformed_fields() {
+        for expr in [
+            "* * * *",
+            "60 * * * *",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "a * * * *",
+        ] {
+            assert!(CronExpr::parse(expr).is_err(), "{}", expr);
+        }
+    }
+}
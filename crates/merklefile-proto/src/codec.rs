@@ -0,0 +1,107 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::{ProtocolError, MAX_FRAME_LEN};
+use merklefile_core::profile::{self, Stage};
+
+/// Length of the big-endian length prefix in front of every frame.
+pub const HEADER_LEN: usize = 8;
+
+/// Most bytes reserved ahead for a frame still arriving, so a peer announcing a huge frame
+/// cannot make the receiver allocate it before sending it.
+const MAX_RESERVE: usize = 1024 * 1024;
+
+/// The protocol's framing as a tokio codec. Decoded frames are split off the read buffer
+/// without copying, and frames to send are `Bytes` that can share memory with stored files.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameCodec {
+    max_len: u64,
+}
+
+impl FrameCodec {
+    /// A codec refusing frames longer than `max_len`, or than `MAX_FRAME_LEN` if that is
+    /// lower.
+    pub fn new(max_len: u64) -> Self {
+        FrameCodec {
+            max_len: max_len.min(MAX_FRAME_LEN),
+        }
+    }
+
+    pub fn max_len(&self) -> u64 {
+        self.max_len
+    }
+}
+
+impl Default for FrameCodec {
+    fn default() -> Self {
+        Self::new(MAX_FRAME_LEN)
+    }
+}
+
+impl Decoder for FrameCodec {
+    type Item = BytesMut;
+    type Error = ProtocolError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<BytesMut>, ProtocolError> {
+        if src.len() < HEADER_LEN {
+            return Ok(None);
+        }
+        let length = u64::from_be_bytes(src[..HEADER_LEN].try_into().unwrap());
+        if length > self.max_len {
+            return Err(ProtocolError::FrameTooLarge(length));
+        }
+        let length = length as usize;
+        let missing = (HEADER_LEN + length).saturating_sub(src.len());
+        if missing > 0 {
+            src.reserve(missing.min(MAX_RESERVE));
+            return Ok(None);
+        }
+        src.advance(HEADER_LEN);
+        Ok(Some(src.split_to(length)))
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<BytesMut>, ProtocolError> {
+        match self.decode(src)? {
+            Some(frame) => Ok(Some(frame)),
+            None if src.is_empty() => Ok(None),
+            None => Err(ProtocolError::Truncated),
+        }
+    }
+}
+
+impl Encoder<Bytes> for FrameCodec {
+    type Error = ProtocolError;
+
+    fn encode(&mut self, payload: Bytes, dst: &mut BytesMut) -> Result<(), ProtocolError> {
+        if payload.len() as u64 > self.max_len {
+            return Err(ProtocolError::FrameTooLarge(payload.len() as u64));
+        }
+        dst.reserve(HEADER_LEN + payload.len());
+        dst.put_u64(payload.len() as u64);
+        dst.extend_from_slice(&payload);
+        Ok(())
+    }
+}
+
+/// Reads the next frame from `reader` through `buffer`, returning its payload split off the
+/// buffer. Bytes read past the frame stay in `buffer` for the next call.
+pub async fn read_frame_buf<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    codec: &mut FrameCodec,
+    buffer: &mut BytesMut,
+) -> Result<BytesMut, ProtocolError> {
+    let mut span = profile::span(Stage::SocketRead);
+    loop {
+        if let Some(frame) = codec.decode(buffer)? {
+            span.add_bytes(frame.len() as u64);
+            return Ok(frame);
+        }
+        if reader.read_buf(buffer).await? == 0 {
+            return match codec.decode_eof(buffer)? {
+                Some(frame) => Ok(frame),
+                None => Err(ProtocolError::Truncated),
+            };
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use std::collections::BTreeMap;
+use std::io;
+use thiserror::Error;
+
+use crate::HashAlgorithm;
+
+/// Failures talking to a peer: transport errors, malformed frames and unexpected replies.
+#[derive(Debug, Error)]
+pub enum ProtocolError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("malformed message")]
+    Malformed(#[from] serde_json::Error),
+    #[error("frame of {0} bytes exceeds the limit")]
+    FrameTooLarge(u64),
+    #[error("frame was truncated")]
+    Truncated,
+    #[error("proof has {0} levels")]
+    ProofTooDeep(usize),
+    #[error("proof contains a malformed hash")]
+    MalformedHash,
+    /// The peer builds its trees with another hash algorithm, so no root or proof it sends
+    /// could be checked.
+    #[error("peer hashes with {0}, expected {expected}", expected = crate::HASH_ALGORITHM)]
+    WrongHashAlgorithm(HashAlgorithm),
+    /// A raw file's bytes do not hash to the hash sent with them.
+    #[error("file does not match the hash sent with it")]
+    HashMismatch,
+    #[error("unexpected response from server")]
+    UnexpectedResponse,
+    /// The server answered with an error message.
+    #[error("server error: {0}")]
+    Remote(String),
+    /// The server refused an upload because the state it expected has changed since.
+    #[error("conflict: the server's files changed since they were last observed")]
+    Conflict {
+        root: Vec<u8>,
+        files: BTreeMap<String, Option<Vec<u8>>>,
+    },
+    /// A commit across servers was applied on some of them but not confirmed on the rest,
+    /// for instance because their prepared upload expired, so they may now hold different
+    /// roots.
+    #[error("upload committed on {committed:?} but not confirmed on {unconfirmed:?}")]
+    PartialCommit {
+        committed: Vec<String>,
+        unconfirmed: Vec<String>,
+    },
+    #[error("{0} timed out")]
+    Timeout(&'static str),
+    #[error("no server reachable")]
+    NoServerReachable,
+}
+
+impl ProtocolError {
+    /// Whether the server reported that the requested file does not exist.
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, ProtocolError::Remote(message) if message == "File not found")
+    }
+}
+
+impl From<ProtocolError> for io::Error {
+    fn from(err: ProtocolError) -> Self {
+        let err = match err {
+            ProtocolError::Io(err) => return err,
+            err => err,
+        };
+        let kind = match &err {
+            ProtocolError::Truncated => io::ErrorKind::UnexpectedEof,
+            ProtocolError::Timeout(_) => io::ErrorKind::TimedOut,
+            ProtocolError::NoServerReachable => io::ErrorKind::NotConnected,
+            err if err.is_not_found() => io::ErrorKind::NotFound,
+            ProtocolError::Remote(_)
+            | ProtocolError::UnexpectedResponse
+            | ProtocolError::Conflict { .. }
+            | ProtocolError::PartialCommit { .. } => io::ErrorKind::Other,
+            _ => io::ErrorKind::InvalidData,
+        };
+        io::Error::new(kind, err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_protocol_errors_keep_kind_and_type() {
+        let err = io::Error::from(ProtocolError::Remote("File not found".to_string()));
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        let inner = err.get_ref().unwrap().downcast_ref::<ProtocolError>();
+        assert!(matches!(inner, Some(ProtocolError::Remote(_))));
+
+        let refused = io::Error::from(io::ErrorKind::ConnectionRefused);
+        let err = io::Error::from(ProtocolError::from(refused));
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+    }
+}
@@ -0,0 +1,949 @@
+//! Wire protocol shared by the client and the server.
+//!
+//! Every message travels in a frame: an 8-byte big-endian payload length followed by the
+//! payload (see `FrameCodec`). The payload is a JSON `Envelope` carrying the sender's
+//! `PROTOCOL_VERSION`, the `HASH_ALGORITHM` of its trees and a `ServerMessage` (requests) or
+//! `ClientMessage` (responses), tagged as `{"type": "<variant>", "body": {...}}`. A
+//! connection carries one request frame and one response frame. The exceptions are
+//! `DownloadRaw`, whose response frame is followed by the file's bytes as they are, outside
+//! any frame, and `UploadRaw`, whose request frame is.
+//!
+//! The schema evolves without breaking older peers:
+//!
+//! - new fields are added with `#[serde(default)]`, and unknown fields are ignored;
+//! - new variants are only sent to peers that understand them, and a peer that does not
+//!   decodes them as `Unknown` and answers with an error instead of dropping the connection;
+//! - existing variants and fields are never renamed or removed within a major version.
+//!
+//! Peers from before the envelope wrote bare messages in serde's default form,
+//! `{"<variant>": {...}}`, or `"<variant>"` for variants without fields. Those still decode,
+//! and `decode_with_format` reports the `WireFormat` a request came in so the server can
+//! answer in the same form.
+//!
+//! Both sides treat the bytes they receive as untrusted: frames longer than `MAX_FRAME_LEN`
+//! are refused before anything is allocated, serde_json caps nesting at 128 levels, and
+//! proofs in responses are checked against `MAX_PROOF_DEPTH` and `HASH_LEN` before use.
+//!
+//! File contents are `Bytes`, so a server can hand stored files, and slices of them, to a
+//! response without copying them.
+
+use bytes::BufMut;
+pub use bytes::{Bytes, BytesMut};
+use merklefile_core::profile::{self, Stage};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+mod codec;
+mod error;
+
+pub use codec::{read_frame_buf, FrameCodec, HEADER_LEN};
+pub use error::ProtocolError;
+pub use merklefile_core::log::SignedTreeHead;
+pub use merklefile_core::merkle_tree::{ConsistencyProof, HashAlgorithm, MultiProof};
+
+/// Schema version written into every envelope. Bump it whenever a message or field is added.
+pub const PROTOCOL_VERSION: u32 = 14;
+
+/// Largest frame either side accepts. Files travel as JSON arrays of numbers, so this
+/// allows uploads of roughly 100 MiB.
+pub const MAX_FRAME_LEN: u64 = 512 * 1024 * 1024;
+
+/// Longest proof accepted, enough for a tree of 2^64 leaves.
+pub const MAX_PROOF_DEPTH: usize = 64;
+
+/// Hash algorithm of every tree, root and proof exchanged. A peer naming another one in
+/// its envelope is refused, since none of its roots could match.
+pub const HASH_ALGORITHM: HashAlgorithm = HashAlgorithm::Sha256;
+
+/// Length of every node hash in a proof.
+pub const HASH_LEN: usize = 32;
+
+/// Every frame's payload: a message, the schema version of the peer that wrote it and the
+/// hash algorithm its trees are built with.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Envelope<T> {
+    pub version: u32,
+    /// Peers before protocol version 11 leave it out; they only build SHA-256 trees.
+    #[serde(default)]
+    pub hash: HashAlgorithm,
+    pub message: T,
+}
+
+/// Requests sent from a client to the server.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", content = "body")]
+pub enum ServerMessage {
+    Upload {
+        client_files: BTreeMap<String, Bytes>,
+        #[serde(default)]
+        dry_run: bool,
+        #[serde(default)]
+        with_proofs: bool,
+        /// What to do with files the server already stores with other contents; the
+        /// server's own policy if unset. Servers before protocol version 8 always overwrite.
+        #[serde(default)]
+        on_collision: Option<CollisionPolicy>,
+        /// Refuse the upload with `Conflict` unless the server is still in this state.
+        /// Servers before protocol version 9 ignore it.
+        #[serde(default)]
+        expect: Option<UploadCondition>,
+    },
+    Download {
+        filename: String,
+    },
+    /// Like `Download`, but answered with `RawFile` followed by the file's bytes, which the
+    /// server can send without encoding them or copying them through its own memory. Not
+    /// allowed in a `Batch`.
+    DownloadRaw {
+        filename: String,
+    },
+    /// Like `Upload` of the single file `filename` with proofs, but followed by exactly
+    /// `len` bytes of the file outside any frame, so the client can stream them from wherever
+    /// they come from. Answered with an `UploadReceipt`. Not allowed in a `Batch`.
+    UploadRaw {
+        filename: String,
+        len: u64,
+        #[serde(default)]
+        on_collision: Option<CollisionPolicy>,
+        #[serde(default)]
+        expect: Option<UploadCondition>,
+    },
+    GetMerkleProof {
+        filename: String,
+    },
+    /// Proof for `filename` in the version of the tree whose root was `root`, if the server
+    /// still keeps that version.
+    GetMerkleProofAt {
+        filename: String,
+        root: Vec<u8>,
+    },
+    /// One proof for all of `filenames` against the current tree, answered with a
+    /// `MultiProof` whose leaves are in the order of `filenames`.
+    GetMultiProof {
+        filenames: Vec<String>,
+    },
+    /// Proof that the current tree extends the tree of its first `old_size` files, answered
+    /// with a `ConsistencyProof`.
+    GetConsistencyProof {
+        old_size: u64,
+    },
+    GetRootHash,
+    ReadRange {
+        filename: String,
+        offset: u64,
+        len: u64,
+        chunk_size: u64,
+    },
+    GetChunkHashes {
+        filename: String,
+        chunk_size: u64,
+    },
+    UploadChunks {
+        filename: String,
+        total_len: u64,
+        chunk_size: u64,
+        chunks: BTreeMap<u64, Bytes>,
+        #[serde(default)]
+        expect: Option<UploadCondition>,
+    },
+    Batch {
+        requests: Vec<ServerMessage>,
+    },
+    /// Names of every stored file, in tree order.
+    ListFiles,
+    /// The latest signed tree head of a server running as a transparency log.
+    GetTreeHead,
+    /// Proof that the log at `new_size` entries extends the log at `old_size` entries.
+    GetLogConsistency {
+        old_size: u64,
+        new_size: u64,
+    },
+    /// Sizes and counters of the running server, answered with `Stats`.
+    GetStats,
+    /// First phase of an upload committed on several servers at once: checks the upload as
+    /// `Upload` would and answers `Prepared` with the root it would produce, without
+    /// applying it. The server then refuses every other upload until `Commit` or `Abort`
+    /// names the transaction, or it expires.
+    Prepare {
+        client_files: BTreeMap<String, Bytes>,
+        #[serde(default)]
+        on_collision: Option<CollisionPolicy>,
+        #[serde(default)]
+        expect: Option<UploadCondition>,
+    },
+    /// Applies a prepared upload, answered with the new root like `Upload`.
+    Commit {
+        transaction: u128,
+    },
+    /// Drops a prepared upload, answered with the unchanged root.
+    Abort {
+        transaction: u128,
+    },
+    /// A request from a newer client that this version does not understand.
+    #[serde(other)]
+    Unknown,
+}
+
+/// Responses sent from the server back to a client.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", content = "body")]
+pub enum ClientMessage {
+    Success {
+        data: Bytes,
+    },
+    MerkleProof {
+        proof: Vec<(Vec<u8>, bool)>,
+        /// Leaves in the tree the proof is for. Servers before protocol version 12 leave it
+        /// out.
+        #[serde(default)]
+        tree_size: Option<u64>,
+    },
+    MultiProof {
+        proof: MultiProof,
+    },
+    ConsistencyProof {
+        proof: ConsistencyProof,
+    },
+    UploadPlan {
+        changes: Vec<FileChange>,
+        root: Vec<u8>,
+    },
+    Chunks {
+        chunks: Vec<ChunkWithProof>,
+    },
+    ChunkHashes {
+        hashes: Vec<Vec<u8>>,
+    },
+    Batch {
+        responses: Vec<ClientMessage>,
+    },
+    /// Proofs are keyed by the name each file is stored under, which differs from the
+    /// uploaded name for the files in `stored_as`.
+    UploadReceipt {
+        root: Vec<u8>,
+        proofs: BTreeMap<String, Vec<(Vec<u8>, bool)>>,
+        /// Uploaded name to stored name, for files stored under a new name by
+        /// `CollisionPolicy::AutoVersion`.
+        #[serde(default)]
+        stored_as: BTreeMap<String, String>,
+        /// Leaves in the tree the proofs are for. Servers before protocol version 12 leave
+        /// it out.
+        #[serde(default)]
+        tree_size: Option<u64>,
+    },
+    FileList {
+        filenames: Vec<String>,
+    },
+    TreeHead {
+        head: SignedTreeHead,
+    },
+    LogConsistency {
+        proof: Vec<Vec<u8>>,
+    },
+    /// Answer to `DownloadRaw`: exactly `len` bytes of the file follow this frame, and their
+    /// SHA-256 is `hash`.
+    RawFile {
+        len: u64,
+        hash: Vec<u8>,
+    },
+    Stats {
+        stats: ServerStats,
+    },
+    /// Answer to `Prepare`: the transaction to commit or abort, and the root committing it
+    /// will produce.
+    Prepared {
+        transaction: u128,
+        root: Vec<u8>,
+    },
+    /// Answer to an upload whose `UploadCondition` no longer holds: the current root, and
+    /// the current hash of every file whose expected hash did not match, `None` for files
+    /// that do not exist. Nothing was stored.
+    Conflict {
+        root: Vec<u8>,
+        files: BTreeMap<String, Option<Vec<u8>>>,
+    },
+    Error {
+        message: String,
+    },
+    /// A response from a newer server that this version does not understand.
+    #[serde(other)]
+    Unknown,
+}
+
+/// A chunk of a file together with its proof against the file's chunk tree root.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ChunkWithProof {
+    pub index: u64,
+    pub data: Bytes,
+    pub proof: Vec<(Vec<u8>, bool)>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum ChangeKind {
+    Added,
+    Modified,
+    Unchanged,
+}
+
+/// The state an upload was prepared against, like an HTTP `If-Match`, so that two clients
+/// updating the same files cannot silently overwrite each other's changes.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct UploadCondition {
+    /// Root the client last observed; any upload since then is a conflict.
+    #[serde(default)]
+    pub root: Option<Vec<u8>>,
+    /// Hash each file was last observed with, or `None` for files expected not to exist.
+    #[serde(default)]
+    pub files: BTreeMap<String, Option<Vec<u8>>>,
+}
+
+/// What an upload does to a file the server already stores under the same name with other
+/// contents. Uploading the same contents again is never a collision.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CollisionPolicy {
+    /// Replace the stored file.
+    #[default]
+    Overwrite,
+    /// Refuse the whole upload.
+    Reject,
+    /// Keep the stored file and store the upload as `<name>.~<n>~`, with `n` the lowest
+    /// number not yet taken, unless one of those versions already holds the same contents.
+    AutoVersion,
+}
+
+impl CollisionPolicy {
+    /// The name the `n`th version of `filename` is stored under by `AutoVersion`.
+    pub fn versioned_name(filename: &str, n: u64) -> String {
+        format!("{}.~{}~", filename, n)
+    }
+}
+
+impl FromStr for CollisionPolicy {
+    type Err = String;
+
+    fn from_str(policy: &str) -> Result<Self, Self::Err> {
+        match policy {
+            "overwrite" => Ok(CollisionPolicy::Overwrite),
+            "reject" => Ok(CollisionPolicy::Reject),
+            "autoversion" => Ok(CollisionPolicy::AutoVersion),
+            _ => Err(format!(
+                "unknown collision policy '{}', expected overwrite, reject or autoversion",
+                policy
+            )),
+        }
+    }
+}
+
+/// Sizes and counters of a running server, for dashboards that only need a few numbers.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ServerStats {
+    pub files: u64,
+    /// Sum of the sizes of the stored files.
+    pub total_bytes: u64,
+    /// Levels of the tree above its leaves.
+    pub tree_depth: u32,
+    /// How long the last rebuild from storage took, if there was one since the server started.
+    pub last_rebuild_ms: Option<u64>,
+    /// Requests answered since the server started, by request type; requests in a batch
+    /// count as well as the batch.
+    pub requests: BTreeMap<String, u64>,
+    /// Stored data found not to match the tree since the server started, by what found it:
+    /// `self_check`, `shadow`, `journal` or `rebuild`.
+    pub verification_failures: BTreeMap<String, u64>,
+    pub uptime_secs: u64,
+    /// How the server computes SHA-256, e.g. `sha-ni` or `portable`.
+    pub hash_backend: String,
+}
+
+/// What an upload would do to a single file on the server.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FileChange {
+    pub filename: String,
+    pub kind: ChangeKind,
+    pub old_size: Option<u64>,
+    pub new_size: u64,
+    pub old_hash: Option<Vec<u8>>,
+    pub new_hash: Vec<u8>,
+}
+
+/// How a peer writes its payloads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WireFormat {
+    /// An `Envelope`, as every peer since protocol version 1 writes.
+    #[default]
+    Envelope,
+    /// The message alone, in serde's default externally tagged form, as peers before
+    /// protocol version 1 wrote.
+    Bare,
+}
+
+/// Wraps a message in an envelope stamped with `PROTOCOL_VERSION` and serializes it.
+pub fn encode<T: Serialize>(message: &T) -> Result<Vec<u8>, ProtocolError> {
+    let mut span = profile::span(Stage::Serialize);
+    let payload = serde_json::to_vec(&Envelope {
+        version: PROTOCOL_VERSION,
+        hash: HASH_ALGORITHM,
+        message,
+    })?;
+    span.add_bytes(payload.len() as u64);
+    Ok(payload)
+}
+
+/// Like `encode`, but serializes into `buf`, replacing its contents, so the caller can
+/// reuse one allocation across messages.
+pub fn encode_into<T: Serialize>(message: &T, buf: &mut BytesMut) -> Result<(), ProtocolError> {
+    let mut span = profile::span(Stage::Serialize);
+    buf.clear();
+    serde_json::to_writer(
+        buf.writer(),
+        &Envelope {
+            version: PROTOCOL_VERSION,
+            hash: HASH_ALGORITHM,
+            message,
+        },
+    )?;
+    span.add_bytes(buf.len() as u64);
+    Ok(())
+}
+
+/// `encode` in the given format.
+pub fn encode_as<T: Serialize>(message: &T, format: WireFormat) -> Result<Vec<u8>, ProtocolError> {
+    match format {
+        WireFormat::Envelope => encode(message),
+        WireFormat::Bare => Ok(serde_json::to_vec(&to_bare(serde_json::to_value(
+            message,
+        )?))?),
+    }
+}
+
+/// `encode_into` in the given format.
+pub fn encode_into_as<T: Serialize>(
+    message: &T,
+    buf: &mut BytesMut,
+    format: WireFormat,
+) -> Result<(), ProtocolError> {
+    match format {
+        WireFormat::Envelope => encode_into(message, buf),
+        WireFormat::Bare => {
+            buf.clear();
+            let bare = to_bare(serde_json::to_value(message)?);
+            Ok(serde_json::to_writer(buf.writer(), &bare)?)
+        }
+    }
+}
+
+/// Parses a payload written by a peer of any version and returns its message. Variants
+/// this version does not know decode as the enum's `Unknown` variant. Envelopes of peers
+/// hashing with another algorithm than `HASH_ALGORITHM` are refused.
+pub fn decode<T: DeserializeOwned>(payload: &[u8]) -> Result<T, ProtocolError> {
+    decode_with_format(payload).map(|(message, _)| message)
+}
+
+/// `decode`, also returning the format the payload was written in.
+pub fn decode_with_format<T: DeserializeOwned>(
+    payload: &[u8],
+) -> Result<(T, WireFormat), ProtocolError> {
+    let _span = profile::span(Stage::Deserialize).with_bytes(payload.len() as u64);
+    let err = match serde_json::from_slice::<Envelope<T>>(payload) {
+        Ok(envelope) => {
+            return check_hash(envelope.hash).map(|()| (envelope.message, WireFormat::Envelope))
+        }
+        Err(err) => err,
+    };
+    let payload: serde_json::Value = serde_json::from_slice(payload)?;
+    let (mut message, format) = match Envelope::<serde_json::Value>::deserialize(&payload) {
+        Ok(envelope) => {
+            check_hash(envelope.hash)?;
+            (envelope.message, WireFormat::Envelope)
+        }
+        // Bare messages of peers before the envelope; they only built SHA-256 trees
+        Err(_) => match from_bare(payload) {
+            Some(message) => (message, WireFormat::Bare),
+            None => return Err(err.into()),
+        },
+    };
+    if let (Ok(message), WireFormat::Bare) = (T::deserialize(&message), format) {
+        return Ok((message, format));
+    }
+    // `#[serde(other)]` only matches variants without a body, so retry with the body dropped
+    let body = message
+        .as_object_mut()
+        .and_then(|message| message.remove("body"));
+    if body.is_none() {
+        return Err(err.into());
+    }
+    serde_json::from_value(message)
+        .map(|message| (message, format))
+        .map_err(|_| err.into())
+}
+
+/// The tagged form of a bare message, or `None` if `bare` is not one. The messages of a
+/// batch are converted too.
+fn from_bare(bare: serde_json::Value) -> Option<serde_json::Value> {
+    use serde_json::Value;
+
+    let (variant, body) = match bare {
+        Value::String(variant) => (variant, None),
+        Value::Object(object) if object.len() == 1 => {
+            let (variant, mut body) = object.into_iter().next()?;
+            for batch in ["requests", "responses"] {
+                if let Some(Value::Array(messages)) = body.get_mut(batch) {
+                    for message in messages {
+                        *message = from_bare(message.take())?;
+                    }
+                }
+            }
+            (variant, Some(body))
+        }
+        _ => return None,
+    };
+    let mut tagged = serde_json::Map::new();
+    tagged.insert("type".to_string(), Value::String(variant));
+    if let Some(body) = body {
+        tagged.insert("body".to_string(), body);
+    }
+    Some(Value::Object(tagged))
+}
+
+/// The bare form of a tagged message, the inverse of `from_bare`.
+fn to_bare(tagged: serde_json::Value) -> serde_json::Value {
+    use serde_json::Value;
+
+    let Value::Object(mut tagged) = tagged else {
+        return tagged;
+    };
+    let Some(Value::String(variant)) = tagged.remove("type") else {
+        return Value::Object(tagged);
+    };
+    match tagged.remove("body") {
+        None => Value::String(variant),
+        Some(mut body) => {
+            for batch in ["requests", "responses"] {
+                if let Some(Value::Array(messages)) = body.get_mut(batch) {
+                    for message in messages {
+                        *message = to_bare(message.take());
+                    }
+                }
+            }
+            Value::Object([(variant, body)].into_iter().collect())
+        }
+    }
+}
+
+fn check_hash(hash: HashAlgorithm) -> Result<(), ProtocolError> {
+    if hash == HASH_ALGORITHM {
+        Ok(())
+    } else {
+        Err(ProtocolError::WrongHashAlgorithm(hash))
+    }
+}
+
+/// Rejects proofs no honest server could produce, before they reach the verifier.
+pub fn check_proof(proof: &[(Vec<u8>, bool)]) -> Result<(), ProtocolError> {
+    if proof.len() > MAX_PROOF_DEPTH {
+        return Err(ProtocolError::ProofTooDeep(proof.len()));
+    }
+    if proof.iter().any(|(hash, _)| hash.len() != HASH_LEN) {
+        return Err(ProtocolError::MalformedHash);
+    }
+    Ok(())
+}
+
+/// Rejects multiproofs no honest server could produce: one for `n` leaves has at most the
+/// siblings of their `n` separate proofs.
+pub fn check_multiproof(proof: &MultiProof) -> Result<(), ProtocolError> {
+    let most = proof.leaf_indices.len().saturating_mul(MAX_PROOF_DEPTH);
+    if proof.siblings.len() > most {
+        return Err(ProtocolError::ProofTooDeep(proof.siblings.len()));
+    }
+    if proof.siblings.iter().any(|hash| hash.len() != HASH_LEN) {
+        return Err(ProtocolError::MalformedHash);
+    }
+    Ok(())
+}
+
+/// Rejects consistency proofs no honest log or server could produce: one between two logs
+/// or trees of up to 2^64 entries has at most two hashes per level.
+pub fn check_consistency_proof(proof: &[Vec<u8>]) -> Result<(), ProtocolError> {
+    if proof.len() > 2 * MAX_PROOF_DEPTH {
+        return Err(ProtocolError::ProofTooDeep(proof.len()));
+    }
+    if proof.iter().any(|hash| hash.len() != HASH_LEN) {
+        return Err(ProtocolError::MalformedHash);
+    }
+    Ok(())
+}
+
+/// Parses a proof in its wire form, a JSON array of `[hash, is_left]` pairs.
+pub fn decode_proof(payload: &[u8]) -> Result<Vec<(Vec<u8>, bool)>, ProtocolError> {
+    let proof: Vec<(Vec<u8>, bool)> = serde_json::from_slice(payload)?;
+    check_proof(&proof)?;
+    Ok(proof)
+}
+
+impl ServerMessage {
+    /// The request's type, as it is tagged on the wire.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ServerMessage::Upload { .. } => "Upload",
+            ServerMessage::Download { .. } => "Download",
+            ServerMessage::DownloadRaw { .. } => "DownloadRaw",
+            ServerMessage::UploadRaw { .. } => "UploadRaw",
+            ServerMessage::GetMerkleProof { .. } => "GetMerkleProof",
+            ServerMessage::GetMerkleProofAt { .. } => "GetMerkleProofAt",
+            ServerMessage::GetMultiProof { .. } => "GetMultiProof",
+            ServerMessage::GetConsistencyProof { .. } => "GetConsistencyProof",
+            ServerMessage::GetRootHash => "GetRootHash",
+            ServerMessage::ReadRange { .. } => "ReadRange",
+            ServerMessage::GetChunkHashes { .. } => "GetChunkHashes",
+            ServerMessage::UploadChunks { .. } => "UploadChunks",
+            ServerMessage::Batch { .. } => "Batch",
+            ServerMessage::ListFiles => "ListFiles",
+            ServerMessage::GetTreeHead => "GetTreeHead",
+            ServerMessage::GetLogConsistency { .. } => "GetLogConsistency",
+            ServerMessage::GetStats => "GetStats",
+            ServerMessage::Prepare { .. } => "Prepare",
+            ServerMessage::Commit { .. } => "Commit",
+            ServerMessage::Abort { .. } => "Abort",
+            ServerMessage::Unknown => "Unknown",
+        }
+    }
+}
+
+impl ClientMessage {
+    /// Checks every proof carried by a response received from the server.
+    pub fn check_proofs(&self) -> Result<(), ProtocolError> {
+        match self {
+            ClientMessage::MerkleProof { proof, .. } => check_proof(proof),
+            ClientMessage::MultiProof { proof } => check_multiproof(proof),
+            ClientMessage::ConsistencyProof { proof } => check_consistency_proof(&proof.hashes),
+            ClientMessage::Chunks { chunks } => chunks
+                .iter()
+                .try_for_each(|chunk| check_proof(&chunk.proof)),
+            ClientMessage::UploadReceipt { proofs, .. } => {
+                proofs.values().try_for_each(|proof| check_proof(proof))
+            }
+            ClientMessage::LogConsistency { proof } => check_consistency_proof(proof),
+            ClientMessage::Batch { responses } => {
+                responses.iter().try_for_each(ClientMessage::check_proofs)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Length prefix of a frame, refusing frames longer than `MAX_FRAME_LEN`.
+pub async fn read_frame_len<R: AsyncRead + Unpin>(reader: &mut R) -> Result<u64, ProtocolError> {
+    let length = reader.read_u64().await?;
+    if length > MAX_FRAME_LEN {
+        return Err(ProtocolError::FrameTooLarge(length));
+    }
+    Ok(length)
+}
+
+pub async fn write_frame<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    payload: &[u8],
+) -> Result<(), ProtocolError> {
+    let _span = profile::span(Stage::SocketWrite).with_bytes(payload.len() as u64);
+    writer.write_u64(payload.len() as u64).await?;
+    writer.write_all(payload).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Reads exactly one frame, never past its end; use `read_frame_buf` to reuse a buffer
+/// across frames.
+pub async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Vec<u8>, ProtocolError> {
+    let mut span = profile::span(Stage::SocketRead);
+    let length = read_frame_len(reader).await?;
+    // Grow the buffer as bytes arrive instead of trusting the length prefix up front
+    let mut payload = Vec::new();
+    reader.take(length).read_to_end(&mut payload).await?;
+    if payload.len() as u64 != length {
+        return Err(ProtocolError::Truncated);
+    }
+    span.add_bytes(length);
+    Ok(payload)
+}
+
+/// Blocking form of `write_frame`, for callers without an async runtime.
+pub fn write_frame_blocking<W: std::io::Write>(
+    writer: &mut W,
+    payload: &[u8],
+) -> Result<(), ProtocolError> {
+    let _span = profile::span(Stage::SocketWrite).with_bytes(payload.len() as u64);
+    writer.write_all(&(payload.len() as u64).to_be_bytes())?;
+    writer.write_all(payload)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Blocking form of `read_frame`, for callers without an async runtime.
+pub fn read_frame_blocking<R: std::io::Read>(reader: &mut R) -> Result<Vec<u8>, ProtocolError> {
+    let mut span = profile::span(Stage::SocketRead);
+    let mut length = [0u8; 8];
+    reader.read_exact(&mut length)?;
+    let length = u64::from_be_bytes(length);
+    if length > MAX_FRAME_LEN {
+        return Err(ProtocolError::FrameTooLarge(length));
+    }
+    let mut payload = Vec::new();
+    std::io::Read::read_to_end(&mut std::io::Read::take(reader, length), &mut payload)?;
+    if payload.len() as u64 != length {
+        return Err(ProtocolError::Truncated);
+    }
+    span.add_bytes(length);
+    Ok(payload)
+}
+
+pub async fn write_message<W, T>(writer: &mut W, message: &T) -> Result<(), ProtocolError>
+where
+    W: AsyncWrite + Unpin,
+    T: Serialize,
+{
+    write_frame(writer, &encode(message)?).await
+}
+
+pub async fn read_message<R, T>(reader: &mut R) -> Result<T, ProtocolError>
+where
+    R: AsyncRead + Unpin,
+    T: DeserializeOwned,
+{
+    decode(&read_frame(reader).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io;
+    use tokio_util::codec::{Decoder, Encoder};
+
+    #[tokio::test]
+    async fn test_message_round_trip() {
+        let (mut client, mut server) = io::duplex(1024);
+        let request = ServerMessage::GetMerkleProof {
+            filename: "a.txt".to_string(),
+        };
+        write_message(&mut client, &request).await.unwrap();
+        match read_message(&mut server).await.unwrap() {
+            ServerMessage::GetMerkleProof { filename } => assert_eq!(filename, "a.txt"),
+            other => panic!("Unexpected message {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_transaction_ids_keep_all_128_bits() {
+        let transaction = u128::MAX - 1;
+        let frame = encode(&ServerMessage::Commit { transaction }).unwrap();
+        match decode(&frame).unwrap() {
+            ServerMessage::Commit { transaction: read } => assert_eq!(read, transaction),
+            other => panic!("Unexpected message {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_oversized_frame_is_rejected() {
+        let (mut client, mut server) = io::duplex(1024);
+        client.write_u64(u64::MAX).await.unwrap();
+        let err = read_frame(&mut server).await.unwrap_err();
+        assert!(matches!(err, ProtocolError::FrameTooLarge(_)));
+    }
+
+    #[tokio::test]
+    async fn test_frames_reuse_buffers() {
+        let (mut client, mut server) = io::duplex(1024);
+        let mut buf = BytesMut::from(&b"stale contents"[..]);
+        encode_into(&ServerMessage::GetRootHash, &mut buf).unwrap();
+        assert_eq!(buf, encode(&ServerMessage::GetRootHash).unwrap());
+        write_frame(&mut client, &buf).await.unwrap();
+        let mut frames = BytesMut::new();
+        FrameCodec::default()
+            .encode(Bytes::from_static(b"second"), &mut frames)
+            .unwrap();
+        FrameCodec::default()
+            .encode(Bytes::from(vec![0; 100]), &mut frames)
+            .unwrap();
+        client.write_all(&frames).await.unwrap();
+        drop(client);
+
+        let mut codec = FrameCodec::new(64);
+        let mut buffer = BytesMut::new();
+        let frame = read_frame_buf(&mut server, &mut codec, &mut buffer)
+            .await
+            .unwrap();
+        assert!(matches!(
+            decode(&frame).unwrap(),
+            ServerMessage::GetRootHash
+        ));
+        let frame = read_frame_buf(&mut server, &mut codec, &mut buffer)
+            .await
+            .unwrap();
+        assert_eq!(&frame[..], b"second");
+        let err = read_frame_buf(&mut server, &mut codec, &mut buffer)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ProtocolError::FrameTooLarge(100)));
+    }
+
+    #[test]
+    fn test_codec_waits_for_whole_frames() {
+        let mut codec = FrameCodec::default();
+        let mut buffer = BytesMut::from(&100u64.to_be_bytes()[..]);
+        buffer.extend_from_slice(&[1; 50]);
+        assert!(codec.decode(&mut buffer).unwrap().is_none());
+        assert!(matches!(
+            codec.decode_eof(&mut buffer),
+            Err(ProtocolError::Truncated)
+        ));
+        buffer.extend_from_slice(&[1; 50]);
+        assert_eq!(codec.decode(&mut buffer).unwrap().unwrap(), vec![1; 100]);
+        assert!(buffer.is_empty());
+
+        // Files travel in the same wire form as before
+        let message = ClientMessage::Success {
+            data: Bytes::from_static(&[1, 2]),
+        };
+        assert!(String::from_utf8(encode(&message).unwrap())
+            .unwrap()
+            .contains(r#""data":[1,2]"#));
+    }
+
+    #[test]
+    fn test_deeply_nested_batch_is_rejected() {
+        let mut payload = String::from(r#"{"version":1,"message":"#);
+        for _ in 0..10_000 {
+            payload.push_str(r#"{"type":"Batch","body":{"requests":["#);
+        }
+        assert!(decode::<ServerMessage>(payload.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_malformed_proofs_are_rejected() {
+        assert!(decode_proof(b"[]").is_ok());
+        assert!(decode_proof(br#"[[[1,2,3],true]]"#).is_err());
+        let too_deep = vec![(vec![0u8; HASH_LEN], true); MAX_PROOF_DEPTH + 1];
+        assert!(check_proof(&too_deep).is_err());
+
+        let mut proof = MultiProof {
+            leaf_indices: vec![0, 5],
+            tree_size: 8,
+            siblings: vec![vec![0u8; HASH_LEN]; 2 * MAX_PROOF_DEPTH],
+        };
+        assert!(check_multiproof(&proof).is_ok());
+        proof.siblings.push(vec![0u8; HASH_LEN]);
+        assert!(check_multiproof(&proof).is_err());
+        proof.siblings = vec![vec![0u8; 20]];
+        assert!(check_multiproof(&proof).is_err());
+    }
+
+    #[test]
+    fn test_version_1_messages_decode() {
+        // Payloads exactly as version 1 peers write them; these must keep decoding
+        let request: ServerMessage = decode(
+            br#"{"version":1,"message":{"type":"ReadRange","body":{"filename":"a.txt","offset":0,"len":10,"chunk_size":4}}}"#,
+        )
+        .unwrap();
+        assert!(matches!(request, ServerMessage::ReadRange { len: 10, .. }));
+
+        let request: ServerMessage =
+            decode(br#"{"version":1,"message":{"type":"GetRootHash"}}"#).unwrap();
+        assert!(matches!(request, ServerMessage::GetRootHash));
+
+        let response: ClientMessage = decode(
+            br#"{"version":1,"message":{"type":"Error","body":{"message":"File not found"}}}"#,
+        )
+        .unwrap();
+        assert!(matches!(response, ClientMessage::Error { .. }));
+    }
+
+    #[test]
+    fn test_other_hash_algorithms_are_refused() {
+        let payload = encode(&ServerMessage::GetRootHash).unwrap();
+        let envelope: serde_json::Value = serde_json::from_slice(&payload).unwrap();
+        assert_eq!(envelope["hash"], "sha256");
+
+        let err = decode::<ServerMessage>(
+            br#"{"version":11,"hash":"sha3-256","message":{"type":"GetRootHash"}}"#,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            ProtocolError::WrongHashAlgorithm(HashAlgorithm::Sha3_256)
+        ));
+        // Also when the message is one this version does not know
+        let err = decode::<ClientMessage>(
+            br#"{"version":11,"hash":"sha512","message":{"type":"Stream","body":{"id":1}}}"#,
+        )
+        .unwrap_err();
+        assert!(matches!(err, ProtocolError::WrongHashAlgorithm(_)));
+    }
+
+    #[test]
+    fn test_messages_from_before_the_envelope_decode() {
+        // Payloads exactly as peers before protocol version 1 wrote them
+        let (request, format): (ServerMessage, _) =
+            decode_with_format(br#"{"Download":{"filename":"a.txt"}}"#).unwrap();
+        assert!(matches!(request, ServerMessage::Download { filename } if filename == "a.txt"));
+        assert_eq!(format, WireFormat::Bare);
+
+        let request: ServerMessage = decode(br#""GetRootHash""#).unwrap();
+        assert!(matches!(request, ServerMessage::GetRootHash));
+
+        let request: ServerMessage =
+            decode(br#"{"Batch":{"requests":["GetRootHash",{"Download":{"filename":"a.txt"}}]}}"#)
+                .unwrap();
+        match request {
+            ServerMessage::Batch { requests } => assert!(matches!(
+                requests[..],
+                [ServerMessage::GetRootHash, ServerMessage::Download { .. }]
+            )),
+            other => panic!("Unexpected message {:?}", other),
+        }
+
+        // And they are answered in their own form
+        let response = ClientMessage::Success {
+            data: Bytes::from_static(b"hi"),
+        };
+        let encoded = encode_as(&response, WireFormat::Bare).unwrap();
+        assert_eq!(encoded, br#"{"Success":{"data":[104,105]}}"#);
+    }
+
+    #[test]
+    fn test_missing_optional_fields_use_defaults() {
+        let request: ServerMessage =
+            decode(br#"{"version":1,"message":{"type":"Upload","body":{"client_files":{}}}}"#)
+                .unwrap();
+        match request {
+            ServerMessage::Upload {
+                dry_run,
+                with_proofs,
+                on_collision,
+                ..
+            } => assert!(!dry_run && !with_proofs && on_collision.is_none()),
+            other => panic!("Unexpected message {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_newer_messages_decode_gracefully() {
+        // A future peer may add variants and fields; neither should break decoding
+        let request: ServerMessage =
+            decode(br#"{"version":7,"message":{"type":"Compact","body":{"level":3}}}"#).unwrap();
+        assert!(matches!(request, ServerMessage::Unknown));
+
+        let request: ServerMessage = decode(
+            br#"{"version":7,"message":{"type":"Download","body":{"filename":"a.txt","priority":2}}}"#,
+        )
+        .unwrap();
+        assert!(matches!(request, ServerMessage::Download { .. }));
+
+        let response: ClientMessage =
+            decode(br#"{"version":7,"message":{"type":"Stream","body":{"id":1}}}"#).unwrap();
+        assert!(matches!(response, ClientMessage::Unknown));
+    }
+}
@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Addresses tracked before those with no recent errors are forgotten.
+const MAX_TRACKED: usize = 4096;
+
+/// Bans addresses that keep sending requests the server cannot parse, or that connect and
+/// then send nothing, so one misbehaving source cannot tie up the server's connections.
+#[derive(Debug)]
+pub(crate) struct Bans {
+    /// Errors that get an address banned; never bans if zero.
+    max_errors: u32,
+    /// How long a ban lasts, and how long an address's errors are remembered.
+    ban: Duration,
+    sources: Mutex<HashMap<IpAddr, Offences>>,
+}
+
+#[derive(Debug)]
+struct Offences {
+    errors: u32,
+    last: Instant,
+    banned_until: Option<Instant>,
+}
+
+impl Bans {
+    pub fn new(max_errors: u32, ban: Duration) -> Self {
+        Bans {
+            max_errors,
+            ban,
+            sources: Mutex::default(),
+        }
+    }
+
+    pub fn is_banned(&self, source: IpAddr) -> bool {
+        let mut sources = self.sources.lock().unwrap_or_else(PoisonError::into_inner);
+        match sources
+            .get(&source)
+            .and_then(|offences| offences.banned_until)
+        {
+            Some(until) if until > Instant::now() => true,
+            Some(_) => {
+                sources.remove(&source);
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Counts an error from `source`, returning whether it got `source` banned.
+    pub fn record_error(&self, source: IpAddr) -> bool {
+        if self.max_errors == 0 {
+            return false;
+        }
+        let now = Instant::now();
+        let mut sources = self.sources.lock().unwrap_or_else(PoisonError::into_inner);
+        if sources.len() >= MAX_TRACKED {
+            sources.retain(|_, offences| {
+                offences.banned_until.is_some_and(|until| until > now)
+                    || now.duration_since(offences.last) < self.ban
+            });
+        }
+        let offences = sources.entry(source).or_insert(Offences {
+            errors: 0,
+            last: now,
+            banned_until: None,
+        });
+        if now.duration_since(offences.last) >= self.ban {
+            offences.errors = 0;
+        }
+        offences.errors += 1;
+        offences.last = now;
+        if offences.errors < self.max_errors || offences.banned_until.is_some() {
+            return false;
+        }
+        offences.banned_until = Some(now + self.ban);
+        true
+    }
+}
+
+/// Bytes of requests all connections may hold at once. Each connection reserves the size its
+/// request announces before reading it, so many connections announcing large requests
+/// cannot together exhaust the server's memory.
+#[derive(Debug)]
+pub(crate) struct MemoryBudget {
+    limit: Option<u64>,
+    used: AtomicU64,
+}
+
+impl MemoryBudget {
+    /// A budget of `limit` bytes, or an unlimited one.
+    pub fn new(limit: Option<u64>) -> Self {
+        MemoryBudget {
+            limit,
+            used: AtomicU64::new(0),
+        }
+    }
+
+    /// Reserves `bytes` until the reservation is dropped, or returns `None` if that would
+    /// exceed the budget.
+    pub fn reserve(self: &Arc<Self>, bytes: u64) -> Option<Reservation> {
+        let limit = self.limit.unwrap_or(u64::MAX);
+        self.used
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+                used.checked_add(bytes).filter(|total| *total <= limit)
+            })
+            .ok()?;
+        Some(Reservation {
+            budget: Arc::clone(self),
+            bytes,
+        })
+    }
+}
+
+/// Bytes held against a `MemoryBudget`, returned when dropped.
+#[derive(Debug)]
+pub(crate) struct Reservation {
+    budget: Arc<MemoryBudget>,
+    bytes: u64,
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.budget.used.fetch_sub(self.bytes, Ordering::SeqCst);
+    }
+}
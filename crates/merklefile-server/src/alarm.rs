@@ -0,0 +1,134 @@
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// What found stored data out of step with what the server attests to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TamperSource {
+    /// The self-check run after every request.
+    SelfCheck,
+    /// A file about to be downloaded no longer matches its leaf.
+    Shadow,
+    /// The root journal no longer ends in the entry the server last wrote.
+    Journal,
+    /// Rebuilding the tree from storage found it or the stored files out of step.
+    Rebuild,
+}
+
+impl fmt::Display for TamperSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TamperSource::SelfCheck => "self_check",
+            TamperSource::Shadow => "shadow",
+            TamperSource::Journal => "journal",
+            TamperSource::Rebuild => "rebuild",
+        })
+    }
+}
+
+/// Evidence that stored bytes no longer match the tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TamperEvent {
+    pub source: TamperSource,
+    /// One line per problem found.
+    pub details: Vec<String>,
+    /// When it was found, in seconds since the Unix epoch.
+    pub timestamp: u64,
+}
+
+impl TamperEvent {
+    pub fn new(source: TamperSource, details: Vec<String>) -> Self {
+        TamperEvent {
+            source,
+            details,
+            timestamp: crate::now(),
+        }
+    }
+}
+
+/// Told whenever the server finds that what it stores does not match its tree, so someone
+/// can act before corrupted data spreads.
+pub trait TamperAlarm: Send + Sync {
+    /// Raises the alarm for `event`. Called from a blocking thread.
+    fn raise(&self, event: &TamperEvent) -> io::Result<()>;
+}
+
+/// Logs the event to standard error.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LogAlarm;
+
+impl TamperAlarm for LogAlarm {
+    fn raise(&self, event: &TamperEvent) -> io::Result<()> {
+        for detail in &event.details {
+            eprintln!("Tamper alarm ({}): {}", event.source, detail);
+        }
+        Ok(())
+    }
+}
+
+/// POSTs `{"event": "tamper", "source": ..., "details": [...], "timestamp": ...}` to a webhook.
+#[derive(Debug, Clone)]
+pub struct WebhookAlarm {
+    pub url: String,
+    pub headers: BTreeMap<String, String>,
+    pub timeout: Duration,
+}
+
+impl TamperAlarm for WebhookAlarm {
+    fn raise(&self, event: &TamperEvent) -> io::Result<()> {
+        let mut request = ureq::post(&self.url).timeout(self.timeout);
+        for (name, value) in &self.headers {
+            request = request.set(name, value);
+        }
+        request
+            .send_json(json!({
+                "event": "tamper",
+                "source": event.source.to_string(),
+                "details": event.details,
+                "timestamp": event.timestamp,
+            }))
+            .map(|_| ())
+            .map_err(|err| io::Error::other(format!("webhook {}: {}", self.url, err)))
+    }
+}
+
+/// Stops the server with exit code `code`, so a supervisor can take it out of service
+/// rather than let it keep serving. Alarms are raised in order, so put this one last.
+#[derive(Debug, Clone, Copy)]
+pub struct ExitAlarm {
+    pub code: i32,
+}
+
+impl TamperAlarm for ExitAlarm {
+    fn raise(&self, event: &TamperEvent) -> io::Result<()> {
+        eprintln!(
+            "Tamper alarm ({}): exiting with code {}",
+            event.source, self.code
+        );
+        std::process::exit(self.code)
+    }
+}
+
+/// Raises `event` with every alarm in turn. An alarm that fails is logged, and the ones
+/// after it are still raised.
+pub fn raise_all(alarms: &[Arc<dyn TamperAlarm>], event: &TamperEvent) {
+    for alarm in alarms {
+        if let Err(err) = alarm.raise(event) {
+            eprintln!("Tamper alarm failed: {}", err);
+        }
+    }
+}
+
+/// `raise_all` on a blocking thread.
+pub(crate) async fn raise_all_blocking(alarms: &[Arc<dyn TamperAlarm>], event: TamperEvent) {
+    if alarms.is_empty() {
+        return;
+    }
+    let alarms = alarms.to_vec();
+    if let Err(err) = tokio::task::spawn_blocking(move || raise_all(&alarms, &event)).await {
+        eprintln!("Tamper alarm failed: {}", err);
+    }
+}
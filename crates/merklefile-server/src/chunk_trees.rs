@@ -0,0 +1,50 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use merklefile_core::merkle_tree::MerkleTree;
+
+/// Chunk trees kept at once; the oldest is dropped to make room for another.
+const CAPACITY: usize = 64;
+
+/// The chunk trees of recently read files, so a `ReadRange` only looks its proofs up instead
+/// of hashing the whole file again. Trees are keyed by the file's leaf hash and the chunk
+/// size, so a file that is replaced is simply never asked for again.
+#[derive(Debug, Default)]
+pub(crate) struct ChunkTrees {
+    trees: Mutex<Trees>,
+}
+
+#[derive(Debug, Default)]
+struct Trees {
+    by_key: HashMap<(Vec<u8>, usize), MerkleTree>,
+    /// Keys from the oldest insertion to the newest.
+    order: VecDeque<(Vec<u8>, usize)>,
+}
+
+impl ChunkTrees {
+    /// The tree over the `chunk_size` chunks of the file whose leaf hash is `hash`, if kept.
+    pub fn get(&self, hash: &[u8], chunk_size: usize) -> Option<MerkleTree> {
+        self.lock()
+            .by_key
+            .get(&(hash.to_vec(), chunk_size))
+            .cloned()
+    }
+
+    pub fn insert(&self, hash: Vec<u8>, chunk_size: usize, tree: MerkleTree) {
+        let mut trees = self.lock();
+        let key = (hash, chunk_size);
+        if trees.by_key.insert(key.clone(), tree).is_some() {
+            return;
+        }
+        trees.order.push_back(key);
+        if trees.order.len() > CAPACITY {
+            if let Some(oldest) = trees.order.pop_front() {
+                trees.by_key.remove(&oldest);
+            }
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Trees> {
+        self.trees.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
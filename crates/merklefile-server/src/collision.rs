@@ -0,0 +1,60 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use merklefile_proto::CollisionPolicy;
+
+use crate::storage::StoredFile;
+
+/// The name an upload of `filename` whose contents hash to `hash` is stored under, or why
+/// `policy` refuses it. `taken` are names other files in the same upload are stored under,
+/// which a new version must not take.
+pub(crate) fn stored_name(
+    files: &BTreeMap<String, StoredFile>,
+    filename: &str,
+    hash: &[u8],
+    policy: CollisionPolicy,
+    taken: &BTreeSet<String>,
+) -> Result<String, String> {
+    match files.get(filename) {
+        Some(stored) if stored.hash != hash => {}
+        _ => return Ok(filename.to_string()),
+    }
+    match policy {
+        CollisionPolicy::Overwrite => Ok(filename.to_string()),
+        CollisionPolicy::Reject => Err(format!(
+            "{} already exists with other contents; upload refused",
+            filename
+        )),
+        CollisionPolicy::AutoVersion => {
+            let mut n = 1;
+            loop {
+                let name = CollisionPolicy::versioned_name(filename, n);
+                match files.get(&name) {
+                    Some(stored) if stored.hash == hash => return Ok(name),
+                    None if !taken.contains(&name) => return Ok(name),
+                    _ => n += 1,
+                }
+            }
+        }
+    }
+}
+
+/// `stored_name` of every file in an upload, in order, or why `policy` refuses the upload.
+pub(crate) fn stored_names<'a>(
+    files: &BTreeMap<String, StoredFile>,
+    uploads: impl IntoIterator<Item = (&'a str, &'a [u8])>,
+    policy: CollisionPolicy,
+) -> Result<Vec<String>, String> {
+    let uploads: Vec<(&str, &[u8])> = uploads.into_iter().collect();
+    // A new version must not take the name of a file uploaded alongside it
+    let mut taken: BTreeSet<String> = uploads
+        .iter()
+        .map(|(filename, _)| filename.to_string())
+        .collect();
+    let mut names = Vec::with_capacity(uploads.len());
+    for (filename, hash) in uploads {
+        let name = stored_name(files, filename, hash, policy, &taken)?;
+        taken.insert(name.clone());
+        names.push(name);
+    }
+    Ok(names)
+}
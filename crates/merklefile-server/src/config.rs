@@ -0,0 +1,281 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use merklefile_proto::{CollisionPolicy, MAX_FRAME_LEN};
+
+use crate::abuse::Bans;
+use crate::alarm::{ExitAlarm, LogAlarm, TamperAlarm, WebhookAlarm};
+use crate::pool::BufferPool;
+use crate::publish::{DnsTxtPublisher, JournalPublisher, NotaryPublisher, RootPublisher};
+use crate::transaction::Transactions;
+
+/// Server settings, loaded from TOML such as:
+///
+/// ```toml
+/// max_message_size = 67108864
+/// buffer_pool_size = 64
+/// stream_threshold = 16777216
+/// sendfile = true
+/// history_versions = 100
+/// hash_concurrency = 2
+/// shadow_verify = true
+/// header_timeout_secs = 5
+/// min_request_rate = 65536
+/// max_parse_errors = 10
+/// ban_secs = 600
+/// request_memory = 1073741824
+/// on_collision = "reject"
+/// prepare_timeout_secs = 10
+///
+/// [[publishers]]
+/// kind = "dns"
+/// api_url = "https://dns.example.com/zones/files.internal/records/root"
+/// name = "root.files.internal"
+/// headers = { Authorization = "Bearer ..." }
+///
+/// [[publishers]]
+/// kind = "notary"
+/// url = "https://notary.example.com/roots"
+///
+/// [[publishers]]
+/// kind = "journal"
+/// path = "/mnt/shared/merklefile/roots.log"
+///
+/// [[alarms]]
+/// kind = "webhook"
+/// url = "https://alerts.example.com/merklefile"
+///
+/// [[alarms]]
+/// kind = "exit"
+/// ```
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Where every new root is pushed before it takes effect.
+    pub publishers: Vec<PublisherConfig>,
+    /// Largest request accepted, in bytes; larger frames are refused before being read.
+    /// Capped at the protocol's own limit.
+    pub max_message_size: u64,
+    /// How many connection buffers are kept for reuse between requests.
+    pub buffer_pool_size: usize,
+    /// Downloads of files stored on disk and at least this large are read from the file and
+    /// sent a step at a time, instead of being read onto the heap first. Off if unset. Also
+    /// accepted as `mmap_threshold`, its name from when these files were memory-mapped.
+    #[serde(alias = "mmap_threshold")]
+    pub stream_threshold: Option<u64>,
+    /// Send the bytes of raw downloads of files stored on disk with sendfile(2), straight
+    /// from the page cache to the socket. Only has an effect on Linux.
+    pub sendfile: bool,
+    /// How many past versions of the tree are kept, so clients can get proofs against a root
+    /// they recorded earlier. Versions that only modify files cost memory in proportion to
+    /// the files they modified.
+    pub history_versions: usize,
+    /// Most uploaded files hashed at once, across all connections; one per core when unset.
+    /// Lower it to keep integrity work from taking every core of a shared host.
+    pub hash_concurrency: Option<usize>,
+    /// Re-hash every file before it is downloaded, and refuse to send it, raising the tamper
+    /// alarms, unless it matches its leaf. Downloads then always read the whole file first,
+    /// so `stream_threshold` and `sendfile` no longer apply to them.
+    pub shadow_verify: bool,
+    /// Raised, in order, whenever stored data is found not to match the tree.
+    pub alarms: Vec<AlarmConfig>,
+    /// Seconds a client has after connecting to send the length of its request before it is
+    /// disconnected, so idle connections cannot pile up.
+    pub header_timeout_secs: u64,
+    /// Bytes per second a client must at least send its request at once the length is in,
+    /// raw upload bodies included. A request of `n` bytes gets the header timeout plus
+    /// `n / min_request_rate` seconds before it is disconnected; zero waits indefinitely.
+    pub min_request_rate: u64,
+    /// Malformed requests, oversized requests and missed deadlines an address may
+    /// cause before it is banned; zero never bans.
+    pub max_parse_errors: u32,
+    /// How long a ban lasts, and how long an address's errors count towards one.
+    pub ban_secs: u64,
+    /// Most bytes of requests held across all connections at once. Each connection
+    /// reserves the size its request announces before reading it, as well as the size of a
+    /// raw upload held in memory or of a file rebuilt from a delta upload, and is answered
+    /// that the server is busy if that would exceed the limit. Unlimited if unset.
+    pub request_memory: Option<u64>,
+    /// What uploads do to stored files they would change, unless the request asks for
+    /// something else: `overwrite`, `reject` or `autoversion`.
+    pub on_collision: CollisionPolicy,
+    /// How long an upload prepared for a commit across servers holds off other uploads
+    /// before it is dropped, should it be neither committed nor aborted.
+    pub prepare_timeout_secs: u64,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            publishers: Vec::new(),
+            max_message_size: MAX_FRAME_LEN,
+            buffer_pool_size: DEFAULT_BUFFER_POOL_SIZE,
+            stream_threshold: None,
+            sendfile: false,
+            history_versions: 0,
+            hash_concurrency: None,
+            shadow_verify: false,
+            alarms: Vec::new(),
+            header_timeout_secs: 10,
+            min_request_rate: 64 * 1024,
+            max_parse_errors: 20,
+            ban_secs: 300,
+            request_memory: None,
+            on_collision: CollisionPolicy::default(),
+            prepare_timeout_secs: 30,
+        }
+    }
+}
+
+/// One place new roots are published to.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "kind", rename_all = "lowercase", deny_unknown_fields)]
+pub enum PublisherConfig {
+    /// A DNS TXT record, written through the DNS provider's HTTP API.
+    Dns {
+        api_url: String,
+        name: String,
+        #[serde(default = "default_ttl")]
+        ttl: u32,
+        #[serde(default)]
+        headers: BTreeMap<String, String>,
+        #[serde(default = "default_timeout")]
+        timeout_secs: u64,
+    },
+    /// An HTTPS notary the root is POSTed to.
+    Notary {
+        url: String,
+        #[serde(default)]
+        headers: BTreeMap<String, String>,
+        #[serde(default = "default_timeout")]
+        timeout_secs: u64,
+    },
+    /// A journal file on shared storage that roots are appended to.
+    Journal { path: PathBuf },
+}
+
+/// One way of raising a tamper alarm.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "kind", rename_all = "lowercase", deny_unknown_fields)]
+pub enum AlarmConfig {
+    /// A line on standard error per problem found.
+    Log,
+    /// A webhook the event is POSTed to as JSON.
+    Webhook {
+        url: String,
+        #[serde(default)]
+        headers: BTreeMap<String, String>,
+        #[serde(default = "default_timeout")]
+        timeout_secs: u64,
+    },
+    /// Stop the server.
+    Exit {
+        #[serde(default = "default_exit_code")]
+        code: i32,
+    },
+}
+
+/// Idle buffers kept by default: enough for a burst of concurrent connections.
+pub(crate) const DEFAULT_BUFFER_POOL_SIZE: usize = 32;
+
+fn default_ttl() -> u32 {
+    60
+}
+
+fn default_timeout() -> u64 {
+    30
+}
+
+/// The CLI's exit code for an integrity violation.
+fn default_exit_code() -> i32 {
+    7
+}
+
+impl ServerConfig {
+    pub fn from_toml(contents: &str) -> io::Result<Self> {
+        toml::from_str(contents)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_toml(&std::fs::read_to_string(path)?)
+    }
+
+    /// A buffer pool with the configured size and message limit.
+    pub fn buffer_pool(&self) -> BufferPool {
+        BufferPool::new(
+            self.buffer_pool_size,
+            self.max_message_size.min(MAX_FRAME_LEN),
+        )
+    }
+
+    /// Bans with the configured thresholds.
+    pub(crate) fn bans(&self) -> Bans {
+        Bans::new(self.max_parse_errors, Duration::from_secs(self.ban_secs))
+    }
+
+    /// Prepared uploads, dropped after the configured timeout.
+    pub(crate) fn transactions(&self) -> Transactions {
+        Transactions::new(Duration::from_secs(self.prepare_timeout_secs))
+    }
+
+    /// The configured publishers, in the order roots are published to them.
+    pub fn publishers(&self) -> Vec<Arc<dyn RootPublisher>> {
+        self.publishers
+            .iter()
+            .map(|publisher| -> Arc<dyn RootPublisher> {
+                match publisher.clone() {
+                    PublisherConfig::Dns {
+                        api_url,
+                        name,
+                        ttl,
+                        headers,
+                        timeout_secs,
+                    } => Arc::new(DnsTxtPublisher {
+                        api_url,
+                        name,
+                        ttl,
+                        headers,
+                        timeout: Duration::from_secs(timeout_secs),
+                    }),
+                    PublisherConfig::Notary {
+                        url,
+                        headers,
+                        timeout_secs,
+                    } => Arc::new(NotaryPublisher {
+                        url,
+                        headers,
+                        timeout: Duration::from_secs(timeout_secs),
+                    }),
+                    PublisherConfig::Journal { path } => Arc::new(JournalPublisher { path }),
+                }
+            })
+            .collect()
+    }
+
+    /// The configured tamper alarms, in the order they are raised.
+    pub fn alarms(&self) -> Vec<Arc<dyn TamperAlarm>> {
+        self.alarms
+            .iter()
+            .map(|alarm| -> Arc<dyn TamperAlarm> {
+                match alarm.clone() {
+                    AlarmConfig::Log => Arc::new(LogAlarm),
+                    AlarmConfig::Webhook {
+                        url,
+                        headers,
+                        timeout_secs,
+                    } => Arc::new(WebhookAlarm {
+                        url,
+                        headers,
+                        timeout: Duration::from_secs(timeout_secs),
+                    }),
+                    AlarmConfig::Exit { code } => Arc::new(ExitAlarm { code }),
+                }
+            })
+            .collect()
+    }
+}
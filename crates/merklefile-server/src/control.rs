@@ -0,0 +1,156 @@
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+#[cfg(unix)]
+use std::sync::Arc;
+
+#[cfg(unix)]
+use crate::{maintenance, Shared};
+
+/// Commands accepted on the server's control socket, one per line. Each is answered with
+/// one line: `ok`, `error: ...`, or for `rebuild` a JSON [`RebuildReport`](crate::RebuildReport).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdminCommand {
+    /// Refuse every request until `resume`, e.g. while storage is being repaired.
+    Maintenance,
+    Resume,
+    /// Rebuild the tree from storage in maintenance mode and report what differed.
+    Rebuild,
+}
+
+impl AdminCommand {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AdminCommand::Maintenance => "maintenance",
+            AdminCommand::Resume => "resume",
+            AdminCommand::Rebuild => "rebuild",
+        }
+    }
+}
+
+impl FromStr for AdminCommand {
+    type Err = String;
+
+    fn from_str(command: &str) -> Result<Self, Self::Err> {
+        match command {
+            "maintenance" => Ok(AdminCommand::Maintenance),
+            "resume" => Ok(AdminCommand::Resume),
+            "rebuild" => Ok(AdminCommand::Rebuild),
+            _ => Err(format!(
+                "unknown command '{}', expected maintenance, resume or rebuild",
+                command
+            )),
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn handle(command: AdminCommand, shared: &Shared) -> String {
+    match command {
+        AdminCommand::Maintenance => {
+            shared.maintenance.set(true);
+            "ok".to_string()
+        }
+        AdminCommand::Resume => {
+            shared.maintenance.set(false);
+            "ok".to_string()
+        }
+        AdminCommand::Rebuild => maintenance::rebuild(
+            &shared.files,
+            &shared.server_mt,
+            &shared.maintenance,
+            &shared.alarms,
+        )
+        .await
+        .to_json(),
+    }
+}
+
+/// Sends one command to the server listening on the control socket at `path` and returns
+/// its answer.
+#[cfg(unix)]
+pub async fn send_command(path: impl AsRef<Path>, command: AdminCommand) -> io::Result<String> {
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    let mut stream = tokio::net::UnixStream::connect(path).await?;
+    stream
+        .write_all(format!("{}\n", command.as_str()).as_bytes())
+        .await?;
+    let mut answer = String::new();
+    BufReader::new(stream).read_line(&mut answer).await?;
+    Ok(answer.trim_end().to_string())
+}
+
+#[cfg(not(unix))]
+pub async fn send_command(_path: impl AsRef<Path>, _command: AdminCommand) -> io::Result<String> {
+    Err(unsupported())
+}
+
+/// Binds the control socket, replacing a stale socket file left by a server that died but
+/// refusing to take over from one still listening. Only the owner may connect.
+#[cfg(unix)]
+pub(crate) fn bind(path: &Path) -> io::Result<tokio::net::UnixListener> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", path.display()),
+            ));
+        }
+        if std::os::unix::net::UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("another server is listening on {}", path.display()),
+            ));
+        }
+        std::fs::remove_file(path)?;
+    }
+    let listener = tokio::net::UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+/// Answers control connections until the listener fails. Each connection may send any
+/// number of commands.
+#[cfg(unix)]
+pub(crate) async fn serve(listener: tokio::net::UnixListener, shared: Arc<Shared>) {
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                eprintln!("Control socket failed: {}", err);
+                return;
+            }
+        };
+        let shared = Arc::clone(&shared);
+        tokio::spawn(async move {
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                let answer = match line.trim().parse() {
+                    Ok(command) => handle(command, &shared).await,
+                    Err(err) => format!("error: {}", err),
+                };
+                if writer
+                    .write_all(format!("{}\n", answer).as_bytes())
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "control sockets are only supported on Unix",
+    )
+}
@@ -0,0 +1,52 @@
+/// Longest filename accepted, in bytes.
+const MAX_FILENAME_LEN: usize = 4096;
+
+/// Longest single path component accepted, in bytes.
+const MAX_COMPONENT_LEN: usize = 255;
+
+/// Longest suffix `CollisionPolicy::AutoVersion` appends to a filename, `.~<u64::MAX>~`. The
+/// last component, and the whole name, leave room for it so every version can be checked out.
+const MAX_VERSION_SUFFIX_LEN: usize = ".~18446744073709551615~".len();
+
+/// Checks a filename an upload wants to store a file under. Stored files are named on disk
+/// by their hash, so a filename never becomes a path on the server, but clients check files
+/// out under these names: only relative paths in the canonical form clients produce are
+/// accepted, with `/` or `\` between non-empty components, none of them `.` or `..`.
+pub(crate) fn validate(filename: &str) -> Result<(), String> {
+    let invalid = |reason: &str| Err(format!("Invalid filename {:?}: {}", filename, reason));
+    if filename.is_empty() {
+        return invalid("empty");
+    }
+    if filename.len() > MAX_FILENAME_LEN - MAX_VERSION_SUFFIX_LEN {
+        return invalid("too long");
+    }
+    if filename.contains('\0') {
+        return invalid("contains a NUL byte");
+    }
+    if filename.starts_with(['/', '\\']) || has_drive_prefix(filename) {
+        return invalid("absolute paths are not allowed");
+    }
+    let mut components = filename.split(['/', '\\']).peekable();
+    while let Some(component) = components.next() {
+        let max_len = match components.peek() {
+            Some(_) => MAX_COMPONENT_LEN,
+            None => MAX_COMPONENT_LEN - MAX_VERSION_SUFFIX_LEN,
+        };
+        match component {
+            ".." => return invalid("parent directory components are not allowed"),
+            "" | "." => return invalid("empty and '.' components are not allowed"),
+            _ if component.len() > max_len => return invalid("component too long"),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Whether `filename` is a Windows drive such as `C:` or a path from its root. Other names
+/// with a colon are allowed, as clients name alternate data streams `file:stream`.
+fn has_drive_prefix(filename: &str) -> bool {
+    match filename.as_bytes() {
+        [drive, b':'] | [drive, b':', b'/' | b'\\', ..] => drive.is_ascii_alphabetic(),
+        _ => false,
+    }
+}
@@ -0,0 +1,159 @@
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Arc;
+
+use merklefile_core::merkle_tree::{
+    MerkleProof, MerkleTree, NodeCache, PersistentTree, TreeSnapshot,
+};
+
+use crate::storage::StoredFile;
+
+/// The server's tree, and the versions before it that are kept for historical proofs.
+#[derive(Debug)]
+pub(crate) struct ServerTree {
+    pub current: MerkleTree,
+    history: History,
+}
+
+impl ServerTree {
+    pub fn new(current: MerkleTree) -> Self {
+        ServerTree {
+            current,
+            history: History::default(),
+        }
+    }
+
+    pub fn snapshot(&self) -> TreeSnapshot {
+        self.current.snapshot()
+    }
+
+    /// Keeps the `versions` most recent versions from now on.
+    pub fn keep_versions(&mut self, versions: usize) {
+        self.history.capacity = versions;
+        self.history.trim();
+    }
+
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    /// Makes `tree` the current tree and, if versions are kept, records the version it was
+    /// derived from and then `next`.
+    pub fn replace(&mut self, tree: MerkleTree, versions: Option<(Version, Version)>) {
+        self.current = tree;
+        if let Some((base, next)) = versions {
+            // `base` is already the latest version unless none was recorded yet
+            if self.history.versions.is_empty() {
+                self.history.versions.push_back(base);
+            }
+            self.history.versions.push_back(next);
+            self.history.trim();
+        }
+    }
+}
+
+/// One past version of the tree, with the filenames of its leaves in leaf order.
+#[derive(Debug, Clone)]
+pub(crate) struct Version {
+    tree: PersistentTree,
+    filenames: Arc<Vec<String>>,
+    /// Subtrees of the versions built in full, which the next such version reuses.
+    nodes: Arc<NodeCache>,
+}
+
+impl Version {
+    fn of(files: &BTreeMap<String, StoredFile>, nodes: Arc<NodeCache>) -> Self {
+        let (leaf_hashes, filenames) = if files.is_empty() {
+            // An empty server holds a tree with a single empty leaf
+            (vec![Sha256::digest([]).to_vec()], Vec::new())
+        } else {
+            (
+                files.values().map(|file| file.hash.clone()).collect(),
+                files.keys().cloned().collect(),
+            )
+        };
+        Version {
+            tree: PersistentTree::from_leaf_hashes_in(leaf_hashes, &nodes),
+            filenames: Arc::new(filenames),
+            nodes,
+        }
+    }
+}
+
+/// The most recent versions of the tree, oldest first. Versions in which files were only
+/// modified share every unchanged subtree and the list of filenames with the version before,
+/// so each costs memory in proportion to the files it changed. Adding a file shifts the
+/// leaves after it, so a version that adds files is built again. It is built through a cache
+/// of the subtrees earlier versions were built from, so it still shares most of those left
+/// of the first added file.
+#[derive(Debug, Default)]
+pub(crate) struct History {
+    versions: VecDeque<Version>,
+    capacity: usize,
+    nodes: Arc<NodeCache>,
+}
+
+impl History {
+    /// The version the next one will be derived from, given the files stored now, or `None`
+    /// if no versions are kept.
+    pub fn base(&self, files: &BTreeMap<String, StoredFile>) -> Option<Version> {
+        if self.capacity == 0 {
+            return None;
+        }
+        Some(match self.versions.back() {
+            Some(version) => version.clone(),
+            None => Version::of(files, Arc::clone(&self.nodes)),
+        })
+    }
+
+    /// The version after `latest` that holds `files`, of which only `changed` differ. Takes
+    /// no lock, so it can be built before the tree is locked to record it.
+    pub fn next(
+        latest: &Version,
+        files: &BTreeMap<String, StoredFile>,
+        changed: &[String],
+    ) -> Version {
+        let rebuilt = || Version::of(files, Arc::clone(&latest.nodes));
+        if latest.filenames.len() != files.len() {
+            return rebuilt();
+        }
+        let mut tree = latest.tree.clone();
+        for filename in changed {
+            let Ok(index) = latest.filenames.binary_search(filename) else {
+                return rebuilt();
+            };
+            tree = tree.set(index, files[filename].hash.clone());
+        }
+        Version {
+            tree,
+            filenames: Arc::clone(&latest.filenames),
+            nodes: Arc::clone(&latest.nodes),
+        }
+    }
+
+    /// Proof for `filename` in the kept version whose root is `root`.
+    pub fn proof(&self, root: &[u8], filename: &str) -> Result<MerkleProof, String> {
+        let version = self
+            .versions
+            .iter()
+            .rev()
+            .find(|version| version.tree.get_root_hash() == root)
+            .ok_or_else(|| format!("No version with root {} is kept", hex::encode(root)))?;
+        match version
+            .filenames
+            .binary_search_by(|name| name.as_str().cmp(filename))
+        {
+            Ok(index) => version
+                .tree
+                .proof(index)
+                .ok_or_else(|| "File not found".to_string()),
+            Err(_) => Err("File not found".to_string()),
+        }
+    }
+
+    fn trim(&mut self) {
+        while self.versions.len() > self.capacity {
+            self.versions.pop_front();
+        }
+    }
+}
@@ -0,0 +1,1707 @@
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::{
+    io::AsyncReadExt,
+    net::{TcpListener, TcpStream},
+    sync::{Mutex, MutexGuard},
+};
+
+use merklefile_core::chunk;
+use merklefile_core::error::display_chain;
+use merklefile_core::log::{self as tlog, SigningKey};
+use merklefile_core::merkle_tree::{self, MerkleTree, TreeSnapshot};
+use merklefile_proto::{
+    self as protocol, Bytes, BytesMut, ChangeKind, ChunkWithProof, ClientMessage, CollisionPolicy,
+    FileChange, FrameCodec, ProtocolError, ServerMessage, ServerStats, UploadCondition,
+};
+use std::io;
+use thiserror::Error;
+
+mod abuse;
+mod alarm;
+mod chunk_trees;
+mod collision;
+mod config;
+mod control;
+mod filename;
+mod history;
+mod log;
+mod maintenance;
+mod operations;
+mod pool;
+mod publish;
+mod raw;
+mod stats;
+mod storage;
+mod streamed;
+mod transaction;
+mod websocket;
+
+use abuse::{Bans, MemoryBudget};
+pub use alarm::{
+    raise_all, ExitAlarm, LogAlarm, TamperAlarm, TamperEvent, TamperSource, WebhookAlarm,
+};
+use chunk_trees::ChunkTrees;
+pub use config::{AlarmConfig, PublisherConfig, ServerConfig};
+pub use control::{send_command, AdminCommand};
+use history::{History, ServerTree};
+pub use log::generate_key;
+use log::TransparencyLog;
+use maintenance::Maintenance;
+pub use maintenance::RebuildReport;
+use operations::OperationLog;
+pub use pool::{BufferPool, PooledBuffer};
+pub use publish::{DnsTxtPublisher, JournalPublisher, NotaryPublisher, RootJournal, RootPublisher};
+use stats::Stats;
+use storage::{FileStore, Hashers, Received, StoredFile};
+use streamed::Streamed;
+use transaction::{Prepared, Transactions};
+
+/// Failures that stop the server or a single connection.
+#[derive(Debug, Error)]
+pub enum ServerError {
+    #[error("failed to bind {addr}")]
+    Bind {
+        addr: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error("connection failed")]
+    Connection(#[from] ProtocolError),
+    #[error("failed to record the starting files")]
+    OperationLog(#[source] io::Error),
+}
+
+/// How downloads of stored files are sent, from `ServerConfig`.
+#[derive(Debug, Clone, Copy, Default)]
+struct DownloadOptions {
+    stream_threshold: Option<u64>,
+    sendfile: bool,
+    /// Re-hash every file before sending it and refuse to send it unless it matches its leaf.
+    shadow_verify: bool,
+}
+
+/// What every connection shares with the server.
+struct Shared {
+    files: Arc<FileStore>,
+    server_mt: Arc<Mutex<ServerTree>>,
+    log: Option<Arc<Mutex<TransparencyLog>>>,
+    publishers: Vec<Arc<dyn RootPublisher>>,
+    operations: Option<Arc<OperationLog>>,
+    buffers: Arc<BufferPool>,
+    downloads: DownloadOptions,
+    alarms: Vec<Arc<dyn TamperAlarm>>,
+    maintenance: Arc<Maintenance>,
+    stats: Arc<Stats>,
+    header_timeout: Duration,
+    min_request_rate: u64,
+    bans: Arc<Bans>,
+    memory: Arc<MemoryBudget>,
+    collision: CollisionPolicy,
+    transactions: Arc<Transactions>,
+    chunk_trees: ChunkTrees,
+}
+
+/// Called with the violations whenever a self-check fails.
+pub type ViolationHandler = Arc<dyn Fn(Vec<String>) + Send + Sync>;
+
+pub struct Server {
+    files: Arc<FileStore>,
+    server_mt: Arc<Mutex<ServerTree>>,
+    log: Option<Arc<Mutex<TransparencyLog>>>,
+    publishers: Vec<Arc<dyn RootPublisher>>,
+    operations: Option<Arc<OperationLog>>,
+    buffers: Arc<BufferPool>,
+    downloads: DownloadOptions,
+    self_check: bool,
+    on_violation: Option<ViolationHandler>,
+    alarms: Vec<Arc<dyn TamperAlarm>>,
+    maintenance: Arc<Maintenance>,
+    control_socket: Option<PathBuf>,
+    websocket: Option<String>,
+    stats: Arc<Stats>,
+    header_timeout: Duration,
+    min_request_rate: u64,
+    bans: Arc<Bans>,
+    memory: Arc<MemoryBudget>,
+    collision: CollisionPolicy,
+    transactions: Arc<Transactions>,
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Server {
+    pub fn new() -> Self {
+        let stats = Arc::new(Stats::default());
+        let config = ServerConfig::default();
+        Server {
+            files: Arc::new(FileStore::default()),
+            server_mt: Arc::new(Mutex::new(ServerTree::new(MerkleTree::new(vec![vec![]])))),
+            log: None,
+            publishers: Vec::new(),
+            operations: None,
+            buffers: Arc::new(config.buffer_pool()),
+            downloads: DownloadOptions::default(),
+            self_check: false,
+            on_violation: None,
+            // Counts verification failures for `GetStats` before any other alarm is raised
+            alarms: vec![Arc::clone(&stats) as Arc<dyn TamperAlarm>],
+            maintenance: Arc::default(),
+            control_socket: None,
+            websocket: None,
+            stats,
+            header_timeout: Duration::from_secs(config.header_timeout_secs),
+            min_request_rate: config.min_request_rate,
+            bans: Arc::new(config.bans()),
+            memory: Arc::new(MemoryBudget::new(config.request_memory)),
+            collision: config.on_collision,
+            transactions: Arc::new(config.transactions()),
+        }
+    }
+
+    /// Runs `debug_validate` after every request and logs any violations.
+    pub fn with_self_check(mut self, self_check: bool) -> Self {
+        self.self_check = self_check;
+        self
+    }
+
+    /// Also hands self-check violations to `handler`, e.g. to raise an alert; only has an
+    /// effect together with `with_self_check`.
+    pub fn with_violation_handler(mut self, handler: ViolationHandler) -> Self {
+        self.on_violation = Some(handler);
+        self
+    }
+
+    /// Re-hashes every file about to be downloaded and answers with an integrity error,
+    /// raising the tamper alarms, instead of sending bytes that do not match the file's leaf.
+    /// Downloads then always read the whole file before sending it.
+    pub fn with_shadow_verify(mut self, shadow_verify: bool) -> Self {
+        self.downloads.shadow_verify = shadow_verify;
+        self
+    }
+
+    /// What uploads do to stored files they would change, unless the request asks for
+    /// something else. Delta uploads always follow it.
+    pub fn with_collision_policy(mut self, policy: CollisionPolicy) -> Self {
+        self.collision = policy;
+        self
+    }
+
+    /// Raises `alarm` whenever stored data is found not to match the tree, after the alarms
+    /// added before it.
+    pub fn with_alarm(mut self, alarm: Arc<dyn TamperAlarm>) -> Self {
+        self.alarms.push(alarm);
+        self
+    }
+
+    /// The alarms raised when stored data is found not to match the tree, for components
+    /// such as a `RootJournal` that check it themselves. Includes the server's own counter
+    /// of verification failures.
+    pub fn alarms(&self) -> &[Arc<dyn TamperAlarm>] {
+        &self.alarms
+    }
+
+    /// Accepts `AdminCommand`s on a Unix socket at `path` once started, such as `rebuild`.
+    /// Only the server's own user may connect.
+    pub fn with_control_socket(mut self, path: impl Into<PathBuf>) -> Self {
+        self.control_socket = Some(path.into());
+        self
+    }
+
+    /// Also accepts requests over WebSocket on `addr` once started, one request envelope per
+    /// message, so browsers can reach the server. Requests that stream bytes after their
+    /// frame, and batches, are only served over TCP.
+    pub fn with_websocket(mut self, addr: impl Into<String>) -> Self {
+        self.websocket = Some(addr.into());
+        self
+    }
+
+    /// Refuses every request while `on`, e.g. while storage is being repaired.
+    pub fn set_maintenance(&self, on: bool) {
+        self.maintenance.set(on);
+    }
+
+    /// Rebuilds the tree from storage in maintenance mode, as the `rebuild` admin command
+    /// does: every stored file is re-hashed, the tree is rebuilt from their recorded hashes
+    /// and replaces the current one if it differs, and any discrepancy raises the tamper
+    /// alarms. Files whose bytes changed are reported but keep their recorded hashes, so
+    /// proofs keep exposing them.
+    pub async fn rebuild(&self) -> RebuildReport {
+        maintenance::rebuild(
+            &self.files,
+            &self.server_mt,
+            &self.maintenance,
+            &self.alarms,
+        )
+        .await
+    }
+
+    /// Runs the server as an append-only transparency log: stored files can no longer be
+    /// replaced, and every upload that adds files appends them to a log whose new head is
+    /// signed with `key`, so clients can demand proof that each head extends the last.
+    pub fn with_transparency_log(mut self, key: SigningKey) -> Self {
+        self.log = Some(Arc::new(Mutex::new(TransparencyLog::new(key))));
+        self
+    }
+
+    /// Keeps file contents in `dir` rather than in memory, starting with the files already
+    /// stored there. Files are read and written without blocking the server's other
+    /// connections. A transparency log is not stored and starts out empty.
+    pub async fn with_storage_dir(mut self, dir: impl AsRef<Path>) -> io::Result<Self> {
+        let files = FileStore::open(dir.as_ref(), self.files.hashers().clone()).await?;
+        let hashes: Vec<Vec<u8>> = files
+            .lock()
+            .await
+            .values()
+            .map(|file| file.hash.clone())
+            .collect();
+        if !hashes.is_empty() {
+            self.tree_mut().current = MerkleTree::lazy(hashes);
+        }
+        self.files = Arc::new(files);
+        Ok(self)
+    }
+
+    /// Applies the settings in `config`.
+    pub fn with_config(mut self, config: &ServerConfig) -> Self {
+        self.publishers.extend(config.publishers());
+        self.alarms.extend(config.alarms());
+        self.buffers = Arc::new(config.buffer_pool());
+        self.header_timeout = Duration::from_secs(config.header_timeout_secs);
+        self.min_request_rate = config.min_request_rate;
+        self.bans = Arc::new(config.bans());
+        self.memory = Arc::new(MemoryBudget::new(config.request_memory));
+        self.collision = config.on_collision;
+        self.transactions = Arc::new(config.transactions());
+        self.tree_mut().keep_versions(config.history_versions);
+        if let Some(concurrency) = config.hash_concurrency {
+            Arc::get_mut(&mut self.files)
+                .expect("the server has not started")
+                .set_hashers(Hashers::new(concurrency));
+        }
+        self.downloads = DownloadOptions {
+            stream_threshold: config.stream_threshold,
+            sendfile: config.sendfile,
+            shadow_verify: config.shadow_verify,
+        };
+        self
+    }
+
+    /// The tree, which only the server's connections share once it has started.
+    fn tree_mut(&mut self) -> &mut ServerTree {
+        Arc::get_mut(&mut self.server_mt)
+            .expect("the server has not started")
+            .get_mut()
+    }
+
+    /// Publishes every new root with `publisher` before it takes effect. Uploads are refused
+    /// while a publisher fails, so every root clients are served has been published.
+    pub fn with_publisher(mut self, publisher: Arc<dyn RootPublisher>) -> Self {
+        self.publishers.push(publisher);
+        self
+    }
+
+    /// Appends every change to the files to an operation log at `path`, starting with the
+    /// files held when the server starts, so that replaying it can show the server's roots
+    /// are explained by logged operations alone (see `merklefile_core::operations`).
+    pub fn with_operation_log(mut self, path: impl Into<PathBuf>) -> Self {
+        self.operations = Some(Arc::new(OperationLog::new(path.into())));
+        self
+    }
+
+    /// Serves connections until the process exits. Only fails if `addr` cannot be bound or
+    /// the starting files cannot be logged; errors accepting or handling a single
+    /// connection are logged.
+    pub async fn start(&self, addr: &str) -> Result<(), ServerError> {
+        let listener = TcpListener::bind(addr)
+            .await
+            .map_err(|source| ServerError::Bind {
+                addr: addr.to_string(),
+                source,
+            })?;
+        eprintln!(
+            "Listening on {}; hashing with {}",
+            addr,
+            merkle_tree::hash_backend()
+        );
+        let shared = Arc::new(Shared {
+            files: Arc::clone(&self.files),
+            server_mt: Arc::clone(&self.server_mt),
+            log: self.log.clone(),
+            publishers: self.publishers.clone(),
+            operations: self.operations.clone(),
+            buffers: Arc::clone(&self.buffers),
+            downloads: self.downloads,
+            alarms: self.alarms.clone(),
+            maintenance: Arc::clone(&self.maintenance),
+            stats: Arc::clone(&self.stats),
+            header_timeout: self.header_timeout,
+            min_request_rate: self.min_request_rate,
+            bans: Arc::clone(&self.bans),
+            memory: Arc::clone(&self.memory),
+            collision: self.collision,
+            transactions: Arc::clone(&self.transactions),
+            chunk_trees: ChunkTrees::default(),
+        });
+        if let Some(operations) = &shared.operations {
+            let files = shared
+                .files
+                .lock()
+                .await
+                .iter()
+                .map(|(filename, file)| (filename.clone(), file.hash.clone()))
+                .collect();
+            operations
+                .snapshot(files)
+                .await
+                .map_err(ServerError::OperationLog)?;
+        }
+        if let Some(path) = &self.control_socket {
+            listen(path, &shared).map_err(|source| ServerError::Bind {
+                addr: path.display().to_string(),
+                source,
+            })?;
+        }
+        if let Some(addr) = &self.websocket {
+            let listener = TcpListener::bind(addr)
+                .await
+                .map_err(|source| ServerError::Bind {
+                    addr: addr.clone(),
+                    source,
+                })?;
+            tokio::spawn(websocket::serve(listener, Arc::clone(&shared)));
+        }
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    // Usually transient, e.g. running out of file descriptors
+                    eprintln!("Accept error: {}", err);
+                    continue;
+                }
+            };
+            if shared.bans.is_banned(peer.ip()) {
+                continue;
+            }
+            let shared = Arc::clone(&shared);
+            let self_check = self.self_check;
+            let on_violation = self.on_violation.clone();
+            tokio::spawn(async move {
+                if let Err(err) = handle_connection(stream, &shared).await {
+                    eprintln!("{}", display_chain(&err));
+                    if is_abuse(&err) && shared.bans.record_error(peer.ip()) {
+                        eprintln!(
+                            "Banning {} after repeated malformed or stalled requests",
+                            peer.ip()
+                        );
+                    }
+                }
+                if self_check {
+                    if let Err(violations) = debug_validate(&shared.files, &shared.server_mt).await
+                    {
+                        for violation in &violations {
+                            eprintln!("Self-check failed: {}", violation);
+                        }
+                        if let Some(on_violation) = on_violation {
+                            on_violation(violations.clone());
+                        }
+                        let event = TamperEvent::new(TamperSource::SelfCheck, violations);
+                        alarm::raise_all_blocking(&shared.alarms, event).await;
+                    }
+                }
+            });
+        }
+    }
+
+    /// Checks that the tree is valid and that its leaves are the hashes of the stored files
+    /// in filename order, listing every violation found.
+    pub async fn debug_validate(&self) -> Result<(), Vec<String>> {
+        debug_validate(&self.files, &self.server_mt).await
+    }
+}
+
+#[cfg(unix)]
+fn listen(path: &Path, shared: &Arc<Shared>) -> io::Result<()> {
+    tokio::spawn(control::serve(control::bind(path)?, Arc::clone(shared)));
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn listen(_path: &Path, _shared: &Arc<Shared>) -> io::Result<()> {
+    Err(control::unsupported())
+}
+
+async fn debug_validate(
+    files: &FileStore,
+    server_mt: &Mutex<ServerTree>,
+) -> Result<(), Vec<String>> {
+    let files = files.lock().await;
+    let server_mt = current_tree(server_mt).await;
+    let mut violations = match server_mt.debug_validate() {
+        Ok(()) => Vec::new(),
+        Err(violations) => violations
+            .into_iter()
+            .map(|violation| format!("tree: {}", violation))
+            .collect(),
+    };
+
+    let leaves = server_mt.leaf_hashes();
+    if files.is_empty() {
+        // An empty server holds a tree with a single empty leaf
+        if leaves.len() != 1 || leaves[0] != Sha256::digest([]).to_vec() {
+            violations.push("no files stored but the tree is not the empty tree".to_string());
+        }
+    } else {
+        if leaves.len() != files.len() {
+            violations.push(format!(
+                "{} files stored but the tree has {} leaves",
+                files.len(),
+                leaves.len()
+            ));
+        }
+        for (index, (filename, file)) in files.iter().enumerate() {
+            let Some(leaf) = leaves.get(index) else {
+                break;
+            };
+            match file.digest().await {
+                Ok(digest) if *leaf == digest => {}
+                Ok(_) => violations.push(format!(
+                    "leaf {} does not hash {}, the file at that position",
+                    index, filename
+                )),
+                Err(err) => violations.push(format!("{} cannot be read: {}", filename, err)),
+            }
+        }
+    }
+
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(violations)
+    }
+}
+
+async fn handle_connection(mut stream: TcpStream, shared: &Shared) -> Result<(), ServerError> {
+    let (files, buffers, downloads) = (&*shared.files, &shared.buffers, shared.downloads);
+    // One buffer carries the request in and then the response out
+    let mut buffer = buffers.get();
+    let mut codec = FrameCodec::new(buffers.max_message_size());
+    read_header(&mut stream, &mut buffer, shared.header_timeout).await?;
+    let announced = u64::from_be_bytes(buffer[..protocol::HEADER_LEN].try_into().unwrap());
+    // Oversized requests are refused by the codec without being read
+    let announced = announced.min(codec.max_len());
+    let Some(_reservation) = shared.memory.reserve(announced) else {
+        protocol::encode_into(&busy(), &mut buffer)?;
+        protocol::write_frame(&mut stream, &buffer).await?;
+        return Ok(());
+    };
+    let read = protocol::read_frame_buf(&mut stream, &mut codec, &mut buffer);
+    let request = tokio::time::timeout(shared.send_deadline(announced), read)
+        .await
+        .map_err(|_| ProtocolError::Timeout("request"))??;
+    let (message, format) = match protocol::decode_with_format::<ServerMessage>(&request) {
+        // Answered, so a client hashing differently learns why instead of being cut off
+        Err(err @ ProtocolError::WrongHashAlgorithm(_)) => {
+            let response = ClientMessage::Error {
+                message: err.to_string(),
+            };
+            protocol::encode_into(&response, &mut buffer)?;
+            protocol::write_frame(&mut stream, &buffer).await?;
+            return Err(err.into());
+        }
+        result => result?,
+    };
+    // Hand the request's space back to the buffer for the response
+    drop(request);
+    shared.stats.count_request(message.kind());
+
+    if shared.maintenance.is_on() {
+        let response = ClientMessage::Error {
+            message: "Server is in maintenance mode; try again shortly".to_string(),
+        };
+        protocol::encode_into_as(&response, &mut buffer, format)?;
+        protocol::write_frame(&mut stream, &buffer).await?;
+        return Ok(());
+    }
+
+    if let (ServerMessage::Download { filename } | ServerMessage::DownloadRaw { filename }, true) =
+        (&message, downloads.shadow_verify)
+    {
+        let raw = matches!(message, ServerMessage::DownloadRaw { .. });
+        if let Some(response) = shadow_download(&mut stream, filename, raw, shared).await? {
+            protocol::encode_into_as(&response, &mut buffer, format)?;
+            protocol::write_frame(&mut stream, &buffer).await?;
+        }
+        return Ok(());
+    }
+    if let ServerMessage::DownloadRaw { filename } = &message {
+        if let Some(file) = files.get(filename).await {
+            return Ok(raw::send_raw(&mut stream, &file, downloads.sendfile).await?);
+        }
+    }
+    if let (ServerMessage::Download { filename }, Some(threshold)) =
+        (&message, downloads.stream_threshold)
+    {
+        if let Some(file) = files
+            .get(filename)
+            .await
+            .filter(|file| file.len >= threshold)
+        {
+            match Streamed::open(&file).await {
+                Ok(Some(streamed)) => {
+                    return Ok(streamed.send(&mut stream, &file.hash, format).await?);
+                }
+                Ok(None) => {}
+                // Fall back to reading the file, which reports the failure to the client
+                Err(err) => eprintln!("Failed to read {}: {}", filename, err),
+            }
+        }
+    }
+    let response = match message {
+        ServerMessage::Batch { requests } => {
+            // Answer every request in order over this one connection
+            let mut responses = Vec::with_capacity(requests.len());
+            for request in requests {
+                shared.stats.count_request(request.kind());
+                let response = match request {
+                    ServerMessage::Batch { .. } => ClientMessage::Error {
+                        message: "Nested batches are not supported".to_string(),
+                    },
+                    ServerMessage::DownloadRaw { .. } => ClientMessage::Error {
+                        message: "Raw downloads cannot be batched".to_string(),
+                    },
+                    ServerMessage::UploadRaw { .. } => ClientMessage::Error {
+                        message: "Raw uploads cannot be batched".to_string(),
+                    },
+                    ServerMessage::GetStats => stats(shared).await,
+                    request => handle_message(request, shared).await,
+                };
+                responses.push(response);
+            }
+            ClientMessage::Batch { responses }
+        }
+        ServerMessage::UploadRaw { len, .. } if len > buffers.max_message_size() => {
+            ClientMessage::Error {
+                message: format!(
+                    "Upload of {} bytes exceeds the server's limit of {} bytes",
+                    len,
+                    buffers.max_message_size()
+                ),
+            }
+        }
+        ServerMessage::GetStats => stats(shared).await,
+        ServerMessage::UploadRaw {
+            filename,
+            len,
+            on_collision,
+            expect,
+        } => match filename::validate(&filename) {
+            // Refused before the file is read, like an upload that is too large
+            Err(message) => ClientMessage::Error { message },
+            Ok(()) => 'upload: {
+                // Streamed to disk in steps when there is a storage directory
+                let in_memory = if files.in_memory() { len } else { 0 };
+                let Some(_held) = shared.memory.reserve(in_memory) else {
+                    break 'upload busy();
+                };
+                // The file starts with whatever was read past the request frame
+                let ahead = buffer.split().freeze();
+                let mut upload = (&ahead[..]).chain(&mut stream);
+                let receive = files.receive(&mut upload, len);
+                let received = tokio::time::timeout(shared.send_deadline(len), receive)
+                    .await
+                    .map_err(|_| ProtocolError::Timeout("upload"))?;
+                match received {
+                    Ok(received) => {
+                        let collision = on_collision.unwrap_or(shared.collision);
+                        upload_received(filename, received, collision, expect, shared).await
+                    }
+                    // The client stopped sending, so there is no one to answer
+                    Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                        return Err(ProtocolError::from(err).into());
+                    }
+                    Err(err) => store_error(&filename, err),
+                }
+            }
+        },
+        message => handle_message(message, shared).await,
+    };
+
+    protocol::encode_into_as(&response, &mut buffer, format)?;
+    protocol::write_frame(&mut stream, &buffer).await?;
+    Ok(())
+}
+
+/// The answer to a request the memory budget has no room for.
+fn busy() -> ClientMessage {
+    ClientMessage::Error {
+        message: "Server is busy; try again shortly".to_string(),
+    }
+}
+
+impl Shared {
+    /// How long a client may take to send `len` bytes once its header is in: the header
+    /// timeout again, plus as long as they take at the minimum rate.
+    fn send_deadline(&self, len: u64) -> Duration {
+        match self.min_request_rate {
+            0 => Duration::MAX,
+            rate => self
+                .header_timeout
+                .saturating_add(Duration::from_secs(len.div_ceil(rate))),
+        }
+    }
+}
+
+/// Reads until `buffer` holds the length prefix of the request, failing if the client does
+/// not send it within `timeout`.
+async fn read_header(
+    stream: &mut TcpStream,
+    buffer: &mut BytesMut,
+    timeout: Duration,
+) -> Result<(), ProtocolError> {
+    let read = async {
+        while buffer.len() < protocol::HEADER_LEN {
+            if stream.read_buf(buffer).await? == 0 {
+                return Err(ProtocolError::Truncated);
+            }
+        }
+        Ok(())
+    };
+    tokio::time::timeout(timeout, read)
+        .await
+        .map_err(|_| ProtocolError::Timeout("request header"))?
+}
+
+/// Whether a connection failed in a way that counts towards banning its source: a request
+/// that could not be parsed, was too large, or never started.
+fn is_abuse(err: &ServerError) -> bool {
+    matches!(
+        err,
+        ServerError::Connection(
+            ProtocolError::Malformed(_)
+                | ProtocolError::FrameTooLarge(_)
+                | ProtocolError::Timeout(_)
+        )
+    )
+}
+
+/// Answers `GetStats`.
+async fn stats(shared: &Shared) -> ClientMessage {
+    let (files, total_bytes) = {
+        let files_guard = shared.files.lock().await;
+        let total_bytes = files_guard.values().map(|file| file.len).sum();
+        (files_guard.len() as u64, total_bytes)
+    };
+    let tree_depth = current_tree(&shared.server_mt).await.depth() as u32;
+    ClientMessage::Stats {
+        stats: ServerStats {
+            files,
+            total_bytes,
+            tree_depth,
+            last_rebuild_ms: shared
+                .maintenance
+                .last_rebuild()
+                .map(|duration| duration.as_millis() as u64),
+            requests: shared.stats.requests(),
+            verification_failures: shared.stats.failures(),
+            uptime_secs: shared.stats.uptime_secs(),
+            hash_backend: merkle_tree::hash_backend().to_string(),
+        },
+    }
+}
+
+/// Answers a download after checking the file's bytes against its leaf in the current tree.
+/// Raw downloads that pass are sent here, and `None` is returned; any other answer is
+/// returned to be sent as a response frame.
+async fn shadow_download(
+    stream: &mut TcpStream,
+    filename: &str,
+    raw: bool,
+    shared: &Shared,
+) -> Result<Option<ClientMessage>, ServerError> {
+    let (leaf, data) = match shadow_read(filename, shared).await? {
+        Ok(checked) => checked,
+        Err(refusal) => return Ok(Some(refusal)),
+    };
+    if raw {
+        raw::send_bytes(stream, &leaf, &data).await?;
+        return Ok(None);
+    }
+    Ok(Some(ClientMessage::Success { data }))
+}
+
+/// Reads `filename` and checks it against its leaf in the current tree, returning the leaf
+/// and the bytes, or the answer refusing the download.
+async fn shadow_read(
+    filename: &str,
+    shared: &Shared,
+) -> Result<Result<(Vec<u8>, Bytes), ClientMessage>, ServerError> {
+    let files_guard = shared.files.lock().await;
+    let Some((index, file)) = files_guard
+        .iter()
+        .enumerate()
+        .find(|(_, (name, _))| *name == filename)
+        .map(|(index, (_, file))| (index, file.clone()))
+    else {
+        return Ok(Err(ClientMessage::Error {
+            message: "File not found".to_string(),
+        }));
+    };
+    let leaf = current_tree(&shared.server_mt).await.leaf_hashes()[index].clone();
+    drop(files_guard);
+
+    let data = match file.read().await {
+        Ok(data) => data,
+        Err(err) => return Ok(Err(read_error(filename, err))),
+    };
+    let digest = {
+        let data = data.clone();
+        tokio::task::spawn_blocking(move || Sha256::digest(&data).to_vec())
+            .await
+            .map_err(|err| ProtocolError::from(io::Error::other(err)))?
+    };
+    if digest != leaf {
+        let problem = format!(
+            "{} no longer matches its leaf hash; download refused",
+            filename
+        );
+        let event = TamperEvent::new(TamperSource::Shadow, vec![problem]);
+        alarm::raise_all_blocking(&shared.alarms, event).await;
+        return Ok(Err(ClientMessage::Error {
+            message: format!("Integrity error: {} does not match the tree", filename),
+        }));
+    }
+    Ok(Ok((leaf, data)))
+}
+
+async fn handle_message(message: ServerMessage, shared: &Shared) -> ClientMessage {
+    let (files, server_mt, log) = (&*shared.files, &*shared.server_mt, shared.log.as_deref());
+    let collision = shared.collision;
+    if let Err(message) = validate_filenames(&message) {
+        return ClientMessage::Error { message };
+    }
+    match message {
+        ServerMessage::Upload {
+            client_files,
+            dry_run: true,
+            on_collision,
+            expect,
+            ..
+        } => {
+            // Report what the upload would change without touching files or server_mt
+            let hashes = files.hashers().digest_all(client_files.values()).await;
+            let files_guard = files.lock().await;
+            if let Err(conflict) = check_condition(expect.as_ref(), &files_guard, server_mt).await {
+                return conflict;
+            }
+            let stored = match collision::stored_names(
+                &files_guard,
+                client_files
+                    .keys()
+                    .map(String::as_str)
+                    .zip(hashes.iter().map(Vec::as_slice)),
+                on_collision.unwrap_or(collision),
+            ) {
+                Ok(stored) => stored,
+                Err(message) => return ClientMessage::Error { message },
+            };
+            let uploads = stored
+                .into_iter()
+                .zip(client_files.values().map(|data| data.len() as u64))
+                .zip(hashes)
+                .map(|((filename, size), hash)| (filename, size, hash));
+            let changes = plan_upload(&files_guard, uploads);
+            // Refused just as the upload itself would be
+            if log.is_some() {
+                let replaced = changes
+                    .iter()
+                    .find(|change| change.kind == ChangeKind::Modified);
+                if let Some(change) = replaced {
+                    return append_only_error(&change.filename);
+                }
+            }
+            if changes
+                .iter()
+                .all(|change| change.kind == ChangeKind::Unchanged)
+            {
+                drop(files_guard);
+                let root = current_tree(server_mt).await.get_root_hash();
+                return ClientMessage::UploadPlan { changes, root };
+            }
+            let mut merged: BTreeMap<String, Vec<u8>> = files_guard
+                .iter()
+                .map(|(filename, file)| (filename.clone(), file.hash.clone()))
+                .collect();
+            drop(files_guard);
+            merged.extend(
+                changes
+                    .iter()
+                    .map(|change| (change.filename.clone(), change.new_hash.clone())),
+            );
+            match MerkleTree::try_from_leaf_hashes(merged.into_values().collect()) {
+                Ok(tree) => ClientMessage::UploadPlan {
+                    changes,
+                    root: tree.get_root_hash(),
+                },
+                Err(err) => ClientMessage::Error {
+                    message: err.to_string(),
+                },
+            }
+        }
+        ServerMessage::Upload {
+            client_files,
+            with_proofs,
+            on_collision,
+            expect,
+            ..
+        } => {
+            let hashes = files.hashers().digest_all(client_files.values()).await;
+            // Update files and merkle_tree
+            let files_guard = files.lock().await;
+            if let Err(conflict) = check_condition(expect.as_ref(), &files_guard, server_mt).await {
+                return conflict;
+            }
+            let stored = match collision::stored_names(
+                &files_guard,
+                client_files
+                    .keys()
+                    .map(String::as_str)
+                    .zip(hashes.iter().map(Vec::as_slice)),
+                on_collision.unwrap_or(collision),
+            ) {
+                Ok(stored) => stored,
+                Err(message) => return ClientMessage::Error { message },
+            };
+            let stored_as: BTreeMap<String, String> = client_files
+                .keys()
+                .zip(&stored)
+                .filter(|(uploaded, stored)| uploaded != stored)
+                .map(|(uploaded, stored)| (uploaded.clone(), stored.clone()))
+                .collect();
+            let uploaded = stored.clone();
+            let client_files: Vec<(String, Vec<u8>, Bytes)> = stored
+                .into_iter()
+                .zip(hashes)
+                .zip(client_files.into_values())
+                .map(|((filename, hash), data)| (filename, hash, data))
+                .collect();
+            if log.is_some() {
+                let replaced = client_files.iter().find(|(filename, hash, _)| {
+                    files_guard
+                        .get(filename)
+                        .is_some_and(|stored| stored.hash != *hash)
+                });
+                if let Some((filename, _, _)) = replaced {
+                    return append_only_error(filename);
+                }
+            }
+            // Only update the Merkle tree if new or modified data was added
+            let mut changes = Vec::new();
+            for (filename, hash, data) in client_files {
+                if files_guard.get(&filename).map(|stored| &stored.hash) == Some(&hash) {
+                    continue;
+                }
+                match files.store(data).await {
+                    Ok(file) => changes.push((filename, file)),
+                    Err(err) => return store_error(&filename, err),
+                }
+            }
+            if let Err(message) = commit(files_guard, changes, shared).await {
+                return ClientMessage::Error { message };
+            }
+
+            if with_proofs {
+                // Lock both so the proofs and the root come from the same tree
+                let files_guard = files.lock().await;
+                let server_mt = current_tree(server_mt).await;
+                let indices: Vec<(String, usize)> = uploaded
+                    .into_iter()
+                    .filter_map(|filename| {
+                        let index = files_guard.keys().position(|x| x == &filename)?;
+                        Some((filename, index))
+                    })
+                    .collect();
+                drop(files_guard);
+                let proofs = indices
+                    .into_iter()
+                    .map(|(filename, index)| (filename, server_mt.get_proof_for(index)))
+                    .collect();
+                return ClientMessage::UploadReceipt {
+                    root: server_mt.get_root_hash(),
+                    proofs,
+                    stored_as,
+                    tree_size: Some(server_mt.leaf_count() as u64),
+                };
+            }
+
+            // Send a success message back to the client
+            let root_hash = current_tree(server_mt).await.get_root_hash();
+            ClientMessage::Success {
+                data: root_hash.into(),
+            }
+        }
+        // Stored files are sent by `handle_connection`, so the file does not exist
+        ServerMessage::DownloadRaw { .. } => ClientMessage::Error {
+            message: "File not found".to_string(),
+        },
+        // Only reached from a batch, which refuses raw uploads before getting here
+        ServerMessage::UploadRaw { .. } => ClientMessage::Error {
+            message: "Raw uploads cannot be batched".to_string(),
+        },
+        ServerMessage::Download { filename } => {
+            // Try to find the requested file in our server files
+            let file = files.lock().await.get(&filename).cloned();
+            match file {
+                Some(file) => match file.read().await {
+                    Ok(data) => ClientMessage::Success { data },
+                    Err(err) => read_error(&filename, err),
+                },
+                None => ClientMessage::Error {
+                    message: "File not found".to_string(),
+                },
+            }
+        }
+        ServerMessage::GetMerkleProof { filename } => {
+            let files_guard = files.lock().await;
+            if let Some(index) = files_guard.keys().position(|x| x == &filename) {
+                let server_mt = current_tree(server_mt).await;
+                drop(files_guard);
+                let proof = server_mt.get_proof_for(index);
+                ClientMessage::MerkleProof {
+                    proof,
+                    tree_size: Some(server_mt.leaf_count() as u64),
+                }
+            } else {
+                ClientMessage::Error {
+                    message: "File not found".to_string(),
+                }
+            }
+        }
+        ServerMessage::GetMerkleProofAt { filename, root } => {
+            // Lock both so the index and the current tree agree
+            let files_guard = files.lock().await;
+            let index = files_guard.keys().position(|x| x == &filename);
+            let server_mt = server_mt.lock().await;
+            drop(files_guard);
+            let current = server_mt.snapshot();
+            let result = if current.get_root_hash() == root {
+                index
+                    .and_then(|index| current.proof(index))
+                    .ok_or_else(|| "File not found".to_string())
+            } else {
+                server_mt.history().proof(&root, &filename)
+            };
+            match result {
+                Ok(proof) => ClientMessage::MerkleProof {
+                    proof: proof.path(),
+                    tree_size: Some(proof.tree_size),
+                },
+                Err(message) => ClientMessage::Error { message },
+            }
+        }
+        ServerMessage::GetMultiProof { filenames } => {
+            let files_guard = files.lock().await;
+            let indices: Option<Vec<usize>> = filenames
+                .iter()
+                .map(|filename| files_guard.keys().position(|x| x == filename))
+                .collect();
+            let server_mt = current_tree(server_mt).await;
+            drop(files_guard);
+            match indices.and_then(|indices| server_mt.get_proof_for_indices(&indices)) {
+                Some(proof) => ClientMessage::MultiProof { proof },
+                None => ClientMessage::Error {
+                    message: "File not found".to_string(),
+                },
+            }
+        }
+        ServerMessage::GetConsistencyProof { old_size } => {
+            let server_mt = current_tree(server_mt).await;
+            let proof = usize::try_from(old_size)
+                .ok()
+                .and_then(|old_size| server_mt.consistency_proof(old_size));
+            match proof {
+                Some(proof) => ClientMessage::ConsistencyProof { proof },
+                None => ClientMessage::Error {
+                    message: format!(
+                        "Cannot prove consistency with a tree of {} files; the tree has {}",
+                        old_size,
+                        server_mt.leaf_count()
+                    ),
+                },
+            }
+        }
+        ServerMessage::GetRootHash => {
+            let root_hash = current_tree(server_mt).await.get_root_hash();
+            ClientMessage::Success {
+                data: root_hash.into(),
+            }
+        }
+        ServerMessage::ReadRange {
+            filename,
+            offset,
+            len,
+            chunk_size,
+        } => {
+            let file = files.lock().await.get(&filename).cloned();
+            match file {
+                Some(file) => match read_range(&file, offset, len, chunk_size, shared).await {
+                    Ok(response) => response,
+                    Err(err) => read_error(&filename, err),
+                },
+                None => ClientMessage::Error {
+                    message: "File not found".to_string(),
+                },
+            }
+        }
+        ServerMessage::GetChunkHashes {
+            filename,
+            chunk_size,
+        } => {
+            let file = files.lock().await.get(&filename).cloned();
+            match file {
+                Some(_) if chunk_size == 0 || chunk_size as usize > chunk::MAX_CHUNK_SIZE => {
+                    ClientMessage::Error {
+                        message: "Invalid chunk size".to_string(),
+                    }
+                }
+                Some(file) => match file.chunk_hashes(chunk_size as usize).await {
+                    Ok(hashes) => ClientMessage::ChunkHashes { hashes },
+                    Err(err) => read_error(&filename, err),
+                },
+                None => ClientMessage::Error {
+                    message: "File not found".to_string(),
+                },
+            }
+        }
+        ServerMessage::UploadChunks {
+            filename,
+            total_len,
+            chunk_size,
+            chunks,
+            expect,
+        } => {
+            let files_guard = files.lock().await;
+            if let Err(conflict) = check_condition(expect.as_ref(), &files_guard, server_mt).await {
+                return conflict;
+            }
+            let old = files_guard.get(&filename);
+            let max_len = shared.buffers.max_message_size();
+            let Some(_rebuilt) = shared.memory.reserve(total_len.min(max_len)) else {
+                return busy();
+            };
+            let data = match patch_chunks(old, total_len, max_len, chunk_size, chunks).await {
+                Ok(data) => data,
+                Err(message) => return ClientMessage::Error { message },
+            };
+            // Objects of refused uploads are removed the next time the files are saved
+            let file = match files.store(data).await {
+                Ok(file) => file,
+                Err(err) => return store_error(&filename, err),
+            };
+            let stored = match collision::stored_name(
+                &files_guard,
+                &filename,
+                &file.hash,
+                collision,
+                &BTreeSet::new(),
+            ) {
+                Ok(stored) => stored,
+                Err(message) => return ClientMessage::Error { message },
+            };
+            match files_guard.get(&stored) {
+                Some(old) if old.hash == file.hash => ClientMessage::Success {
+                    data: current_tree(server_mt).await.get_root_hash().into(),
+                },
+                Some(_) if log.is_some() => append_only_error(&stored),
+                _ => {
+                    let changes = vec![(stored, file)];
+                    match commit(files_guard, changes, shared).await {
+                        Ok(()) => ClientMessage::Success {
+                            data: current_tree(server_mt).await.get_root_hash().into(),
+                        },
+                        Err(message) => ClientMessage::Error { message },
+                    }
+                }
+            }
+        }
+        ServerMessage::ListFiles => ClientMessage::FileList {
+            filenames: files.lock().await.keys().cloned().collect(),
+        },
+        ServerMessage::GetTreeHead => match log {
+            Some(log) => ClientMessage::TreeHead {
+                head: log.lock().await.head().clone(),
+            },
+            None => not_a_log(),
+        },
+        ServerMessage::GetLogConsistency { old_size, new_size } => match log {
+            Some(log) => match log.lock().await.consistency_proof(old_size, new_size) {
+                Ok(proof) => ClientMessage::LogConsistency { proof },
+                Err(message) => ClientMessage::Error { message },
+            },
+            None => not_a_log(),
+        },
+        ServerMessage::Unknown => ClientMessage::Error {
+            message: format!(
+                "Unsupported request; server speaks protocol version {}",
+                protocol::PROTOCOL_VERSION
+            ),
+        },
+        ServerMessage::Batch { .. } => ClientMessage::Error {
+            message: "Nested batches are not supported".to_string(),
+        },
+        ServerMessage::Prepare {
+            client_files,
+            on_collision,
+            expect,
+        } => {
+            prepare(
+                client_files,
+                on_collision.unwrap_or(collision),
+                expect,
+                shared,
+            )
+            .await
+        }
+        ServerMessage::Commit { transaction } => commit_prepared(transaction, shared).await,
+        ServerMessage::Abort { transaction } => {
+            shared.transactions.take(transaction);
+            ClientMessage::Success {
+                data: current_tree(server_mt).await.get_root_hash().into(),
+            }
+        }
+        // Answered by `handle_connection`, which has the server's counters
+        ServerMessage::GetStats => ClientMessage::Error {
+            message: "Statistics are not available here".to_string(),
+        },
+    }
+}
+
+/// Stores a file streamed by `UploadRaw` as `filename`, or where `collision` puts it, and
+/// answers with its proof.
+async fn upload_received(
+    filename: String,
+    received: Received,
+    collision: CollisionPolicy,
+    expect: Option<UploadCondition>,
+    shared: &Shared,
+) -> ClientMessage {
+    let (files, server_mt, log) = (&*shared.files, &*shared.server_mt, shared.log.as_deref());
+    let files_guard = files.lock().await;
+    if let Err(conflict) = check_condition(expect.as_ref(), &files_guard, server_mt).await {
+        return conflict;
+    }
+    let stored = match collision::stored_name(
+        &files_guard,
+        &filename,
+        &received.hash,
+        collision,
+        &BTreeSet::new(),
+    ) {
+        Ok(stored) => stored,
+        Err(message) => return ClientMessage::Error { message },
+    };
+    match files_guard.get(&stored) {
+        Some(file) if file.hash == received.hash => drop(files_guard),
+        Some(_) if log.is_some() => return append_only_error(&stored),
+        _ => {
+            let file = match files.store_received(&received).await {
+                Ok(file) => file,
+                Err(err) => return store_error(&stored, err),
+            };
+            let changes = vec![(stored.clone(), file)];
+            if let Err(message) = commit(files_guard, changes, shared).await {
+                return ClientMessage::Error { message };
+            }
+        }
+    }
+
+    // Lock both so the proof and the root come from the same tree
+    let files_guard = files.lock().await;
+    let server_mt = current_tree(server_mt).await;
+    let index = files_guard.keys().position(|x| x == &stored);
+    drop(files_guard);
+    let mut stored_as = BTreeMap::new();
+    if stored != filename {
+        stored_as.insert(filename, stored.clone());
+    }
+    match index {
+        Some(index) => ClientMessage::UploadReceipt {
+            root: server_mt.get_root_hash(),
+            proofs: BTreeMap::from([(stored, server_mt.get_proof_for(index))]),
+            stored_as,
+            tree_size: Some(server_mt.leaf_count() as u64),
+        },
+        None => ClientMessage::Error {
+            message: "File not found".to_string(),
+        },
+    }
+}
+
+/// Answers `Conflict` unless the stored files and the current root are still as `expect`
+/// says. Called with the files locked, so they cannot change before the upload is applied.
+async fn check_condition(
+    expect: Option<&UploadCondition>,
+    files_guard: &BTreeMap<String, StoredFile>,
+    server_mt: &Mutex<ServerTree>,
+) -> Result<(), ClientMessage> {
+    let Some(expect) = expect else {
+        return Ok(());
+    };
+    let root = current_tree(server_mt).await.get_root_hash();
+    let changed: BTreeMap<String, Option<Vec<u8>>> = expect
+        .files
+        .iter()
+        .filter_map(|(filename, expected)| {
+            let current = files_guard.get(filename).map(|file| file.hash.clone());
+            (current != *expected).then(|| (filename.clone(), current))
+        })
+        .collect();
+    if changed.is_empty()
+        && expect
+            .root
+            .as_ref()
+            .is_none_or(|expected| *expected == root)
+    {
+        return Ok(());
+    }
+    Err(ClientMessage::Conflict {
+        root,
+        files: changed,
+    })
+}
+
+/// Checks the name of every file `message` would store; see `filename::validate`.
+fn validate_filenames(message: &ServerMessage) -> Result<(), String> {
+    match message {
+        ServerMessage::Upload { client_files, .. }
+        | ServerMessage::Prepare { client_files, .. } => client_files
+            .keys()
+            .try_for_each(|name| filename::validate(name)),
+        ServerMessage::UploadRaw { filename, .. }
+        | ServerMessage::UploadChunks { filename, .. } => filename::validate(filename),
+        _ => Ok(()),
+    }
+}
+
+/// Answers `Prepare`: checks the upload as `Upload` would and holds it for `Commit`. The
+/// files stay locked until it is held, so no other upload can take effect between the root
+/// it was checked against and the moment it starts holding off other uploads.
+async fn prepare(
+    client_files: BTreeMap<String, Bytes>,
+    collision: CollisionPolicy,
+    expect: Option<UploadCondition>,
+    shared: &Shared,
+) -> ClientMessage {
+    let (files, server_mt) = (&*shared.files, &*shared.server_mt);
+    let hashes = files.hashers().digest_all(client_files.values()).await;
+    let files_guard = files.lock().await;
+    if let Err(conflict) = check_condition(expect.as_ref(), &files_guard, server_mt).await {
+        return conflict;
+    }
+    let stored = match collision::stored_names(
+        &files_guard,
+        client_files
+            .keys()
+            .map(String::as_str)
+            .zip(hashes.iter().map(Vec::as_slice)),
+        collision,
+    ) {
+        Ok(stored) => stored,
+        Err(message) => return ClientMessage::Error { message },
+    };
+    let mut merged: BTreeMap<String, Vec<u8>> = files_guard
+        .iter()
+        .map(|(filename, file)| (filename.clone(), file.hash.clone()))
+        .collect();
+    let mut changes = Vec::new();
+    for ((filename, hash), data) in stored
+        .into_iter()
+        .zip(hashes)
+        .zip(client_files.into_values())
+    {
+        match files_guard.get(&filename) {
+            Some(file) if file.hash == hash => continue,
+            Some(_) if shared.log.is_some() => return append_only_error(&filename),
+            _ => {}
+        }
+        merged.insert(filename.clone(), hash);
+        changes.push((filename, data));
+    }
+    let base_root = current_tree(server_mt).await.get_root_hash();
+    let root = if changes.is_empty() {
+        base_root.clone()
+    } else {
+        MerkleTree::from_leaf_hashes(merged.into_values().collect()).get_root_hash()
+    };
+    match shared.transactions.prepare(Prepared { base_root, changes }) {
+        Some(transaction) => ClientMessage::Prepared { transaction, root },
+        None => ClientMessage::Error {
+            message: "Another upload is being committed across servers; try again shortly"
+                .to_string(),
+        },
+    }
+}
+
+/// Answers `Commit`: applies the upload prepared as `transaction`, unless it was aborted or
+/// has expired.
+async fn commit_prepared(transaction: u128, shared: &Shared) -> ClientMessage {
+    let (files, server_mt) = (&*shared.files, &*shared.server_mt);
+    let files_guard = files.lock().await;
+    let Some(prepared) = shared.transactions.take(transaction) else {
+        return ClientMessage::Error {
+            message: format!(
+                "Transaction {} is not prepared; it may have expired",
+                transaction
+            ),
+        };
+    };
+    // Other uploads are refused while one is prepared, so the root cannot have moved
+    let root = current_tree(server_mt).await.get_root_hash();
+    if root != prepared.base_root {
+        return ClientMessage::Conflict {
+            root,
+            files: BTreeMap::new(),
+        };
+    }
+    let mut changes = Vec::with_capacity(prepared.changes.len());
+    for (filename, data) in prepared.changes {
+        match files.store(data).await {
+            Ok(file) => changes.push((filename, file)),
+            Err(err) => return store_error(&filename, err),
+        }
+    }
+    match commit(files_guard, changes, shared).await {
+        Ok(()) => ClientMessage::Success {
+            data: current_tree(server_mt).await.get_root_hash().into(),
+        },
+        Err(message) => ClientMessage::Error { message },
+    }
+}
+
+/// Adds `changes` to the files and rebuilds the tree. The new root is published, the files
+/// are saved and the changes are appended to the log before `files` is unlocked, so roots are
+/// published in the order they take effect. If a publisher fails or the files cannot be
+/// saved, the previous files are restored and the upload is refused; publishers before the
+/// failing step may already hold a root that was never served.
+async fn commit(
+    mut files_guard: MutexGuard<'_, BTreeMap<String, StoredFile>>,
+    changes: Vec<(String, StoredFile)>,
+    shared: &Shared,
+) -> Result<(), String> {
+    let (files, server_mt, log) = (&*shared.files, &*shared.server_mt, shared.log.as_deref());
+    let publishers = &shared.publishers;
+    if changes.is_empty() {
+        return Ok(());
+    }
+    if shared.transactions.is_pending() {
+        return Err(
+            "Upload refused; another upload is being committed across servers, try again shortly"
+                .to_string(),
+        );
+    }
+    let entries: Vec<Vec<u8>> = changes
+        .iter()
+        .map(|(filename, file)| tlog::entry_hash_of_digest(filename, &file.hash))
+        .collect();
+    let base = server_mt.lock().await.history().base(&files_guard);
+    let previous: Vec<(String, Option<StoredFile>)> = changes
+        .into_iter()
+        .map(|(filename, file)| {
+            let old = files_guard.insert(filename.clone(), file);
+            (filename, old)
+        })
+        .collect();
+    // Only the nodes above new or modified leaves need hashing
+    let new_merkle_tree = if let Some(leaf_hashes) = appended(&files_guard, &previous) {
+        let mut tree = server_mt.lock().await.current.clone();
+        tree.extend_leaves(leaf_hashes);
+        tree
+    } else if let Some(updates) = modified(&files_guard, &previous) {
+        let mut tree = server_mt.lock().await.current.clone();
+        for (index, leaf_hash) in updates {
+            tree.update_leaf_hash(index, leaf_hash);
+        }
+        tree
+    } else {
+        let hashes = files_guard.values().map(|file| file.hash.clone()).collect();
+        MerkleTree::from_leaf_hashes(hashes)
+    };
+
+    let mut result = Ok(());
+    if !publishers.is_empty() {
+        let size = files_guard.len() as u64;
+        result = publish::publish_all(publishers, new_merkle_tree.get_root_hash(), size)
+            .await
+            .map_err(|err| format!("Upload refused; the new root was not published: {}", err));
+    }
+    if result.is_ok() {
+        result = files
+            .save(&files_guard)
+            .await
+            .map_err(|err| format!("Upload refused; the files could not be saved: {}", err));
+    }
+    if result.is_err() {
+        for (filename, old) in previous {
+            match old {
+                Some(file) => files_guard.insert(filename, file),
+                None => files_guard.remove(&filename),
+            };
+        }
+        return result;
+    }
+    if let Some(operations) = &shared.operations {
+        let uploaded = previous
+            .iter()
+            .map(|(filename, _)| (filename.clone(), files_guard[filename].hash.clone()))
+            .collect();
+        // The upload has taken effect either way; replaying the log will show it unexplained
+        if let Err(err) = operations.upload(uploaded).await {
+            eprintln!("Failed to log upload: {}", err);
+        }
+    }
+    if let Some(log) = log {
+        // Still holding files, so log order matches the order files were stored in
+        log.lock().await.append(entries);
+    }
+    let versions = base.map(|base| {
+        let changed: Vec<String> = previous.into_iter().map(|(filename, _)| filename).collect();
+        let next = History::next(&base, &files_guard, &changed);
+        (base, next)
+    });
+    // Still holding files, so whoever locks them next sees the tree that matches them, as
+    // conditional uploads rely on
+    server_mt.lock().await.replace(new_merkle_tree, versions);
+    drop(files_guard);
+    Ok(())
+}
+
+/// Leaf hashes of the files `changes` added, in order, if they all sort after the files held
+/// before, which therefore keep their leaves. `files` already holds them.
+fn appended(
+    files: &BTreeMap<String, StoredFile>,
+    changes: &[(String, Option<StoredFile>)],
+) -> Option<Vec<Vec<u8>>> {
+    if changes.iter().any(|(_, old)| old.is_some()) {
+        return None;
+    }
+    let first_added = changes.iter().map(|(filename, _)| filename).min()?;
+    // None if no file was held before: an empty server's tree holds a placeholder leaf
+    let last_kept = files.keys().nth_back(changes.len())?;
+    (first_added > last_kept).then(|| {
+        files
+            .values()
+            .skip(files.len() - changes.len())
+            .map(|file| file.hash.clone())
+            .collect()
+    })
+}
+
+/// Leaf indices and new leaf hashes of the files `changes` modified, if it added none, so
+/// every file keeps its leaf. `files` already holds them.
+fn modified(
+    files: &BTreeMap<String, StoredFile>,
+    changes: &[(String, Option<StoredFile>)],
+) -> Option<Vec<(usize, Vec<u8>)>> {
+    if changes.iter().any(|(_, old)| old.is_none()) {
+        return None;
+    }
+    let changed: BTreeSet<&String> = changes.iter().map(|(filename, _)| filename).collect();
+    Some(
+        files
+            .iter()
+            .enumerate()
+            .filter(|(_, (filename, _))| changed.contains(filename))
+            .map(|(index, (_, file))| (index, file.hash.clone()))
+            .collect(),
+    )
+}
+
+/// The tree as it is now. The lock is only held to take the snapshot, so computing roots and
+/// proofs from it never holds up an upload.
+async fn current_tree(server_mt: &Mutex<ServerTree>) -> TreeSnapshot {
+    server_mt.lock().await.snapshot()
+}
+
+fn append_only_error(filename: &str) -> ClientMessage {
+    ClientMessage::Error {
+        message: format!(
+            "{} already exists and this server is an append-only log",
+            filename
+        ),
+    }
+}
+
+fn store_error(filename: &str, err: io::Error) -> ClientMessage {
+    eprintln!("Failed to store {}: {}", filename, err);
+    ClientMessage::Error {
+        message: format!("{} could not be stored", filename),
+    }
+}
+
+fn read_error(filename: &str, err: io::Error) -> ClientMessage {
+    eprintln!("Failed to read {}: {}", filename, err);
+    ClientMessage::Error {
+        message: format!("{} could not be read", filename),
+    }
+}
+
+fn not_a_log() -> ClientMessage {
+    ClientMessage::Error {
+        message: "This server is not running as a transparency log".to_string(),
+    }
+}
+
+/// Collects the chunks covering `offset..offset + len` of a file, each with its chunk proof.
+/// Only the requested chunks are read. The rest of the file is only hashed, one chunk at a
+/// time, to build the chunk tree, and only on the first read of the file at that chunk size.
+async fn read_range(
+    file: &StoredFile,
+    offset: u64,
+    len: u64,
+    chunk_size: u64,
+    shared: &Shared,
+) -> io::Result<ClientMessage> {
+    let chunk_size = chunk_size as usize;
+    if chunk_size == 0 || chunk_size > chunk::MAX_CHUNK_SIZE {
+        return Ok(ClientMessage::Error {
+            message: "Invalid chunk size".to_string(),
+        });
+    }
+    match offset.checked_add(len) {
+        Some(end) if end <= file.len => {}
+        _ => {
+            return Ok(ClientMessage::Error {
+                message: "Range out of bounds".to_string(),
+            })
+        }
+    }
+    if len == 0 {
+        return Ok(ClientMessage::Chunks { chunks: Vec::new() });
+    }
+
+    let tree = match shared.chunk_trees.get(&file.hash, chunk_size) {
+        Some(tree) => tree,
+        None => {
+            let tree = MerkleTree::from_leaf_hashes(file.chunk_hashes(chunk_size).await?);
+            shared
+                .chunk_trees
+                .insert(file.hash.clone(), chunk_size, tree.clone());
+            tree
+        }
+    };
+    let first = offset as usize / chunk_size;
+    let last = (offset + len - 1) as usize / chunk_size;
+    let mut chunks = Vec::with_capacity(last - first + 1);
+    for index in first..=last {
+        let start = index * chunk_size;
+        let end = (start + chunk_size).min(file.len as usize);
+        chunks.push(ChunkWithProof {
+            index: index as u64,
+            data: file.read_at(start as u64, end - start).await?,
+            proof: tree.get_proof_for(index),
+        });
+    }
+    Ok(ClientMessage::Chunks { chunks })
+}
+
+/// Rebuilds a file of `total_len` bytes from the uploaded chunks, taking every chunk that was
+/// not sent from the currently stored version. `total_len` is the client's word, so it is
+/// checked against `max_len` and against what the chunks and the stored version can supply
+/// before anything is read, and the file grows as its chunks are added rather than being
+/// allocated at that size up front.
+async fn patch_chunks(
+    old: Option<&StoredFile>,
+    total_len: u64,
+    max_len: u64,
+    chunk_size: u64,
+    mut chunks: BTreeMap<u64, Bytes>,
+) -> Result<Bytes, String> {
+    if total_len > max_len {
+        return Err(format!(
+            "Upload of {} bytes exceeds the server's limit of {} bytes",
+            total_len, max_len
+        ));
+    }
+    let sent: u64 = chunks.values().map(|chunk| chunk.len() as u64).sum();
+    let stored = old.map_or(0, |old| old.len);
+    if sent > total_len || total_len - sent > stored {
+        return Err(format!("Chunks do not add up to {} bytes", total_len));
+    }
+    let chunk_size = chunk_size as usize;
+    let total_len = total_len as usize;
+    if chunk_size == 0 || chunk_size > chunk::MAX_CHUNK_SIZE {
+        return Err("Invalid chunk size".to_string());
+    }
+
+    let count = chunk::chunk_count(total_len, chunk_size);
+    if chunks.keys().any(|&index| index as usize >= count) {
+        return Err("Chunk index out of bounds".to_string());
+    }
+
+    let mut data = Vec::new();
+    for index in 0..count {
+        let start = index * chunk_size;
+        let expected_len = chunk_size.min(total_len - start);
+        let chunk = match (chunks.remove(&(index as u64)), old) {
+            (Some(chunk), _) => chunk,
+            (None, Some(old)) if (start + expected_len) as u64 <= old.len => old
+                .read_at(start as u64, expected_len)
+                .await
+                .map_err(|err| {
+                    eprintln!("Failed to read stored chunk {}: {}", index, err);
+                    format!("Stored chunk {} could not be read", index)
+                })?,
+            (None, _) => return Err(format!("Missing chunk {}", index)),
+        };
+        if chunk.len() != expected_len {
+            return Err(format!("Chunk {} has the wrong length", index));
+        }
+        data.extend_from_slice(&chunk);
+    }
+    Ok(Bytes::from(data))
+}
+
+/// Changes uploading files, given as stored name, size and hash, would make.
+fn plan_upload(
+    files: &BTreeMap<String, StoredFile>,
+    uploads: impl IntoIterator<Item = (String, u64, Vec<u8>)>,
+) -> Vec<FileChange> {
+    uploads
+        .into_iter()
+        .map(|(filename, new_size, new_hash)| {
+            let existing = files.get(&filename);
+            let old_hash = existing.map(|old| old.hash.clone());
+            let kind = match &old_hash {
+                None => ChangeKind::Added,
+                Some(old_hash) if *old_hash == new_hash => ChangeKind::Unchanged,
+                Some(_) => ChangeKind::Modified,
+            };
+            FileChange {
+                old_size: existing.map(|old| old.len),
+                filename,
+                kind,
+                new_size,
+                old_hash,
+                new_hash,
+            }
+        })
+        .collect()
+}
+
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+pub fn new_server() -> Arc<Server> {
+    Arc::new(Server::new())
+}
@@ -0,0 +1,65 @@
+use merklefile_core::log::{self, SignedTreeHead, SigningKey};
+
+use crate::now;
+
+/// Generates a fresh key for signing tree heads.
+pub fn generate_key() -> SigningKey {
+    SigningKey::generate(&mut rand_core::OsRng)
+}
+
+/// Every file added to a server running as a transparency log, in the order added, and the
+/// head signed after the latest addition.
+pub(crate) struct TransparencyLog {
+    key: SigningKey,
+    leaves: Vec<Vec<u8>>,
+    head: SignedTreeHead,
+}
+
+impl TransparencyLog {
+    pub(crate) fn new(key: SigningKey) -> Self {
+        let head = SignedTreeHead::sign(0, now(), log::root(&[]), &key);
+        Self {
+            key,
+            leaves: Vec::new(),
+            head,
+        }
+    }
+
+    /// Appends the entries of one addition and signs the new head.
+    pub(crate) fn append(&mut self, entries: Vec<Vec<u8>>) {
+        if entries.is_empty() {
+            return;
+        }
+        self.leaves.extend(entries);
+        self.head = SignedTreeHead::sign(
+            self.leaves.len() as u64,
+            now(),
+            log::root(&self.leaves),
+            &self.key,
+        );
+    }
+
+    pub(crate) fn head(&self) -> &SignedTreeHead {
+        &self.head
+    }
+
+    /// Proof that the log at `new_size` entries extends the log at `old_size` entries.
+    pub(crate) fn consistency_proof(
+        &self,
+        old_size: u64,
+        new_size: u64,
+    ) -> Result<Vec<Vec<u8>>, String> {
+        if old_size > new_size || new_size > self.leaves.len() as u64 {
+            return Err(format!(
+                "No consistency proof from size {} to {}; the log has {} entries",
+                old_size,
+                new_size,
+                self.leaves.len()
+            ));
+        }
+        Ok(log::consistency_proof(
+            &self.leaves[..new_size as usize],
+            old_size as usize,
+        ))
+    }
+}
@@ -0,0 +1,135 @@
+use serde_json::json;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, PoisonError};
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+use merklefile_core::merkle_tree::MerkleTree;
+
+use crate::alarm::{self, TamperAlarm, TamperEvent, TamperSource};
+use crate::history::ServerTree;
+use crate::storage::FileStore;
+
+/// What rebuilding the tree from storage found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RebuildReport {
+    /// Root of the tree before the rebuild.
+    pub previous_root: Vec<u8>,
+    /// Root of the tree rebuilt from the recorded hashes of the stored files.
+    pub rebuilt_root: Vec<u8>,
+    pub files: usize,
+    /// One line per problem found; empty if storage and tree agree.
+    pub discrepancies: Vec<String>,
+    pub duration: Duration,
+}
+
+impl RebuildReport {
+    pub fn is_clean(&self) -> bool {
+        self.discrepancies.is_empty()
+    }
+
+    /// The report as one line of JSON, with hex roots.
+    pub fn to_json(&self) -> String {
+        json!({
+            "previous_root": hex::encode(&self.previous_root),
+            "rebuilt_root": hex::encode(&self.rebuilt_root),
+            "files": self.files,
+            "discrepancies": self.discrepancies,
+            "duration_ms": self.duration.as_millis() as u64,
+        })
+        .to_string()
+    }
+}
+
+/// Refuses requests while set, so the tree can be rebuilt or storage repaired.
+#[derive(Debug, Default)]
+pub(crate) struct Maintenance {
+    on: AtomicBool,
+    last_rebuild: std::sync::Mutex<Option<Duration>>,
+}
+
+impl Maintenance {
+    pub fn is_on(&self) -> bool {
+        self.on.load(Ordering::SeqCst)
+    }
+
+    /// Turns maintenance mode on or off, returning whether it was on.
+    pub fn set(&self, on: bool) -> bool {
+        self.on.swap(on, Ordering::SeqCst)
+    }
+
+    /// How long the last rebuild took, if there was one.
+    pub fn last_rebuild(&self) -> Option<Duration> {
+        *self
+            .last_rebuild
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Puts the server in maintenance mode, re-hashes every stored file and rebuilds the tree
+/// from their recorded hashes. A tree that differs from the rebuilt one is replaced by it;
+/// files whose bytes no longer match their recorded hash are reported, not adopted, so
+/// proofs keep exposing them. Discrepancies raise `alarms`. Service resumes afterwards
+/// unless maintenance mode was already on.
+pub(crate) async fn rebuild(
+    files: &FileStore,
+    server_mt: &Mutex<ServerTree>,
+    maintenance: &Maintenance,
+    alarms: &[Arc<dyn TamperAlarm>],
+) -> RebuildReport {
+    let started = Instant::now();
+    let was_on = maintenance.set(true);
+    let files_guard = files.lock().await;
+    let mut discrepancies = Vec::new();
+    for (filename, file) in files_guard.iter() {
+        match file.digest().await {
+            Ok(digest) if digest == file.hash => {}
+            Ok(_) => discrepancies.push(format!(
+                "{}: stored bytes no longer match the recorded hash",
+                filename
+            )),
+            Err(err) => discrepancies.push(format!("{}: cannot be read: {}", filename, err)),
+        }
+    }
+    let rebuilt = if files_guard.is_empty() {
+        // An empty server holds a tree with a single empty leaf
+        MerkleTree::new(vec![vec![]])
+    } else {
+        MerkleTree::from_leaf_hashes(files_guard.values().map(|file| file.hash.clone()).collect())
+    };
+
+    let mut server_mt = server_mt.lock().await;
+    let previous_root = server_mt.current.get_root_hash();
+    let rebuilt_root = rebuilt.get_root_hash();
+    if previous_root != rebuilt_root {
+        discrepancies.push(format!(
+            "tree root {} differs from the root {} rebuilt from storage; replaced",
+            hex::encode(&previous_root),
+            hex::encode(&rebuilt_root)
+        ));
+        server_mt.replace(rebuilt, None);
+    }
+    drop(server_mt);
+    let report = RebuildReport {
+        previous_root,
+        rebuilt_root,
+        files: files_guard.len(),
+        discrepancies,
+        duration: started.elapsed(),
+    };
+    drop(files_guard);
+    *maintenance
+        .last_rebuild
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner) = Some(report.duration);
+
+    if !report.is_clean() {
+        let event = TamperEvent::new(TamperSource::Rebuild, report.discrepancies.clone());
+        alarm::raise_all_blocking(alarms, event).await;
+    }
+    if !was_on {
+        maintenance.set(false);
+    }
+    report
+}
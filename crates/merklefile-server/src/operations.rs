@@ -0,0 +1,72 @@
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use merklefile_core::operations::{Operation, OperationKind};
+
+use crate::now;
+
+/// Appends every change to the files to an operation log in the format of
+/// `merklefile_core::operations`, so `operations::replay` can later check that the
+/// server's roots are explained by them.
+#[derive(Debug)]
+pub(crate) struct OperationLog {
+    path: PathBuf,
+}
+
+impl OperationLog {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        OperationLog { path }
+    }
+
+    /// Records the files the server starts out with, by name and leaf hash.
+    pub(crate) async fn snapshot(
+        self: &Arc<Self>,
+        files: BTreeMap<String, Vec<u8>>,
+    ) -> io::Result<()> {
+        self.append(OperationKind::Snapshot, files).await
+    }
+
+    /// Records the files an upload added or replaced, by name and leaf hash.
+    pub(crate) async fn upload(
+        self: &Arc<Self>,
+        files: BTreeMap<String, Vec<u8>>,
+    ) -> io::Result<()> {
+        self.append(OperationKind::Upload, files).await
+    }
+
+    async fn append(
+        self: &Arc<Self>,
+        kind: OperationKind,
+        files: BTreeMap<String, Vec<u8>>,
+    ) -> io::Result<()> {
+        let log = Arc::clone(self);
+        tokio::task::spawn_blocking(move || {
+            let operation = Operation {
+                kind,
+                timestamp: now(),
+                files,
+            };
+            log.write_line(&operation.to_line()).map_err(|err| {
+                io::Error::new(
+                    err.kind(),
+                    format!("operation log {}: {}", log.path.display(), err),
+                )
+            })
+        })
+        .await
+        .map_err(io::Error::other)?
+    }
+
+    fn write_line(&self, line: &str) -> io::Result<()> {
+        let mut log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        // One write per line, so concurrent writers never interleave within a line
+        log.write_all(format!("{}\n", line).as_bytes())?;
+        log.sync_data()
+    }
+}
@@ -0,0 +1,81 @@
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+
+use merklefile_proto::BytesMut;
+
+/// Buffers that connections read requests into and write responses from, reused across
+/// connections so a busy server does not allocate, fault in and free a buffer per request.
+///
+/// At most `capacity` idle buffers are kept, each no larger than `max_message_size`, so the
+/// memory held between requests stays bounded however large the requests were.
+#[derive(Debug)]
+pub struct BufferPool {
+    idle: Mutex<Vec<BytesMut>>,
+    capacity: usize,
+    max_message_size: u64,
+}
+
+impl BufferPool {
+    pub fn new(capacity: usize, max_message_size: u64) -> Self {
+        BufferPool {
+            idle: Mutex::new(Vec::with_capacity(capacity)),
+            capacity,
+            max_message_size,
+        }
+    }
+
+    /// Largest request frame accepted, and the largest buffer kept for reuse.
+    pub fn max_message_size(&self) -> u64 {
+        self.max_message_size
+    }
+
+    /// An empty buffer, reused if one is idle. It returns to the pool when dropped.
+    pub fn get(self: &Arc<Self>) -> PooledBuffer {
+        let buffer = self.idle.lock().unwrap().pop().unwrap_or_default();
+        PooledBuffer {
+            buffer,
+            pool: Arc::clone(self),
+        }
+    }
+
+    /// Number of buffers waiting to be reused.
+    pub fn idle(&self) -> usize {
+        self.idle.lock().unwrap().len()
+    }
+}
+
+/// A buffer borrowed from a `BufferPool`.
+#[derive(Debug)]
+pub struct PooledBuffer {
+    buffer: BytesMut,
+    pool: Arc<BufferPool>,
+}
+
+impl Deref for PooledBuffer {
+    type Target = BytesMut;
+
+    fn deref(&self) -> &BytesMut {
+        &self.buffer
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut BytesMut {
+        &mut self.buffer
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        let mut buffer = std::mem::take(&mut self.buffer);
+        // Growing by doubling can overshoot the largest message; never hold on to more
+        if buffer.capacity() as u64 > self.pool.max_message_size {
+            return;
+        }
+        buffer.clear();
+        let mut idle = self.pool.idle.lock().unwrap();
+        if idle.len() < self.pool.capacity {
+            idle.push(buffer);
+        }
+    }
+}
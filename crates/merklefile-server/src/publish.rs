@@ -0,0 +1,228 @@
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use merklefile_core::journal::{self, JournalEntry};
+use merklefile_core::log::SigningKey;
+
+use crate::alarm::{self, TamperAlarm, TamperEvent, TamperSource};
+
+/// Prefix of the TXT record a published root is written to, as `RootSource::DnsTxt` reads it.
+const ROOT_PREFIX: &str = "merklefile-root=";
+
+/// Pushes every new root somewhere the file server does not control, so clients can verify
+/// downloads against a root that a compromised server cannot rewrite on its own.
+pub trait RootPublisher: Send + Sync {
+    /// Publishes `root`, over `size` files, which took effect at `timestamp` (seconds since
+    /// the Unix epoch). Called from a blocking thread, one root at a time, in the order the
+    /// roots take effect.
+    fn publish(&self, root: &[u8], size: u64, timestamp: u64) -> io::Result<()>;
+}
+
+/// Writes the root to a DNS TXT record through a provider's HTTP API: the record is PUT
+/// to `api_url` as `{"type": "TXT", "name": ..., "content": "merklefile-root=<hex>", "ttl": ...}`.
+#[derive(Debug, Clone)]
+pub struct DnsTxtPublisher {
+    pub api_url: String,
+    /// Name of the TXT record, which clients fetch with `--root-url dns:<name>`.
+    pub name: String,
+    pub ttl: u32,
+    pub headers: BTreeMap<String, String>,
+    pub timeout: Duration,
+}
+
+impl RootPublisher for DnsTxtPublisher {
+    fn publish(&self, root: &[u8], _size: u64, _timestamp: u64) -> io::Result<()> {
+        let body = json!({
+            "type": "TXT",
+            "name": self.name,
+            "content": format!("{}{}", ROOT_PREFIX, hex::encode(root)),
+            "ttl": self.ttl,
+        });
+        let request = ureq::put(&self.api_url).timeout(self.timeout);
+        send(request, &self.headers, &body)
+            .map_err(|err| io::Error::other(format!("DNS API {}: {}", self.api_url, err)))
+    }
+}
+
+/// POSTs `{"root": "<hex>", "timestamp": ...}` to a notary, which records or countersigns it.
+#[derive(Debug, Clone)]
+pub struct NotaryPublisher {
+    pub url: String,
+    pub headers: BTreeMap<String, String>,
+    pub timeout: Duration,
+}
+
+impl RootPublisher for NotaryPublisher {
+    fn publish(&self, root: &[u8], _size: u64, timestamp: u64) -> io::Result<()> {
+        let body = json!({ "root": hex::encode(root), "timestamp": timestamp });
+        let request = ureq::post(&self.url).timeout(self.timeout);
+        send(request, &self.headers, &body)
+            .map_err(|err| io::Error::other(format!("notary {}: {}", self.url, err)))
+    }
+}
+
+/// Appends `<timestamp> <hex root>` lines to a journal file, typically on a mounted shared
+/// object store, so every root the server ever had stays on record.
+#[derive(Debug, Clone)]
+pub struct JournalPublisher {
+    pub path: PathBuf,
+}
+
+impl RootPublisher for JournalPublisher {
+    fn publish(&self, root: &[u8], _size: u64, timestamp: u64) -> io::Result<()> {
+        let journal_error = |err: io::Error| {
+            io::Error::new(
+                err.kind(),
+                format!("journal {}: {}", self.path.display(), err),
+            )
+        };
+        let mut journal = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(journal_error)?;
+        // One write per line, so concurrent writers never interleave within a line
+        journal
+            .write_all(format!("{} {}\n", timestamp, hex::encode(root)).as_bytes())
+            .and_then(|()| journal.sync_data())
+            .map_err(journal_error)
+    }
+}
+
+/// Appends every root to a hash-chained journal signed with `key`, in the format of
+/// `merklefile_core::journal`. Unlike `JournalPublisher`'s plain lines, entries cannot be
+/// dropped, reordered or rewritten without `journal::verify_journal` noticing, so the journal
+/// remains evidence of past roots even if the server's storage is wiped. Before each
+/// append, the journal must still end in the entry last written to it; if it does not, the
+/// root is refused and the tamper alarms are raised.
+pub struct RootJournal {
+    path: PathBuf,
+    key: SigningKey,
+    /// The latest entry, which the next one is chained to.
+    last: Mutex<Option<JournalEntry>>,
+    alarms: Vec<Arc<dyn TamperAlarm>>,
+}
+
+/// Journal bytes read back to find its last line, comfortably more than one entry.
+const TAIL_LEN: u64 = 1024;
+
+impl RootJournal {
+    /// Opens the journal at `path`, which need not exist yet, after checking that the
+    /// entries it holds form one chain signed by `key`.
+    pub fn open(path: impl AsRef<Path>, key: SigningKey) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let last = match journal::read_journal(&path, &key.verifying_key()) {
+            Ok(mut entries) => entries.pop(),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => {
+                return Err(io::Error::new(
+                    err.kind(),
+                    format!("journal {}: {}", path.display(), err),
+                ))
+            }
+        };
+        Ok(RootJournal {
+            path,
+            key,
+            last: Mutex::new(last),
+            alarms: Vec::new(),
+        })
+    }
+
+    /// Raises `alarms` when the journal is found changed behind the server's back.
+    pub fn with_alarms(mut self, alarms: Vec<Arc<dyn TamperAlarm>>) -> Self {
+        self.alarms = alarms;
+        self
+    }
+
+    /// Whether the journal still ends in `last`, or is still empty if there is none.
+    fn ends_in(&self, last: Option<&JournalEntry>) -> io::Result<bool> {
+        let mut journal = match File::open(&self.path) {
+            Ok(journal) => journal,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(last.is_none()),
+            Err(err) => return Err(err),
+        };
+        let len = journal.metadata()?.len();
+        journal.seek(SeekFrom::Start(len.saturating_sub(TAIL_LEN)))?;
+        let mut tail = Vec::new();
+        journal.read_to_end(&mut tail)?;
+        let last_line = tail
+            .strip_suffix(b"\n")
+            .and_then(|tail| tail.split(|&byte| byte == b'\n').next_back());
+        Ok(match (last, last_line) {
+            (None, _) => len == 0,
+            (Some(entry), Some(line)) => line == entry.to_line().as_bytes(),
+            (Some(_), None) => false,
+        })
+    }
+}
+
+impl std::fmt::Debug for RootJournal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RootJournal")
+            .field("path", &self.path)
+            .field("alarms", &self.alarms.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl RootPublisher for RootJournal {
+    fn publish(&self, root: &[u8], size: u64, timestamp: u64) -> io::Result<()> {
+        let mut last = self.last.lock().unwrap_or_else(PoisonError::into_inner);
+        if !self.ends_in(last.as_ref())? {
+            let problem = format!(
+                "journal {} no longer ends in the last entry written to it",
+                self.path.display()
+            );
+            let event = TamperEvent::new(TamperSource::Journal, vec![problem.clone()]);
+            alarm::raise_all(&self.alarms, &event);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, problem));
+        }
+        // A clock stepped back must not make the journal look tampered with
+        let timestamp = last
+            .as_ref()
+            .map_or(timestamp, |last| timestamp.max(last.timestamp));
+        let entry = JournalEntry::sign(last.as_ref(), size, timestamp, root.to_vec(), &self.key);
+        let mut journal = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        journal.write_all(format!("{}\n", entry.to_line()).as_bytes())?;
+        journal.sync_data()?;
+        *last = Some(entry);
+        Ok(())
+    }
+}
+
+fn send(
+    mut request: ureq::Request,
+    headers: &BTreeMap<String, String>,
+    body: &serde_json::Value,
+) -> Result<(), Box<ureq::Error>> {
+    for (name, value) in headers {
+        request = request.set(name, value);
+    }
+    request.send_json(body).map(|_| ()).map_err(Box::new)
+}
+
+/// Publishes `root` with every publisher in turn, stopping at the first that fails.
+pub(crate) async fn publish_all(
+    publishers: &[Arc<dyn RootPublisher>],
+    root: Vec<u8>,
+    size: u64,
+) -> io::Result<()> {
+    let publishers = publishers.to_vec();
+    tokio::task::spawn_blocking(move || {
+        let timestamp = crate::now();
+        publishers
+            .iter()
+            .try_for_each(|publisher| publisher.publish(&root, size, timestamp))
+    })
+    .await
+    .map_err(io::Error::other)?
+}
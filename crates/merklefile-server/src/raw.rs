@@ -0,0 +1,111 @@
+use std::io;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use merklefile_core::profile::{self, Stage};
+use merklefile_proto::{self as protocol, ClientMessage, ProtocolError};
+
+use crate::storage::StoredFile;
+
+/// Answers a `DownloadRaw` of `file`: a `RawFile` frame, then the file's bytes as they are.
+///
+/// With `sendfile` on Linux, a file stored on disk goes from the page cache straight to the
+/// socket, never passing through the server's memory. Otherwise it is copied through a small
+/// buffer, or written from memory. Either way the bytes are not checked against the leaf
+/// hash here; the client checks them against the hash in the frame.
+pub(crate) async fn send_raw(
+    stream: &mut TcpStream,
+    file: &StoredFile,
+    #[cfg_attr(not(target_os = "linux"), allow(unused_variables))] sendfile: bool,
+) -> Result<(), ProtocolError> {
+    let header = protocol::encode(&ClientMessage::RawFile {
+        len: file.len,
+        hash: file.hash.clone(),
+    })?;
+    protocol::write_frame(stream, &header).await?;
+
+    let _span = profile::span(Stage::SocketWrite).with_bytes(file.len);
+
+    match file.path() {
+        #[cfg(target_os = "linux")]
+        Some(path) if sendfile => {
+            let source = tokio::fs::File::open(path).await?.into_std().await;
+            linux::sendfile(stream, &source, file.len).await?;
+        }
+        Some(path) => {
+            let mut source = tokio::fs::File::open(path).await?.take(file.len);
+            if tokio::io::copy(&mut source, stream).await? != file.len {
+                return Err(shorter_than_recorded().into());
+            }
+        }
+        None => stream.write_all(&file.read().await?).await?,
+    }
+    stream.flush().await?;
+    Ok(())
+}
+
+/// Answers a `DownloadRaw` with bytes already read and checked against `hash`.
+pub(crate) async fn send_bytes(
+    stream: &mut TcpStream,
+    hash: &[u8],
+    data: &[u8],
+) -> Result<(), ProtocolError> {
+    let header = protocol::encode(&ClientMessage::RawFile {
+        len: data.len() as u64,
+        hash: hash.to_vec(),
+    })?;
+    protocol::write_frame(stream, &header).await?;
+    let _span = profile::span(Stage::SocketWrite).with_bytes(data.len() as u64);
+    stream.write_all(data).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+fn shorter_than_recorded() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "stored file is shorter than recorded",
+    )
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::fs::File;
+    use std::io;
+    use std::os::fd::AsRawFd;
+    use tokio::io::Interest;
+    use tokio::net::TcpStream;
+
+    /// Most bytes handed to one `sendfile` call, so one download does not hold the socket's
+    /// readiness for long.
+    const MAX_SEND: u64 = 8 * 1024 * 1024;
+
+    /// Sends the first `len` bytes of `source` to `stream` with sendfile(2), waiting for the
+    /// socket to become writable whenever its send buffer is full.
+    pub(super) async fn sendfile(stream: &TcpStream, source: &File, len: u64) -> io::Result<()> {
+        let mut offset: libc::off_t = 0;
+        while (offset as u64) < len {
+            stream.writable().await?;
+            let count = (len - offset as u64).min(MAX_SEND) as usize;
+            let sent = stream.try_io(Interest::WRITABLE, || {
+                // SAFETY: both descriptors are open for the duration of the call, and
+                // `offset` is a valid pointer that sendfile advances past the bytes sent
+                let sent = unsafe {
+                    libc::sendfile(stream.as_raw_fd(), source.as_raw_fd(), &mut offset, count)
+                };
+                if sent < 0 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    Ok(sent)
+                }
+            });
+            match sent {
+                Ok(0) => return Err(super::shorter_than_recorded()),
+                Ok(_) => {}
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::{Mutex, PoisonError};
+use std::time::Instant;
+
+use crate::alarm::{TamperAlarm, TamperEvent};
+
+/// Counters kept since the server started, for `GetStats`. Also a tamper alarm, so every
+/// verification failure is counted whichever check found it.
+#[derive(Debug)]
+pub(crate) struct Stats {
+    started: Instant,
+    requests: Mutex<BTreeMap<&'static str, u64>>,
+    failures: Mutex<BTreeMap<String, u64>>,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Stats {
+            started: Instant::now(),
+            requests: Mutex::default(),
+            failures: Mutex::default(),
+        }
+    }
+}
+
+impl Stats {
+    /// Counts one request of type `kind`.
+    pub fn count_request(&self, kind: &'static str) {
+        *self
+            .requests
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(kind)
+            .or_default() += 1;
+    }
+
+    pub fn requests(&self) -> BTreeMap<String, u64> {
+        self.requests
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .map(|(kind, count)| (kind.to_string(), *count))
+            .collect()
+    }
+
+    pub fn failures(&self) -> BTreeMap<String, u64> {
+        self.failures
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    pub fn uptime_secs(&self) -> u64 {
+        self.started.elapsed().as_secs()
+    }
+}
+
+impl TamperAlarm for Stats {
+    fn raise(&self, event: &TamperEvent) -> io::Result<()> {
+        *self
+            .failures
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(event.source.to_string())
+            .or_default() += 1;
+        Ok(())
+    }
+}
@@ -0,0 +1,460 @@
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, SeekFrom};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::fs;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::{Mutex, MutexGuard, Semaphore};
+
+use merklefile_proto::{Bytes, BytesMut};
+
+/// Bytes read from disk at a time when hashing a stored file.
+const READ_CHUNK: usize = 256 * 1024;
+
+/// One stored file: its leaf hash and where its contents live.
+#[derive(Debug, Clone)]
+pub(crate) struct StoredFile {
+    /// SHA-256 of the contents, the file's leaf in the tree.
+    pub hash: Vec<u8>,
+    pub len: u64,
+    contents: Contents,
+}
+
+#[derive(Debug, Clone)]
+enum Contents {
+    Memory(Bytes),
+    Disk(PathBuf),
+}
+
+impl StoredFile {
+    pub fn in_memory(data: Bytes) -> Self {
+        StoredFile {
+            hash: Sha256::digest(&data).to_vec(),
+            len: data.len() as u64,
+            contents: Contents::Memory(data),
+        }
+    }
+
+    /// The whole file.
+    pub async fn read(&self) -> io::Result<Bytes> {
+        self.read_at(0, self.len as usize).await
+    }
+
+    /// `len` bytes starting at `offset`, which the caller has checked are within the file.
+    pub async fn read_at(&self, offset: u64, len: usize) -> io::Result<Bytes> {
+        match &self.contents {
+            Contents::Memory(data) => Ok(data.slice(offset as usize..offset as usize + len)),
+            Contents::Disk(path) => {
+                let mut file = fs::File::open(path).await?;
+                file.seek(SeekFrom::Start(offset)).await?;
+                // Sized from the stored length rather than grown by `read_to_end`
+                let mut data = BytesMut::with_capacity(len);
+                while data.len() < len {
+                    if file.read_buf(&mut data).await? == 0 {
+                        return Err(io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            format!("{} is shorter than recorded", path.display()),
+                        ));
+                    }
+                }
+                data.truncate(len);
+                Ok(data.freeze())
+            }
+        }
+    }
+
+    /// Where the file is stored, unless it is kept in memory.
+    pub fn path(&self) -> Option<&Path> {
+        match &self.contents {
+            Contents::Memory(_) => None,
+            Contents::Disk(path) => Some(path),
+        }
+    }
+
+    /// SHA-256 of every `chunk_size` chunk, reading the file one chunk at a time.
+    pub async fn chunk_hashes(&self, chunk_size: usize) -> io::Result<Vec<Vec<u8>>> {
+        let mut hashes = Vec::new();
+        self.for_each_chunk(chunk_size, |chunk| {
+            hashes.push(Sha256::digest(chunk).to_vec())
+        })
+        .await?;
+        Ok(hashes)
+    }
+
+    /// SHA-256 of the contents as they are now, to catch files changed behind the server's
+    /// back.
+    pub async fn digest(&self) -> io::Result<Vec<u8>> {
+        let mut hasher = Sha256::new();
+        self.for_each_chunk(READ_CHUNK, |chunk| hasher.update(chunk))
+            .await?;
+        Ok(hasher.finalize().to_vec())
+    }
+
+    /// Calls `f` with every `chunk_size` chunk in order; an empty file is one empty chunk.
+    async fn for_each_chunk(&self, chunk_size: usize, mut f: impl FnMut(&[u8])) -> io::Result<()> {
+        let path = match &self.contents {
+            Contents::Memory(data) => {
+                if data.is_empty() {
+                    f(&[]);
+                }
+                data.chunks(chunk_size).for_each(f);
+                return Ok(());
+            }
+            Contents::Disk(path) => path,
+        };
+        let mut file = fs::File::open(path).await?;
+        let mut chunk = vec![0; chunk_size];
+        let mut remaining = self.len;
+        loop {
+            let len = chunk_size.min(remaining as usize);
+            file.read_exact(&mut chunk[..len]).await?;
+            f(&chunk[..len]);
+            remaining -= len as u64;
+            if remaining == 0 {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// An uploaded file whose contents have arrived but are not stored yet; see
+/// `FileStore::receive`.
+#[derive(Debug)]
+pub(crate) struct Received {
+    pub hash: Vec<u8>,
+    len: u64,
+    /// In memory, or in a file under `incoming/` that is deleted when this is dropped.
+    contents: Contents,
+}
+
+impl Drop for Received {
+    fn drop(&mut self) {
+        if let Contents::Disk(path) = &self.contents {
+            // Gone already if it was moved into place
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Keeps file contents on disk instead of in memory. Contents are stored once per distinct
+/// hash under `objects/`, and `index.json` maps every filename to its hash:
+///
+/// ```text
+/// <dir>/index.json          {"a.txt": "9f86d081...", ...}
+/// <dir>/objects/9f86d081...
+/// <dir>/incoming/           streamed uploads, until they are stored
+/// ```
+///
+/// Both are replaced atomically, so a crash leaves the previous state or the new one.
+#[derive(Debug)]
+pub(crate) struct DiskStore {
+    dir: PathBuf,
+}
+
+impl DiskStore {
+    /// Opens the store in `dir`, creating it if needed, and returns the files it holds.
+    pub async fn open(dir: &Path) -> io::Result<(Self, BTreeMap<String, StoredFile>)> {
+        let store = DiskStore {
+            dir: dir.to_path_buf(),
+        };
+        fs::create_dir_all(store.dir.join("objects")).await?;
+        // Uploads cut short by a crash
+        let _ = fs::remove_dir_all(store.incoming_dir()).await;
+        fs::create_dir_all(store.incoming_dir()).await?;
+        let index: BTreeMap<String, String> = match fs::read(store.index_path()).await {
+            Ok(json) => serde_json::from_slice(&json)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(err) => return Err(err),
+        };
+
+        let mut files = BTreeMap::new();
+        for (filename, hash) in index {
+            let invalid = || {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("index entry for {} is invalid", filename),
+                )
+            };
+            let hash = hex::decode(&hash)
+                .ok()
+                .filter(|hash| hash.len() == 32)
+                .ok_or_else(invalid)?;
+            let path = store.object_path(&hash);
+            let len = fs::metadata(&path).await?.len();
+            files.insert(
+                filename,
+                StoredFile {
+                    hash,
+                    len,
+                    contents: Contents::Disk(path),
+                },
+            );
+        }
+        Ok((store, files))
+    }
+
+    /// Writes `data` to disk unless a file with the same contents is already stored.
+    pub async fn store(&self, data: Bytes) -> io::Result<StoredFile> {
+        let hash = Sha256::digest(&data).to_vec();
+        let path = self.object_path(&hash);
+        if fs::metadata(&path).await.is_err() {
+            write_atomic(&path, &data).await?;
+        }
+        Ok(StoredFile {
+            hash,
+            len: data.len() as u64,
+            contents: Contents::Disk(path),
+        })
+    }
+
+    /// Streams `len` bytes from `reader` into a file under `incoming/`, hashing them on the
+    /// way, so a large upload is never held in memory.
+    pub async fn receive(
+        &self,
+        reader: &mut (impl AsyncRead + Unpin),
+        len: u64,
+    ) -> io::Result<Received> {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        let path = self
+            .incoming_dir()
+            .join(NEXT.fetch_add(1, Ordering::Relaxed).to_string());
+        let mut received = Received {
+            hash: Vec::new(),
+            len,
+            contents: Contents::Disk(path.clone()),
+        };
+        let mut file = fs::File::create(&path).await?;
+        let mut hasher = Sha256::new();
+        let mut chunk = vec![0; READ_CHUNK];
+        let mut remaining = len;
+        while remaining > 0 {
+            let read = reader
+                .read(&mut chunk[..READ_CHUNK.min(remaining as usize)])
+                .await?;
+            if read == 0 {
+                return Err(upload_ended_early());
+            }
+            hasher.update(&chunk[..read]);
+            file.write_all(&chunk[..read]).await?;
+            remaining -= read as u64;
+        }
+        file.sync_data().await?;
+        received.hash = hasher.finalize().to_vec();
+        Ok(received)
+    }
+
+    /// Moves a received file into place unless a file with the same contents is stored.
+    pub async fn store_received(&self, received: &Received) -> io::Result<StoredFile> {
+        let path = self.object_path(&received.hash);
+        if let Contents::Disk(incoming) = &received.contents {
+            if fs::metadata(&path).await.is_err() {
+                fs::rename(incoming, &path).await?;
+            }
+        }
+        Ok(StoredFile {
+            hash: received.hash.clone(),
+            len: received.len,
+            contents: Contents::Disk(path),
+        })
+    }
+
+    /// Records `files` as the stored files and deletes every object none of them refers to,
+    /// including objects stored for uploads that were refused.
+    pub async fn save_index(&self, files: &BTreeMap<String, StoredFile>) -> io::Result<()> {
+        let index: BTreeMap<&str, String> = files
+            .iter()
+            .map(|(filename, file)| (filename.as_str(), hex::encode(&file.hash)))
+            .collect();
+        let json = serde_json::to_vec(&index).map_err(io::Error::other)?;
+        write_atomic(&self.index_path(), &json).await?;
+
+        let referenced: BTreeSet<&String> = index.values().collect();
+        let mut objects = fs::read_dir(self.dir.join("objects")).await?;
+        while let Some(entry) = objects.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !referenced.contains(&name) {
+                fs::remove_file(entry.path()).await?;
+            }
+        }
+        Ok(())
+    }
+
+    fn incoming_dir(&self) -> PathBuf {
+        self.dir.join("incoming")
+    }
+
+    fn index_path(&self) -> PathBuf {
+        self.dir.join("index.json")
+    }
+
+    fn object_path(&self, hash: &[u8]) -> PathBuf {
+        self.dir.join("objects").join(hex::encode(hash))
+    }
+}
+
+fn upload_ended_early() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "upload ended before its announced length",
+    )
+}
+
+/// Writes `data` to `path` through a `.partial` file that is synced and then renamed.
+async fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+    let mut file = fs::File::create(&partial).await?;
+    file.write_all(data).await?;
+    file.sync_data().await?;
+    drop(file);
+    fs::rename(partial, path).await
+}
+
+/// Hashes uploaded files off the async runtime, at most a set number at once across all
+/// connections, so a burst of uploads cannot take every core of a shared host.
+#[derive(Debug, Clone)]
+pub(crate) struct Hashers {
+    permits: Arc<Semaphore>,
+}
+
+impl Hashers {
+    pub fn new(concurrency: usize) -> Self {
+        Hashers {
+            permits: Arc::new(Semaphore::new(concurrency.max(1))),
+        }
+    }
+
+    /// SHA-256 of every file in `data`, in order.
+    pub async fn digest_all(&self, data: impl IntoIterator<Item = &Bytes>) -> Vec<Vec<u8>> {
+        let mut hashing = Vec::new();
+        for data in data {
+            let permit = Arc::clone(&self.permits)
+                .acquire_owned()
+                .await
+                .expect("the semaphore is never closed");
+            let data = data.clone();
+            hashing.push(tokio::task::spawn_blocking(move || {
+                let _permit = permit;
+                Sha256::digest(&data).to_vec()
+            }));
+        }
+        let mut hashes = Vec::with_capacity(hashing.len());
+        for hash in hashing {
+            hashes.push(hash.await.expect("hashing does not panic"));
+        }
+        hashes
+    }
+}
+
+/// One hasher per core.
+impl Default for Hashers {
+    fn default() -> Self {
+        Self::new(std::thread::available_parallelism().map_or(1, NonZeroUsize::get))
+    }
+}
+
+/// The stored files by filename, with their contents in memory or, given a storage
+/// directory, on disk.
+#[derive(Debug, Default)]
+pub(crate) struct FileStore {
+    files: Mutex<BTreeMap<String, StoredFile>>,
+    disk: Option<DiskStore>,
+    hashers: Hashers,
+}
+
+impl FileStore {
+    /// A store keeping contents in `dir`, holding the files already there.
+    pub async fn open(dir: &Path, hashers: Hashers) -> io::Result<Self> {
+        let (disk, files) = DiskStore::open(dir).await?;
+        Ok(FileStore {
+            files: Mutex::new(files),
+            disk: Some(disk),
+            hashers,
+        })
+    }
+
+    /// Whether received files are held in memory rather than streamed to disk.
+    pub fn in_memory(&self) -> bool {
+        self.disk.is_none()
+    }
+
+    /// Hashes uploaded files.
+    pub fn hashers(&self) -> &Hashers {
+        &self.hashers
+    }
+
+    pub fn set_hashers(&mut self, hashers: Hashers) {
+        self.hashers = hashers;
+    }
+
+    /// The stored file `filename`.
+    pub async fn get(&self, filename: &str) -> Option<StoredFile> {
+        self.files.lock().await.get(filename).cloned()
+    }
+
+    pub async fn lock(&self) -> MutexGuard<'_, BTreeMap<String, StoredFile>> {
+        self.files.lock().await
+    }
+
+    /// Stores `data` for a file about to be added. Only call with the files locked, so
+    /// `save` cannot delete it before it is added.
+    pub async fn store(&self, data: Bytes) -> io::Result<StoredFile> {
+        match &self.disk {
+            Some(disk) => disk.store(data).await,
+            None => Ok(StoredFile::in_memory(data)),
+        }
+    }
+
+    /// Reads an uploaded file of `len` bytes from `reader`. On disk, it is streamed to a
+    /// file rather than held in memory; `store_received` then stores it.
+    pub async fn receive(
+        &self,
+        reader: &mut (impl AsyncRead + Unpin),
+        len: u64,
+    ) -> io::Result<Received> {
+        if let Some(disk) = &self.disk {
+            return disk.receive(reader, len).await;
+        }
+        let mut data = vec![0; len as usize];
+        reader
+            .read_exact(&mut data)
+            .await
+            .map_err(|err| match err.kind() {
+                io::ErrorKind::UnexpectedEof => upload_ended_early(),
+                _ => err,
+            })?;
+        let data = Bytes::from(data);
+        let hash = self.hashers.digest_all([&data]).await.remove(0);
+        Ok(Received {
+            hash,
+            len,
+            contents: Contents::Memory(data),
+        })
+    }
+
+    /// Stores a received file for a file about to be added. Only call with the files locked,
+    /// as with `store`.
+    pub async fn store_received(&self, received: &Received) -> io::Result<StoredFile> {
+        match (&self.disk, &received.contents) {
+            (Some(disk), _) => disk.store_received(received).await,
+            (None, contents) => Ok(StoredFile {
+                hash: received.hash.clone(),
+                len: received.len,
+                contents: contents.clone(),
+            }),
+        }
+    }
+
+    /// Makes `files` the stored files once they have taken effect; only needed on disk.
+    pub async fn save(&self, files: &BTreeMap<String, StoredFile>) -> io::Result<()> {
+        match &self.disk {
+            Some(disk) => disk.save_index(files).await,
+            None => Ok(()),
+        }
+    }
+}
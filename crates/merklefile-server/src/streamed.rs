@@ -0,0 +1,146 @@
+use sha2::{Digest, Sha256};
+use std::io;
+use std::path::{Path, PathBuf};
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use merklefile_core::profile::{self, Stage};
+use merklefile_proto::{self as protocol, Bytes, ClientMessage, ProtocolError, WireFormat};
+
+use crate::storage::StoredFile;
+
+/// File bytes read, encoded and hashed per step, so the response buffer stays a few MiB
+/// however large the file is.
+const STEP: usize = 1024 * 1024;
+
+/// A download of a stored file sent straight from disk, one step at a time, instead of
+/// being read onto the heap first. The file is read twice: once up front for the length of
+/// the response, and once as it is sent. Plain reads, unlike a memory map, fail cleanly if
+/// the file is truncated or rewritten in between.
+pub(crate) struct Streamed {
+    path: PathBuf,
+    len: u64,
+    array_len: u64,
+}
+
+impl Streamed {
+    /// Prepares to send `file`, if it is stored on disk and not empty. Fails before anything
+    /// is sent if the file is shorter than recorded.
+    pub async fn open(file: &StoredFile) -> io::Result<Option<Self>> {
+        let path = match file.path() {
+            Some(path) if file.len > 0 => path.to_path_buf(),
+            _ => return Ok(None),
+        };
+        let mut array_len = 0;
+        for_each_step(&path, file.len, |step| {
+            array_len += self::array_len(step) as u64 + 1
+        })
+        .await?;
+        Ok(Some(Streamed {
+            path,
+            len: file.len,
+            // No comma before the first byte
+            array_len: array_len - 1,
+        }))
+    }
+
+    /// Sends the response to the download. The contents are hashed as they are sent. If
+    /// they no longer match `hash`, or the file can no longer be read in full, the response
+    /// is cut short, so the client sees a truncated frame rather than a file that fails
+    /// verification.
+    pub async fn send<W: AsyncWrite + Unpin>(
+        self,
+        writer: &mut W,
+        hash: &[u8],
+        format: WireFormat,
+    ) -> Result<(), ProtocolError> {
+        let _span = profile::span(Stage::SocketWrite).with_bytes(self.len);
+        // The exact bytes `encode_as` would produce, with the array of file bytes spliced in
+        let empty = protocol::encode_as(&ClientMessage::Success { data: Bytes::new() }, format)?;
+        let split = empty
+            .windows(2)
+            .position(|window| window == b"[]")
+            .expect("an empty file encodes as an empty array")
+            + 1;
+        let (prefix, suffix) = empty.split_at(split);
+        writer
+            .write_u64(prefix.len() as u64 + self.array_len + suffix.len() as u64)
+            .await?;
+        writer.write_all(prefix).await?;
+
+        let mut file = File::open(&self.path).await?;
+        let mut hasher = Sha256::new();
+        let (mut step, mut encoded) = (vec![0; STEP], Vec::with_capacity(4 * STEP));
+        let mut sent = 0;
+        while sent < self.len {
+            let len = STEP.min((self.len - sent) as usize);
+            file.read_exact(&mut step[..len]).await?;
+            let first = sent == 0;
+            (hasher, step, encoded) = tokio::task::spawn_blocking(move || {
+                hasher.update(&step[..len]);
+                encoded.clear();
+                encode_step(&step[..len], first, &mut encoded);
+                (hasher, step, encoded)
+            })
+            .await
+            .map_err(io::Error::other)?;
+            writer.write_all(&encoded).await?;
+            sent += len as u64;
+        }
+        if hasher.finalize().as_slice() != hash {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "stored file no longer matches its leaf hash; download cut short",
+            )
+            .into());
+        }
+        writer.write_all(suffix).await?;
+        writer.flush().await?;
+        Ok(())
+    }
+}
+
+/// Calls `f` with the first `len` bytes of the file at `path`, a step at a time, failing if
+/// it is shorter.
+async fn for_each_step(path: &Path, len: u64, mut f: impl FnMut(&[u8])) -> io::Result<()> {
+    let mut file = File::open(path).await?;
+    let mut step = vec![0; STEP];
+    let mut read = 0;
+    while read < len {
+        let n = STEP.min((len - read) as usize);
+        file.read_exact(&mut step[..n]).await?;
+        f(&step[..n]);
+        read += n as u64;
+    }
+    Ok(())
+}
+
+/// Length of `data` as a JSON array of numbers, without the brackets.
+fn array_len(data: &[u8]) -> usize {
+    let digits: usize = data.iter().map(|&byte| digits(byte)).sum();
+    digits + data.len().saturating_sub(1)
+}
+
+fn digits(byte: u8) -> usize {
+    match byte {
+        0..=9 => 1,
+        10..=99 => 2,
+        _ => 3,
+    }
+}
+
+/// Appends `step` as JSON array elements, with a leading comma unless it is the first.
+fn encode_step(step: &[u8], first: bool, out: &mut Vec<u8>) {
+    for (i, &byte) in step.iter().enumerate() {
+        if i > 0 || !first {
+            out.push(b',');
+        }
+        if byte >= 100 {
+            out.push(b'0' + byte / 100);
+        }
+        if byte >= 10 {
+            out.push(b'0' + byte / 10 % 10);
+        }
+        out.push(b'0' + byte % 10);
+    }
+}
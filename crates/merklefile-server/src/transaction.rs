@@ -0,0 +1,85 @@
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use merklefile_proto::Bytes;
+use rand_core::{OsRng, RngCore};
+
+/// An upload checked by `Prepare`, waiting for `Commit`.
+#[derive(Debug)]
+pub(crate) struct Prepared {
+    /// Root when it was prepared, which is still the root when it is committed.
+    pub base_root: Vec<u8>,
+    /// Files to store, by the name they are stored under; unchanged files are left out.
+    pub changes: Vec<(String, Bytes)>,
+}
+
+/// The upload prepared for a commit coordinated across servers, if any. While it is pending,
+/// every other upload is refused, so its commit cannot conflict with anything. One that is
+/// neither committed nor aborted in time is dropped, and uploads resume. Transactions are
+/// named by 128 random bits, so only the client that prepared one can commit or abort it.
+#[derive(Debug)]
+pub(crate) struct Transactions {
+    timeout: Duration,
+    pending: Mutex<Option<Pending>>,
+}
+
+#[derive(Debug)]
+struct Pending {
+    id: u128,
+    expires: Instant,
+    prepared: Prepared,
+}
+
+impl Transactions {
+    pub fn new(timeout: Duration) -> Self {
+        Transactions {
+            timeout,
+            pending: Mutex::default(),
+        }
+    }
+
+    /// Whether a prepared upload is holding off other uploads.
+    pub fn is_pending(&self) -> bool {
+        self.lock()
+            .as_ref()
+            .is_some_and(|pending| pending.expires > Instant::now())
+    }
+
+    /// Holds `prepared` until it is committed or aborted, returning its id, or `None` if
+    /// another upload is already prepared.
+    pub fn prepare(&self, prepared: Prepared) -> Option<u128> {
+        let mut pending = self.lock();
+        let now = Instant::now();
+        if pending
+            .as_ref()
+            .is_some_and(|pending| pending.expires > now)
+        {
+            return None;
+        }
+        let mut id = [0; 16];
+        OsRng.fill_bytes(&mut id);
+        let id = u128::from_be_bytes(id);
+        *pending = Some(Pending {
+            id,
+            expires: now + self.timeout,
+            prepared,
+        });
+        Some(id)
+    }
+
+    /// Releases the prepared upload `id`, returning it unless it expired.
+    pub fn take(&self, id: u128) -> Option<Prepared> {
+        let mut pending = self.lock();
+        if pending.as_ref().is_none_or(|pending| pending.id != id) {
+            return None;
+        }
+        pending
+            .take()
+            .filter(|pending| pending.expires > Instant::now())
+            .map(|pending| pending.prepared)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Option<Pending>> {
+        self.pending.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
@@ -0,0 +1,135 @@
+//! Requests over WebSocket, for browsers, which cannot open the plain TCP connections the
+//! protocol otherwise uses. Each binary or text message carries one request envelope,
+//! exactly as it follows the length prefix on TCP, and is answered with one binary message
+//! holding the response envelope. A connection can carry any number of requests.
+
+use std::sync::Arc;
+
+use futures_util::{SinkExt, StreamExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::tungstenite::error::CapacityError;
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+use tokio_tungstenite::tungstenite::{self, Message};
+
+use merklefile_core::error::display_chain;
+use merklefile_proto::{self as protocol, ClientMessage, ProtocolError, ServerMessage};
+
+use crate::{busy, handle_message, is_abuse, shadow_read, stats, ServerError, Shared};
+
+/// Accepts WebSocket connections on `listener` for as long as the server runs.
+pub(crate) async fn serve(listener: TcpListener, shared: Arc<Shared>) {
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                eprintln!("WebSocket accept error: {}", err);
+                continue;
+            }
+        };
+        if shared.bans.is_banned(peer.ip()) {
+            continue;
+        }
+        let shared = Arc::clone(&shared);
+        tokio::spawn(async move {
+            if let Err(err) = handle_connection(stream, &shared).await {
+                eprintln!("{}", display_chain(&err));
+                if is_abuse(&err) && shared.bans.record_error(peer.ip()) {
+                    eprintln!(
+                        "Banning {} after repeated malformed or stalled requests",
+                        peer.ip()
+                    );
+                }
+            }
+        });
+    }
+}
+
+/// Answers requests on one WebSocket connection until the client closes it or lets it sit
+/// idle for longer than the header timeout.
+async fn handle_connection(stream: TcpStream, shared: &Shared) -> Result<(), ServerError> {
+    let max_len = shared.buffers.max_message_size() as usize;
+    let config = WebSocketConfig {
+        max_message_size: Some(max_len),
+        max_frame_size: Some(max_len),
+        ..WebSocketConfig::default()
+    };
+    let handshake = tokio_tungstenite::accept_async_with_config(stream, Some(config));
+    let mut socket = tokio::time::timeout(shared.header_timeout, handshake)
+        .await
+        .map_err(|_| ProtocolError::Timeout("WebSocket handshake"))?
+        .map_err(websocket_error)?;
+    loop {
+        let next = tokio::time::timeout(shared.header_timeout, socket.next())
+            .await
+            .map_err(|_| ProtocolError::Timeout("WebSocket request"))?;
+        let request = match next {
+            None => return Ok(()),
+            Some(message) => message.map_err(websocket_error)?,
+        };
+        let payload = match request {
+            Message::Binary(payload) => payload,
+            Message::Text(text) => text.into_bytes(),
+            Message::Close(_) => return Ok(()),
+            _ => continue,
+        };
+        // Held until the answer is sent, like a request read over TCP
+        let reservation = shared.memory.reserve(payload.len() as u64);
+        let (response, failure) = match protocol::decode::<ServerMessage>(&payload) {
+            Ok(_) if reservation.is_none() => (busy(), None),
+            Ok(message) => (answer(message, shared).await?, None),
+            Err(err) => (
+                ClientMessage::Error {
+                    message: err.to_string(),
+                },
+                Some(err),
+            ),
+        };
+        drop(payload);
+        let response = protocol::encode(&response)?;
+        socket
+            .send(Message::binary(response))
+            .await
+            .map_err(websocket_error)?;
+        drop(reservation);
+        // Answered, so the client learns why, and then treated like a bad frame on TCP
+        if let Some(err) = failure {
+            return Err(err.into());
+        }
+    }
+}
+
+/// The answer to one request. Requests that stream bytes after their frame, and batches of
+/// requests, are only served over TCP.
+async fn answer(message: ServerMessage, shared: &Shared) -> Result<ClientMessage, ServerError> {
+    shared.stats.count_request(message.kind());
+    if shared.maintenance.is_on() {
+        return Ok(ClientMessage::Error {
+            message: "Server is in maintenance mode; try again shortly".to_string(),
+        });
+    }
+    Ok(match message {
+        ServerMessage::Batch { .. }
+        | ServerMessage::DownloadRaw { .. }
+        | ServerMessage::UploadRaw { .. } => ClientMessage::Error {
+            message: format!("{} is not supported over WebSocket", message.kind()),
+        },
+        ServerMessage::Download { filename } if shared.downloads.shadow_verify => {
+            match shadow_read(&filename, shared).await? {
+                Ok((_, data)) => ClientMessage::Success { data },
+                Err(refusal) => refusal,
+            }
+        }
+        ServerMessage::GetStats => stats(shared).await,
+        message => handle_message(message, shared).await,
+    })
+}
+
+fn websocket_error(err: tungstenite::Error) -> ProtocolError {
+    match err {
+        tungstenite::Error::Io(err) => ProtocolError::Io(err),
+        tungstenite::Error::Capacity(CapacityError::MessageTooLong { size, .. }) => {
+            ProtocolError::FrameTooLarge(size as u64)
+        }
+        err => ProtocolError::Io(std::io::Error::other(err)),
+    }
+}
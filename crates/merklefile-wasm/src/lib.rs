@@ -0,0 +1,165 @@
+//! Browser bindings for merklefile-core's proof verifier and a WebSocket client, built with
+//! `wasm-pack`.
+//!
+//! The page downloads a file and its Merkle proof from a server started with a WebSocket
+//! listener, and checks them locally against a root it already trusts:
+//!
+//! ```js
+//! import init, { downloadVerified } from "./pkg/merklefile_wasm.js";
+//!
+//! await init();
+//! const file = await downloadVerified("ws://files.internal:8081", "a.txt", trustedRootHex);
+//! status.textContent = `Verified against root ${trustedRootHex}`;
+//! ```
+//!
+//! Files fetched some other way can be checked with a `Verifier`:
+//!
+//! ```js
+//! const verifier = new Verifier(trustedRootHex);
+//! if (verifier.verify(fileBytes, proofJson)) {
+//!     status.textContent = `Verified against root ${verifier.root}`;
+//! }
+//! ```
+//!
+//! `proofJson` is a serialized `MerkleProof`, or the proof exactly as the server encodes it:
+//! a JSON array of `[sibling_hash_bytes, is_left]` pairs.
+
+use js_sys::{Function, Promise, Uint8Array};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{BinaryType, MessageEvent, WebSocket};
+
+use merklefile_core::merkle_tree::MerkleProof;
+use merklefile_proto::{self as protocol, ClientMessage, ServerMessage};
+
+fn parse_root(root_hex: &str) -> Result<Vec<u8>, JsError> {
+    hex::decode(root_hex).map_err(|err| JsError::new(&format!("invalid root hash: {}", err)))
+}
+
+fn parse_proof(proof_json: &str) -> Result<MerkleProof, JsError> {
+    serde_json::from_str(proof_json).map_err(|err| JsError::new(&format!("invalid proof: {}", err)))
+}
+
+/// Checks downloaded files against one trusted root.
+#[wasm_bindgen]
+pub struct Verifier {
+    root: Vec<u8>,
+}
+
+#[wasm_bindgen]
+impl Verifier {
+    #[wasm_bindgen(constructor)]
+    pub fn new(root_hex: &str) -> Result<Verifier, JsError> {
+        Ok(Self {
+            root: parse_root(root_hex)?,
+        })
+    }
+
+    /// The hex-encoded root every file is checked against, for display.
+    #[wasm_bindgen(getter)]
+    pub fn root(&self) -> String {
+        hex::encode(&self.root)
+    }
+
+    /// Whether `proof_json` links `data` to the trusted root.
+    pub fn verify(&self, data: &[u8], proof_json: &str) -> Result<bool, JsError> {
+        let proof = parse_proof(proof_json)?;
+        Ok(proof.verify(&self.root, data))
+    }
+}
+
+/// One-shot form of `Verifier::verify`.
+#[wasm_bindgen(js_name = verifyProof)]
+pub fn verify_proof(data: &[u8], proof_json: &str, root_hex: &str) -> Result<bool, JsError> {
+    Verifier::new(root_hex)?.verify(data, proof_json)
+}
+
+/// Downloads `filename` over WebSocket from the server at `url`, such as
+/// `ws://files.internal:8081`, with its proof, and resolves to the file's bytes only if the
+/// proof links them to `root_hex`. Nothing the server sends is trusted before that check.
+#[wasm_bindgen(js_name = downloadVerified)]
+pub async fn download_verified(
+    url: String,
+    filename: String,
+    root_hex: String,
+) -> Result<Vec<u8>, JsError> {
+    let root = parse_root(&root_hex)?;
+    let connection = Connection::open(&url).await?;
+    let data = match connection
+        .request(&ServerMessage::Download {
+            filename: filename.clone(),
+        })
+        .await?
+    {
+        ClientMessage::Success { data } => data,
+        ClientMessage::Error { message } => return Err(JsError::new(&message)),
+        _ => return Err(JsError::new("unexpected response from server")),
+    };
+    let proof = match connection
+        .request(&ServerMessage::GetMerkleProof { filename })
+        .await?
+    {
+        ClientMessage::MerkleProof {
+            proof,
+            tree_size: Some(tree_size),
+        } => MerkleProof::from_path(&proof, tree_size),
+        ClientMessage::MerkleProof { proof, .. } => MerkleProof::from_bare_path(&proof),
+        ClientMessage::Error { message } => return Err(JsError::new(&message)),
+        _ => return Err(JsError::new("unexpected response from server")),
+    };
+    match proof {
+        Some(proof) if proof.verify(&root, &data) => Ok(data.to_vec()),
+        Some(_) => Err(JsError::new("file does not match the trusted root")),
+        None => Err(JsError::new("server sent a malformed proof")),
+    }
+}
+
+/// A WebSocket to the server, carrying one request at a time; closed when dropped.
+struct Connection {
+    socket: WebSocket,
+}
+
+impl Connection {
+    async fn open(url: &str) -> Result<Self, JsError> {
+        let socket = WebSocket::new(url).map_err(|_| JsError::new("invalid server URL"))?;
+        socket.set_binary_type(BinaryType::Arraybuffer);
+        let opened = Promise::new(&mut |resolve: Function, reject: Function| {
+            socket.set_onopen(Some(&resolve));
+            socket.set_onerror(Some(&reject));
+        });
+        let connection = Connection { socket };
+        JsFuture::from(opened)
+            .await
+            .map_err(|_| JsError::new("could not connect to the server"))?;
+        Ok(connection)
+    }
+
+    /// Sends `message` and waits for the server's answer.
+    async fn request(&self, message: &ServerMessage) -> Result<ClientMessage, JsError> {
+        let frame = protocol::encode(message)?;
+        let answered = Promise::new(&mut |resolve: Function, reject: Function| {
+            self.socket.set_onmessage(Some(&resolve));
+            self.socket.set_onerror(Some(&reject));
+            self.socket.set_onclose(Some(&reject));
+        });
+        self.socket
+            .send_with_u8_array(&frame)
+            .map_err(|_| JsError::new("could not send to the server"))?;
+        let event: MessageEvent = JsFuture::from(answered)
+            .await
+            .map_err(|_| JsError::new("connection to the server closed"))?
+            .unchecked_into();
+        let payload = Uint8Array::new(&event.data()).to_vec();
+        Ok(protocol::decode(&payload)?)
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.socket.set_onopen(None);
+        self.socket.set_onmessage(None);
+        self.socket.set_onerror(None);
+        self.socket.set_onclose(None);
+        let _ = self.socket.close();
+    }
+}
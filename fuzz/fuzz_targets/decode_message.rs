@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use merklefile::protocol::{self, ClientMessage, ServerMessage};
+
+// Feeds arbitrary frame payloads to both sides' decoders; any panic is a finding
+fuzz_target!(|payload: &[u8]| {
+    let _ = protocol::decode::<ServerMessage>(payload);
+    if let Ok(response) = protocol::decode::<ClientMessage>(payload) {
+        let _ = response.check_proofs();
+    }
+});
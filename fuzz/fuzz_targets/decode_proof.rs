@@ -0,0 +1,14 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use merklefile::chunk;
+use merklefile::merkle_tree::MerkleTree;
+use merklefile::protocol;
+
+// Every proof that passes `decode_proof` must be safe to hand to the verifier
+fuzz_target!(|payload: &[u8]| {
+    if let Ok(proof) = protocol::decode_proof(payload) {
+        let _ = chunk::proof_index(&proof);
+        let _ = MerkleTree::verify_proof(&proof, &[0; protocol::HASH_LEN], payload);
+    }
+});
@@ -0,0 +1,26 @@
+//! Typed errors, so embedders can react to failures without parsing messages.
+//!
+//! Client functions keep returning `io::Result`, so network failures keep their
+//! `io::ErrorKind` and failover keeps working. The errors below travel inside those
+//! `io::Error`s and can be recovered by downcasting:
+//!
+//! ```no_run
+//! # async fn example(client: &merklefile::client::Client) {
+//! use merklefile::error::MerkleError;
+//!
+//! if let Err(err) = client.download_with_repair("a.txt", &[0; 32], &[]).await {
+//!     match err.get_ref().and_then(|err| err.downcast_ref::<MerkleError>()) {
+//!         Some(MerkleError::ChunkUnrepairable { index }) => eprintln!("chunk {} is lost", index),
+//!         _ => eprintln!("download failed: {}", err),
+//!     }
+//! }
+//! # }
+//! ```
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use merklefile_client::StorageError;
+pub use merklefile_core::error::{display_chain, MerkleError};
+#[cfg(not(target_arch = "wasm32"))]
+pub use merklefile_proto::ProtocolError;
+#[cfg(not(target_arch = "wasm32"))]
+pub use merklefile_server::ServerError;
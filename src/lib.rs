@@ -1,4 +1,13 @@
-// Declare the server and client modules
-pub mod client;
-pub mod merkle_tree;
-pub mod server;
+// Umbrella crate: re-exports the workspace crates under their original module paths
+#[cfg(not(target_arch = "wasm32"))]
+pub use merklefile_client as client;
+pub use merklefile_core::{append, chunk, journal, log, merkle_tree, operations, transcript};
+pub mod error;
+#[cfg(not(target_arch = "wasm32"))]
+pub use merklefile_monitor as monitor;
+#[cfg(not(target_arch = "wasm32"))]
+pub use merklefile_proto as protocol;
+#[cfg(feature = "python")]
+mod python;
+#[cfg(not(target_arch = "wasm32"))]
+pub use merklefile_server as server;
@@ -0,0 +1,1255 @@
+use clap::{Args, Parser, Subcommand};
+use merklefile::client::{
+    self, Client, ClientConfig, CollisionPolicy, HashCache, ManifestSigner, ManifestVerifier,
+    Repository, RepositoryKey, RootSource, SpecialFilePolicy, SymlinkPolicy, SyncState,
+};
+use merklefile::error::{display_chain, MerkleError, StorageError};
+use merklefile::journal;
+use merklefile::log::VerifyingKey;
+use merklefile::monitor::{
+    send_command, Alert, AlertConfig, Alerter, BaselineDb, ControlCommand, Daemon, LeafEncoding,
+    Monitor, MonitorConfig, ReportFormat, ScanOutcome, ScanPath, ScanRules,
+};
+use merklefile::operations;
+use merklefile::server;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+#[derive(Parser)]
+#[command(
+    name = "merklefile",
+    about = "Upload, download and verify files against a Merkle root"
+)]
+struct Cli {
+    /// TOML client configuration; the flags below override its values
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+    /// Server address
+    #[arg(long, global = true)]
+    server: Option<String>,
+    /// Server to fail over to, tried in the order given (repeatable)
+    #[arg(long = "failover", global = true)]
+    failover_addrs: Vec<String>,
+    /// Request timeout in seconds
+    #[arg(long, global = true)]
+    timeout: Option<u64>,
+    /// Chunk size in bytes for chunk-level operations
+    #[arg(long, global = true)]
+    chunk_size: Option<usize>,
+    /// Cap upload and download bandwidth, in bytes per second (suffixes K, M and G allowed)
+    #[arg(long, global = true, value_parser = parse_bytes)]
+    limit_rate: Option<u64>,
+    /// Re-hash every file instead of trusting unchanged size and mtime
+    #[arg(long, global = true)]
+    paranoid: bool,
+    /// Number of files hashed in parallel (defaults to one per core)
+    #[arg(long, global = true)]
+    jobs: Option<usize>,
+    /// Symbolic links in scanned directories: follow, target (hash the link's target path)
+    /// or skip
+    #[arg(long, global = true)]
+    symlinks: Option<SymlinkPolicy>,
+    /// FIFOs, sockets and device nodes in scanned directories: skip or error
+    #[arg(long, global = true)]
+    special_files: Option<SpecialFilePolicy>,
+    /// Uploads of files the server already stores with other contents: overwrite, reject or
+    /// autoversion (store as `<name>.~<n>~`); the server decides when not given
+    #[arg(long, global = true)]
+    on_collision: Option<CollisionPolicy>,
+    /// Write a signed transcript of every request and response digest of this run here,
+    /// for settling disputes over what the server returned
+    #[arg(long, global = true, requires = "transcript_key")]
+    transcript: Option<PathBuf>,
+    /// Key the transcript is signed with (see `log keygen`)
+    #[arg(long, global = true, requires = "transcript")]
+    transcript_key: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+impl Cli {
+    fn client_config(&self) -> io::Result<ClientConfig> {
+        let mut config = match &self.config {
+            Some(path) => ClientConfig::from_file(path)?,
+            None => ClientConfig::default(),
+        };
+        if let Some(server) = &self.server {
+            config.server_addr = server.clone();
+        }
+        if !self.failover_addrs.is_empty() {
+            config.failover_addrs = self.failover_addrs.clone();
+        }
+        if let Some(timeout) = self.timeout {
+            config.request_timeout_secs = Some(timeout);
+        }
+        if let Some(chunk_size) = self.chunk_size {
+            config.chunk_size = chunk_size;
+        }
+        if self.paranoid {
+            config.paranoid = true;
+        }
+        if let Some(jobs) = self.jobs {
+            config.hash_concurrency = Some(jobs.max(1));
+        }
+        if let Some(symlinks) = self.symlinks {
+            config.symlinks = symlinks;
+        }
+        if let Some(special_files) = self.special_files {
+            config.special_files = special_files;
+        }
+        if let Some(on_collision) = self.on_collision {
+            config.on_collision = Some(on_collision);
+        }
+        if let Some(rate) = self.limit_rate {
+            config.upload_rate_limit = Some(rate);
+            config.download_rate_limit = Some(rate);
+        }
+        Ok(config)
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the file server
+    Serve(ServeArgs),
+    /// Upload files and directories and print the resulting Merkle root
+    Upload {
+        /// Name to store standard input under, given as `-`
+        #[arg(long)]
+        name: Option<String>,
+        /// List what would be added or modified and print the root the upload would produce,
+        /// without storing anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Files and directories to upload, or `-` for standard input
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Print the Merkle root a directory would have once uploaded
+    Scan {
+        /// Hash cache database, to skip re-hashing files with unchanged size and mtime
+        #[arg(long)]
+        cache: Option<PathBuf>,
+        dir: PathBuf,
+    },
+    /// Upload the files in a directory that changed since the last sync
+    Sync {
+        /// Local sync state database
+        #[arg(long, default_value = "merklefile-state.db")]
+        state: PathBuf,
+        /// List what would be uploaded and which removed files forgotten, without contacting
+        /// the server or changing the state
+        #[arg(long)]
+        dry_run: bool,
+        dir: PathBuf,
+    },
+    /// Download a file and verify it against a trusted Merkle root
+    Download {
+        /// Hex-encoded root hash recorded at upload time
+        #[arg(long, conflicts_with = "root_url")]
+        root: Option<String>,
+        /// Fetch the trusted root from where it is published instead: https://... or
+        /// dns:<name> (defaults to the configured trusted_root_url)
+        #[arg(long)]
+        root_url: Option<RootSource>,
+        /// Where to write the verified file (defaults to the file name), or `-` for standard
+        /// output
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        filename: String,
+    },
+    /// Download a random sample of stored files and verify each against a trusted root
+    Audit {
+        /// How many files to check
+        #[arg(long, default_value_t = 10)]
+        sample: usize,
+        /// Hex-encoded root hash recorded at upload time
+        #[arg(long, conflicts_with = "root_url")]
+        root: Option<String>,
+        /// Fetch the trusted root from where it is published instead: https://... or
+        /// dns:<name> (defaults to the configured trusted_root_url)
+        #[arg(long)]
+        root_url: Option<RootSource>,
+    },
+    /// Print the server's sizes and counters, one `name value` per line
+    Stats,
+    /// Record and check integrity baselines of local files
+    Monitor {
+        #[command(subcommand)]
+        command: MonitorCommand,
+    },
+    /// Verify the signed tree heads of a server running as a transparency log
+    Log {
+        #[command(subcommand)]
+        command: LogCommand,
+    },
+    /// Export and verify GPG- or SSH-signed manifests of a root and the files it covers
+    Manifest {
+        #[command(subcommand)]
+        command: ManifestCommand,
+    },
+    /// Back files up to a local repository instead of a server, and restore them with proofs
+    Repo {
+        #[command(subcommand)]
+        command: RepoCommand,
+    },
+    /// Send a command to a running server's control socket (see `serve --control-socket`)
+    Admin {
+        /// The server's control socket
+        socket: PathBuf,
+        /// maintenance, resume or rebuild
+        command: server::AdminCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum ManifestCommand {
+    /// Write the manifest of a directory and sign it, leaving a detached signature next to it
+    Export {
+        /// Where to write the manifest
+        #[arg(short, long, default_value = "manifest.txt")]
+        output: PathBuf,
+        /// GPG key to sign with (defaults to gpg's default key)
+        #[arg(long, conflicts_with = "ssh_key")]
+        gpg_key: Option<String>,
+        /// Sign with this SSH private key instead of GPG
+        #[arg(long)]
+        ssh_key: Option<PathBuf>,
+        /// Hash cache database, to skip re-hashing files with unchanged size and mtime
+        #[arg(long)]
+        cache: Option<PathBuf>,
+        dir: PathBuf,
+    },
+    /// Verify a manifest's signature, print its root and optionally check a directory against it
+    Verify {
+        /// GPG keyring holding the signer's public key (defaults to the user's keyring)
+        #[arg(long, conflicts_with = "allowed_signers")]
+        keyring: Option<PathBuf>,
+        /// Full fingerprint of the GPG key the manifest must be signed with
+        #[arg(
+            long,
+            required_unless_present = "allowed_signers",
+            conflicts_with = "allowed_signers"
+        )]
+        gpg_fingerprint: Option<String>,
+        /// Verify an SSH signature against this allowed_signers file instead of GPG
+        #[arg(long, requires = "identity")]
+        allowed_signers: Option<PathBuf>,
+        /// Signer identity in the allowed_signers file
+        #[arg(long)]
+        identity: Option<String>,
+        /// Directory whose files must match the manifest
+        #[arg(long)]
+        dir: Option<PathBuf>,
+        manifest: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum RepoCommand {
+    /// Create an empty repository, in chunks of --chunk-size bytes
+    Init {
+        /// Encrypt the repository with a new key, written hex-encoded to this file
+        #[arg(long)]
+        new_key: Option<PathBuf>,
+        repo: PathBuf,
+    },
+    /// Store files and directories and print the repository's root
+    Upload {
+        /// Key of an encrypted repository
+        #[arg(long)]
+        key: Option<PathBuf>,
+        /// Name to store standard input under, given as `-`
+        #[arg(long)]
+        name: Option<String>,
+        repo: PathBuf,
+        /// Files and directories to store, or `-` for standard input
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Restore a file after verifying it against a root printed by an earlier upload
+    Download {
+        /// Key of an encrypted repository
+        #[arg(long)]
+        key: Option<PathBuf>,
+        /// Hex-encoded root hash recorded at upload time
+        #[arg(long)]
+        root: String,
+        /// Where to write the verified file (defaults to the file name), or `-` for standard
+        /// output
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        repo: PathBuf,
+        filename: String,
+    },
+    /// Read back every stored file and list those that are damaged
+    Check {
+        /// Key of an encrypted repository
+        #[arg(long)]
+        key: Option<PathBuf>,
+        repo: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum LogCommand {
+    /// Generate a tree head signing key and print its public key
+    Keygen {
+        /// Where to write the secret key
+        key: PathBuf,
+    },
+    /// Fetch the current tree head, check that it extends the last one seen, and print it
+    Head {
+        /// The newest verified tree head, updated on success
+        #[arg(long, default_value = "merklefile-head.json")]
+        state: PathBuf,
+    },
+    /// Check tree heads received from other clients or monitors against our own
+    Gossip {
+        #[arg(long, default_value = "merklefile-head.json")]
+        state: PathBuf,
+        /// Tree head files of other clients, as saved by `log head`
+        #[arg(required = true)]
+        heads: Vec<PathBuf>,
+    },
+    /// Check that a root journal written by `serve --journal` is one unbroken signed chain,
+    /// and print its latest entry
+    Journal {
+        /// Hex public key of the journal's signing key, as printed by `log keygen`
+        #[arg(long)]
+        public_key: String,
+        journal: PathBuf,
+    },
+    /// Replay an operation log written by `serve --operation-log` and check that the roots
+    /// it produces are the journal's, ending in the server's current root
+    Replay {
+        /// Hex public key of the journal's signing key
+        #[arg(long, requires = "journal")]
+        public_key: Option<String>,
+        /// Root journal written by `serve --journal` to check every root against
+        #[arg(long, requires = "public_key")]
+        journal: Option<PathBuf>,
+        /// Do not ask the server for its current root
+        #[arg(long)]
+        offline: bool,
+        operations: PathBuf,
+    },
+    /// Check a transcript written with --transcript and print its entries
+    Transcript {
+        /// Hex public key of the key the transcript was signed with
+        #[arg(long)]
+        public_key: String,
+        transcript: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum MonitorCommand {
+    /// Hash the given paths and store them as a named baseline
+    Init {
+        /// Baseline database
+        #[arg(long, default_value = "merklefile-monitor.db")]
+        db: PathBuf,
+        /// Baseline name; an existing baseline of this name is replaced
+        #[arg(long, default_value = "default")]
+        name: String,
+        /// Also upload the baseline root to the server
+        #[arg(long)]
+        push: bool,
+        /// Also hash permissions, ownership, mtime and symlink targets into each leaf
+        #[arg(long)]
+        metadata: bool,
+        #[command(flatten)]
+        rules: ScanRuleArgs,
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// Report files added, removed or modified since a stored baseline
+    Check {
+        #[arg(long, default_value = "merklefile-monitor.db")]
+        db: PathBuf,
+        #[arg(long, default_value = "default")]
+        name: String,
+        /// Report format: text, json or csv
+        #[arg(long, default_value = "text")]
+        format: ReportFormat,
+        #[command(flatten)]
+        rules: ScanRuleArgs,
+        /// Paths to scan (defaults to the paths and rules the baseline was recorded with)
+        paths: Vec<PathBuf>,
+    },
+    /// List the stored baselines
+    List {
+        #[arg(long, default_value = "merklefile-monitor.db")]
+        db: PathBuf,
+    },
+    /// Run scans unattended on the schedule in a monitor configuration file
+    Daemon {
+        /// Monitor configuration file: paths, baseline and schedule
+        #[arg(value_name = "MONITOR_CONFIG")]
+        monitor_config: PathBuf,
+    },
+    /// Send a command to a running daemon's control socket
+    Ctl {
+        /// The daemon's control_socket
+        socket: PathBuf,
+        /// status, scan-now, pause, resume or rotate-baseline
+        command: ControlCommand,
+    },
+}
+
+/// Scan rules applied to every path given on the command line.
+#[derive(Args)]
+struct ServeArgs {
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    addr: String,
+    /// Validate the tree against the stored files after every request (slow)
+    #[arg(long)]
+    self_check: bool,
+    /// Re-hash every file before sending it, and refuse to send any that no longer match
+    /// the tree (see also `shadow_verify` in the server configuration)
+    #[arg(long)]
+    shadow_verify: bool,
+    /// Alert configuration; self-check failures are sent to its webhook and email
+    #[arg(long)]
+    alerts: Option<PathBuf>,
+    /// Run as an append-only transparency log, signing tree heads with the key in this
+    /// file (see `log keygen`)
+    #[arg(long)]
+    log_key: Option<PathBuf>,
+    /// TOML server configuration, e.g. where to publish every new root
+    #[arg(long)]
+    server_config: Option<PathBuf>,
+    /// Keep stored files in this directory instead of in memory, so they survive restarts
+    #[arg(long)]
+    storage_dir: Option<PathBuf>,
+    /// Accept admin commands such as `rebuild` on a Unix socket at this path
+    #[arg(long)]
+    control_socket: Option<PathBuf>,
+    /// Also accept requests over WebSocket on this address, for browsers
+    #[arg(long)]
+    websocket: Option<String>,
+    /// Append every root to a hash-chained journal at this path, signed with --journal-key
+    #[arg(long, requires = "journal_key")]
+    journal: Option<PathBuf>,
+    /// Key the journal's entries are signed with (see `log keygen`)
+    #[arg(long, requires = "journal")]
+    journal_key: Option<PathBuf>,
+    /// Append every change to the files to an operation log at this path, for `log replay`
+    #[arg(long)]
+    operation_log: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct ScanRuleArgs {
+    /// Skip paths matching this glob, relative to the scanned path (repeatable)
+    #[arg(long)]
+    exclude: Vec<String>,
+    /// Directory levels to descend below each scanned path
+    #[arg(long)]
+    max_depth: Option<usize>,
+    /// Do not cross mount points
+    #[arg(long)]
+    one_file_system: bool,
+    /// Skip files larger than this (suffixes K, M and G allowed)
+    #[arg(long, value_parser = parse_bytes)]
+    max_file_size: Option<u64>,
+}
+
+impl ScanRuleArgs {
+    fn scan_paths(self, paths: Vec<PathBuf>) -> Vec<ScanPath> {
+        let rules = ScanRules {
+            exclude: self.exclude,
+            max_depth: self.max_depth,
+            one_file_system: self.one_file_system,
+            max_file_size: self.max_file_size,
+        };
+        paths
+            .into_iter()
+            .map(|path| ScanPath {
+                path,
+                rules: rules.clone(),
+            })
+            .collect()
+    }
+}
+
+/// Parses a byte count or rate such as `500K` or `10M`, curl style.
+fn parse_bytes(value: &str) -> Result<u64, String> {
+    let (digits, multiplier) = match value.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&value[..value.len() - 1], 1024),
+        Some('M') => (&value[..value.len() - 1], 1024 * 1024),
+        Some('G') => (&value[..value.len() - 1], 1024 * 1024 * 1024),
+        _ => (value, 1),
+    };
+    match digits.parse::<u64>() {
+        Ok(rate) if rate > 0 => rate
+            .checked_mul(multiplier)
+            .ok_or_else(|| "value is too large".to_string()),
+        _ => Err(format!("invalid byte count '{}'", value)),
+    }
+}
+
+/// Process exit codes, so wrapper scripts can tell tampering apart from transient failures.
+/// Exit code 2 is left to clap for usage errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Failure {
+    Other = 1,
+    Network = 3,
+    NotFound = 4,
+    ProofInvalid = 5,
+    RootMismatch = 6,
+    IntegrityViolation = 7,
+    LogInconsistent = 8,
+}
+
+impl From<io::Error> for Failure {
+    fn from(err: io::Error) -> Self {
+        eprintln!("Error: {}", display_chain(&err));
+        match err.kind() {
+            io::ErrorKind::NotFound => Failure::NotFound,
+            io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::NotConnected
+            | io::ErrorKind::AddrNotAvailable
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::HostUnreachable
+            | io::ErrorKind::NetworkUnreachable => Failure::Network,
+            _ => Failure::Other,
+        }
+    }
+}
+
+/// Tells a log caught forking, rolling back or presenting unsigned heads apart from other
+/// failures.
+fn log_failure(err: io::Error) -> Failure {
+    match err
+        .get_ref()
+        .and_then(|err| err.downcast_ref::<MerkleError>())
+    {
+        Some(
+            MerkleError::TreeHeadSignatureInvalid { .. }
+            | MerkleError::InconsistentTreeHeads { .. },
+        ) => {
+            eprintln!("Error: {}", display_chain(&err));
+            Failure::LogInconsistent
+        }
+        _ => Failure::from(err),
+    }
+}
+
+impl From<StorageError> for Failure {
+    fn from(err: StorageError) -> Self {
+        Failure::from(io::Error::from(err))
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let mut client = match cli.client_config() {
+        Ok(config) => Client::new(config),
+        Err(err) => {
+            eprintln!("Error: invalid configuration: {}", err);
+            return ExitCode::from(Failure::Other as u8);
+        }
+    };
+    let transcript = match (cli.transcript, cli.transcript_key) {
+        (Some(path), Some(key)) => match merklefile::log::read_signing_key(&key) {
+            Ok(key) => {
+                client = client.with_transcript();
+                Some((path, key, client.clone()))
+            }
+            Err(err) => {
+                eprintln!("Error: {}: {}", key.display(), err);
+                return ExitCode::from(Failure::Other as u8);
+            }
+        },
+        _ => None,
+    };
+    let result = match cli.command {
+        Command::Serve(args) => serve(args).await,
+        Command::Upload {
+            name,
+            dry_run,
+            files,
+        } => upload(&client, name, dry_run, files).await,
+        Command::Scan { cache, dir } => scan(&client, cache.as_deref(), &dir),
+        Command::Sync {
+            state,
+            dry_run,
+            dir,
+        } => sync(&client, &state, dry_run, &dir).await,
+        Command::Download {
+            root,
+            root_url,
+            output,
+            filename,
+        } => download(&client, root, root_url, output, &filename).await,
+        Command::Audit {
+            sample,
+            root,
+            root_url,
+        } => audit(&client, sample, root, root_url).await,
+        Command::Stats => stats(&client).await,
+        Command::Monitor { command } => monitor(client, command).await,
+        Command::Log { command } => log(&client, command).await,
+        Command::Manifest { command } => manifest(&client, command),
+        Command::Repo { command } => repo(&client, command).await,
+        Command::Admin { socket, command } => admin(&socket, command).await,
+    };
+
+    // Written even if the command failed, when the evidence matters most
+    if let Some((path, key, client)) = transcript {
+        let signed = client.transcript().unwrap_or_default().sign(&key);
+        if let Err(err) = client::save_transcript(&path, &signed) {
+            eprintln!("Error: {}: {}", path.display(), err);
+            return ExitCode::from(Failure::Other as u8);
+        }
+    }
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(failure) => ExitCode::from(failure as u8),
+    }
+}
+
+async fn serve(args: ServeArgs) -> Result<(), Failure> {
+    let mut server = server::Server::new().with_self_check(args.self_check);
+    if let Some(storage_dir) = &args.storage_dir {
+        server = server.with_storage_dir(storage_dir).await?;
+    }
+    let config = match &args.server_config {
+        Some(server_config) => server::ServerConfig::from_file(server_config)?,
+        None => server::ServerConfig::default(),
+    };
+    server = server.with_config(&config);
+    if args.shadow_verify {
+        server = server.with_shadow_verify(true);
+    }
+    if let Some(control_socket) = &args.control_socket {
+        server = server.with_control_socket(control_socket);
+    }
+    if let Some(websocket) = &args.websocket {
+        server = server.with_websocket(websocket);
+    }
+    if let Some(log_key) = &args.log_key {
+        server = server.with_transparency_log(merklefile::log::read_signing_key(log_key)?);
+    }
+    if let (Some(journal), Some(journal_key)) = (&args.journal, &args.journal_key) {
+        let key = merklefile::log::read_signing_key(journal_key)?;
+        let root_journal = match server::RootJournal::open(journal, key) {
+            Ok(root_journal) => root_journal.with_alarms(server.alarms().to_vec()),
+            Err(err) => {
+                if err.kind() == io::ErrorKind::InvalidData {
+                    let event = server::TamperEvent::new(
+                        server::TamperSource::Journal,
+                        vec![err.to_string()],
+                    );
+                    server::raise_all(server.alarms(), &event);
+                }
+                return Err(err.into());
+            }
+        };
+        server = server.with_publisher(Arc::new(root_journal));
+    }
+    if let Some(operation_log) = &args.operation_log {
+        server = server.with_operation_log(operation_log);
+    }
+    if let Some(alerts) = &args.alerts {
+        let alerter = Arc::new(Alerter::new(AlertConfig::from_file(alerts)?));
+        let source = args.addr.clone();
+        server = server.with_violation_handler(Arc::new(move |violations| {
+            let (alerter, alert) = (
+                Arc::clone(&alerter),
+                Alert::self_check_failed(&source, &violations),
+            );
+            tokio::spawn(async move {
+                if let Err(err) = alerter.send(alert).await {
+                    eprintln!("Alert failed: {}", err);
+                }
+            });
+        }));
+    }
+    server.start(&args.addr).await.map_err(|err| {
+        eprintln!("Error: {}", display_chain(&err));
+        Failure::Other
+    })
+}
+
+async fn stats(client: &Client) -> Result<(), Failure> {
+    let stats = client.stats().await?;
+    println!("files {}", stats.files);
+    println!("total_bytes {}", stats.total_bytes);
+    println!("tree_depth {}", stats.tree_depth);
+    if let Some(last_rebuild_ms) = stats.last_rebuild_ms {
+        println!("last_rebuild_ms {}", last_rebuild_ms);
+    }
+    for (kind, count) in &stats.requests {
+        println!("requests.{} {}", kind, count);
+    }
+    for (source, count) in &stats.verification_failures {
+        println!("verification_failures.{} {}", source, count);
+    }
+    println!("uptime_secs {}", stats.uptime_secs);
+    println!("hash_backend {}", stats.hash_backend);
+    Ok(())
+}
+
+async fn admin(socket: &Path, command: server::AdminCommand) -> Result<(), Failure> {
+    let answer = server::send_command(socket, command).await?;
+    println!("{}", answer);
+    if answer.starts_with("error:") {
+        return Err(Failure::Other);
+    }
+    // The report is compact JSON, so a clean rebuild has exactly this empty list
+    if command == server::AdminCommand::Rebuild && !answer.contains("\"discrepancies\":[]") {
+        return Err(Failure::IntegrityViolation);
+    }
+    Ok(())
+}
+
+async fn upload(
+    client: &Client,
+    stdin_name: Option<String>,
+    dry_run: bool,
+    paths: Vec<PathBuf>,
+) -> Result<(), Failure> {
+    let files = read_files(client, stdin_name, paths).await?;
+    if dry_run {
+        let plan = client.upload_files_dry_run(files).await?;
+        for change in &plan.changes {
+            println!("{:?} {}", change.kind, change.filename);
+        }
+        println!("{}", hex::encode(plan.root));
+        return Ok(());
+    }
+    // The root the upload produced, not whatever the server holds by the time we ask
+    let root = client.upload_files(files).await?;
+    println!("{}", hex::encode(root));
+    Ok(())
+}
+
+/// Reads the files and directories given to an upload, keyed as they will be stored.
+async fn read_files(
+    client: &Client,
+    stdin_name: Option<String>,
+    paths: Vec<PathBuf>,
+) -> Result<BTreeMap<String, Vec<u8>>, Failure> {
+    let mut files = BTreeMap::new();
+    let mut stdin_name = stdin_name;
+    for path in paths {
+        if path == Path::new("-") {
+            // Standard input can only be read once, so a second `-` finds no name left
+            let Some(name) = stdin_name.take() else {
+                eprintln!("Error: standard input needs a single --name to be stored under");
+                return Err(Failure::Other);
+            };
+            let mut data = Vec::new();
+            tokio::io::stdin().read_to_end(&mut data).await?;
+            files.insert(client.config().normalize_key(&name), data);
+            continue;
+        }
+        if path.is_dir() {
+            files.extend(client.read_dir(&path)?);
+            continue;
+        }
+        let name = match path.file_name() {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => {
+                eprintln!("Error: {} is not a file", path.display());
+                return Err(Failure::Other);
+            }
+        };
+        files.insert(
+            client.config().normalize_key(&name),
+            tokio::fs::read(&path).await?,
+        );
+    }
+    if stdin_name.is_some() {
+        eprintln!("Error: --name names standard input, but no `-` was given");
+        return Err(Failure::Other);
+    }
+    Ok(files)
+}
+
+fn scan(client: &Client, cache: Option<&Path>, dir: &Path) -> Result<(), Failure> {
+    let cache = cache.map(HashCache::open).transpose()?;
+    let root = client.dir_root_hash(dir, cache.as_ref())?;
+    println!("{}", hex::encode(root));
+    Ok(())
+}
+
+async fn sync(client: &Client, state: &Path, dry_run: bool, dir: &Path) -> Result<(), Failure> {
+    let state = SyncState::open(state)?;
+    if dry_run {
+        let report = client.sync_dir_dry_run(dir, &state).await?;
+        for name in &report.uploaded {
+            println!("upload {}", name);
+        }
+        for name in &report.removed {
+            println!("removed {}", name);
+        }
+        return Ok(());
+    }
+    let report = client.sync_dir(dir, &state).await?;
+    if let Some(root) = report.root {
+        println!("{}", hex::encode(root));
+    }
+    Ok(())
+}
+
+async fn download(
+    client: &Client,
+    root: Option<String>,
+    root_url: Option<RootSource>,
+    output: Option<PathBuf>,
+    filename: &str,
+) -> Result<(), Failure> {
+    let trusted_root = trusted_root(client, root, root_url).await?;
+    let data = client.download_file(filename).await?;
+    let proof = client.get_merkle_proof(filename).await?;
+
+    if !client::verify_merkle_proof(&proof, &trusted_root, &data) {
+        // Tell apart a server that now attests a different file set from data that matches nothing
+        let server_root = client.get_root_hash().await?;
+        if server_root != trusted_root && client::verify_merkle_proof(&proof, &server_root, &data) {
+            eprintln!(
+                "Error: server root {} does not match trusted root {}",
+                hex::encode(server_root),
+                hex::encode(&trusted_root)
+            );
+            return Err(Failure::RootMismatch);
+        }
+        eprintln!("Error: Merkle proof for {} is invalid", filename);
+        return Err(Failure::ProofInvalid);
+    }
+
+    write_output(output, filename, &data).await
+}
+
+/// Writes a verified file to `output`, standard output if it is `-`, or else a file named
+/// after it.
+async fn write_output(output: Option<PathBuf>, filename: &str, data: &[u8]) -> Result<(), Failure> {
+    match output {
+        Some(output) if output == Path::new("-") => {
+            let mut stdout = tokio::io::stdout();
+            stdout.write_all(data).await?;
+            stdout.flush().await?;
+        }
+        output => tokio::fs::write(output.unwrap_or_else(|| PathBuf::from(filename)), data).await?,
+    }
+    Ok(())
+}
+
+async fn audit(
+    client: &Client,
+    sample: usize,
+    root: Option<String>,
+    root_url: Option<RootSource>,
+) -> Result<(), Failure> {
+    let trusted_root = trusted_root(client, root, root_url).await?;
+    let report = client.audit(&trusted_root, sample).await?;
+    print!("{}", report);
+    if report.is_clean() {
+        Ok(())
+    } else {
+        Err(Failure::ProofInvalid)
+    }
+}
+
+/// The root downloads are verified against: given on the command line, fetched from where
+/// it is published, or the configured `trusted_root_url`.
+async fn trusted_root(
+    client: &Client,
+    root: Option<String>,
+    root_url: Option<RootSource>,
+) -> Result<Vec<u8>, Failure> {
+    match (root, root_url) {
+        (Some(root), _) => hex::decode(root).map_err(|err| {
+            eprintln!("Error: invalid root hash: {}", err);
+            Failure::Other
+        }),
+        (None, Some(source)) => Ok(source.fetch(client.config().request_timeout()).await?),
+        (None, None) => match client.published_root().await? {
+            Some(root) => Ok(root),
+            None => {
+                eprintln!(
+                    "Error: no trusted root; pass --root or --root-url, or set trusted_root_url"
+                );
+                Err(Failure::Other)
+            }
+        },
+    }
+}
+
+async fn monitor(client: Client, command: MonitorCommand) -> Result<(), Failure> {
+    match command {
+        MonitorCommand::Init {
+            db,
+            name,
+            push,
+            metadata,
+            rules,
+            paths,
+        } => {
+            let mut db = BaselineDb::open(db)?;
+            let encoding = if metadata {
+                LeafEncoding::Metadata
+            } else {
+                LeafEncoding::Content
+            };
+            let monitor = Monitor::new(client, rules.scan_paths(paths)).with_encoding(encoding);
+            let baseline = monitor.scan()?;
+            db.save(&name, &baseline)?;
+            println!("{}", hex::encode(&baseline.root));
+            if push {
+                let server_root = monitor.push(&baseline, &name).await?;
+                println!(
+                    "pushed as monitor/{}.root, server root {}",
+                    name,
+                    hex::encode(server_root)
+                );
+            }
+            Ok(())
+        }
+        MonitorCommand::Check {
+            db,
+            name,
+            format,
+            rules,
+            paths,
+        } => {
+            let Some(baseline) = BaselineDb::open(db)?.load(&name)? else {
+                eprintln!("Error: no baseline named {}", name);
+                return Err(Failure::NotFound);
+            };
+            let paths = if paths.is_empty() {
+                baseline.paths.clone()
+            } else {
+                rules.scan_paths(paths)
+            };
+            let report = Monitor::new(client, paths).check(&baseline)?;
+            println!("{}", report.render(format).trim_end());
+            if report.is_clean() {
+                Ok(())
+            } else {
+                Err(Failure::IntegrityViolation)
+            }
+        }
+        MonitorCommand::List { db } => {
+            for info in BaselineDb::open(db)?.list()? {
+                println!(
+                    "{}\t{}\t{} files\tcreated {}",
+                    info.name,
+                    hex::encode(&info.root),
+                    info.file_count,
+                    info.created_at
+                );
+            }
+            Ok(())
+        }
+        MonitorCommand::Daemon { monitor_config } => {
+            let config = MonitorConfig::from_file(monitor_config)?;
+            let daemon = Daemon::new(client, config)?;
+            let format = daemon.config().format;
+            daemon
+                .run(|outcome| match outcome {
+                    ScanOutcome::Recorded(root) => {
+                        println!("recorded baseline {}", hex::encode(root))
+                    }
+                    ScanOutcome::Checked(report)
+                        if report.is_clean() && format == ReportFormat::Text =>
+                    {
+                        println!("no changes, root {}", hex::encode(&report.new_root))
+                    }
+                    ScanOutcome::Checked(report) => {
+                        println!("{}", report.render(format).trim_end())
+                    }
+                    ScanOutcome::Skipped => println!("previous scan still running, skipped"),
+                })
+                .await?;
+            Ok(())
+        }
+        MonitorCommand::Ctl { socket, command } => {
+            let answer = send_command(socket, command).await?;
+            println!("{}", answer);
+            if answer.starts_with("error:") {
+                Err(Failure::Other)
+            } else {
+                Ok(())
+            }
+        }
+    }
+}
+
+async fn log(client: &Client, command: LogCommand) -> Result<(), Failure> {
+    match command {
+        LogCommand::Keygen { key } => {
+            let signing_key = server::generate_key();
+            write_secret(&key, hex::encode(signing_key.to_bytes()).as_bytes())?;
+            println!("{}", hex::encode(signing_key.verifying_key().to_bytes()));
+            Ok(())
+        }
+        LogCommand::Head { state } => {
+            let trusted = client::load_tree_head(&state)?;
+            let head = client
+                .tree_head(trusted.as_ref())
+                .await
+                .map_err(log_failure)?;
+            client::save_tree_head(&state, &head)?;
+            println!(
+                "size {}\troot {}\ttimestamp {}",
+                head.size,
+                hex::encode(&head.root),
+                head.timestamp
+            );
+            Ok(())
+        }
+        LogCommand::Gossip { state, heads } => {
+            let trusted = client::load_tree_head(&state)?;
+            let ours = client
+                .tree_head(trusted.as_ref())
+                .await
+                .map_err(log_failure)?;
+            client::save_tree_head(&state, &ours)?;
+            for path in heads {
+                let Some(theirs) = client::load_tree_head(&path)? else {
+                    eprintln!("Error: {} does not exist", path.display());
+                    return Err(Failure::NotFound);
+                };
+                client
+                    .check_gossiped_head(&ours, &theirs)
+                    .await
+                    .map_err(log_failure)?;
+                println!("{}: consistent at size {}", path.display(), theirs.size);
+            }
+            Ok(())
+        }
+        LogCommand::Journal {
+            public_key,
+            journal,
+        } => {
+            let key = parse_public_key(&public_key)?;
+            let text = std::fs::read_to_string(&journal)?;
+            let entries = journal::verify_journal(&text, &key).map_err(|err| {
+                eprintln!("Error: {}: {}", journal.display(), err);
+                Failure::LogInconsistent
+            })?;
+            match entries.last() {
+                Some(entry) => println!(
+                    "size {}\troot {}\ttimestamp {}\tentries {}",
+                    entry.size,
+                    hex::encode(&entry.root),
+                    entry.timestamp,
+                    entries.len()
+                ),
+                None => println!("empty"),
+            }
+            Ok(())
+        }
+        LogCommand::Replay {
+            public_key,
+            journal,
+            offline,
+            operations,
+        } => {
+            let text = std::fs::read_to_string(&operations)?;
+            let unexplained = |err| {
+                eprintln!("Error: {}: {}", operations.display(), err);
+                Failure::LogInconsistent
+            };
+            let replay = operations::replay(&text).map_err(unexplained)?;
+            if let (Some(public_key), Some(journal)) = (public_key, journal) {
+                let key = parse_public_key(&public_key)?;
+                let text = std::fs::read_to_string(&journal)?;
+                let entries = journal::verify_journal(&text, &key).map_err(|err| {
+                    eprintln!("Error: {}: {}", journal.display(), err);
+                    Failure::LogInconsistent
+                })?;
+                replay.check_journal(&entries).map_err(unexplained)?;
+            }
+            if !offline {
+                let root = client.get_root_hash().await?;
+                replay.check_current(&root).map_err(unexplained)?;
+            }
+            println!(
+                "size {}\troot {}\tuploads {}",
+                replay.files.len(),
+                hex::encode(replay.root()),
+                replay.roots.len()
+            );
+            Ok(())
+        }
+        LogCommand::Transcript {
+            public_key,
+            transcript,
+        } => {
+            let key = parse_public_key(&public_key)?;
+            let signed = client::load_transcript(&transcript).map_err(|err| {
+                eprintln!("Error: {}: {}", transcript.display(), err);
+                Failure::Other
+            })?;
+            signed.verify(&key).map_err(|err| {
+                eprintln!("Error: {}: {}", transcript.display(), err);
+                Failure::LogInconsistent
+            })?;
+            for entry in &signed.entries {
+                println!(
+                    "{}\t{}\trequest {}\tresponse {}\troot {}",
+                    entry.timestamp,
+                    entry.server,
+                    hex::encode(&entry.request),
+                    hex::encode(&entry.response),
+                    entry.root.as_ref().map_or("-".to_string(), hex::encode)
+                );
+            }
+            Ok(())
+        }
+    }
+}
+
+fn parse_public_key(public_key: &str) -> Result<VerifyingKey, Failure> {
+    hex::decode(public_key)
+        .ok()
+        .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+        .and_then(|bytes| VerifyingKey::from_bytes(&bytes).ok())
+        .ok_or_else(|| {
+            eprintln!("Error: --public-key is not a hex Ed25519 public key");
+            Failure::Other
+        })
+}
+
+fn manifest(client: &Client, command: ManifestCommand) -> Result<(), Failure> {
+    match command {
+        ManifestCommand::Export {
+            output,
+            gpg_key,
+            ssh_key,
+            cache,
+            dir,
+        } => {
+            let signer = match ssh_key {
+                Some(key) => ManifestSigner::Ssh { key },
+                None => ManifestSigner::Gpg { key: gpg_key },
+            };
+            let cache = cache.map(HashCache::open).transpose()?;
+            let manifest = client.manifest(&dir, cache.as_ref())?;
+            let signature = client::export_manifest(&manifest, &output, &signer)?;
+            eprintln!("Signed {} in {}", output.display(), signature.display());
+            println!("{}", hex::encode(&manifest.root));
+            Ok(())
+        }
+        ManifestCommand::Verify {
+            keyring,
+            gpg_fingerprint,
+            allowed_signers,
+            identity,
+            dir,
+            manifest,
+        } => {
+            let verifier = match (allowed_signers, identity) {
+                (Some(allowed_signers), Some(identity)) => ManifestVerifier::Ssh {
+                    allowed_signers,
+                    identity,
+                },
+                _ => ManifestVerifier::Gpg {
+                    keyring,
+                    fingerprint: gpg_fingerprint.unwrap_or_default(),
+                },
+            };
+            let manifest = client::import_manifest(&manifest, &verifier).map_err(|err| {
+                eprintln!("Error: {}", err);
+                Failure::ProofInvalid
+            })?;
+            if let Some(dir) = dir {
+                let differences = manifest.differences(&client.hash_dir(&dir, None)?);
+                if !differences.is_empty() {
+                    for difference in differences {
+                        eprintln!("{}", difference);
+                    }
+                    return Err(Failure::ProofInvalid);
+                }
+            }
+            println!("{}", hex::encode(&manifest.root));
+            Ok(())
+        }
+    }
+}
+
+/// Writes a file only its owner can read.
+async fn repo(client: &Client, command: RepoCommand) -> Result<(), Failure> {
+    let open = |repo: PathBuf, key: Option<PathBuf>| async move {
+        let key = key.map(RepositoryKey::read).transpose()?;
+        Repository::open(repo, key).await
+    };
+    match command {
+        RepoCommand::Init { new_key, repo } => {
+            let key = match new_key {
+                Some(path) => {
+                    let key = RepositoryKey::generate();
+                    write_secret(&path, hex::encode(key.to_bytes()).as_bytes())?;
+                    Some(key)
+                }
+                None => None,
+            };
+            Repository::init(&repo, client.config().chunk_size, key).await?;
+            Ok(())
+        }
+        RepoCommand::Upload {
+            key,
+            name,
+            repo,
+            files,
+        } => {
+            let repo = open(repo, key).await?;
+            repo.upload_files(read_files(client, name, files).await?)
+                .await?;
+            println!("{}", hex::encode(repo.get_root_hash().await?));
+            Ok(())
+        }
+        RepoCommand::Download {
+            key,
+            root,
+            output,
+            repo,
+            filename,
+        } => {
+            let trusted_root = trusted_root(client, Some(root), None).await?;
+            let repo = open(repo, key).await?;
+            let data = repo.download_file(&filename).await?;
+            let proof = repo.get_merkle_proof(&filename).await?;
+            if !client::verify_merkle_proof(&proof, &trusted_root, &data) {
+                eprintln!("Error: Merkle proof for {} is invalid", filename);
+                return Err(Failure::ProofInvalid);
+            }
+            write_output(output, &filename, &data).await
+        }
+        RepoCommand::Check { key, repo } => {
+            let damaged = open(repo, key).await?.check().await?;
+            for filename in &damaged {
+                eprintln!("{} is damaged", filename);
+            }
+            if damaged.is_empty() {
+                Ok(())
+            } else {
+                Err(Failure::IntegrityViolation)
+            }
+        }
+    }
+}
+
+fn write_secret(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    io::Write::write_all(&mut options.open(path)?, contents)
+}
@@ -0,0 +1,168 @@
+//! Python bindings, enabled with the `python` feature.
+//!
+//! ```python
+//! import merklefile
+//!
+//! tree = merklefile.MerkleTree([b"a", b"b", b"c"])
+//! assert merklefile.verify_proof(tree.proof(1), tree.root(), b"b")
+//!
+//! client = merklefile.Client("127.0.0.1:8080")
+//! root = client.upload({"notes.txt": b"hello"})
+//! data = client.download_verified("notes.txt", root)
+//! ```
+
+// The `#[pymethods]` expansion converts `PyErr` into itself
+#![allow(clippy::useless_conversion)]
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use std::collections::BTreeMap;
+use tokio::runtime::Runtime;
+
+use crate::client::{self, ClientConfig};
+use crate::merkle_tree;
+
+type Proof = Vec<(Vec<u8>, bool)>;
+
+/// Converts a proof to `[(bytes, bool)]` rather than lists of ints.
+fn proof_to_py<'py>(py: Python<'py>, proof: Proof) -> Vec<(Bound<'py, PyBytes>, bool)> {
+    proof
+        .into_iter()
+        .map(|(hash, is_left)| (PyBytes::new_bound(py, &hash), is_left))
+        .collect()
+}
+
+#[pyclass(name = "MerkleTree", module = "merklefile")]
+struct MerkleTree {
+    inner: merkle_tree::MerkleTree,
+    leaf_count: usize,
+}
+
+#[pymethods]
+impl MerkleTree {
+    #[new]
+    fn new(leaves: Vec<Vec<u8>>) -> PyResult<Self> {
+        if leaves.is_empty() {
+            return Err(PyValueError::new_err(
+                "a Merkle tree needs at least one leaf",
+            ));
+        }
+        Ok(Self {
+            leaf_count: leaves.len(),
+            inner: merkle_tree::MerkleTree::new(leaves),
+        })
+    }
+
+    fn root<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new_bound(py, &self.inner.get_root_hash())
+    }
+
+    /// Proof for the leaf at `index`, as a list of `(sibling_hash, is_left)` pairs.
+    fn proof<'py>(
+        &self,
+        py: Python<'py>,
+        index: usize,
+    ) -> PyResult<Vec<(Bound<'py, PyBytes>, bool)>> {
+        if index >= self.leaf_count {
+            return Err(PyValueError::new_err("leaf index out of range"));
+        }
+        Ok(proof_to_py(py, self.inner.get_proof_for(index)))
+    }
+
+    fn __len__(&self) -> usize {
+        self.leaf_count
+    }
+}
+
+/// Whether `proof` links the raw `leaf` data to `root`.
+#[pyfunction]
+fn verify_proof(proof: Proof, root: Vec<u8>, leaf: Vec<u8>) -> bool {
+    merkle_tree::MerkleTree::verify_proof(&proof, &root, &leaf)
+}
+
+/// Blocking client; each call runs to completion on a private Tokio runtime.
+#[pyclass(name = "Client", module = "merklefile")]
+struct Client {
+    inner: client::Client,
+    runtime: Runtime,
+}
+
+#[pymethods]
+impl Client {
+    #[new]
+    #[pyo3(signature = (server_addr = "127.0.0.1:8080", config_path = None))]
+    fn new(server_addr: &str, config_path: Option<&str>) -> PyResult<Self> {
+        let config = match config_path {
+            Some(path) => ClientConfig::from_file(path)?,
+            None => ClientConfig::new(server_addr),
+        };
+        Ok(Self {
+            inner: client::Client::new(config),
+            runtime: Runtime::new()?,
+        })
+    }
+
+    /// Uploads `{filename: bytes}` and returns the server's new root hash.
+    fn upload<'py>(
+        &self,
+        py: Python<'py>,
+        files: BTreeMap<String, Vec<u8>>,
+    ) -> PyResult<Bound<'py, PyBytes>> {
+        let root = py.allow_threads(|| self.runtime.block_on(self.inner.upload_files(files)))?;
+        Ok(PyBytes::new_bound(py, &root))
+    }
+
+    /// Downloads a file without verifying it.
+    fn download<'py>(&self, py: Python<'py>, filename: &str) -> PyResult<Bound<'py, PyBytes>> {
+        let data =
+            py.allow_threads(|| self.runtime.block_on(self.inner.download_file(filename)))?;
+        Ok(PyBytes::new_bound(py, &data))
+    }
+
+    /// Downloads a file and raises `ValueError` unless its proof matches the trusted `root`.
+    fn download_verified<'py>(
+        &self,
+        py: Python<'py>,
+        filename: &str,
+        root: Vec<u8>,
+    ) -> PyResult<Bound<'py, PyBytes>> {
+        let (data, proof) = py.allow_threads(|| {
+            self.runtime.block_on(async {
+                let data = self.inner.download_file(filename).await?;
+                let proof = self.inner.get_merkle_proof(filename).await?;
+                Ok::<_, std::io::Error>((data, proof))
+            })
+        })?;
+        if !proof.verify(&root, &data) {
+            return Err(PyValueError::new_err(format!(
+                "Merkle proof for {} is invalid",
+                filename
+            )));
+        }
+        Ok(PyBytes::new_bound(py, &data))
+    }
+
+    fn proof<'py>(
+        &self,
+        py: Python<'py>,
+        filename: &str,
+    ) -> PyResult<Vec<(Bound<'py, PyBytes>, bool)>> {
+        let proof =
+            py.allow_threads(|| self.runtime.block_on(self.inner.get_merkle_proof(filename)))?;
+        Ok(proof_to_py(py, proof.path()))
+    }
+
+    fn root_hash<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyBytes>> {
+        let root = py.allow_threads(|| self.runtime.block_on(self.inner.get_root_hash()))?;
+        Ok(PyBytes::new_bound(py, &root))
+    }
+}
+
+#[pymodule]
+fn merklefile(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<MerkleTree>()?;
+    m.add_class::<Client>()?;
+    m.add_function(wrap_pyfunction!(verify_proof, m)?)?;
+    Ok(())
+}
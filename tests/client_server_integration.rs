@@ -7,7 +7,7 @@ async fn test_client_server_interaction() {
     let server_addr = "127.0.0.1:8080";
     let server_instance = server::new_server(); // Created a new instance of server
     tokio::spawn(async move {
-        server_instance.start(server_addr).await; // used the instance to call start()
+        server_instance.start(server_addr).await.unwrap(); // used the instance to call start()
     });
 
     // Give server time to start
@@ -53,3 +53,1784 @@ async fn test_client_server_interaction() {
         "Downloaded data does not match original"
     );
 }
+
+#[tokio::test]
+async fn test_upload_dry_run_does_not_mutate() {
+    let server_addr = "127.0.0.1:8081";
+    let server_instance = server::new_server();
+    tokio::spawn(async move {
+        server_instance.start(server_addr).await.unwrap();
+    });
+
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let mut files = BTreeMap::<String, Vec<u8>>::new();
+    files.insert("a.txt".to_string(), b"first".to_vec());
+    files.insert("b.txt".to_string(), b"second".to_vec());
+    client::upload_files(files.clone(), server_addr)
+        .await
+        .unwrap();
+
+    let mut changed = BTreeMap::<String, Vec<u8>>::new();
+    changed.insert("a.txt".to_string(), b"first".to_vec());
+    changed.insert("b.txt".to_string(), b"second, edited".to_vec());
+    changed.insert("c.txt".to_string(), b"third".to_vec());
+
+    let plan = client::upload_files_dry_run(changed.clone(), server_addr)
+        .await
+        .unwrap();
+    let kinds: Vec<_> = plan.changes.iter().map(|c| c.kind.clone()).collect();
+    assert_eq!(
+        kinds,
+        vec![
+            client::ChangeKind::Unchanged,
+            client::ChangeKind::Modified,
+            client::ChangeKind::Added
+        ]
+    );
+    assert_eq!(
+        plan.root,
+        client::compute_merkle_root_hash(changed.values().cloned().collect())
+    );
+
+    // Nothing was written by the dry run
+    let download = client::download_file("b.txt", server_addr).await.unwrap();
+    assert_eq!(download, b"second".to_vec());
+    assert!(client::download_file("c.txt", server_addr).await.is_err());
+}
+
+#[tokio::test]
+async fn test_upload_dry_runs_match_what_the_upload_would_do() {
+    use merklefile::log::SigningKey;
+
+    let fresh_addr = "127.0.0.1:8129";
+    let fresh = server::new_server();
+    tokio::spawn(async move {
+        fresh.start(fresh_addr).await.unwrap();
+    });
+    let log_addr = "127.0.0.1:8130";
+    let log = server::Server::new().with_transparency_log(SigningKey::from_bytes(&[5; 32]));
+    tokio::spawn(async move {
+        log.start(log_addr).await.unwrap();
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    // Nothing to upload to an empty server: the plan is empty and the root unchanged
+    let plan = client::upload_files_dry_run(BTreeMap::new(), fresh_addr)
+        .await
+        .unwrap();
+    assert!(plan.changes.is_empty());
+    assert_eq!(plan.root, client::get_root_hash(fresh_addr).await.unwrap());
+
+    // An append-only log refuses to replace a file, in a dry run too
+    let files = BTreeMap::from([("a.txt".to_string(), b"first".to_vec())]);
+    client::upload_files(files, log_addr).await.unwrap();
+    let edited = BTreeMap::from([("a.txt".to_string(), b"edited".to_vec())]);
+    let err = client::upload_files_dry_run(edited, log_addr)
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("append-only"), "{}", err);
+}
+
+#[tokio::test]
+async fn test_verified_read_range() {
+    let server_addr = "127.0.0.1:8082";
+    let server_instance = server::new_server();
+    tokio::spawn(async move {
+        server_instance.start(server_addr).await.unwrap();
+    });
+
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let contents: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+    let chunk_root = client::compute_chunk_root_hash(&contents);
+    let mut files = BTreeMap::<String, Vec<u8>>::new();
+    files.insert("big.bin".to_string(), contents.clone());
+    client::upload_files(files, server_addr).await.unwrap();
+
+    // A range spanning a chunk boundary
+    let slice = client::read_range("big.bin", 65_000, 1_000, &chunk_root, server_addr)
+        .await
+        .unwrap();
+    assert_eq!(slice, contents[65_000..66_000].to_vec());
+
+    // The tail of the file, inside the short last chunk
+    let slice = client::read_range("big.bin", 199_990, 10, &chunk_root, server_addr)
+        .await
+        .unwrap();
+    assert_eq!(slice, contents[199_990..].to_vec());
+
+    let wrong_root = client::compute_chunk_root_hash(b"something else");
+    assert!(
+        client::read_range("big.bin", 0, 10, &wrong_root, server_addr)
+            .await
+            .is_err(),
+        "Range read should fail against the wrong chunk root"
+    );
+    assert!(
+        client::read_range("big.bin", 199_990, 11, &chunk_root, server_addr)
+            .await
+            .is_err(),
+        "Range read past the end of the file should fail"
+    );
+
+    // Replacing the file replaces the chunk tree its ranges are proven against
+    let replaced: Vec<u8> = contents.iter().map(|byte| byte ^ 0xff).collect();
+    let replaced_root = client::compute_chunk_root_hash(&replaced);
+    let files = BTreeMap::from([("big.bin".to_string(), replaced.clone())]);
+    client::upload_files(files, server_addr).await.unwrap();
+    let slice = client::read_range("big.bin", 65_000, 1_000, &replaced_root, server_addr)
+        .await
+        .unwrap();
+    assert_eq!(slice, replaced[65_000..66_000].to_vec());
+    assert!(
+        client::read_range("big.bin", 65_000, 1_000, &chunk_root, server_addr)
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn test_delta_upload_sends_changed_chunks_only() {
+    let server_addr = "127.0.0.1:8083";
+    let server_instance = server::new_server();
+    tokio::spawn(async move {
+        server_instance.start(server_addr).await.unwrap();
+    });
+
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let mut contents: Vec<u8> = (0..300_000u32).map(|i| (i % 199) as u8).collect();
+    let sent = client::upload_file_delta("image.bin", &contents, server_addr)
+        .await
+        .unwrap();
+    assert_eq!(sent, 5, "A new file should be uploaded in full");
+
+    contents[100_000] ^= 0xff;
+    contents.extend_from_slice(b"appended");
+    let sent = client::upload_file_delta("image.bin", &contents, server_addr)
+        .await
+        .unwrap();
+    assert_eq!(sent, 2, "Only the edited and the last chunk should be sent");
+
+    let downloaded = client::download_file("image.bin", server_addr)
+        .await
+        .unwrap();
+    assert_eq!(downloaded, contents);
+    assert_eq!(
+        client::get_root_hash(server_addr).await.unwrap(),
+        client::compute_merkle_root_hash(vec![contents])
+    );
+}
+
+#[tokio::test]
+async fn test_batch_requests_answered_in_order() {
+    let server_addr = "127.0.0.1:8084";
+    let server_instance = server::new_server();
+    tokio::spawn(async move {
+        server_instance.start(server_addr).await.unwrap();
+    });
+
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let mut files = BTreeMap::<String, Vec<u8>>::new();
+    files.insert("a.txt".to_string(), b"alpha".to_vec());
+    files.insert("b.txt".to_string(), b"beta".to_vec());
+    let root = client::compute_merkle_root_hash(files.values().cloned().collect());
+    client::upload_files(files, server_addr).await.unwrap();
+
+    let client = client::Client::new(client::ClientConfig::new(server_addr));
+    let responses = client
+        .batch()
+        .download("b.txt")
+        .proof("b.txt")
+        .download("missing.txt")
+        .root_hash()
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(responses.len(), 4);
+
+    let data = match &responses[0] {
+        client::ClientMessage::Success { data } => data.to_vec(),
+        other => panic!("Unexpected response {:?}", other),
+    };
+    match &responses[1] {
+        client::ClientMessage::MerkleProof { proof, tree_size } => {
+            let proof = client::MerkleProof::from_path(proof, tree_size.unwrap()).unwrap();
+            assert!(client::verify_merkle_proof(&proof, &root, &data))
+        }
+        other => panic!("Unexpected response {:?}", other),
+    }
+    assert!(matches!(responses[2], client::ClientMessage::Error { .. }));
+    assert!(matches!(
+        &responses[3],
+        client::ClientMessage::Success { data } if *data == root
+    ));
+}
+
+#[tokio::test]
+async fn test_download_repairs_bad_chunks_from_replica() {
+    let primary_addr = "127.0.0.1:8085";
+    let replica_addr = "127.0.0.1:8086";
+    for addr in [primary_addr, replica_addr] {
+        let server_instance = server::new_server();
+        tokio::spawn(async move {
+            server_instance.start(addr).await.unwrap();
+        });
+    }
+
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let original: Vec<u8> = (0..200_000u32).map(|i| (i % 241) as u8).collect();
+    let chunk_root = client::compute_chunk_root_hash(&original);
+    let mut files = BTreeMap::<String, Vec<u8>>::new();
+    files.insert("disk.img".to_string(), original.clone());
+    client::upload_files(files.clone(), primary_addr)
+        .await
+        .unwrap();
+    client::upload_files(files, replica_addr).await.unwrap();
+
+    // Corrupt one chunk on the primary
+    let mut corrupted = original.clone();
+    corrupted[70_000] ^= 0xff;
+    let mut files = BTreeMap::<String, Vec<u8>>::new();
+    files.insert("disk.img".to_string(), corrupted);
+    client::upload_files(files, primary_addr).await.unwrap();
+
+    let primary = client::Client::new(client::ClientConfig::new(primary_addr));
+    let replica = client::Client::new(client::ClientConfig::new(replica_addr));
+    assert!(
+        primary
+            .download_with_repair("disk.img", &chunk_root, &[])
+            .await
+            .is_err(),
+        "Without a healthy replica the download cannot be repaired"
+    );
+    let mut events = primary.subscribe();
+    let repaired = primary
+        .download_with_repair("disk.img", &chunk_root, &[replica])
+        .await
+        .unwrap();
+    assert_eq!(repaired, original);
+
+    let mut received = Vec::new();
+    while let Ok(event) = events.try_recv() {
+        received.push(event);
+    }
+    assert!(received.contains(&client::ClientEvent::ChunkRepaired {
+        name: "disk.img".to_string(),
+        index: 1
+    }));
+    assert_eq!(
+        received.last(),
+        Some(&client::ClientEvent::Verified {
+            name: "disk.img".to_string()
+        })
+    );
+}
+
+#[tokio::test]
+async fn test_failover_requires_consistent_roots() {
+    let down_addr = "127.0.0.1:8087";
+    let backup_addr = "127.0.0.1:8088";
+    let diverged_addr = "127.0.0.1:8089";
+    for addr in [backup_addr, diverged_addr] {
+        let server_instance = server::new_server();
+        tokio::spawn(async move {
+            server_instance.start(addr).await.unwrap();
+        });
+    }
+
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let mut files = BTreeMap::<String, Vec<u8>>::new();
+    files.insert("a.txt".to_string(), b"replicated".to_vec());
+    client::upload_files(files, backup_addr).await.unwrap();
+
+    let mut config = client::ClientConfig::new(down_addr);
+    config.failover_addrs = vec![backup_addr.to_string()];
+    let client = client::Client::new(config.clone());
+    let data = client.download_file("a.txt").await.unwrap();
+    assert_eq!(data, b"replicated".to_vec());
+
+    // A server presenting a different root makes the whole set untrusted
+    let mut files = BTreeMap::<String, Vec<u8>>::new();
+    files.insert("a.txt".to_string(), b"diverged".to_vec());
+    client::upload_files(files, diverged_addr).await.unwrap();
+    config.failover_addrs.push(diverged_addr.to_string());
+    let client = client::Client::new(config);
+    assert!(client.download_file("a.txt").await.is_err());
+}
+
+#[tokio::test]
+async fn test_servers_down_during_the_root_check_are_checked_when_they_return() {
+    let late_addr = "127.0.0.1:8121";
+    let backup_addr = "127.0.0.1:8122";
+    let backup = server::new_server();
+    tokio::spawn(async move {
+        backup.start(backup_addr).await.unwrap();
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+    let mut files = BTreeMap::<String, Vec<u8>>::new();
+    files.insert("a.txt".to_string(), b"replicated".to_vec());
+    client::upload_files(files, backup_addr).await.unwrap();
+
+    // Only the backup answers the first check, so its root is the agreed one
+    let mut config = client::ClientConfig::new(late_addr);
+    config.failover_addrs = vec![backup_addr.to_string()];
+    let client = client::Client::new(config);
+    assert_eq!(
+        client.download_file("a.txt").await.unwrap(),
+        b"replicated".to_vec()
+    );
+
+    // The first server comes back with other contents, and is refused rather than used
+    let late = server::new_server();
+    tokio::spawn(async move {
+        late.start(late_addr).await.unwrap();
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+    let mut files = BTreeMap::<String, Vec<u8>>::new();
+    files.insert("a.txt".to_string(), b"diverged".to_vec());
+    client::upload_files(files, late_addr).await.unwrap();
+    let err = client.download_file("a.txt").await.unwrap_err();
+    assert!(err.to_string().contains(late_addr), "{}", err);
+}
+
+#[tokio::test]
+async fn test_failover_after_an_upload_checks_against_the_new_root() {
+    let primary_addr = "127.0.0.1:8131";
+    let backup_addr = "127.0.0.1:8132";
+    let primary = server::new_server();
+    let primary = tokio::spawn(async move {
+        primary.start(primary_addr).await.unwrap();
+    });
+    let backup = server::new_server();
+    tokio::spawn(async move {
+        backup.start(backup_addr).await.unwrap();
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let mut config = client::ClientConfig::new(primary_addr);
+    config.failover_addrs = vec![backup_addr.to_string()];
+    let client = client::Client::new(config);
+    let files = BTreeMap::from([("a.txt".to_string(), b"uploaded".to_vec())]);
+    let root = client.upload_files(files.clone()).await.unwrap();
+
+    // The backup still has the root from before the upload, and is not trusted with it
+    primary.abort();
+    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+    let err = client.get_root_hash().await.unwrap_err();
+    assert!(err.to_string().contains(backup_addr), "{}", err);
+
+    // Once it catches up, it is used
+    client::upload_files(files, backup_addr).await.unwrap();
+    assert_eq!(client.get_root_hash().await.unwrap(), root);
+    assert_eq!(
+        client.download_file("a.txt").await.unwrap(),
+        b"uploaded".to_vec()
+    );
+}
+
+#[tokio::test]
+async fn test_sync_uploads_only_changed_files() {
+    let server_addr = "127.0.0.1:8090";
+    let server_instance = server::new_server();
+    tokio::spawn(async move {
+        server_instance.start(server_addr).await.unwrap();
+    });
+
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let dir = std::env::temp_dir().join(format!("merklefile-sync-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("nested")).unwrap();
+    std::fs::write(dir.join("a.txt"), b"first").unwrap();
+    std::fs::write(dir.join("nested/b.txt"), b"second").unwrap();
+
+    let client = client::Client::new(client::ClientConfig::new(server_addr));
+    let state = client::SyncState::open_in_memory().unwrap();
+
+    let report = client.sync_dir(&dir, &state).await.unwrap();
+    assert_eq!(report.uploaded, vec!["a.txt", "nested/b.txt"]);
+
+    let report = client.sync_dir(&dir, &state).await.unwrap();
+    assert!(report.uploaded.is_empty());
+    assert_eq!(report.unchanged.len(), 2);
+
+    std::fs::write(dir.join("nested/b.txt"), b"second, edited").unwrap();
+    std::fs::remove_file(dir.join("a.txt")).unwrap();
+    let report = client.sync_dir(&dir, &state).await.unwrap();
+    assert_eq!(report.uploaded, vec!["nested/b.txt"]);
+    assert_eq!(report.removed, vec!["a.txt"]);
+    assert_eq!(
+        state.get("nested/b.txt").unwrap().unwrap().root,
+        report.root.unwrap()
+    );
+
+    // A sync whose upload fails forgets nothing
+    std::fs::write(dir.join("c.txt"), b"third").unwrap();
+    std::fs::remove_file(dir.join("nested/b.txt")).unwrap();
+    let offline = client::Client::new(client::ClientConfig::new("127.0.0.1:1"));
+    assert!(offline.sync_dir(&dir, &state).await.is_err());
+    assert!(state.get("nested/b.txt").unwrap().is_some());
+
+    // A dry run plans the same sync without the server and without touching the state
+    let plan = offline.sync_dir_dry_run(&dir, &state).await.unwrap();
+    assert_eq!(plan.uploaded, vec!["c.txt"]);
+    assert_eq!(plan.removed, vec!["nested/b.txt"]);
+    assert!(plan.root.is_none());
+    assert!(state.get("nested/b.txt").unwrap().is_some());
+    assert!(state.get("c.txt").unwrap().is_none());
+    let report = client.sync_dir(&dir, &state).await.unwrap();
+    assert_eq!(report.removed, vec!["nested/b.txt"]);
+    assert!(state.get("nested/b.txt").unwrap().is_none());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_upload_returns_proofs() {
+    let server_addr = "127.0.0.1:8091";
+    let server_instance = server::new_server();
+    tokio::spawn(async move {
+        server_instance.start(server_addr).await.unwrap();
+    });
+
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let mut existing = BTreeMap::<String, Vec<u8>>::new();
+    existing.insert("b.txt".to_string(), b"already there".to_vec());
+    // The upload's answer carries the root it produced
+    let root = client::upload_files(existing.clone(), server_addr)
+        .await
+        .unwrap();
+    assert_eq!(
+        root,
+        client::compute_merkle_root_hash(existing.values().cloned().collect())
+    );
+
+    let mut files = BTreeMap::<String, Vec<u8>>::new();
+    files.insert("a.txt".to_string(), b"new file".to_vec());
+    files.insert("c.txt".to_string(), b"another".to_vec());
+    let receipt = client::upload_files_with_proofs(files.clone(), server_addr)
+        .await
+        .unwrap();
+
+    existing.extend(files.clone());
+    assert_eq!(
+        receipt.root,
+        client::compute_merkle_root_hash(existing.values().cloned().collect())
+    );
+    assert_eq!(receipt.proofs.len(), 2);
+    for (filename, data) in &files {
+        assert!(client::verify_merkle_proof(
+            &receipt.proofs[filename],
+            &receipt.root,
+            data
+        ));
+    }
+}
+
+#[tokio::test]
+async fn test_server_invariants_hold_after_uploads() {
+    let server_addr = "127.0.0.1:8092";
+    let server_instance = server::new_server();
+    let server = server_instance.clone();
+    tokio::spawn(async move {
+        server.start(server_addr).await.unwrap();
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+    assert_eq!(server_instance.debug_validate().await, Ok(()));
+
+    let mut files = BTreeMap::new();
+    files.insert("b.txt".to_string(), b"second".to_vec());
+    files.insert("a.txt".to_string(), b"first".to_vec());
+    client::upload_files(files, server_addr).await.unwrap();
+    client::upload_file_delta("a.txt", b"first, edited", server_addr)
+        .await
+        .unwrap();
+
+    assert_eq!(server_instance.debug_validate().await, Ok(()));
+}
+
+#[cfg(feature = "sync")]
+#[tokio::test]
+async fn test_blocking_client() {
+    use merklefile::client::blocking;
+
+    let server_addr = "127.0.0.1:8093";
+    let server_instance = server::new_server();
+    tokio::spawn(async move {
+        server_instance.start(server_addr).await.unwrap();
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    // The blocking calls must stay off the runtime thread that drives the server
+    tokio::task::spawn_blocking(move || {
+        let mut files = BTreeMap::new();
+        files.insert("a.txt".to_string(), b"alpha".to_vec());
+        files.insert("b.txt".to_string(), b"beta".to_vec());
+        let root = blocking::upload_files(files, server_addr).unwrap();
+        assert_eq!(blocking::get_root_hash(server_addr).unwrap(), root);
+
+        let data = blocking::download_verified("b.txt", &root, server_addr).unwrap();
+        assert_eq!(data, b"beta".to_vec());
+        assert!(blocking::download_verified("b.txt", &[0; 32], server_addr).is_err());
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_transparency_log_heads_stay_consistent() {
+    use merklefile::log::SigningKey;
+
+    let server_addr = "127.0.0.1:8094";
+    let key = SigningKey::from_bytes(&[3; 32]);
+    let server_instance = server::Server::new().with_transparency_log(key.clone());
+    tokio::spawn(async move {
+        server_instance.start(server_addr).await.unwrap();
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let mut config = client::ClientConfig::new(server_addr);
+    config.log_public_key = Some(hex::encode(key.verifying_key().to_bytes()));
+    let client = client::Client::new(config);
+    let empty = client.tree_head(None).await.unwrap();
+    assert_eq!(empty.size, 0);
+
+    let mut files = BTreeMap::new();
+    files.insert("a.txt".to_string(), b"first".to_vec());
+    files.insert("b.txt".to_string(), b"second".to_vec());
+    client.upload_files(files.clone()).await.unwrap();
+    let first = client.tree_head(Some(&empty)).await.unwrap();
+    assert_eq!(first.size, 2);
+
+    // Stored files cannot be replaced, but re-uploading them unchanged is harmless
+    let mut replaced = BTreeMap::new();
+    replaced.insert("a.txt".to_string(), b"tampered".to_vec());
+    assert!(client.upload_files(replaced).await.is_err());
+    files.insert("c.txt".to_string(), b"third".to_vec());
+    client.upload_files(files).await.unwrap();
+    let second = client.tree_head(Some(&first)).await.unwrap();
+    assert_eq!(second.size, 3);
+
+    // A peer's older head checks out; a forged or rolled back one does not
+    client.check_gossiped_head(&second, &first).await.unwrap();
+    let mut forged = first.clone();
+    forged.root = vec![0; 32];
+    assert!(client.check_gossiped_head(&second, &forged).await.is_err());
+    let forked = merklefile::log::SignedTreeHead::sign(2, first.timestamp, vec![0; 32], &key);
+    assert!(client.check_gossiped_head(&second, &forked).await.is_err());
+    assert!(client.tree_head(Some(&second)).await.is_ok());
+    let future = merklefile::log::SignedTreeHead::sign(4, second.timestamp, vec![0; 32], &key);
+    assert!(client.tree_head(Some(&future)).await.is_err());
+}
+
+#[tokio::test]
+async fn test_audit_spot_checks_against_pinned_root() {
+    let server_addr = "127.0.0.1:8096";
+    let server_instance = server::new_server();
+    tokio::spawn(async move {
+        server_instance.start(server_addr).await.unwrap();
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let files: BTreeMap<String, Vec<u8>> = (0..5)
+        .map(|i| {
+            (
+                format!("file_{}.txt", i),
+                format!("contents {}", i).into_bytes(),
+            )
+        })
+        .collect();
+    client::upload_files(files, server_addr).await.unwrap();
+    let root = client::get_root_hash(server_addr).await.unwrap();
+
+    let report = client::audit(server_addr, &root, 3).await.unwrap();
+    assert_eq!(report.listed, 5);
+    assert_eq!(report.checks.len(), 3);
+    assert!(report.is_clean(), "{}", report);
+
+    // Once the server's contents move on, the old pinned root no longer matches any of them
+    let mut changed = BTreeMap::new();
+    changed.insert("file_0.txt".to_string(), b"replaced".to_vec());
+    client::upload_files(changed, server_addr).await.unwrap();
+    let report = client::audit(server_addr, &root, 10).await.unwrap();
+    assert_eq!(report.checks.len(), 5);
+    assert!(report.checks.iter().all(|check| check.problem.is_some()));
+}
+
+#[tokio::test]
+async fn test_roots_are_published_before_they_take_effect() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    struct Switch(AtomicBool);
+
+    impl server::RootPublisher for Switch {
+        fn publish(&self, _root: &[u8], _size: u64, _timestamp: u64) -> std::io::Result<()> {
+            if self.0.load(Ordering::SeqCst) {
+                Ok(())
+            } else {
+                Err(std::io::Error::other("notary unavailable"))
+            }
+        }
+    }
+
+    let server_addr = "127.0.0.1:8097";
+    let dir = std::env::temp_dir().join(format!("merklefile-publish-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let journal = dir.join("roots.log");
+    let config = server::ServerConfig::from_toml(&format!(
+        "[[publishers]]\nkind = \"journal\"\npath = {:?}",
+        journal
+    ))
+    .unwrap();
+    let switch = Arc::new(Switch(AtomicBool::new(true)));
+    let server_instance = server::Server::new()
+        .with_publisher(switch.clone())
+        .with_config(&config);
+    tokio::spawn(async move {
+        server_instance.start(server_addr).await.unwrap();
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let mut files = BTreeMap::new();
+    files.insert("a.txt".to_string(), b"first".to_vec());
+    client::upload_files(files, server_addr).await.unwrap();
+    let root = client::get_root_hash(server_addr).await.unwrap();
+
+    // A root that cannot be published never takes effect
+    switch.0.store(false, Ordering::SeqCst);
+    let mut files = BTreeMap::new();
+    files.insert("b.txt".to_string(), b"second".to_vec());
+    assert!(client::upload_files(files, server_addr).await.is_err());
+    assert_eq!(client::get_root_hash(server_addr).await.unwrap(), root);
+    assert!(client::download_file("b.txt", server_addr).await.is_err());
+
+    let published = std::fs::read_to_string(&journal).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(published.lines().count(), 1);
+    assert!(published.trim_end().ends_with(&hex::encode(&root)));
+}
+
+#[tokio::test]
+async fn test_server_refuses_requests_over_the_configured_size() {
+    let server_addr = "127.0.0.1:8098";
+    let config =
+        server::ServerConfig::from_toml("max_message_size = 4096\nbuffer_pool_size = 2").unwrap();
+    let server_instance = server::Server::new().with_config(&config);
+    tokio::spawn(async move {
+        server_instance.start(server_addr).await.unwrap();
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let mut files = BTreeMap::new();
+    files.insert("big.bin".to_string(), vec![7u8; 4096]);
+    assert!(client::upload_files(files, server_addr).await.is_err());
+
+    // Pooled buffers that held earlier requests serve later ones unchanged
+    for i in 0..4u8 {
+        let mut files = BTreeMap::new();
+        files.insert(format!("{}.txt", i), vec![i; 16]);
+        client::upload_files(files, server_addr).await.unwrap();
+        let data = client::download_file(&format!("{}.txt", i), server_addr)
+            .await
+            .unwrap();
+        assert_eq!(data, vec![i; 16]);
+    }
+    assert!(client::download_file("big.bin", server_addr).await.is_err());
+}
+
+#[tokio::test]
+async fn test_disk_storage_survives_restarts() {
+    let dir = std::env::temp_dir().join(format!("merklefile-storage-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let server_addr = "127.0.0.1:8099";
+    let server_instance = server::Server::new().with_storage_dir(&dir).await.unwrap();
+    tokio::spawn(async move {
+        server_instance.start(server_addr).await.unwrap();
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let big: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+    let mut files = BTreeMap::new();
+    files.insert("big.bin".to_string(), big.clone());
+    files.insert("a.txt".to_string(), b"alpha".to_vec());
+    files.insert("copy.txt".to_string(), b"alpha".to_vec());
+    client::upload_files(files, server_addr).await.unwrap();
+    files = BTreeMap::new();
+    files.insert("a.txt".to_string(), b"replaced".to_vec());
+    client::upload_files(files, server_addr).await.unwrap();
+    let root = client::get_root_hash(server_addr).await.unwrap();
+
+    // One object per distinct contents, and none for contents no longer stored
+    let objects = std::fs::read_dir(dir.join("objects")).unwrap().count();
+    assert_eq!(objects, 3);
+
+    // A second server on the same directory picks up where the first left off
+    let server_addr = "127.0.0.1:8100";
+    let server_instance = server::Server::new()
+        .with_storage_dir(&dir)
+        .await
+        .unwrap()
+        .with_self_check(true);
+    server_instance.debug_validate().await.unwrap();
+    tokio::spawn(async move {
+        server_instance.start(server_addr).await.unwrap();
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    assert_eq!(client::get_root_hash(server_addr).await.unwrap(), root);
+    assert_eq!(
+        client::download_file("big.bin", server_addr).await.unwrap(),
+        big
+    );
+    assert_eq!(
+        client::download_file("copy.txt", server_addr)
+            .await
+            .unwrap(),
+        b"alpha"
+    );
+    // Ranges are read from disk chunk by chunk
+    let chunk_root = client::compute_chunk_root_hash(&big);
+    let range = client::read_range("big.bin", 70_000, 5_000, &chunk_root, server_addr)
+        .await
+        .unwrap();
+    assert_eq!(range, big[70_000..75_000]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_large_downloads_are_streamed_from_disk() {
+    let dir = std::env::temp_dir().join(format!("merklefile-streamed-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let server_addr = "127.0.0.1:8101";
+    let config = server::ServerConfig::from_toml("stream_threshold = 1000").unwrap();
+    let server_instance = server::Server::new()
+        .with_storage_dir(&dir)
+        .await
+        .unwrap()
+        .with_config(&config);
+    tokio::spawn(async move {
+        server_instance.start(server_addr).await.unwrap();
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let big: Vec<u8> = (0..2_500_000u32).map(|i| (i * 7 % 256) as u8).collect();
+    let mut files = BTreeMap::new();
+    files.insert("big.bin".to_string(), big.clone());
+    files.insert("small.txt".to_string(), b"small".to_vec());
+    client::upload_files(files, server_addr).await.unwrap();
+    assert_eq!(
+        client::download_file("big.bin", server_addr).await.unwrap(),
+        big
+    );
+    assert_eq!(
+        client::download_file("small.txt", server_addr)
+            .await
+            .unwrap(),
+        b"small"
+    );
+
+    // Contents changed on disk behind the server's back are never sent in full
+    let object = std::fs::read_dir(dir.join("objects"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .find(|path| std::fs::metadata(path).unwrap().len() == big.len() as u64)
+        .unwrap();
+    let mut tampered = big.clone();
+    tampered[2_000_000] ^= 1;
+    std::fs::write(&object, &tampered).unwrap();
+    let result = client::download_file("big.bin", server_addr).await;
+    assert!(result.is_err());
+
+    // Nor are files truncated behind its back, and the server keeps serving
+    std::fs::write(&object, &big[..1_000_000]).unwrap();
+    let result = client::download_file("big.bin", server_addr).await;
+    let small = client::download_file("small.txt", server_addr).await;
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(result.is_err());
+    assert_eq!(small.unwrap(), b"small");
+}
+
+#[tokio::test]
+async fn test_raw_downloads_are_checked_against_their_hash() {
+    let dir = std::env::temp_dir().join(format!("merklefile-raw-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let server_addr = "127.0.0.1:8102";
+    let config = server::ServerConfig::from_toml("sendfile = true").unwrap();
+    let server_instance = server::Server::new()
+        .with_storage_dir(&dir)
+        .await
+        .unwrap()
+        .with_config(&config);
+    tokio::spawn(async move {
+        server_instance.start(server_addr).await.unwrap();
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let mut client_config = client::ClientConfig::new(server_addr);
+    client_config.raw_downloads = true;
+    let client = client::Client::new(client_config);
+    let big: Vec<u8> = (0..3_000_000u32).map(|i| (i * 13 % 256) as u8).collect();
+    let mut files = BTreeMap::new();
+    files.insert("big.bin".to_string(), big.clone());
+    client.upload_files(files).await.unwrap();
+    assert_eq!(client.download_file("big.bin").await.unwrap(), big);
+    let missing = client.download_file("missing.bin").await.unwrap_err();
+    assert_eq!(missing.kind(), std::io::ErrorKind::NotFound);
+
+    let object = std::fs::read_dir(dir.join("objects"))
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap()
+        .path();
+    let mut tampered = big.clone();
+    tampered[1_234_567] ^= 1;
+    std::fs::write(&object, &tampered).unwrap();
+    let result = client.download_file("big.bin").await;
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[tokio::test]
+async fn test_proofs_against_earlier_roots() {
+    let server_addr = "127.0.0.1:8103";
+    let config = server::ServerConfig::from_toml("history_versions = 2").unwrap();
+    let server_instance = server::Server::new().with_config(&config);
+    tokio::spawn(async move {
+        server_instance.start(server_addr).await.unwrap();
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let client = client::Client::new(client::ClientConfig::new(server_addr));
+    let mut files = BTreeMap::new();
+    files.insert("a.txt".to_string(), b"a1".to_vec());
+    files.insert("b.txt".to_string(), b"b1".to_vec());
+    client.upload_files(files).await.unwrap();
+    let first_root = client.get_root_hash().await.unwrap();
+
+    let mut modified = BTreeMap::new();
+    modified.insert("a.txt".to_string(), b"a2".to_vec());
+    client.upload_files(modified).await.unwrap();
+    let second_root = client.get_root_hash().await.unwrap();
+
+    for (filename, data, root) in [
+        ("a.txt", b"a1", &first_root),
+        ("b.txt", b"b1", &first_root),
+        ("a.txt", b"a2", &second_root),
+    ] {
+        let proof = client.get_merkle_proof_at(filename, root).await.unwrap();
+        assert!(client::verify_merkle_proof(&proof, root, data));
+    }
+    assert!(client
+        .get_merkle_proof_at("c.txt", &first_root)
+        .await
+        .is_err());
+
+    // Only the two latest versions are kept
+    let mut added = BTreeMap::new();
+    added.insert("c.txt".to_string(), b"c1".to_vec());
+    client.upload_files(added).await.unwrap();
+    assert!(client
+        .get_merkle_proof_at("a.txt", &first_root)
+        .await
+        .is_err());
+    assert!(client
+        .get_merkle_proof_at("a.txt", &second_root)
+        .await
+        .is_ok());
+}
+
+#[tokio::test]
+async fn test_uploads_streamed_from_a_reader() {
+    let dir = std::env::temp_dir().join(format!("merklefile-stream-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let server_addr = "127.0.0.1:8104";
+    let server_instance = server::Server::new().with_storage_dir(&dir).await.unwrap();
+    tokio::spawn(async move {
+        server_instance.start(server_addr).await.unwrap();
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let big: Vec<u8> = (0..1_000_000u32).map(|i| (i * 7 % 256) as u8).collect();
+    let receipt = client::upload_from_reader("big.bin", &big[..], big.len() as u64, server_addr)
+        .await
+        .unwrap();
+    assert_eq!(
+        receipt.root,
+        client::get_root_hash(server_addr).await.unwrap()
+    );
+    assert_eq!(
+        client::download_file("big.bin", server_addr).await.unwrap(),
+        big
+    );
+
+    // A reader that ends early stores nothing
+    let short = client::upload_from_reader("short.bin", &big[..10], 20, server_addr).await;
+    assert_eq!(short.unwrap_err().kind(), std::io::ErrorKind::UnexpectedEof);
+    tokio::time::sleep(tokio::time::Duration::from_millis(200)).await;
+    let incoming = std::fs::read_dir(dir.join("incoming")).unwrap().count();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(incoming, 0);
+    assert!(client::download_file("short.bin", server_addr)
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn test_session_transcript_records_every_exchange() {
+    let server_addr = "127.0.0.1:8105";
+    let server_instance = server::new_server();
+    tokio::spawn(async move {
+        server_instance.start(server_addr).await.unwrap();
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let client = client::Client::new(client::ClientConfig::new(server_addr)).with_transcript();
+    let mut files = BTreeMap::new();
+    files.insert("a.txt".to_string(), b"alpha".to_vec());
+    let receipt = client.upload_files_with_proofs(files).await.unwrap();
+    let root = client.get_root_hash().await.unwrap();
+    client.download_file("a.txt").await.unwrap();
+
+    let transcript = client.transcript().unwrap();
+    let roots: Vec<Option<Vec<u8>>> = transcript
+        .entries()
+        .iter()
+        .map(|entry| entry.root.clone())
+        .collect();
+    assert_eq!(roots[..2], [Some(receipt.root), Some(root)]);
+    assert!(roots.len() >= 3);
+
+    let key = merklefile::log::SigningKey::from_bytes(&[1; 32]);
+    let signed = transcript.sign(&key);
+    assert!(signed.verify(&key.verifying_key()).is_ok());
+}
+
+#[tokio::test]
+async fn test_tamper_alarms_are_raised() {
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<server::TamperEvent>>);
+
+    impl server::TamperAlarm for Recorder {
+        fn raise(&self, event: &server::TamperEvent) -> std::io::Result<()> {
+            self.0.lock().unwrap().push(event.clone());
+            Ok(())
+        }
+    }
+
+    let dir = std::env::temp_dir().join(format!("merklefile-alarm-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let journal = dir.join("roots.journal");
+    let recorder = Arc::new(Recorder::default());
+    let root_journal = server::RootJournal::open(&journal, server::generate_key())
+        .unwrap()
+        .with_alarms(vec![recorder.clone()]);
+    let server_addr = "127.0.0.1:8106";
+    let server_instance = server::Server::new()
+        .with_storage_dir(dir.join("storage"))
+        .await
+        .unwrap()
+        .with_self_check(true)
+        .with_publisher(Arc::new(root_journal))
+        .with_alarm(recorder.clone());
+    tokio::spawn(async move {
+        server_instance.start(server_addr).await.unwrap();
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let mut files = BTreeMap::new();
+    files.insert("a.txt".to_string(), b"alpha".to_vec());
+    client::upload_files(files.clone(), server_addr)
+        .await
+        .unwrap();
+    assert!(recorder.0.lock().unwrap().is_empty());
+
+    // Stored bytes changed on disk are caught by the self-check after the next request
+    let object = std::fs::read_dir(dir.join("storage/objects"))
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap()
+        .path();
+    std::fs::write(&object, b"tampered").unwrap();
+    client::get_root_hash(server_addr).await.unwrap();
+    tokio::time::sleep(tokio::time::Duration::from_millis(200)).await;
+    let source = recorder.0.lock().unwrap().last().map(|event| event.source);
+    assert_eq!(source, Some(server::TamperSource::SelfCheck));
+
+    // A journal rewritten behind the server's back refuses the next root
+    std::fs::write(&journal, b"").unwrap();
+    files.insert("b.txt".to_string(), b"beta".to_vec());
+    assert!(client::upload_files(files, server_addr).await.is_err());
+    let journal_alarms = recorder
+        .0
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|event| event.source == server::TamperSource::Journal)
+        .count();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(journal_alarms, 1);
+}
+
+#[tokio::test]
+async fn test_shadow_verification_refuses_corrupted_downloads() {
+    use std::sync::{Arc, Mutex};
+
+    struct Recorder(Mutex<Vec<server::TamperSource>>);
+
+    impl server::TamperAlarm for Recorder {
+        fn raise(&self, event: &server::TamperEvent) -> std::io::Result<()> {
+            self.0.lock().unwrap().push(event.source);
+            Ok(())
+        }
+    }
+
+    let dir = std::env::temp_dir().join(format!("merklefile-shadow-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let recorder = Arc::new(Recorder(Mutex::new(Vec::new())));
+    let server_addr = "127.0.0.1:8107";
+    let server_instance = server::Server::new()
+        .with_storage_dir(&dir)
+        .await
+        .unwrap()
+        .with_shadow_verify(true)
+        .with_alarm(recorder.clone());
+    tokio::spawn(async move {
+        server_instance.start(server_addr).await.unwrap();
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let mut files = BTreeMap::new();
+    files.insert("a.txt".to_string(), b"alpha".to_vec());
+    client::upload_files(files, server_addr).await.unwrap();
+    let mut config = client::ClientConfig::new(server_addr);
+    config.raw_downloads = true;
+    let raw_client = client::Client::new(config);
+    assert_eq!(raw_client.download_file("a.txt").await.unwrap(), b"alpha");
+
+    let object = std::fs::read_dir(dir.join("objects"))
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap()
+        .path();
+    std::fs::write(&object, b"alphb").unwrap();
+    let err = client::download_file("a.txt", server_addr)
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("Integrity error"), "{}", err);
+    assert!(raw_client.download_file("a.txt").await.is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(
+        *recorder.0.lock().unwrap(),
+        [server::TamperSource::Shadow, server::TamperSource::Shadow]
+    );
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_rebuild_and_maintenance_over_the_control_socket() {
+    use server::AdminCommand;
+    use std::sync::Arc;
+
+    let dir = std::env::temp_dir().join(format!("merklefile-rebuild-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let socket = dir.join("control.sock");
+    let server_addr = "127.0.0.1:8108";
+    let server_instance = Arc::new(
+        server::Server::new()
+            .with_storage_dir(dir.join("storage"))
+            .await
+            .unwrap()
+            .with_control_socket(&socket),
+    );
+    let running = Arc::clone(&server_instance);
+    tokio::spawn(async move {
+        running.start(server_addr).await.unwrap();
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let mut files = BTreeMap::new();
+    files.insert("a.txt".to_string(), b"alpha".to_vec());
+    files.insert("b.txt".to_string(), b"beta".to_vec());
+    client::upload_files(files, server_addr).await.unwrap();
+    let root = client::get_root_hash(server_addr).await.unwrap();
+    let report = server_instance.rebuild().await;
+    assert!(report.is_clean(), "{:?}", report.discrepancies);
+    assert_eq!(report.rebuilt_root, root);
+
+    assert_eq!(
+        server::send_command(&socket, AdminCommand::Maintenance)
+            .await
+            .unwrap(),
+        "ok"
+    );
+    let refused = client::get_root_hash(server_addr).await.unwrap_err();
+    assert!(refused.to_string().contains("maintenance"), "{}", refused);
+    server::send_command(&socket, AdminCommand::Resume)
+        .await
+        .unwrap();
+
+    // b.txt is the only stored object of four bytes
+    let object = std::fs::read_dir(dir.join("storage/objects"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .find(|path| std::fs::metadata(path).unwrap().len() == 4)
+        .unwrap();
+    std::fs::write(&object, b"bet4").unwrap();
+    let answer = server::send_command(&socket, AdminCommand::Rebuild)
+        .await
+        .unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(answer.contains(&format!("\"rebuilt_root\":\"{}\"", hex::encode(&root))));
+    assert!(answer
+        .contains("\"discrepancies\":[\"b.txt: stored bytes no longer match the recorded hash\"]"));
+    // Service resumes after the rebuild
+    assert_eq!(client::get_root_hash(server_addr).await.unwrap(), root);
+}
+
+#[tokio::test]
+async fn test_stats_count_requests_and_verification_failures() {
+    use std::sync::Arc;
+
+    let dir = std::env::temp_dir().join(format!("merklefile-stats-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let server_addr = "127.0.0.1:8109";
+    let server_instance = Arc::new(
+        server::Server::new()
+            .with_storage_dir(&dir)
+            .await
+            .unwrap()
+            .with_shadow_verify(true),
+    );
+    let running = Arc::clone(&server_instance);
+    tokio::spawn(async move {
+        running.start(server_addr).await.unwrap();
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let mut files = BTreeMap::new();
+    files.insert("a.txt".to_string(), b"alpha".to_vec());
+    files.insert("b.txt".to_string(), b"beta".to_vec());
+    files.insert("c.txt".to_string(), b"gamma".to_vec());
+    client::upload_files(files, server_addr).await.unwrap();
+    let client = client::Client::new(client::ClientConfig::new(server_addr));
+    assert_eq!(client.download_file("b.txt").await.unwrap(), b"beta");
+
+    // b.txt is the only stored object of four bytes
+    let object = std::fs::read_dir(dir.join("objects"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .find(|path| std::fs::metadata(path).unwrap().len() == 4)
+        .unwrap();
+    std::fs::write(&object, b"bet4").unwrap();
+    assert!(client.download_file("b.txt").await.is_err());
+
+    let stats = client.stats().await.unwrap();
+    assert_eq!(stats.files, 3);
+    assert_eq!(stats.total_bytes, 14);
+    assert_eq!(stats.tree_depth, 2);
+    assert_eq!(stats.last_rebuild_ms, None);
+    assert_eq!(stats.requests["Download"], 2);
+    assert_eq!(stats.requests["GetStats"], 1);
+    assert_eq!(
+        stats.verification_failures,
+        BTreeMap::from([("shadow".to_string(), 1)])
+    );
+    assert!(!stats.hash_backend.is_empty());
+
+    server_instance.rebuild().await;
+    let stats = client.stats().await.unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(stats.last_rebuild_ms.is_some());
+    assert_eq!(stats.verification_failures["rebuild"], 1);
+}
+
+#[tokio::test]
+async fn test_stalled_and_malformed_clients_are_banned() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let server_addr = "127.0.0.1:8110";
+    let config = server::ServerConfig::from_toml(
+        "header_timeout_secs = 1\nmax_parse_errors = 2\nban_secs = 60",
+    )
+    .unwrap();
+    let server_instance = server::Server::new().with_config(&config);
+    tokio::spawn(async move {
+        server_instance.start(server_addr).await.unwrap();
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    // A client that never sends its header is disconnected
+    let mut stalled = tokio::net::TcpStream::connect(server_addr).await.unwrap();
+    let mut reply = Vec::new();
+    let read = tokio::time::timeout(
+        tokio::time::Duration::from_secs(5),
+        stalled.read_to_end(&mut reply),
+    );
+    assert!(read.await.is_ok());
+
+    // A second offence, this time garbage, gets the address banned
+    let mut garbage = tokio::net::TcpStream::connect(server_addr).await.unwrap();
+    garbage.write_all(&4u64.to_be_bytes()).await.unwrap();
+    garbage.write_all(b"\xff\xff\xff\xff").await.unwrap();
+    let _ = garbage.read_to_end(&mut reply).await;
+    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+
+    let mut files = BTreeMap::new();
+    files.insert("a.txt".to_string(), b"alpha".to_vec());
+    assert!(client::upload_files(files, server_addr).await.is_err());
+}
+
+#[tokio::test]
+async fn test_slow_requests_and_large_raw_uploads_are_bounded() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let server_addr = "127.0.0.1:8123";
+    let config = server::ServerConfig::from_toml(
+        "header_timeout_secs = 1\nmin_request_rate = 1048576\nrequest_memory = 4096",
+    )
+    .unwrap();
+    let server_instance = server::Server::new().with_config(&config);
+    tokio::spawn(async move {
+        server_instance.start(server_addr).await.unwrap();
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    // A client that sends its header on time and then trickles the frame is disconnected
+    let mut trickle = tokio::net::TcpStream::connect(server_addr).await.unwrap();
+    trickle.write_all(&1000u64.to_be_bytes()).await.unwrap();
+    trickle.write_all(b"{\"GetRootHash\"").await.unwrap();
+    let mut reply = Vec::new();
+    let read = tokio::time::timeout(
+        tokio::time::Duration::from_secs(5),
+        trickle.read_to_end(&mut reply),
+    );
+    assert!(read.await.is_ok());
+
+    // A raw upload held in memory counts against the budget like a request does
+    let big = vec![7u8; 10_000];
+    let refused = client::upload_from_reader("big.bin", &big[..], big.len() as u64, server_addr);
+    assert!(refused.await.is_err());
+    let small = client::upload_from_reader("small.bin", &big[..100], 100, server_addr).await;
+    assert!(small.is_ok());
+}
+
+#[tokio::test]
+async fn test_collision_policies() {
+    let server_addr = "127.0.0.1:8111";
+    let config = server::ServerConfig::from_toml("on_collision = \"reject\"").unwrap();
+    let server_instance = server::Server::new().with_config(&config);
+    tokio::spawn(async move {
+        server_instance.start(server_addr).await.unwrap();
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let files = |data: &[u8]| BTreeMap::from([("a.txt".to_string(), data.to_vec())]);
+    client::upload_files(files(b"first"), server_addr)
+        .await
+        .unwrap();
+    // Uploading the same contents again is not a collision
+    client::upload_files(files(b"first"), server_addr)
+        .await
+        .unwrap();
+    assert!(client::upload_files(files(b"second"), server_addr)
+        .await
+        .is_err());
+    assert_eq!(
+        client::download_file("a.txt", server_addr).await.unwrap(),
+        b"first"
+    );
+
+    let mut config = client::ClientConfig::new(server_addr);
+    config.on_collision = Some(client::CollisionPolicy::AutoVersion);
+    let versioning = client::Client::new(config);
+    let receipt = versioning
+        .upload_files_with_proofs(files(b"second"))
+        .await
+        .unwrap();
+    assert_eq!(receipt.stored_as["a.txt"], "a.txt.~1~");
+    assert!(receipt.proofs.contains_key("a.txt.~1~"));
+    // A version already holding the same contents is reused
+    let receipt = versioning
+        .upload_files_with_proofs(files(b"second"))
+        .await
+        .unwrap();
+    assert_eq!(receipt.stored_as["a.txt"], "a.txt.~1~");
+    versioning.upload_files(files(b"third")).await.unwrap();
+    assert_eq!(
+        versioning.download_file("a.txt.~2~").await.unwrap(),
+        b"third"
+    );
+    assert_eq!(versioning.download_file("a.txt").await.unwrap(), b"first");
+
+    let mut config = client::ClientConfig::new(server_addr);
+    config.on_collision = Some(client::CollisionPolicy::Overwrite);
+    let overwriting = client::Client::new(config);
+    overwriting.upload_files(files(b"fourth")).await.unwrap();
+    assert_eq!(overwriting.download_file("a.txt").await.unwrap(), b"fourth");
+}
+
+#[tokio::test]
+async fn test_unsafe_filenames_are_refused() {
+    let server_addr = "127.0.0.1:8112";
+    let server_instance = server::new_server();
+    tokio::spawn(async move {
+        server_instance.start(server_addr).await.unwrap();
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let long = "a".repeat(256);
+    // No room left for a `.~<n>~` version suffix
+    let long_last = format!("{}/{}", "a".repeat(255), "b".repeat(233));
+    for name in [
+        "../escape.txt",
+        "dir/../../escape.txt",
+        "/etc/passwd",
+        "C:\\Windows\\win.ini",
+        "a\0b",
+        "dir//a.txt",
+        "./a.txt",
+        "",
+        long.as_str(),
+        long_last.as_str(),
+    ] {
+        let files = BTreeMap::from([(name.to_string(), b"data".to_vec())]);
+        assert!(
+            client::upload_files(files, server_addr).await.is_err(),
+            "{:?} was accepted",
+            name
+        );
+    }
+    let raw = client::upload_from_reader("../raw.bin", &b"data"[..], 4, server_addr).await;
+    assert!(raw.is_err());
+
+    let files = BTreeMap::from([
+        ("dir/a.txt".to_string(), b"data".to_vec()),
+        ("file.txt:stream".to_string(), b"data".to_vec()),
+        (
+            format!("{}/{}", "a".repeat(255), "b".repeat(232)),
+            b"data".to_vec(),
+        ),
+    ]);
+    client::upload_files(files, server_addr).await.unwrap();
+    assert_eq!(
+        client::download_file("dir/a.txt", server_addr)
+            .await
+            .unwrap(),
+        b"data"
+    );
+}
+
+#[tokio::test]
+async fn test_conditional_uploads_detect_conflicts() {
+    use merklefile::merkle_tree::MerkleTree;
+    use merklefile::protocol::ProtocolError;
+
+    let server_addr = "127.0.0.1:8113";
+    let server_instance = server::new_server();
+    tokio::spawn(async move {
+        server_instance.start(server_addr).await.unwrap();
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let files = |data: &[u8]| BTreeMap::from([("a.txt".to_string(), data.to_vec())]);
+    let hash = |data: &[u8]| MerkleTree::new(vec![data.to_vec()]).leaf_hashes()[0].clone();
+    let client = client::Client::new(client::ClientConfig::new(server_addr));
+    let created = client::UploadCondition {
+        root: None,
+        files: BTreeMap::from([("a.txt".to_string(), None)]),
+    };
+    let root = client
+        .upload_files_if(files(b"first"), created.clone())
+        .await
+        .unwrap();
+    let first = hash(b"first");
+
+    // Another writer gets in between
+    client::upload_files(files(b"theirs"), server_addr)
+        .await
+        .unwrap();
+    let ours = client::UploadCondition {
+        root: None,
+        files: BTreeMap::from([("a.txt".to_string(), Some(first))]),
+    };
+    let err = client
+        .upload_files_if(files(b"ours"), ours)
+        .await
+        .unwrap_err();
+    match err.get_ref().unwrap().downcast_ref::<ProtocolError>() {
+        Some(ProtocolError::Conflict { files, .. }) => {
+            assert_eq!(files["a.txt"], Some(hash(b"theirs")));
+        }
+        other => panic!("Unexpected error {:?}", other),
+    }
+    let stale_root = client::UploadCondition {
+        root: Some(root),
+        files: BTreeMap::new(),
+    };
+    assert!(client
+        .upload_files_if(files(b"ours"), stale_root)
+        .await
+        .is_err());
+    assert!(client
+        .upload_files_if(files(b"ours"), created)
+        .await
+        .is_err());
+    assert_eq!(client.download_file("a.txt").await.unwrap(), b"theirs");
+
+    let current = client::UploadCondition {
+        root: Some(client.get_root_hash().await.unwrap()),
+        files: BTreeMap::from([("a.txt".to_string(), Some(hash(b"theirs")))]),
+    };
+    client
+        .upload_files_if(files(b"ours"), current)
+        .await
+        .unwrap();
+    assert_eq!(client.download_file("a.txt").await.unwrap(), b"ours");
+}
+
+#[tokio::test]
+async fn test_upload_to_all_commits_everywhere_or_nowhere() {
+    let (first_addr, second_addr) = ("127.0.0.1:8114", "127.0.0.1:8115");
+    for addr in [first_addr, second_addr] {
+        let server_instance = server::new_server();
+        tokio::spawn(async move {
+            server_instance.start(addr).await.unwrap();
+        });
+    }
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let mut config = client::ClientConfig::new(first_addr);
+    config.failover_addrs = vec![second_addr.to_string()];
+    let client = client::Client::new(config);
+    let files = BTreeMap::from([("a.txt".to_string(), b"first".to_vec())]);
+    let root = client.upload_files_to_all(files).await.unwrap();
+    for addr in [first_addr, second_addr] {
+        assert_eq!(client::get_root_hash(addr).await.unwrap(), root);
+    }
+
+    // Once the second server has drifted, the upload would leave them on different roots
+    let drift = BTreeMap::from([("b.txt".to_string(), b"drift".to_vec())]);
+    client::upload_files(drift, second_addr).await.unwrap();
+    let files = BTreeMap::from([("a.txt".to_string(), b"second".to_vec())]);
+    assert!(client.upload_files_to_all(files.clone()).await.is_err());
+    assert_eq!(client::get_root_hash(first_addr).await.unwrap(), root);
+
+    // The aborted upload no longer holds off other uploads
+    client::upload_files(files, first_addr).await.unwrap();
+    assert_ne!(client::get_root_hash(first_addr).await.unwrap(), root);
+}
+
+#[tokio::test]
+async fn test_upload_to_all_reports_servers_left_behind() {
+    use merklefile::protocol::ProtocolError;
+
+    let (first_addr, second_addr) = ("127.0.0.1:8124", "127.0.0.1:8125");
+    let first = server::Server::new();
+    // Prepared uploads expire at once on the second server, before their commit arrives
+    let config = server::ServerConfig::from_toml("prepare_timeout_secs = 0").unwrap();
+    let second = server::Server::new().with_config(&config);
+    for (addr, server_instance) in [(first_addr, first), (second_addr, second)] {
+        tokio::spawn(async move {
+            server_instance.start(addr).await.unwrap();
+        });
+    }
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let mut config = client::ClientConfig::new(first_addr);
+    config.failover_addrs = vec![second_addr.to_string()];
+    let client = client::Client::new(config);
+    let files = BTreeMap::from([("a.txt".to_string(), b"first".to_vec())]);
+    let err = client.upload_files_to_all(files).await.unwrap_err();
+    match err.get_ref().unwrap().downcast_ref::<ProtocolError>() {
+        Some(ProtocolError::PartialCommit {
+            committed,
+            unconfirmed,
+        }) => {
+            assert_eq!(committed, &[first_addr.to_string()]);
+            assert_eq!(unconfirmed, &[second_addr.to_string()]);
+        }
+        other => panic!("Unexpected error {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_websocket_downloads_verify_against_the_root() {
+    use futures_util::{SinkExt, StreamExt};
+    use merklefile::merkle_tree::MerkleProof;
+    use merklefile::protocol::{self, ClientMessage, ServerMessage};
+    use tokio_tungstenite::tungstenite::Message;
+
+    let (server_addr, websocket_addr) = ("127.0.0.1:8126", "127.0.0.1:8127");
+    let server_instance = server::Server::new().with_websocket(websocket_addr);
+    tokio::spawn(async move {
+        server_instance.start(server_addr).await.unwrap();
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let files = BTreeMap::from([
+        ("a.txt".to_string(), b"alpha".to_vec()),
+        ("b.txt".to_string(), b"beta".to_vec()),
+    ]);
+    client::upload_files(files, server_addr).await.unwrap();
+    let root = client::get_root_hash(server_addr).await.unwrap();
+
+    // Both requests go over one connection, one message each, as the browser client sends them
+    let url = format!("ws://{}", websocket_addr);
+    let (mut socket, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+    let mut request = async |message: ServerMessage| {
+        let frame = protocol::encode(&message).unwrap();
+        socket.send(Message::binary(frame)).await.unwrap();
+        let answer = socket.next().await.unwrap().unwrap().into_data();
+        protocol::decode::<ClientMessage>(&answer).unwrap()
+    };
+    let filename = "b.txt".to_string();
+    let data = match request(ServerMessage::Download {
+        filename: filename.clone(),
+    })
+    .await
+    {
+        ClientMessage::Success { data } => data,
+        other => panic!("Unexpected response {:?}", other),
+    };
+    let proof = match request(ServerMessage::GetMerkleProof { filename }).await {
+        ClientMessage::MerkleProof {
+            proof,
+            tree_size: Some(tree_size),
+        } => MerkleProof::from_path(&proof, tree_size).unwrap(),
+        other => panic!("Unexpected response {:?}", other),
+    };
+    assert_eq!(&data[..], b"beta");
+    assert!(proof.verify(&root, &data));
+
+    // Requests that stream bytes after their frame need TCP
+    let raw = ServerMessage::DownloadRaw {
+        filename: "a.txt".to_string(),
+    };
+    assert!(matches!(request(raw).await, ClientMessage::Error { .. }));
+}
+
+#[tokio::test]
+async fn test_clients_from_before_the_envelope_are_answered_in_their_format() {
+    use merklefile::protocol;
+
+    let server_addr = "127.0.0.1:8128";
+    let server_instance = server::Server::new();
+    tokio::spawn(async move {
+        server_instance.start(server_addr).await.unwrap();
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let files = BTreeMap::from([("a.txt".to_string(), b"hi".to_vec())]);
+    client::upload_files(files, server_addr).await.unwrap();
+
+    // A request exactly as clients before protocol version 1 sent it
+    let mut stream = tokio::net::TcpStream::connect(server_addr).await.unwrap();
+    protocol::write_frame(&mut stream, br#"{"Download":{"filename":"a.txt"}}"#)
+        .await
+        .unwrap();
+    let response = protocol::read_frame(&mut stream).await.unwrap();
+    assert_eq!(response, br#"{"Success":{"data":[104,105]}}"#);
+}
+
+#[tokio::test]
+async fn test_operation_log_replays_to_journaled_roots() {
+    use merklefile::operations;
+    use std::sync::Arc;
+
+    let server_addr = "127.0.0.1:8116";
+    let dir = std::env::temp_dir().join(format!("merklefile-replay-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (journal, operation_log) = (dir.join("roots.journal"), dir.join("ops.log"));
+    let key = server::generate_key();
+    let public_key = key.verifying_key();
+    let server_instance = server::Server::new()
+        .with_publisher(Arc::new(server::RootJournal::open(&journal, key).unwrap()))
+        .with_operation_log(&operation_log);
+    tokio::spawn(async move {
+        server_instance.start(server_addr).await.unwrap();
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    for (filename, data) in [("a.txt", "first"), ("b.txt", "second"), ("a.txt", "third")] {
+        let files = BTreeMap::from([(filename.to_string(), data.as_bytes().to_vec())]);
+        client::upload_files(files, server_addr).await.unwrap();
+    }
+    let root = client::get_root_hash(server_addr).await.unwrap();
+
+    let text = std::fs::read_to_string(&operation_log).unwrap();
+    let journal = merklefile::journal::read_journal(&journal, &public_key).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    let replay = operations::replay(&text).unwrap();
+    assert_eq!(replay.roots.len(), 3);
+    replay.check_journal(&journal).unwrap();
+    replay.check_current(&root).unwrap();
+
+    // An upload missing from the log leaves the later roots unexplained
+    let tampered: Vec<&str> = text
+        .lines()
+        .enumerate()
+        .filter(|(index, _)| *index != 2)
+        .map(|(_, line)| line)
+        .collect();
+    let replay = operations::replay(&tampered.join("\n")).unwrap();
+    assert!(replay.check_journal(&journal).is_err());
+    assert!(replay.check_current(&root).is_err());
+}
+
+#[tokio::test]
+async fn test_peers_hashing_differently_are_refused() {
+    use merklefile::protocol::{self, ClientMessage};
+
+    let server_addr = "127.0.0.1:8117";
+    let server_instance = server::Server::new();
+    tokio::spawn(async move {
+        server_instance.start(server_addr).await.unwrap();
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let mut stream = tokio::net::TcpStream::connect(server_addr).await.unwrap();
+    let request = br#"{"version":11,"hash":"sha512","message":{"type":"GetRootHash"}}"#;
+    protocol::write_frame(&mut stream, request).await.unwrap();
+    let response: ClientMessage =
+        protocol::decode(&protocol::read_frame(&mut stream).await.unwrap()).unwrap();
+    match response {
+        ClientMessage::Error { message } => assert!(message.contains("sha512"), "{}", message),
+        other => panic!("Unexpected response {:?}", other),
+    }
+    assert!(client::get_root_hash(server_addr).await.is_ok());
+}
+
+#[tokio::test]
+async fn test_one_proof_for_several_files() {
+    let server_addr = "127.0.0.1:8118";
+    let server_instance = server::Server::new();
+    tokio::spawn(async move {
+        server_instance.start(server_addr).await.unwrap();
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let files: BTreeMap<String, Vec<u8>> = (0..10)
+        .map(|i| {
+            (
+                format!("file_{}.txt", i),
+                format!("contents {}", i).into_bytes(),
+            )
+        })
+        .collect();
+    client::upload_files(files.clone(), server_addr)
+        .await
+        .unwrap();
+    let client = client::Client::new(client::ClientConfig::new(server_addr));
+    let root = client.get_root_hash().await.unwrap();
+
+    let names = ["file_7.txt", "file_2.txt", "file_3.txt"];
+    let proof = client.get_merkle_proofs(&names).await.unwrap();
+    let contents: Vec<&[u8]> = names.iter().map(|name| files[*name].as_slice()).collect();
+    assert!(proof.verify(&root, &contents));
+    // 2 and 3 are each other's siblings, and their path meets 7's below the root
+    let mut separate = 0;
+    for name in names {
+        separate += client.get_merkle_proof(name).await.unwrap().siblings.len();
+    }
+    assert_eq!(separate, 12);
+    assert_eq!(proof.siblings.len(), 4);
+
+    let mut swapped = contents.clone();
+    swapped.swap(0, 1);
+    assert!(!proof.verify(&root, &swapped));
+
+    let err = client
+        .get_merkle_proofs(&["file_1.txt", "missing.txt"])
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("File not found"), "{}", err);
+}
+
+#[tokio::test]
+async fn test_grown_trees_prove_they_extend_older_ones() {
+    let server_addr = "127.0.0.1:8119";
+    let server_instance = server::Server::new();
+    tokio::spawn(async move {
+        server_instance.start(server_addr).await.unwrap();
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let client = client::Client::new(client::ClientConfig::new(server_addr));
+    let files = |names: &[&str]| -> BTreeMap<String, Vec<u8>> {
+        names
+            .iter()
+            .map(|name| (name.to_string(), name.as_bytes().to_vec()))
+            .collect()
+    };
+    let receipt = client
+        .upload_files_with_proofs(files(&["b.txt", "c.txt", "d.txt"]))
+        .await
+        .unwrap();
+    let (old_root, old_size) = (receipt.root, receipt.proofs["b.txt"].tree_size);
+
+    // Files named after every stored one are appended as leaves
+    client
+        .upload_files(files(&["e.txt", "f.txt", "g.txt", "h.txt"]))
+        .await
+        .unwrap();
+    let root = client.get_root_hash().await.unwrap();
+    let proof = client.get_consistency_proof(old_size).await.unwrap();
+    assert_eq!((proof.old_size, proof.new_size), (3, 7));
+    assert!(proof.verify(&old_root, &root));
+
+    // One named before them moves the old files along, so the tree no longer extends theirs
+    client.upload_files(files(&["a.txt"])).await.unwrap();
+    let root = client.get_root_hash().await.unwrap();
+    let proof = client.get_consistency_proof(old_size).await.unwrap();
+    assert!(!proof.verify(&old_root, &root));
+
+    assert!(client.get_consistency_proof(0).await.is_err());
+    assert!(client.get_consistency_proof(9).await.is_err());
+}
+
+#[tokio::test]
+async fn test_delta_uploads_with_impossible_lengths_are_refused() {
+    use merklefile::protocol::{self, ClientMessage, ServerMessage};
+
+    let server_addr = "127.0.0.1:8120";
+    let server_instance = server::Server::new();
+    tokio::spawn(async move {
+        server_instance.start(server_addr).await.unwrap();
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    // Neither the length alone nor one the chunks cannot fill is allocated
+    for total_len in [1u64 << 45, 1 << 20] {
+        let request = ServerMessage::UploadChunks {
+            filename: "huge.bin".to_string(),
+            total_len,
+            chunk_size: 4096,
+            chunks: BTreeMap::from([(0, protocol::Bytes::from(vec![0u8; 4096]))]),
+            expect: None,
+        };
+        let mut stream = tokio::net::TcpStream::connect(server_addr).await.unwrap();
+        protocol::write_frame(&mut stream, &protocol::encode(&request).unwrap())
+            .await
+            .unwrap();
+        let response: ClientMessage =
+            protocol::decode(&protocol::read_frame(&mut stream).await.unwrap()).unwrap();
+        assert!(
+            matches!(response, ClientMessage::Error { .. }),
+            "{:?}",
+            response
+        );
+    }
+    assert!(client::get_root_hash(server_addr).await.is_ok());
+}